@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS "idx_scan_state_song_id";
+DROP INDEX IF EXISTS "idx_scan_state_library_path";
+
+DROP TABLE IF EXISTS "scan_state";
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS "scan_state" (
+    "path" TEXT NOT NULL PRIMARY KEY,
+    "library_path" TEXT NOT NULL,
+    "size" BIGINT NOT NULL,
+    "mtime" BIGINT NOT NULL,
+    "song_id" TEXT NOT NULL,
+    "album_name" TEXT NOT NULL,
+    "song" TEXT NOT NULL,
+    "scanned_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX "idx_scan_state_library_path" ON "scan_state"("library_path");
+CREATE INDEX "idx_scan_state_song_id" ON "scan_state"("song_id");
@@ -0,0 +1,20 @@
+DROP INDEX IF EXISTS "idx_song_path";
+DROP INDEX IF EXISTS "idx_song_album_id";
+DROP INDEX IF EXISTS "idx_album_name";
+DROP INDEX IF EXISTS "idx_album_artist_id";
+DROP INDEX IF EXISTS "idx_artist_name";
+
+ALTER TABLE "song" DROP COLUMN "position";
+ALTER TABLE "song" DROP COLUMN "music_video";
+ALTER TABLE "song" DROP COLUMN "duration";
+ALTER TABLE "song" DROP COLUMN "path";
+ALTER TABLE "song" DROP COLUMN "track_number";
+ALTER TABLE "song" DROP COLUMN "contributing_artist_ids";
+ALTER TABLE "song" DROP COLUMN "contributing_artists";
+ALTER TABLE "song" DROP COLUMN "artist";
+ALTER TABLE "song" DROP COLUMN "name";
+ALTER TABLE "song" DROP COLUMN "album_id";
+
+DROP TABLE IF EXISTS "album_release";
+DROP TABLE IF EXISTS "album";
+DROP TABLE IF EXISTS "artist";
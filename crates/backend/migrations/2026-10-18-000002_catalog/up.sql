@@ -0,0 +1,50 @@
+CREATE TABLE IF NOT EXISTS "artist" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "name" TEXT NOT NULL,
+    "icon_url" TEXT NOT NULL DEFAULT '',
+    "followers" BIGINT NOT NULL DEFAULT 0,
+    "description" TEXT NOT NULL DEFAULT '',
+    "tadb_music_videos" TEXT,
+    "featured_on_album_ids" TEXT NOT NULL DEFAULT '[]',
+    "position" INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS "album" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "artist_id" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "cover_url" TEXT NOT NULL DEFAULT '',
+    "first_release_date" TEXT NOT NULL DEFAULT '',
+    "musicbrainz_id" TEXT NOT NULL DEFAULT '',
+    "wikidata_id" TEXT,
+    "primary_type" TEXT NOT NULL DEFAULT '',
+    "description" TEXT NOT NULL DEFAULT '',
+    "contributing_artists" TEXT NOT NULL DEFAULT '[]',
+    "contributing_artists_ids" TEXT NOT NULL DEFAULT '[]',
+    "position" INTEGER NOT NULL DEFAULT 0,
+    CONSTRAINT "album_artist_id_fkey" FOREIGN KEY ("artist_id") REFERENCES "artist" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS "album_release" (
+    "album_id" TEXT NOT NULL PRIMARY KEY,
+    "release_album" TEXT,
+    "release_group_album" TEXT,
+    CONSTRAINT "album_release_album_id_fkey" FOREIGN KEY ("album_id") REFERENCES "album" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+ALTER TABLE "song" ADD COLUMN "album_id" TEXT;
+ALTER TABLE "song" ADD COLUMN "name" TEXT NOT NULL DEFAULT '';
+ALTER TABLE "song" ADD COLUMN "artist" TEXT NOT NULL DEFAULT '';
+ALTER TABLE "song" ADD COLUMN "contributing_artists" TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE "song" ADD COLUMN "contributing_artist_ids" TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE "song" ADD COLUMN "track_number" INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE "song" ADD COLUMN "path" TEXT NOT NULL DEFAULT '';
+ALTER TABLE "song" ADD COLUMN "duration" DOUBLE NOT NULL DEFAULT 0;
+ALTER TABLE "song" ADD COLUMN "music_video" TEXT;
+ALTER TABLE "song" ADD COLUMN "position" INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX "idx_artist_name" ON "artist"("name" COLLATE NOCASE);
+CREATE INDEX "idx_album_artist_id" ON "album"("artist_id");
+CREATE INDEX "idx_album_name" ON "album"("name" COLLATE NOCASE);
+CREATE INDEX "idx_song_album_id" ON "song"("album_id");
+CREATE INDEX "idx_song_path" ON "song"("path");
@@ -0,0 +1,3 @@
+ALTER TABLE "album" DROP COLUMN "edition";
+ALTER TABLE "song" DROP COLUMN "disc_total";
+ALTER TABLE "song" DROP COLUMN "disc_number";
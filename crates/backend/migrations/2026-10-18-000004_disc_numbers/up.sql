@@ -0,0 +1,6 @@
+ALTER TABLE "song" ADD COLUMN "disc_number" INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE "song" ADD COLUMN "disc_total" INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE "album" ADD COLUMN "edition" TEXT;
+
+-- Cached songs predate disc numbers, so every file is read again on the next scan.
+DELETE FROM "scan_state";
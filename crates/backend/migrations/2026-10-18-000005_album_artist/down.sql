@@ -0,0 +1,3 @@
+ALTER TABLE "scan_state" DROP COLUMN "compilation";
+ALTER TABLE "scan_state" DROP COLUMN "album_artist";
+ALTER TABLE "album" DROP COLUMN "album_type";
@@ -0,0 +1,6 @@
+ALTER TABLE "album" ADD COLUMN "album_type" TEXT NOT NULL DEFAULT 'album';
+ALTER TABLE "scan_state" ADD COLUMN "album_artist" TEXT;
+ALTER TABLE "scan_state" ADD COLUMN "compilation" BOOLEAN NOT NULL DEFAULT 0;
+
+-- Cached songs were read without album artist tags, so every file is read again on the next scan.
+DELETE FROM "scan_state";
@@ -0,0 +1,2 @@
+-- Cleared scan state is rebuilt by the next scan; there is nothing to restore.
+SELECT 1;
@@ -0,0 +1,2 @@
+-- Cached songs had their artist tags split from a single string, so every file is read again on the next scan.
+DELETE FROM "scan_state";
@@ -0,0 +1,7 @@
+ALTER TABLE "song" DROP COLUMN "file_size";
+ALTER TABLE "song" DROP COLUMN "bitrate";
+ALTER TABLE "song" DROP COLUMN "channels";
+ALTER TABLE "song" DROP COLUMN "bit_depth";
+ALTER TABLE "song" DROP COLUMN "sample_rate";
+ALTER TABLE "song" DROP COLUMN "container";
+ALTER TABLE "song" DROP COLUMN "codec";
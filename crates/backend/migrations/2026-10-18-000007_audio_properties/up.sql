@@ -0,0 +1,10 @@
+ALTER TABLE "song" ADD COLUMN "codec" TEXT;
+ALTER TABLE "song" ADD COLUMN "container" TEXT;
+ALTER TABLE "song" ADD COLUMN "sample_rate" INTEGER;
+ALTER TABLE "song" ADD COLUMN "bit_depth" INTEGER;
+ALTER TABLE "song" ADD COLUMN "channels" INTEGER;
+ALTER TABLE "song" ADD COLUMN "bitrate" INTEGER;
+ALTER TABLE "song" ADD COLUMN "file_size" BIGINT;
+
+-- Cached songs were read without audio properties, so every file is read again on the next scan.
+DELETE FROM "scan_state";
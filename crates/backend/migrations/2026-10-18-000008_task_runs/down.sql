@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS "idx_task_run_task";
+
+DROP TABLE IF EXISTS "task_run";
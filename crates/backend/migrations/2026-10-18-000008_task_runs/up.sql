@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS "task_run" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "task" TEXT NOT NULL,
+    "status" TEXT NOT NULL,
+    "message" TEXT,
+    "started_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "finished_at" DATETIME
+);
+
+CREATE INDEX "idx_task_run_task" ON "task_run"("task");
@@ -0,0 +1,7 @@
+ALTER TABLE "task_run" DROP COLUMN "job_id";
+
+DROP INDEX IF EXISTS "idx_job_log_job_id";
+DROP TABLE IF EXISTS "job_log";
+
+DROP INDEX IF EXISTS "idx_job_status";
+DROP TABLE IF EXISTS "job";
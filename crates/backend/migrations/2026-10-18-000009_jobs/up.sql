@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS "job" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "kind" TEXT NOT NULL,
+    "payload" TEXT NOT NULL,
+    "status" TEXT NOT NULL,
+    "attempts" INTEGER NOT NULL DEFAULT 0,
+    "max_attempts" INTEGER NOT NULL,
+    "progress" TEXT,
+    "result" TEXT,
+    "error" TEXT,
+    "run_after" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "created_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "started_at" DATETIME,
+    "finished_at" DATETIME
+);
+
+CREATE INDEX "idx_job_status" ON "job"("status");
+
+CREATE TABLE IF NOT EXISTS "job_log" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "job_id" INTEGER NOT NULL REFERENCES "job"("id") ON DELETE CASCADE,
+    "message" TEXT NOT NULL,
+    "created_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX "idx_job_log_job_id" ON "job_log"("job_id");
+
+ALTER TABLE "task_run" ADD COLUMN "job_id" INTEGER REFERENCES "job"("id") ON DELETE SET NULL;
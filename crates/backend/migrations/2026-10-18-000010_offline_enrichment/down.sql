@@ -0,0 +1,5 @@
+ALTER TABLE "scan_state" DROP COLUMN "genres";
+ALTER TABLE "scan_state" DROP COLUMN "release_date";
+ALTER TABLE "album" DROP COLUMN "genres";
+ALTER TABLE "album" DROP COLUMN "pending_enrichment";
+ALTER TABLE "artist" DROP COLUMN "pending_enrichment";
@@ -0,0 +1,8 @@
+ALTER TABLE "artist" ADD COLUMN "pending_enrichment" BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE "album" ADD COLUMN "pending_enrichment" BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE "album" ADD COLUMN "genres" TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE "scan_state" ADD COLUMN "release_date" TEXT;
+ALTER TABLE "scan_state" ADD COLUMN "genres" TEXT NOT NULL DEFAULT '[]';
+
+-- Cached songs were read without their dates and genres, so every file is read again on the next scan.
+DELETE FROM "scan_state";
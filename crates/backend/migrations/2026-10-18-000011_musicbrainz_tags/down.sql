@@ -0,0 +1,8 @@
+ALTER TABLE "scan_state" DROP COLUMN "musicbrainz_artist_id";
+ALTER TABLE "scan_state" DROP COLUMN "musicbrainz_release_group_id";
+ALTER TABLE "scan_state" DROP COLUMN "musicbrainz_release_id";
+ALTER TABLE "song" DROP COLUMN "musicbrainz_track_id";
+ALTER TABLE "song" DROP COLUMN "musicbrainz_recording_id";
+ALTER TABLE "album" DROP COLUMN "musicbrainz_release_group_id";
+ALTER TABLE "album" DROP COLUMN "musicbrainz_release_id";
+ALTER TABLE "artist" DROP COLUMN "musicbrainz_id";
@@ -0,0 +1,11 @@
+ALTER TABLE "artist" ADD COLUMN "musicbrainz_id" TEXT;
+ALTER TABLE "album" ADD COLUMN "musicbrainz_release_id" TEXT;
+ALTER TABLE "album" ADD COLUMN "musicbrainz_release_group_id" TEXT;
+ALTER TABLE "song" ADD COLUMN "musicbrainz_recording_id" TEXT;
+ALTER TABLE "song" ADD COLUMN "musicbrainz_track_id" TEXT;
+ALTER TABLE "scan_state" ADD COLUMN "musicbrainz_release_id" TEXT;
+ALTER TABLE "scan_state" ADD COLUMN "musicbrainz_release_group_id" TEXT;
+ALTER TABLE "scan_state" ADD COLUMN "musicbrainz_artist_id" TEXT;
+
+-- Cached songs were read without their MusicBrainz IDs, so every file is read again on the next scan.
+DELETE FROM "scan_state";
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS "idx_scan_error_library_path";
+DROP TABLE IF EXISTS "scan_error";
+
+DROP TABLE IF EXISTS "scan_report";
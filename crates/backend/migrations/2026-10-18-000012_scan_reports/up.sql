@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS "scan_report" (
+    "library_path" TEXT NOT NULL PRIMARY KEY,
+    "added" TEXT NOT NULL,
+    "removed" TEXT NOT NULL,
+    "moved" TEXT NOT NULL,
+    "changed" TEXT NOT NULL,
+    "scanned_at" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS "scan_error" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "library_path" TEXT NOT NULL REFERENCES "scan_report"("library_path") ON DELETE CASCADE,
+    "path" TEXT NOT NULL,
+    "kind" TEXT NOT NULL,
+    "message" TEXT NOT NULL
+);
+
+CREATE INDEX "idx_scan_error_library_path" ON "scan_error"("library_path");
@@ -10,9 +10,7 @@ use actix_cors::Cors;
 use actix_web::HttpResponse;
 use actix_web::{middleware, web, App, HttpServer};
 use actix_web_httpauth::middleware::HttpAuthentication;
-use notify::{Event, RecursiveMode, Watcher};
 use routes::authentication::{admin_guard, is_valid, refresh, renew_refresh_token};
-use tokio::sync::broadcast;
 use tokio::task;
 use tracing::{error, info, Level};
 use tracing_subscriber::FmtSubscriber;
@@ -21,9 +19,16 @@ use routes::{album, database, genres, music};
 use routes::artist;
 use routes::authentication::{login, register, validator};
 use routes::filesystem;
+use routes::backups::{backup_diff, get_backup_retention, get_backups, restore_backup_route, set_backup_retention};
 use routes::image::image;
+use routes::jobs::{cancel_job_route, get_job_details, get_jobs};
+use routes::metadata::{
+    get_album_candidates, get_artist_candidates, get_metadata_providers, match_album, match_artist, set_metadata_providers,
+};
 use routes::music::{
-    index, index_library_no_cover_url, library_refresh, process_music_library_no_ws, read_library_paths, Libraries 
+    add_library, cancel_scan_route, get_artist_splitting, get_library_settings, get_schedule, get_task_runs, index, index_library_no_cover_url,
+    library_diff, library_refresh, list_libraries, refresh_metadata_route, remove_library, run_job, scan_report, scan_report_errors,
+    set_artist_splitting, set_library_settings, set_schedule, update_changed_files, update_library
 };
 use routes::playlist;
 use routes::search::{self, populate_search_data};
@@ -33,10 +38,18 @@ use routes::song;
 use routes::user;
 use routes::web as web_routes;
 
-use utils::config::{self, get_libraries_config_path};
-use utils::database::database::{migrations_ran, redo_migrations};
+use utils::backups::load_backup_retention;
+use utils::catalog::import_catalog_from_json;
+use utils::config;
+use utils::database::database::{establish_connection, redo_migrations};
+use utils::format::load_artist_split_settings;
 use utils::database::database::run_migrations;
+use utils::id_migration::migrate_ids;
+use utils::jobs::{requeue_interrupted_jobs, start_job_workers};
+use utils::metadata_providers::load_metadata_provider_settings;
+use utils::scheduler::{interrupt_stale_task_runs, load_schedule_settings, run_scheduler};
 // use utils::update::check_for_updates;
+use utils::watcher::start_library_watcher;
 use utils::websocket::ws;
 
 use rust_embed::RustEmbed;
@@ -131,71 +144,75 @@ async fn main() -> std::io::Result<()> {
         }
     }
 
-    info!("Starting server on port {}", port); 
+    // The catalog has to be in the database, with current ids, before the
+    // server, the job workers or the library watcher read or cache it
+    let setup = task::spawn_blocking(|| {
+        // Pending migrations are applied on every start, since checking only
+        // whether any migration ever ran would skip ones added in an update
+        if let Err(e) = run_migrations() {
+            eprintln!("Failed to run migrations: {}", e);
+        }
 
-    let (tx, _rx) = broadcast::channel(16);
-    let tx_clone = tx.clone();
+        if let Err(e) = import_catalog_from_json() {
+            eprintln!("Failed to import music.json into the database: {}", e);
+        }
 
-    std::thread::spawn(move || {
-    let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
-        match res {
-            Ok(_) => {
-                if let Err(e) = tx_clone.send(()) {
-                    error!("Failed to send watch event: {}", e);
-                }
-            }
-            Err(e) => error!("Watch error: {}", e),
+        if let Err(e) = migrate_ids() {
+            eprintln!("Failed to migrate catalog IDs: {}", e);
         }
-    }).expect("Failed to create watcher");
 
-    let config_content = std::fs::read_to_string(get_libraries_config_path())
-        .expect("Failed to read libraries config");
-    let libraries: Libraries = serde_json::from_str(&config_content)
-        .expect("Failed to parse libraries config");
-    
-    for library_path in &libraries.paths {
-        let path = std::path::Path::new(library_path);
-        let watch_path = path.parent().unwrap_or(path);
-    
-        watcher.watch(watch_path, RecursiveMode::NonRecursive)
-            .expect("Failed to watch library path");
+        if let Err(e) = interrupt_stale_task_runs() {
+            eprintln!("Failed to update the task run history: {}", e);
+        }
 
-        std::thread::park();
-        watcher.watch(watch_path, RecursiveMode::NonRecursive)
-            .expect("Failed to watch libraries path");
+        if let Err(e) = requeue_interrupted_jobs() {
+            eprintln!("Failed to requeue interrupted jobs: {}", e);
+        }
+    })
+    .await;
+    if let Err(e) = setup {
+        eprintln!("Database setup failed: {}", e);
     }
-    
-    std::thread::park();
-});
 
-    let mut rx = tx.subscribe();
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().expect("Failed to create runtime");
-        
-        while rt.block_on(rx.recv()).is_ok() {
-            info!("Libraries file changed");
-            let paths = rt.block_on(read_library_paths());
-                for path in paths {
-                    if let Err(e) = rt.block_on(process_music_library_no_ws(&path)) {
-                        error!("Failed to process library {}: {}", path, e);
-                    }
-                }
+    info!("Starting server on port {}", port); 
+
+    // One pool for the whole server, shared with the routes through app data
+    let pool = establish_connection();
+
+    // The watcher calls back on its own thread, so it gets a runtime of its own
+    let watcher_runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
+    start_library_watcher(move |library_path, paths| {
+        if let Err(e) = watcher_runtime.block_on(update_changed_files(library_path, paths)) {
+            error!("Failed to update library {}: {}", library_path, e);
         }
     });
 
+    if let Err(e) = load_artist_split_settings() {
+        eprintln!("Failed to load artist splitting settings: {}", e);
+    }
+
+    if let Err(e) = load_schedule_settings() {
+        eprintln!("Failed to load schedule settings: {}", e);
+    }
+
+    if let Err(e) = load_backup_retention() {
+        eprintln!("Failed to load backup retention: {}", e);
+    }
+
+    if let Err(e) = load_metadata_provider_settings() {
+        eprintln!("Failed to load metadata provider settings: {}", e);
+    }
+
     task::spawn(async move {
-        if !migrations_ran() {
-            if let Err(e) = run_migrations() {
-                eprintln!("Failed to run migrations: {}", e);
-            }
-        }
-    
         if let Err(e) = populate_search_data().await {
             eprintln!("Failed to populate search data: {}", e);
         }
         // run_modules().await;
     });
     
+    start_job_workers(run_job);
+    actix_web::rt::spawn(run_scheduler());
+
     HttpServer::new(move || {
         let authentication = HttpAuthentication::with_fn(validator);
         let admin = HttpAuthentication::with_fn(admin_guard);
@@ -222,9 +239,40 @@ async fn main() -> std::io::Result<()> {
             .wrap(admin)
             .service(index_library_no_cover_url)
             .service(index)
-            .service(library_refresh);
+            .service(library_refresh)
+            .service(cancel_scan_route)
+            .service(scan_report)
+            .service(scan_report_errors)
+            .service(library_diff)
+            .service(get_artist_splitting)
+            .service(set_artist_splitting)
+            .service(get_schedule)
+            .service(set_schedule)
+            .service(get_task_runs)
+            .service(get_jobs)
+            .service(get_job_details)
+            .service(cancel_job_route)
+            .service(get_backups)
+            .service(get_backup_retention)
+            .service(set_backup_retention)
+            .service(backup_diff)
+            .service(restore_backup_route)
+            .service(get_metadata_providers)
+            .service(set_metadata_providers)
+            .service(get_album_candidates)
+            .service(match_album)
+            .service(get_artist_candidates)
+            .service(match_artist)
+            .service(refresh_metadata_route)
+            .service(get_library_settings)
+            .service(set_library_settings)
+            .service(list_libraries)
+            .service(add_library)
+            .service(update_library)
+            .service(remove_library);
 
         App::new()
+            .app_data(web::Data::new(pool.clone()))
             .wrap(
                 Cors::default()
                     .allow_any_origin()
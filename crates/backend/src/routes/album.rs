@@ -7,7 +7,7 @@ use tracing::error;
 
 use crate::routes::search::populate_search_data;
 use crate::structures::structures::{Album, Artist, ReleaseAlbum, ReleaseGroupAlbum, Song};
-use crate::utils::config::{fetch_library, get_config, refresh_cache, save_library};
+use crate::utils::config::{fetch_catalog_index, fetch_library, lock_catalog, refresh_cache, save_library};
 use crate::utils::hash::hash_artist;
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -29,8 +29,7 @@ pub struct ResponseAlbum {
 }
 
 pub async fn fetch_random_albums(amount: usize) -> Result<Vec<ResponseAlbum>, ()> {
-    let config = get_config().await.map_err(|_| ())?;
-    let library: Vec<Artist> = serde_json::from_str(&config).map_err(|_| ())?;
+    let library = fetch_library().await.map_err(|_| ())?;
 
     let mut random_albums_with_artists = Vec::new();
     let mut rng = rand::thread_rng();
@@ -84,40 +83,36 @@ pub enum AlbumInfo {
 }
 
 pub async fn fetch_album_info(album_id: String, bare: Option<bool>) -> Result<AlbumInfo, ()> {
-    let library = fetch_library().await.map_err(|_| ())?;
+    let index = fetch_catalog_index().await.map_err(|_| ())?;
     let bare = bare.unwrap_or(false);
 
-    for artist in library.iter() {
-        for album in artist.albums.iter() {
-            if album.id == album_id {
-                if bare {
-                    return Ok(AlbumInfo::Bare(album.clone()))
-                }
-                return Ok(AlbumInfo::Full(ResponseAlbum {
-                    id: album.id.clone(),
-                    name: album.name.clone(),
-                    cover_url: album.cover_url.clone(),
-                    songs: album.songs.clone(),
-                    first_release_date: album.first_release_date.clone(),
-                    musicbrainz_id: album.musicbrainz_id.clone(),
-                    wikidata_id: album.wikidata_id.clone(),
-                    primary_type: album.primary_type.clone(),
-                    description: album.description.clone(),
-                    artist_object: artist.clone(),
-                    contributing_artists: album.contributing_artists.clone(),
-                    contributing_artists_ids: album.contributing_artists_ids.clone(),
-                    release_album: album.release_album.clone(),
-                    release_group_album: album.release_group_album.clone()
-                }));
-            }
-        }
+    let (album, artist) = index.album(&album_id).ok_or(())?;
+    let album = album.clone();
+    if bare {
+        return Ok(AlbumInfo::Bare(album))
     }
 
-    Err(())
+    Ok(AlbumInfo::Full(ResponseAlbum {
+        id: album.id,
+        name: album.name,
+        cover_url: album.cover_url,
+        songs: album.songs,
+        first_release_date: album.first_release_date,
+        musicbrainz_id: album.musicbrainz_id,
+        wikidata_id: album.wikidata_id,
+        primary_type: album.primary_type,
+        description: album.description,
+        artist_object: artist.clone(),
+        contributing_artists: album.contributing_artists,
+        contributing_artists_ids: album.contributing_artists_ids,
+        release_album: album.release_album,
+        release_group_album: album.release_group_album
+    }))
 }
 
 #[post("/edit/{id}")]
 async fn edit_album_metadata(form: web::Json<Album>) -> HttpResponse {
+    let _catalog_lock = lock_catalog().await;
     let mut library = match fetch_library().await {
         Ok(lib) => lib,
         Err(_) => return HttpResponse::InternalServerError().finish(),
@@ -181,6 +176,7 @@ pub async fn add_album(form: web::Json<AddAlbumForm>) -> HttpResponse {
     let new_album = form.album.clone();
     let artist_id = form.artist_id.clone();
 
+    let _catalog_lock = lock_catalog().await;
     let mut library = match fetch_library().await {
         Ok(lib) => lib,
         Err(_) => return HttpResponse::InternalServerError().finish(),
@@ -215,6 +211,8 @@ pub async fn add_album(form: web::Json<AddAlbumForm>) -> HttpResponse {
                 followers: 0,
                 description: String::new(),
                 tadb_music_videos: None,
+                pending_enrichment: false,
+                musicbrainz_id: None,
             };
             Arc::make_mut(&mut library).push(new_artist);
         }
@@ -247,6 +245,7 @@ pub struct DeleteAlbumForm {
 pub async fn delete_album(form: web::Json<DeleteAlbumForm>) -> HttpResponse {
     let album_id = form.album_id.clone();
 
+    let _catalog_lock = lock_catalog().await;
     let mut library = match fetch_library().await {
         Ok(lib) => lib,
         Err(_) => return HttpResponse::InternalServerError().finish(),
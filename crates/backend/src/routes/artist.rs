@@ -5,11 +5,10 @@ use rand::seq::SliceRandom;
 use serde::Deserialize;
 
 pub use crate::structures::structures::Artist;
-use crate::utils::config::{fetch_library, get_config, refresh_cache, save_library};
+use crate::utils::config::{fetch_catalog_index, fetch_library, lock_catalog, refresh_cache, save_library};
 
 pub async fn fetch_random_artists(amount: usize) -> Result<Vec<Artist>, ()> {
-    let config = get_config().await.map_err(|_| ())?;
-    let library: Vec<Artist> = serde_json::from_str(&config).map_err(|_| ())?;
+    let library = fetch_library().await.map_err(|_| ())?;
 
     let artists_with_albums: Vec<&Artist> = library.iter().filter(|artist| !artist.albums.is_empty()).collect();
 
@@ -30,16 +29,8 @@ pub async fn fetch_random_artists(amount: usize) -> Result<Vec<Artist>, ()> {
 }
 
 pub async fn fetch_artist_info(artist_id: String) -> Result<Artist, ()> {
-    let config = get_config().await.map_err(|_| ())?;
-    let library: Vec<Artist> = serde_json::from_str(&config).map_err(|_| ())?;
-
-    for artist in library.iter() {
-        if artist.id == artist_id {
-            return Ok(artist.clone());
-        }
-    }
-
-    Err(())
+    let index = fetch_catalog_index().await.map_err(|_| ())?;
+    index.artist(&artist_id).cloned().ok_or(())
 }
 
 #[get("/random/{amount}")]
@@ -60,6 +51,7 @@ async fn get_artist_info(id: web::Path<String>) -> HttpResponse {
 
 #[post("/edit/{id}")]
 async fn edit_artist_metadata(form: web::Json<Artist>) -> HttpResponse {
+    let _catalog_lock = lock_catalog().await;
     let mut library = match fetch_library().await {
         Ok(lib) => lib,
         Err(_) => return HttpResponse::InternalServerError().finish(),
@@ -98,6 +90,7 @@ pub struct AddArtistForm {
 pub async fn add_artist(form: web::Json<AddArtistForm>) -> HttpResponse {
     let new_artist = form.artist.clone();
 
+    let _catalog_lock = lock_catalog().await;
     let mut library = match fetch_library().await {
         Ok(lib) => lib,
         Err(_) => return HttpResponse::InternalServerError().finish(),
@@ -125,6 +118,7 @@ pub struct DeleteArtistForm {
 pub async fn delete_artist(form: web::Json<DeleteArtistForm>) -> HttpResponse {
     let artist_id = form.artist_id.clone();
 
+    let _catalog_lock = lock_catalog().await;
     let mut library = match fetch_library().await {
         Ok(lib) => lib,
         Err(_) => return HttpResponse::InternalServerError().finish(),
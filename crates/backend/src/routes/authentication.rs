@@ -14,7 +14,7 @@ use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header,
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::utils::{config::get_jwt_secret, database::{database::establish_connection, models::NewUser}};
+use crate::utils::{config::get_jwt_secret, database::{database::{with_connection, DbError, DbPool}, models::NewUser}};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -140,26 +140,17 @@ pub async fn is_valid(req: HttpRequest) -> impl Responder {
 
 
 #[post("/login")]
-pub async fn login(form: web::Json<AuthData>) -> impl Responder {
+pub async fn login(pool: web::Data<DbPool>, form: web::Json<AuthData>) -> impl Responder {
     use crate::utils::database::schema::user::dsl::*;
     dotenv().ok();
 
-    let mut connection = match establish_connection().get() {
-        Ok(conn) => conn,
-        Err(_) => {
-            return HttpResponse::InternalServerError().json(ResponseAuthData {
-                status: false,
-                access_token: String::new(),
-                refresh_token: String::new(),
-                message: Some(String::from("Failed to establish database connection")),
-            });
-        }
-    };
-
-    let result = user
-        .filter(username.eq(&form.username))
-        .select((password, id, bitrate, role))
-        .first::<(String, i32, i32, String)>(&mut connection);
+    let form_username = form.username.clone();
+    let result = with_connection(&pool, move |connection| {
+        user
+            .filter(username.eq(&form_username))
+            .select((password, id, bitrate, role))
+            .first::<(String, i32, i32, String)>(connection)
+    }).await;
 
     match result {
         Ok((stored_password_hash, user_id, user_bitrate, user_role)) => {
@@ -199,7 +190,7 @@ pub async fn login(form: web::Json<AuthData>) -> impl Responder {
                 })
             }
         },
-        Err(_) => {
+        Err(DbError::Query(_)) => {
             HttpResponse::Ok().json(ResponseAuthData { 
                 status: false, 
                 access_token: String::new(), 
@@ -207,6 +198,14 @@ pub async fn login(form: web::Json<AuthData>) -> impl Responder {
                 message: Some(String::from("Invalid Username or Password!")),
             })
         }
+        Err(_) => {
+            HttpResponse::InternalServerError().json(ResponseAuthData {
+                status: false,
+                access_token: String::new(),
+                refresh_token: String::new(),
+                message: Some(String::from("Failed to establish database connection")),
+            })
+        }
     }
 }
 
@@ -218,12 +217,13 @@ pub struct RegisterData {
 }
 
 #[post("/register")]
-pub async fn register(form: web::Json<RegisterData>, req: HttpRequest) -> impl Responder {
+pub async fn register(pool: web::Data<DbPool>, form: web::Json<RegisterData>, req: HttpRequest) -> impl Responder {
     use crate::utils::database::schema::user::dsl::*;
     dotenv().ok();
 
-    let mut connection = match establish_connection().get() {
-        Ok(conn) => conn,
+    let existing_users_count: i64 = match with_connection(&pool, |connection| user.count().get_result(connection)).await {
+        Ok(count) => count,
+        Err(DbError::Query(_)) => 0,
         Err(_) => {
             return HttpResponse::InternalServerError().json(ResponseAuthData {
                 status: false,
@@ -234,8 +234,6 @@ pub async fn register(form: web::Json<RegisterData>, req: HttpRequest) -> impl R
         }
     };
 
-    let existing_users_count: i64 = user.count().get_result(&mut connection).unwrap_or(0);
-
     let new_user_role = if existing_users_count == 0 {
         "admin".to_string()
     }     else {
@@ -283,10 +281,11 @@ pub async fn register(form: web::Json<RegisterData>, req: HttpRequest) -> impl R
         role: new_user_role,
     };
 
-    diesel::insert_into(user)
-        .values(&new_user)
-        .execute(&mut connection)
-        .expect("Error saving new user");
+    with_connection(&pool, move |connection| {
+        diesel::insert_into(user)
+            .values(&new_user)
+            .execute(connection)
+    }).await.expect("Error saving new user");
 
     HttpResponse::Ok().json(ResponseAuthData {
         status: true,
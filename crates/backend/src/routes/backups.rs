@@ -0,0 +1,150 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::routes::search::update_search_data;
+use crate::utils::backups::{
+    backup_retention, find_backup, list_backups, prune_backups, read_backup, save_backup_retention, Backup, BackupRetention,
+};
+use crate::utils::compare::{preview_library_diff, LibraryDiff};
+use crate::utils::config::{fetch_library, lock_catalog, save_config};
+use crate::utils::progress::{begin_scan, ScanError, ScanPhase};
+use crate::utils::websocket::log_to_ws;
+
+#[get("/backups")]
+pub async fn get_backups() -> impl Responder {
+    match list_backups() {
+        Ok(backups) => HttpResponse::Ok().json(backups),
+        Err(e) => {
+            error!("Failed to list catalog backups: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[get("/backups/retention")]
+pub async fn get_backup_retention() -> impl Responder {
+    HttpResponse::Ok().json(backup_retention())
+}
+
+#[derive(Serialize)]
+pub struct RetentionResponse {
+    #[serde(flatten)]
+    pub retention: BackupRetention,
+    /// Backups deleted because the new policy no longer keeps them
+    pub pruned: Vec<Backup>,
+}
+
+#[post("/backups/retention")]
+pub async fn set_backup_retention(retention: web::Json<BackupRetention>) -> impl Responder {
+    if let Err(e) = retention.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    if let Err(e) = save_backup_retention(retention.into_inner()) {
+        error!("Failed to save backup retention: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    let retention = backup_retention();
+    match prune_backups(&retention) {
+        Ok(pruned) => HttpResponse::Ok().json(RetentionResponse { retention, pruned }),
+        Err(e) => {
+            error!("Failed to prune catalog backups: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct BackupDiff {
+    pub backup: Backup,
+    /// What restoring the backup would change in the live catalog
+    #[serde(flatten)]
+    pub diff: LibraryDiff,
+}
+
+#[get("/backups/{id}/diff")]
+pub async fn backup_diff(id: web::Path<u32>) -> impl Responder {
+    let backup = match find_backup(*id) {
+        Ok(Some(backup)) => backup,
+        Ok(None) => return HttpResponse::NotFound().body("Backup not found"),
+        Err(e) => {
+            error!("Failed to find backup {}: {}", id, e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let result = async {
+        let current = fetch_library().await?;
+        let mut restored = read_backup(&backup)?;
+        Ok::<_, Box<dyn std::error::Error>>(preview_library_diff("", &current, &mut restored, Vec::new()))
+    }.await;
+
+    match result {
+        Ok(diff) => HttpResponse::Ok().json(BackupDiff { backup, diff }),
+        Err(e) => {
+            error!("Failed to diff backup {}: {:?}", backup.file_name, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RestoredBackup {
+    pub restored: Backup,
+    /// The catalog as it was before the restore, so the restore can be undone
+    pub previous: Option<Backup>,
+}
+
+/// Replaces the catalog with a backup. Runs as a scan so it can't overlap a
+/// refresh that would save over it.
+async fn restore_backup(backup: Backup) -> Result<RestoredBackup, Box<dyn std::error::Error>> {
+    let progress = begin_scan()?;
+    progress.start_library("");
+    progress.set_phase(ScanPhase::Saving);
+
+    let result = async {
+        let _catalog_lock = lock_catalog().await;
+        let before = fetch_library().await?;
+        let restored = read_backup(&backup)?;
+
+        let previous = save_config(&restored, true).await?;
+
+        if let Err(e) = update_search_data(&before, &restored).await {
+            error!("Failed to update search data: {:?}", e);
+        }
+
+        let message = format!("Restored the catalog from {}", backup.file_name);
+        info!("{}", message);
+        log_to_ws(message).await;
+
+        Ok(RestoredBackup { restored: backup, previous })
+    }.await;
+
+    progress.finish(&result).await;
+    result
+}
+
+#[post("/backups/{id}/restore")]
+pub async fn restore_backup_route(id: web::Path<u32>) -> impl Responder {
+    let backup = match find_backup(*id) {
+        Ok(Some(backup)) => backup,
+        Ok(None) => return HttpResponse::NotFound().body("Backup not found"),
+        Err(e) => {
+            error!("Failed to find backup {}: {}", id, e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    match restore_backup(backup).await {
+        Ok(restored) => HttpResponse::Ok().json(restored),
+        Err(e) => match e.downcast_ref::<ScanError>() {
+            Some(ScanError::AlreadyRunning) => HttpResponse::Conflict().body(e.to_string()),
+            _ => {
+                error!("Failed to restore backup {}: {:?}", id, e);
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+    }
+}
@@ -1,8 +1,8 @@
 use std::error::Error;
-use actix_web::{post, web, HttpResponse, Responder};
+use actix_web::{get, post, web, HttpResponse, Responder};
 use actix_web_httpauth::middleware::HttpAuthentication;
 
-use crate::utils::database::database::redo_migrations;
+use crate::utils::database::database::{pool_stats, redo_migrations, DbPool};
 
 use super::authentication::admin_guard;
 
@@ -14,6 +14,11 @@ async fn redo_migrations_handler() -> Result<impl Responder, Box<dyn Error>> {
     }
 }
 
+#[get("/pool")]
+async fn pool_stats_handler(pool: web::Data<DbPool>) -> impl Responder {
+    HttpResponse::Ok().json(pool_stats(&pool))
+}
+
 pub fn configure(cfg: &mut web::ServiceConfig) {
     let admin = HttpAuthentication::with_fn(admin_guard);
 
@@ -21,5 +26,6 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
       web::scope("/database")
         .wrap(admin)
         .service(redo_migrations_handler)
+        .service(pool_stats_handler)
     );
 }
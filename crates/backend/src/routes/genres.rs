@@ -1,10 +1,8 @@
 use actix_web::{get, web, HttpResponse};
 use std::collections::HashSet;
 use crate::structures::structures::{Album, Artist, Genre, Song};
-use crate::config::get_config;
-use crate::utils::config::fetch_library;
+use crate::utils::config::{fetch_catalog_index, fetch_library};
 use serde::Deserialize;
-use serde_json::from_str;
 
 #[derive(Deserialize)]
 struct GenresQuery {
@@ -47,13 +45,12 @@ async fn get_songs_by_genres(query: web::Query<GenresQuery>) -> HttpResponse {
 }
 
 pub async fn list_all_genres() -> Result<HashSet<String>, ()> {
-    let config = get_config().await.map_err(|_| ())?;
-    let library: Vec<Artist> = from_str(&config).map_err(|_| ())?;
+    let library = fetch_library().await.map_err(|_| ())?;
 
     let mut genres = HashSet::new();
     let mut seen_genres = HashSet::new();
 
-    for artist in &library {
+    for artist in library.iter() {
         for album in &artist.albums {
             if let Some(release_album) = &album.release_album {
                 for genre in &release_album.genres {
@@ -73,25 +70,22 @@ pub async fn list_all_genres() -> Result<HashSet<String>, ()> {
                     }
                 }
             }
+            genres.extend(album.genres.iter().cloned());
         }
     }
     Ok(genres)
 }
 
 pub async fn fetch_albums_by_genres(genres: Vec<String>) -> Result<Vec<Album>, ()> {
-    let config = get_config().await.map_err(|_| ())?;
-    let library: Vec<Artist> = from_str(&config).map_err(|_| ())?;
+    let library = fetch_library().await.map_err(|_| ())?;
 
     let mut response_albums = Vec::new();
     let genre_set: HashSet<String> = genres.into_iter().collect();
 
-    for artist in &library {
+    for artist in library.iter() {
         for album in &artist.albums {
-            if let Ok(album_genres) = get_genre_info_by_album(&album.id).await {
-                let album_genre_names: HashSet<String> = album_genres.into_iter().map(|g| g.name).collect();
-                if !album_genre_names.is_disjoint(&genre_set) {
-                    response_albums.push(album.clone());
-                }
+            if album_has_genre(album, &genre_set) {
+                response_albums.push(album.clone());
             }
         }
     }
@@ -99,20 +93,16 @@ pub async fn fetch_albums_by_genres(genres: Vec<String>) -> Result<Vec<Album>, (
 }
 
 pub async fn fetch_artists_by_genres(genres: Vec<String>) -> Result<Vec<Artist>, ()> {
-    let config = get_config().await.map_err(|_| ())?;
-    let library: Vec<Artist> = from_str(&config).map_err(|_| ())?;
+    let library = fetch_library().await.map_err(|_| ())?;
 
     let mut response_artists = Vec::new();
     let genre_set: HashSet<String> = genres.into_iter().collect();
 
-    for artist in &library {
+    for artist in library.iter() {
         for album in &artist.albums {
-            if let Ok(album_genres) = get_genre_info_by_album(&album.id).await {
-                let album_genre_names: HashSet<String> = album_genres.into_iter().map(|g| g.name).collect();
-                if !album_genre_names.is_disjoint(&genre_set) {
-                    response_artists.push(artist.clone());
-                    break;
-                }
+            if album_has_genre(album, &genre_set) {
+                response_artists.push(artist.clone());
+                break;
             }
         }
     }
@@ -120,21 +110,15 @@ pub async fn fetch_artists_by_genres(genres: Vec<String>) -> Result<Vec<Artist>,
 }
 
 pub async fn fetch_songs_by_genres(genres: Vec<String>) -> Result<Vec<Song>, ()> {
-    let config = get_config().await.map_err(|_| ())?;
-    let library: Vec<Artist> = from_str(&config).map_err(|_| ())?;
+    let library = fetch_library().await.map_err(|_| ())?;
 
     let mut response_songs = Vec::new();
     let genre_set: HashSet<String> = genres.into_iter().collect();
 
-    for artist in &library {
+    for artist in library.iter() {
         for album in &artist.albums {
-            for song in &album.songs {
-                if let Ok(song_genres) = get_genre_info_by_song(&song.id).await {
-                    let song_genre_names: HashSet<String> = song_genres.into_iter().map(|g| g.name).collect();
-                    if !song_genre_names.is_disjoint(&genre_set) {
-                        response_songs.push(song.clone());
-                    }
-                }
+            if album_has_genre(album, &genre_set) {
+                response_songs.extend(album.songs.iter().cloned());
             }
         }
     }
@@ -142,33 +126,14 @@ pub async fn fetch_songs_by_genres(genres: Vec<String>) -> Result<Vec<Song>, ()>
 }
 
 pub async fn get_genre_info_by_song(song_id: &str) -> Result<Vec<Genre>, ()> {
-    let library = fetch_library().await.map_err(|_| ())?;
+    let index = fetch_catalog_index().await.map_err(|_| ())?;
+    let (_, album, _) = index.song(song_id).ok_or(())?;
 
-    for artist in library.iter() {
-        for album in artist.albums.iter() {
-            for song in album.songs.iter() {
-                if song.id == song_id {
-                    return Ok(get_genres_from_album(album));
-                }
-            }
-        }
-    }
-
-    Err(())
+    Ok(get_genres_from_album(album))
 }
 
-pub async fn get_genre_info_by_album(album_id: &str) -> Result<Vec<Genre>, ()> {
-    let library = fetch_library().await.map_err(|_| ())?;
-
-    for artist in library.iter() {
-        for album in artist.albums.iter() {
-            if album.id == album_id {
-                return Ok(get_genres_from_album(album));
-            }
-        }
-    }
-
-    Err(())
+fn album_has_genre(album: &Album, genre_set: &HashSet<String>) -> bool {
+    get_genres_from_album(album).iter().any(|genre| genre_set.contains(&genre.name))
 }
 
 fn get_genres_from_album(album: &Album) -> Vec<Genre> {
@@ -179,6 +144,18 @@ fn get_genres_from_album(album: &Album) -> Vec<Genre> {
     if let Some(release_group_album) = &album.release_group_album {
         genres.extend(release_group_album.genres.clone());
     }
+
+    // Tag genres carry only a name, and are left out when MusicBrainz already has them
+    for name in &album.genres {
+        if !genres.iter().any(|genre| genre.name.eq_ignore_ascii_case(name)) {
+            genres.push(Genre {
+                musicbrainz_id: String::new(),
+                disambiguation: String::new(),
+                name: name.clone(),
+                count: 0,
+            });
+        }
+    }
     genres
 }
 
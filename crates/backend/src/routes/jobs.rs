@@ -0,0 +1,84 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::utils::database::models::JobLog;
+use crate::utils::jobs::{cancel_job, enqueue_job, get_job, job_logs, list_jobs, Job, JobError, JobKind, JobStatus};
+
+#[derive(Serialize)]
+pub struct JobAccepted {
+    pub job_id: i32,
+    /// True when an identical job was already waiting, whose id is returned
+    pub already_queued: bool,
+}
+
+/// Queues a job and answers with its id instead of waiting for it.
+pub async fn enqueue_response(kind: JobKind) -> HttpResponse {
+    match enqueue_job(kind).await {
+        Ok((job_id, queued)) => HttpResponse::Accepted().json(JobAccepted { job_id, already_queued: !queued }),
+        Err(e) => {
+            error!("Failed to queue job: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JobsQuery {
+    pub status: Option<JobStatus>,
+    pub kind: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[get("/jobs")]
+pub async fn get_jobs(query: web::Query<JobsQuery>) -> impl Responder {
+    let query = query.into_inner();
+    match list_jobs(query.status, query.kind, query.limit.unwrap_or(50)).await {
+        Ok(jobs) => HttpResponse::Ok().json(jobs),
+        Err(e) => {
+            error!("Failed to list jobs: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct JobDetails {
+    #[serde(flatten)]
+    pub job: Job,
+    pub logs: Vec<JobLog>,
+}
+
+#[get("/jobs/{id}")]
+pub async fn get_job_details(id: web::Path<i32>) -> impl Responder {
+    let job_id = id.into_inner();
+    let details = match get_job(job_id).await {
+        Ok(Some(job)) => job_logs(job_id).await.map(|logs| Some(JobDetails { job, logs })),
+        Ok(None) => Ok(None),
+        Err(e) => Err(e),
+    };
+
+    match details {
+        Ok(Some(details)) => HttpResponse::Ok().json(details),
+        Ok(None) => HttpResponse::NotFound().body("Job not found"),
+        Err(e) => {
+            error!("Failed to load job {}: {}", job_id, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[post("/jobs/{id}/cancel")]
+pub async fn cancel_job_route(id: web::Path<i32>) -> impl Responder {
+    match cancel_job(id.into_inner()).await {
+        Ok(job) => HttpResponse::Ok().json(job),
+        Err(e) => match e.downcast_ref::<JobError>() {
+            Some(JobError::NotFound) => HttpResponse::NotFound().body(e.to_string()),
+            Some(JobError::Finished(_)) => HttpResponse::Conflict().body(e.to_string()),
+            None => {
+                error!("Failed to cancel job: {}", e);
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+    }
+}
@@ -0,0 +1,298 @@
+use std::sync::Arc;
+
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::routes::search::update_search_data;
+use crate::structures::structures::{Album, Artist};
+use crate::utils::config::{fetch_catalog_index, fetch_library, lock_catalog, save_library};
+use crate::utils::metadata_http::metadata_client;
+use crate::utils::metadata_matching::{album_candidates, artist_candidates, pin_album, pin_artist, ReleaseKind};
+use crate::utils::metadata_providers::{
+    metadata_provider_settings, musicbrainz_base_url, save_metadata_provider_settings, MetadataField,
+    MetadataProviderSettings, MetadataProviders, ProviderKind,
+};
+
+#[derive(Serialize)]
+pub struct AvailableProvider {
+    pub provider: ProviderKind,
+    pub fields: &'static [MetadataField],
+}
+
+#[derive(Serialize)]
+pub struct MetadataProvidersResponse {
+    #[serde(flatten)]
+    pub settings: MetadataProviderSettings,
+    /// Every provider and the fields it can be listed for in `priority`
+    pub available: Vec<AvailableProvider>,
+}
+
+fn metadata_providers_response() -> MetadataProvidersResponse {
+    MetadataProvidersResponse {
+        settings: metadata_provider_settings(),
+        available: ProviderKind::ALL
+            .into_iter()
+            .map(|provider| AvailableProvider { provider, fields: provider.fields() })
+            .collect(),
+    }
+}
+
+#[get("/metadata/providers")]
+pub async fn get_metadata_providers() -> impl Responder {
+    HttpResponse::Ok().json(metadata_providers_response())
+}
+
+#[post("/metadata/providers")]
+pub async fn set_metadata_providers(settings: web::Json<MetadataProviderSettings>) -> impl Responder {
+    if let Err(e) = settings.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    if let Err(e) = save_metadata_provider_settings(settings.into_inner()) {
+        error!("Failed to save metadata provider settings: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().json(metadata_providers_response())
+}
+
+fn musicbrainz_unavailable() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(json!({
+        "error": "MusicBrainz is disabled or the server is in offline mode"
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct AlbumCandidatesQuery {
+    /// Searched for instead of the album's name
+    title: Option<String>,
+    /// Searched for instead of the album artist's name
+    artist: Option<String>,
+}
+
+#[get("/metadata/albums/{id}/candidates")]
+pub async fn get_album_candidates(id: web::Path<String>, query: web::Query<AlbumCandidatesQuery>) -> impl Responder {
+    let Some(base_url) = musicbrainz_base_url(&metadata_provider_settings()) else {
+        return musicbrainz_unavailable();
+    };
+
+    let catalog = match fetch_catalog_index().await {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            error!("Failed to load the catalog: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let Some((album, artist)) = catalog.album(&id) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let client = match metadata_client() {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let artist_name = query.artist.as_deref().unwrap_or(&artist.name);
+    match album_candidates(&client, &base_url, artist_name, album, query.title.as_deref()).await {
+        Ok(candidates) => HttpResponse::Ok().json(candidates),
+        Err(e) => {
+            error!("Failed to search MusicBrainz for {}: {:?}", album.name, e);
+            HttpResponse::BadGateway().json(json!({ "error": e.to_string() }))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PinAlbumForm {
+    musicbrainz_id: String,
+    kind: ReleaseKind,
+}
+
+/// Matches an album to the chosen release or release group and looks its
+/// release details, cover and description up again through it.
+#[post("/metadata/albums/{id}/match")]
+pub async fn match_album(id: web::Path<String>, form: web::Json<PinAlbumForm>) -> impl Responder {
+    let Some(base_url) = musicbrainz_base_url(&metadata_provider_settings()) else {
+        return musicbrainz_unavailable();
+    };
+
+    let (mut album, artist_name) = match fetch_catalog_index().await {
+        Ok(catalog) => match catalog.album(&id) {
+            Some((album, artist)) => (album.clone(), artist.name.clone()),
+            None => return HttpResponse::NotFound().finish(),
+        },
+        Err(e) => {
+            error!("Failed to load the catalog: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let client = match metadata_client() {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let providers = MetadataProviders::from_settings();
+    let musicbrainz_id = form.musicbrainz_id.trim().to_lowercase();
+    match pin_album(&client, &providers, &base_url, &artist_name, &mut album, &musicbrainz_id, form.kind).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().json(json!({ "error": "MusicBrainz doesn't know that ID" })),
+        Err(e) => {
+            error!("Failed to look up {} on MusicBrainz: {:?}", musicbrainz_id, e);
+            return HttpResponse::BadGateway().json(json!({ "error": e.to_string() }));
+        }
+    }
+
+    // The lookups take a while, so the match is applied to the catalog as it is now
+    let saved = save_matched(|catalog| {
+        let Some(current) = catalog.iter_mut().flat_map(|artist| artist.albums.iter_mut()).find(|a| a.id == album.id) else {
+            return false;
+        };
+        copy_album_metadata(current, &album);
+        true
+    }).await;
+
+    match saved {
+        Ok(true) => {
+            info!("Matched {} to MusicBrainz {}", album.name, musicbrainz_id);
+            HttpResponse::Ok().json(album)
+        }
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            error!("Failed to save the matched album: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ArtistCandidatesQuery {
+    /// Searched for instead of the artist's name
+    name: Option<String>,
+}
+
+#[get("/metadata/artists/{id}/candidates")]
+pub async fn get_artist_candidates(id: web::Path<String>, query: web::Query<ArtistCandidatesQuery>) -> impl Responder {
+    let Some(base_url) = musicbrainz_base_url(&metadata_provider_settings()) else {
+        return musicbrainz_unavailable();
+    };
+
+    let catalog = match fetch_catalog_index().await {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            error!("Failed to load the catalog: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let Some(artist) = catalog.artist(&id) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let client = match metadata_client() {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match artist_candidates(&client, &base_url, artist, query.name.as_deref()).await {
+        Ok(candidates) => HttpResponse::Ok().json(candidates),
+        Err(e) => {
+            error!("Failed to search MusicBrainz for {}: {:?}", artist.name, e);
+            HttpResponse::BadGateway().json(json!({ "error": e.to_string() }))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PinArtistForm {
+    musicbrainz_id: String,
+}
+
+/// Matches an artist to the chosen MusicBrainz artist and looks their
+/// description up again through it.
+#[post("/metadata/artists/{id}/match")]
+pub async fn match_artist(id: web::Path<String>, form: web::Json<PinArtistForm>) -> impl Responder {
+    let Some(base_url) = musicbrainz_base_url(&metadata_provider_settings()) else {
+        return musicbrainz_unavailable();
+    };
+
+    let mut artist = match fetch_catalog_index().await {
+        Ok(catalog) => match catalog.artist(&id) {
+            Some(artist) => artist.clone(),
+            None => return HttpResponse::NotFound().finish(),
+        },
+        Err(e) => {
+            error!("Failed to load the catalog: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let client = match metadata_client() {
+        Ok(client) => client,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let providers = MetadataProviders::from_settings();
+    let musicbrainz_id = form.musicbrainz_id.trim().to_lowercase();
+    match pin_artist(&client, &providers, &base_url, &mut artist, &musicbrainz_id).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().json(json!({ "error": "MusicBrainz doesn't know that ID" })),
+        Err(e) => {
+            error!("Failed to look up {} on MusicBrainz: {:?}", musicbrainz_id, e);
+            return HttpResponse::BadGateway().json(json!({ "error": e.to_string() }));
+        }
+    }
+
+    let saved = save_matched(|catalog| {
+        let Some(current) = catalog.iter_mut().find(|a| a.id == artist.id) else {
+            return false;
+        };
+        current.musicbrainz_id = artist.musicbrainz_id.clone();
+        current.description = artist.description.clone();
+        current.pending_enrichment = false;
+        true
+    }).await;
+
+    match saved {
+        Ok(true) => {
+            info!("Matched {} to MusicBrainz {}", artist.name, musicbrainz_id);
+            let mut artist = artist;
+            // The albums are served by their own routes
+            artist.albums.clear();
+            HttpResponse::Ok().json(artist)
+        }
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            error!("Failed to save the matched artist: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Only the looked up fields are copied, so tags read by a rescan during the
+/// lookup survive.
+fn copy_album_metadata(current: &mut Album, matched: &Album) {
+    current.cover_url = matched.cover_url.clone();
+    current.description = matched.description.clone();
+    current.first_release_date = matched.first_release_date.clone();
+    current.musicbrainz_id = matched.musicbrainz_id.clone();
+    current.wikidata_id = matched.wikidata_id.clone();
+    current.primary_type = matched.primary_type.clone();
+    current.release_album = matched.release_album.clone();
+    current.release_group_album = matched.release_group_album.clone();
+    current.pending_enrichment = false;
+}
+
+/// Applies a match to the current catalog and saves it, returning false when
+/// the entity is gone from the catalog.
+async fn save_matched(apply: impl FnOnce(&mut Vec<Artist>) -> bool) -> Result<bool, Box<dyn std::error::Error>> {
+    let _catalog_lock = lock_catalog().await;
+    let before = fetch_library().await?;
+    let mut catalog = (*before).clone();
+    if !apply(&mut catalog) {
+        return Ok(false);
+    }
+
+    save_library(&Arc::new(catalog.clone())).await?;
+    if let Err(e) = update_search_data(&before, &catalog).await {
+        error!("Failed to update search data: {:?}", e);
+    }
+    Ok(true)
+}
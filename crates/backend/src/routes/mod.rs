@@ -1,9 +1,12 @@
 pub mod album;
 pub mod artist;
 pub mod authentication;
+pub mod backups;
 pub mod filesystem;
 pub mod image;
 pub mod index;
+pub mod jobs;
+pub mod metadata;
 pub mod music;
 pub mod playlist;
 pub mod search;
@@ -1,9 +1,12 @@
-use std::fs;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::process::Stdio;
+use std::sync::Arc;
 use std::time::Instant;
 
 use actix_web::http::header;
-use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncReadExt;
 use tokio::process::Command;
@@ -11,13 +14,28 @@ use tokio_util::io::ReaderStream;
 use tracing::{error, info, warn};
 use walkdir::WalkDir;
 
-use crate::routes::search::populate_search_data;
-use crate::structures::structures::Artist;
-use crate::utils::compare::compare;
-use crate::utils::config::{get_config, get_libraries_config_path, refresh_cache, save_config};
-use crate::utils::format::format_contributing_artists;
-use crate::utils::library::index_library;
-use crate::utils::metadata::{get_access_token, process_album, process_albums, process_artist, process_artists, refresh_audio_db_info};
+use crate::routes::jobs::enqueue_response;
+use crate::routes::search::{populate_search_data, update_search_data};
+use crate::structures::structures::{Album, Artist};
+use crate::utils::compare::{
+    apply_scan_report, diff_library, load_scan_errors, load_scan_reports, preview_library_diff, remove_scan_report,
+    store_scan_report, update_report_errors, LibraryDiff, ScanErrorKind, ScanFileError, ScanReport, ScanTotals,
+};
+use crate::utils::catalog::{count_song_references, SongReferences};
+use crate::utils::config::{fetch_library, lock_catalog, save_config, save_library};
+use crate::utils::database::database::{with_connection, DbPool};
+use crate::utils::format::{artist_split_settings, format_contributing_artists, save_artist_split_settings, ArtistSplitSettings};
+use crate::utils::formats::{client_profile, detect_format, is_audio_file, mp3_target, stream_decision, StreamDecision};
+use crate::utils::library::{index_library, IndexedLibrary, remove_library_songs, update_library_files, RemovedSongs};
+use crate::utils::library_settings::{read_libraries, write_libraries, Library, LibrarySettings};
+use crate::utils::scan_state::{clear_library_scan_states, clear_scan_states};
+use crate::utils::jobs::JobKind;
+use crate::utils::scheduler::{save_schedule_settings, schedule_settings, task_runs, ScheduleSettings, ScheduledTask};
+use crate::utils::progress::{begin_scan, cancel_scan, ScanError, ScanPhase, ScanProgress};
+use crate::utils::metadata::{process_album, process_albums, process_artist, process_artists, refresh_audio_db_info};
+use crate::utils::metadata_http::metadata_client;
+use crate::utils::metadata_providers::MetadataProviders;
+use crate::utils::watcher::sync_watched_libraries;
 use crate::utils::websocket::log_to_ws;
 
 #[get("/songs/list/{path}")]
@@ -27,9 +45,7 @@ pub async fn songs_list(path: web::Path<String>) -> impl Responder {
     let mut i = 0;
     for entry in WalkDir::new(path.as_str()) {
         let entry = entry.unwrap();
-        let extension = entry.path().extension().unwrap_or_default();
-
-        if entry.file_type().is_file() && extension == "mp3" || extension == "flac" {
+        if entry.file_type().is_file() && is_audio_file(entry.path()) {
             i += 1;
             // println!("{}", entry.path().display());
         }
@@ -40,69 +56,42 @@ pub async fn songs_list(path: web::Path<String>) -> impl Responder {
     HttpResponse::Ok().body(message)
 }
 
-pub fn transfer_metadata(old_library: &[Artist], mut new_library: Vec<Artist>) -> Vec<Artist> {
-    for new_artist in new_library.iter_mut() {
-        if let Some(old_artist) = old_library.iter().find(|a| a.id == new_artist.id) {
-            new_artist.name = old_artist.name.clone();
-            new_artist.icon_url = old_artist.icon_url.clone();
-            new_artist.followers = old_artist.followers;
-            new_artist.featured_on_album_ids = old_artist.featured_on_album_ids.clone();
-            new_artist.description = old_artist.description.clone();
-            new_artist.tadb_music_videos = old_artist.tadb_music_videos.clone();
-
-            for new_album in new_artist.albums.iter_mut() {
-                if let Some(old_album) = old_artist.albums.iter().find(|a| a.id == new_album.id) {
-                    new_album.name = old_album.name.clone();
-                    new_album.first_release_date = old_album.first_release_date.clone();
-                    new_album.musicbrainz_id = old_album.musicbrainz_id.clone();
-                    new_album.wikidata_id = old_album.wikidata_id.clone();
-                    new_album.primary_type = old_album.primary_type.clone();
-                    new_album.description = old_album.description.clone();
-                    new_album.contributing_artists = old_album.contributing_artists.clone();
-                    new_album.contributing_artists_ids = old_album.contributing_artists_ids.clone();
-                    new_album.release_album = old_album.release_album.clone();
-                    new_album.release_group_album = old_album.release_group_album.clone();
-
-                    for new_song in new_album.songs.iter_mut() {
-                        if let Some(old_song) = old_album.songs.iter().find(|s| s.id == new_song.id) {
-                            new_song.name = old_song.name.clone();
-                            new_song.artist = old_song.artist.clone();
-                            new_song.contributing_artists = old_song.contributing_artists.clone();
-                            new_song.contributing_artist_ids = old_song.contributing_artist_ids.clone();
-                            new_song.track_number = old_song.track_number;
-                            new_song.duration = old_song.duration;
-                            new_song.music_video = old_song.music_video.clone();
-                        }
-                    }
-                }
-            }
-        }
-    }
-    new_library
-}
-
-async fn process_music_library(path: &str) -> Result<String, Box<dyn std::error::Error>> {
+/// Scans one library and merges it into `current_library`, without saving
+/// anything, so a cancelled scan leaves the stored catalog as it was.
+async fn scan_music_library(
+    path: &str,
+    mut current_library: Vec<Artist>,
+    progress: &Arc<ScanProgress>,
+) -> Result<(Vec<Artist>, ScanReport), Box<dyn std::error::Error>> {
     let now = Instant::now();
-    let library = index_library(path).await?;
-
-    let client = reqwest::Client::builder()
-        .user_agent("ParsonLabsMusic/0.1 (will@parsonlabs.com)")
-        .build()?; 
-    
-    let config_data = get_config().await.unwrap_or_default();
-    let mut current_library: Vec<Artist> = if config_data.is_empty() {
-        Vec::new()
-    } else {
-        serde_json::from_str(&config_data).unwrap_or_else(|_| Vec::new())
-    };
+    progress.start_library(path);
+    let IndexedLibrary { library, errors } = index_library(path, progress.clone(), false).await?;
+
+    let client = metadata_client()?;
+    let providers = MetadataProviders::from_settings();
+
+    progress.set_phase(ScanPhase::Metadata);
 
     if current_library.is_empty() {
         let mut library_guard = library.lock().unwrap();
-        process_artists(&client, &mut *library_guard).await;
-        process_albums(&client, &mut *library_guard).await;
+        process_artists(&client, &providers, &mut *library_guard, progress).await;
+        process_albums(&client, &providers, &mut *library_guard, progress).await;
     }
+    progress.check_cancelled()?;
+
+    let report = {
+        let mut library_guard = library.lock().unwrap();
+        let mut report = diff_library(path, &current_library, &mut library_guard);
+        report.errors = errors;
+        report
+    };
+    info!("{}", report.summary());
+    log_to_ws(report.summary()).await;
+
+    // A first scan looks up metadata for the whole library above
+    if !current_library.is_empty() {
+        let (mut new_artist_entries, mut new_album_entries) = report.new_entries(&library.lock().unwrap());
 
-    if let Ok((mut new_artist_entries, mut new_album_entries, _new_song_entries)) = compare(&library).await {
         if !new_artist_entries.is_empty() {
             let artists_without_icon_count = new_artist_entries
                 .iter()
@@ -112,27 +101,13 @@ async fn process_music_library(path: &str) -> Result<String, Box<dyn std::error:
             info!(log);
             log_to_ws(log).await;
 
-           match get_access_token().await {
-               Ok(token) => {
-                   for artist in new_artist_entries.iter_mut() {
-                       process_artist(&client, artist, Some(token.clone()), false).await;
-                       current_library.push(artist.clone());
-                   }
-               }
-               Err(err) => {
-                   warn!("Spotify token error, falling back to AudioDB for artist icons: {}", err);
-                   log_to_ws(format!(
-                       "Spotify token error, using AudioDB fallback: {}",
-                       err
-                   ))
-                   .await;
-
-                   for artist in new_artist_entries.iter_mut() {
-                       process_artist(&client, artist, None, true).await;
-                       current_library.push(artist.clone());
-                   }
-               }
-           }
+            progress.metadata_queued(new_artist_entries.len());
+            for artist in new_artist_entries.iter_mut() {
+                progress.check_cancelled()?;
+                process_artist(&client, &providers, artist).await;
+                progress.metadata_finished();
+                current_library.push(artist.clone());
+            }
         }
 
         if !new_album_entries.is_empty() {
@@ -144,33 +119,114 @@ async fn process_music_library(path: &str) -> Result<String, Box<dyn std::error:
             info!(log);
             log_to_ws(log).await;
 
+            progress.metadata_queued(new_album_entries.len());
             for modified_album in new_album_entries.iter_mut() {
-                process_album(&client, modified_album.artist_name.clone(), &mut modified_album.album).await;
+                progress.check_cancelled()?;
+                process_album(&client, &providers, modified_album.artist_name.clone(), &mut modified_album.album).await;
+                progress.metadata_finished();
+                if let Some(artist) = current_library.iter_mut().find(|a| a.id == modified_album.artist_id) {
+                    match artist.albums.iter_mut().find(|a| a.id == modified_album.album.id) {
+                        Some(existing_album) => {
+                            if !modified_album.album.cover_url.is_empty() {
+                                existing_album.cover_url = modified_album.album.cover_url.clone();
+                            }
+                        },
+                        None => artist.albums.push(modified_album.album.clone()),
+                    }
+                    refresh_audio_db_info(artist);
+                }
             }
         }
     }
 
-    let final_data = if current_library.is_empty() {
-        library.lock().unwrap().clone()
-    } else {
-        let updated = {
-            let library_guard = library.lock().unwrap();
-            transfer_metadata(&current_library, library_guard.clone())
-        };
-        updated
-    };
+    progress.check_cancelled()?;
 
     let elapsed = now.elapsed().as_secs();
     info!("Finished Indexing Library in {} seconds", elapsed);
     log_to_ws(format!("Finished Indexing Library in {} seconds", elapsed)).await;
 
-    let json = serde_json::to_string(&final_data)?;
+    let scanned_library = {
+        let library_guard = library.lock().unwrap();
+        if current_library.is_empty() {
+            library_guard.clone()
+        } else {
+            apply_scan_report(&mut current_library, &library_guard, &report);
+            current_library
+        }
+    };
+
+    Ok((scanned_library, report))
+}
+
+/// Scans the given libraries one after another into a single catalog and
+/// saves it once at the end. Cancelling or failing part way through keeps the
+/// previous catalog.
+async fn process_music_libraries(paths: &[String]) -> Result<Vec<ScanTotals>, Box<dyn std::error::Error>> {
+    let progress = begin_scan()?;
+
+    let result = async {
+        let _catalog_lock = lock_catalog().await;
+        let mut catalog: Vec<Artist> = fetch_library()
+            .await
+            .map(|library| (*library).clone())
+            .unwrap_or_default();
+        let mut reports = Vec::new();
+        let mut last_error = None;
+
+        for path in paths {
+            match scan_music_library(path, catalog.clone(), &progress).await {
+                Ok((scanned_library, report)) => {
+                    catalog = scanned_library;
+                    reports.push(report);
+                }
+                Err(e) if e.is::<ScanError>() => return Err(e),
+                Err(e) => {
+                    error!("Failed to process library {}: {:?}", path, e);
+                    progress.error();
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        if let (true, Some(e)) = (reports.is_empty(), last_error) {
+            return Err(e);
+        }
+
+        progress.check_cancelled()?;
+        progress.set_phase(ScanPhase::Saving);
+
+        save_config(&catalog, true).await?;
+        let totals = reports.iter().map(|report| report.totals()).collect();
+        for report in reports {
+            let library_path = report.library_path.clone();
+            if let Err(e) = store_scan_report(report).await {
+                warn!("Failed to save the scan report for {}: {}", library_path, e);
+            }
+        }
+        populate_search_data().await.expect("Could not Populate the Search Data");
 
-    save_config(&json, true).await?;
-    populate_search_data().await.expect("Could not Populate the Search Data");
-    refresh_cache().await.expect("Could not Refresh Music Data Cache");
-    
-    Ok(json)
+        Ok(totals)
+    }.await;
+
+    progress.finish(&result).await;
+    result
+}
+
+async fn process_music_library(path: &str) -> Result<Vec<ScanTotals>, Box<dyn std::error::Error>> {
+    process_music_libraries(&[path.to_string()]).await
+}
+
+/// Maps a failed scan to a response, telling cancelled and overlapping scans
+/// apart from real failures.
+fn scan_error_response(e: &(dyn std::error::Error + 'static)) -> HttpResponse {
+    match e.downcast_ref::<ScanError>() {
+        Some(ScanError::Cancelled) => HttpResponse::Ok().json(serde_json::json!({ "cancelled": true })),
+        Some(ScanError::AlreadyRunning) => HttpResponse::Conflict().body(e.to_string()),
+        None => {
+            error!("Failed to process library: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
 }
 
 #[get("/index/{pathToLibrary}")]
@@ -181,184 +237,173 @@ pub async fn index(path_to_library: web::Path<String>) -> impl Responder {
     if let Err(e) = save_library_path(&path_to_library).await {
         error!("Failed to save library path: {:?}", e);
     }
+    sync_watched_libraries();
 
-    match process_music_library(&path_to_library).await {
-        Ok(json) => HttpResponse::Ok().content_type("application/json; charset=utf-8").body(json),
-        Err(e) => {
-            error!("Failed to process library: {:?}", e);
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    enqueue_response(JobKind::Index { path: path_to_library.into_inner() }).await
 }
 
-pub async fn process_music_library_no_ws(path: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let now = Instant::now();
-    let library = index_library(path).await?;
-
-    let client = reqwest::Client::builder()
-        .user_agent("ParsonLabsMusic/0.1 (will@parsonlabs.com)")
-        .build()?;
-    let config_data = get_config().await.unwrap_or_default();
-    let mut current_library: Vec<Artist> = if config_data.is_empty() {
-        Vec::new()
-    } else {
-        serde_json::from_str(&config_data).unwrap_or_else(|_| Vec::new())
+/// Applies a batch of filesystem changes from the library watcher to the
+/// catalog and search index, touching only the affected songs.
+pub async fn update_changed_files(library_path: &str, paths: Vec<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    let _catalog_lock = lock_catalog().await;
+    let before = fetch_library().await?;
+    let mut catalog = (*before).clone();
+
+    // Reading tags and the scan state blocks, so it runs off the async workers
+    let (catalog, paths, mut changes) = {
+        let library_path = library_path.to_string();
+        tokio::task::spawn_blocking(move || {
+            let changes = update_library_files(&mut catalog, &library_path, &paths);
+            (catalog, paths, changes)
+        })
+        .await?
     };
+    if let Err(e) = update_report_errors(library_path, &paths, std::mem::take(&mut changes.errors)).await {
+        warn!("Failed to update the scan report for {}: {}", library_path, e);
+    }
+    if changes.is_empty() {
+        return Ok(());
+    }
 
-    if current_library.is_empty() {
-        let mut library_guard = library.lock().unwrap();
-        process_artists(&client, &mut *library_guard).await;
-        process_albums(&client, &mut *library_guard).await;
+    save_library(&Arc::new(catalog.clone())).await?;
+
+    if let Err(e) = update_search_data(&before, &catalog).await {
+        error!("Failed to update search data: {:?}", e);
     }
 
-    if let Ok((mut new_artist_entries, mut new_album_entries, _new_song_entries)) = compare(&library).await {
-        if !new_artist_entries.is_empty() {
-            let artists_without_icon_count = new_artist_entries
-                .iter()
-                .filter(|artist| artist.icon_url.is_empty())
-                .count();
-            info!("Searching icon art for {} artists...", artists_without_icon_count);
-
-           match get_access_token().await {
-               Ok(token) => {
-                   for artist in new_artist_entries.iter_mut() {
-                       process_artist(&client, artist, Some(token.clone()), false).await;
-                       current_library.push(artist.clone());
-                   }
-               }
-               Err(err) => {
-                   warn!("Spotify token error, falling back to AudioDB for artist icons: {}", err);
-                   log_to_ws(format!(
-                       "Spotify token error, using AudioDB fallback: {}",
-                       err
-                   ))
-                   .await;
-
-                   for artist in new_artist_entries.iter_mut() {
-                       process_artist(&client, artist, None, true).await;
-                       current_library.push(artist.clone());
-                   }
-               }
-           }
+    let message = format!("{}: {} files updated, {} songs removed", library_path, changes.updated, changes.removed);
+    info!("{}", message);
+    log_to_ws(message).await;
 
-        }
+    Ok(())
+}
 
-        if !new_album_entries.is_empty() {
-            let albums_without_cover_count = new_album_entries
-                .iter()
-                .filter(|modified_album| modified_album.album.cover_url.is_empty())
-                .count();
-            info!("Searching cover art for {} albums...", albums_without_cover_count);
+pub async fn refresh_libraries() -> Result<Vec<ScanTotals>, Box<dyn std::error::Error>> {
+    info!("Refreshing all library paths...");
+    log_to_ws("Refreshing all library paths...".to_string()).await;
 
-            for modified_album in new_album_entries.iter_mut() {
-                process_album(&client, modified_album.artist_name.clone(), &mut modified_album.album).await;
-                if let Some(artist) = current_library.iter_mut().find(|a| a.id == modified_album.artist_id) {
-                    match artist.albums.iter_mut().find(|a| a.id == modified_album.album.id) {
-                        Some(existing_album) => {
-                            for new_song in modified_album.album.songs.iter() {
-                                if let Some(existing_song) = existing_album.songs.iter_mut()
-                                    .find(|s| s.id == new_song.id) {
-                                    existing_song.path = new_song.path.clone();
-                                }
-                            }
+    let paths = read_library_paths().await;
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    process_music_libraries(&paths).await
+}
 
-                            if !modified_album.album.cover_url.is_empty() {
-                                existing_album.cover_url = modified_album.album.cover_url.clone();
-                            }
-                        },
-                        None => artist.albums.push(modified_album.album.clone()),
-                    }
-                    refresh_audio_db_info(artist);
-                }
-            }
-        }
+#[get("/refresh")]
+pub async fn library_refresh() -> impl Responder {
+    if read_library_paths().await.is_empty() {
+        return HttpResponse::InternalServerError().finish();
     }
 
-    let library_guard = library.lock().unwrap();
-    let elapsed = now.elapsed().as_secs();
-    info!("Finished Indexing Library in {} seconds", elapsed);
+    enqueue_response(JobKind::Refresh).await
+}
 
-    let data_to_serialize = if current_library.is_empty() {
-        &*library_guard
+#[post("/cancel")]
+pub async fn cancel_scan_route() -> impl Responder {
+    if cancel_scan() {
+        log_to_ws("Cancelling the running scan...".to_string()).await;
+        HttpResponse::Ok().finish()
     } else {
-        &current_library
-    };
-
-    let json = serde_json::to_string(data_to_serialize)?;
-    save_config(&json, true).await?;
-    populate_search_data().await.expect("Could not Populate the Search Data");
-    refresh_cache().await.expect("Could not Refresh Music Data Cache");
-
-    Ok(json)
+        HttpResponse::NotFound().body("No scan is running")
+    }
 }
 
-pub async fn refresh_libraries() -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    info!("Refreshing all library paths...");
-    log_to_ws("Refreshing all library paths...".to_string()).await;
+#[derive(Deserialize)]
+pub struct ScanReportQuery {
+    /// Library id or path
+    pub library: Option<String>,
+    pub kind: Option<ScanErrorKind>,
+}
 
-    let paths = read_library_paths().await;
-    let mut results = Vec::new();
+/// Resolves a library given by id or path to its path.
+fn resolve_library_path(library: &str) -> String {
+    read_libraries()
+        .get(library)
+        .map(|library| library.path.clone())
+        .unwrap_or_else(|| library.to_string())
+}
 
-    for path in paths {
-        match process_music_library(&path).await {
-            Ok(json) => results.push(json),
-            Err(e) => {
-                error!("Failed to process library {}: {:?}", path, e);
-            }
+/// The last scan report of every library, or only of one.
+#[get("/report")]
+pub async fn scan_report(query: web::Query<ScanReportQuery>) -> impl Responder {
+    match load_scan_reports(query.library.as_deref().map(resolve_library_path)).await {
+        Ok(reports) => HttpResponse::Ok().json(reports),
+        Err(e) => {
+            error!("Failed to load scan reports: {}", e);
+            HttpResponse::InternalServerError().finish()
         }
     }
+}
 
-    Ok(results)
+#[derive(Serialize)]
+pub struct LibraryScanError {
+    pub library_path: String,
+    #[serde(flatten)]
+    pub error: ScanFileError,
 }
 
-#[get("/refresh")]
-pub async fn library_refresh() -> impl Responder {
-    match refresh_libraries().await {
-        Ok(results) => {
-            if results.is_empty() {
-                HttpResponse::InternalServerError().finish()
-            } else {
-                HttpResponse::Ok()
-                    .content_type("application/json; charset=utf-8")
-                    .body(results.join(","))
-            }
+/// The per-file errors from the last scans, filterable by library and kind.
+#[get("/report/errors")]
+pub async fn scan_report_errors(query: web::Query<ScanReportQuery>) -> impl Responder {
+    match load_scan_errors(query.library.as_deref().map(resolve_library_path), query.kind).await {
+        Ok(errors) => {
+            let errors: Vec<LibraryScanError> = errors
+                .into_iter()
+                .map(|(library_path, error)| LibraryScanError { library_path, error })
+                .collect();
+            HttpResponse::Ok().json(errors)
+        }
+        Err(e) => {
+            error!("Failed to load scan errors: {}", e);
+            HttpResponse::InternalServerError().finish()
         }
-        Err(_) => HttpResponse::InternalServerError().finish()
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-pub struct Libraries {
-    pub paths: Vec<String>
+#[derive(Deserialize)]
+pub struct LibraryDiffQuery {
+    /// Library id or path
+    pub library: String,
 }
 
-pub async fn read_library_paths() -> Vec<String> {
-    let libraries_file= get_libraries_config_path();
-    
-    if !libraries_file.exists() {
-        return Vec::new();
+/// Scans a library and reports what a rescan would change. The scan is a dry
+/// run, so the catalog, the file read cache and the cover art are left as they are.
+async fn preview_library(path: &str) -> Result<LibraryDiff, Box<dyn std::error::Error>> {
+    let progress = begin_scan()?;
+
+    let result = async {
+        progress.start_library(path);
+        let IndexedLibrary { library, errors } = index_library(path, progress.clone(), true).await?;
+        let current_library = fetch_library().await.unwrap_or_default();
+
+        let mut new_library = library.lock().unwrap();
+        Ok(preview_library_diff(path, &current_library, &mut new_library, errors))
+    }.await;
+
+    progress.finish(&result).await;
+    result
+}
+
+/// Queues a dry-run rescan; the diff is the job's result.
+#[get("/diff")]
+pub async fn library_diff(query: web::Query<LibraryDiffQuery>) -> impl Responder {
+    let path = resolve_library_path(&query.library);
+    if !std::path::Path::new(&path).is_dir() {
+        return HttpResponse::NotFound().body("Library not found");
     }
-    
-    let content = fs::read_to_string(libraries_file).expect("Could not read the Libraries JSON File");
-    let libraries: Libraries = serde_json::from_str(&content).expect("Could not Parse the JSON as a String");
-    
-    libraries.paths
+
+    enqueue_response(JobKind::Diff { path }).await
 }
 
-async fn save_library_path(path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let libraries_file = get_libraries_config_path();
+pub async fn read_library_paths() -> Vec<String> {
+    read_libraries().enabled_paths()
+}
 
-    let mut libraries = if libraries_file.exists() {
-        let content = fs::read_to_string(&libraries_file)?;
-        serde_json::from_str(&content).unwrap_or_default()
-    } else {
-        Libraries { paths: Vec::new() }
-    };
+async fn save_library_path(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut libraries = read_libraries();
 
-    if !libraries.paths.contains(&path.to_string()) {
-        libraries.paths.push(path.to_string());
-        let json = serde_json::to_string_pretty(&libraries)?;
-        fs::write(libraries_file, json)?;
+    if libraries.add(Library::new(path, None)) {
+        write_libraries(&libraries)?;
     }
 
     Ok(())
@@ -368,6 +413,7 @@ async fn save_library_path(path: &str) -> Result<(), Box<dyn std::error::Error>>
 pub struct BitrateQueryParams {
     pub bitrate: u32,
     pub slowed_reverb: Option<bool>,
+    pub client: Option<String>,
 }
 
 #[get("/stream/{song}")]
@@ -408,18 +454,26 @@ async fn stream_song(
         (0, song_file_size - 1)
     };
 
-    if bitrate == 0 && !slowed_reverb {
-        use tokio::io::AsyncSeekExt;
-        
-        let content_type = match path_obj.extension().and_then(|ext| ext.to_str()) {
-            Some("flac") => "audio/flac",
-            Some("mp3") => "audio/mpeg",
+    let user_agent = req.headers().get(header::USER_AGENT).and_then(|v| v.to_str().ok());
+    let client = client_profile(query.client.as_deref(), user_agent);
+    let audio_format = detect_format(path_obj);
+
+    let decision = match audio_format {
+        Some(format) => stream_decision(format, client, bitrate),
+        // Music videos aren't audio formats and are served as they are
+        None if bitrate == 0 => StreamDecision::Direct(match path_obj.extension().and_then(|ext| ext.to_str()) {
             Some("mp4") => "video/mp4",
-            Some("webm") => "video/webm", 
+            Some("webm") => "video/webm",
             Some("mkv") => "video/x-matroska",
             _ => "application/octet-stream",
-        };
+        }),
+        None => StreamDecision::Transcode(mp3_target(bitrate)),
+    };
+
+    if let (StreamDecision::Direct(content_type), false) = (&decision, slowed_reverb) {
+        use tokio::io::AsyncSeekExt;
 
+        let content_type = *content_type;
         let mut file = match tokio::fs::File::open(&song).await {
             Ok(file) => file,
             Err(_) => return HttpResponse::NotFound().finish()
@@ -464,18 +518,26 @@ async fn stream_song(
                 "-threads", "2",
                 "pipe:1",
             ]);
-        } else {
-            command.args(&[
-                "-map", "0:a:0",
-                "-b:a", &format!("{}k", bitrate),
-                "-f", "mp3",
-                "-c:a", "aac", 
-                "-chunk_size", "131072",
-                "-threads", "2",
-                "pipe:1",
-            ]);
+        } else if let StreamDecision::Transcode(target) = &decision {
+            command.args(["-map", "0:a:0", "-c:a", target.codec]);
+
+            if let Some(bitrate) = target.bitrate {
+                command.args(["-b:a", &format!("{}k", bitrate)]);
+            }
+
+            // DSD sample rates are far above what FLAC can store
+            if audio_format.is_some_and(|format| format.codec == "DSD") {
+                command.args(["-ar", "176400"]);
+            }
+
+            command.args(["-f", target.ffmpeg_format, "-threads", "2", "pipe:1"]);
         }
 
+        let content_type = match &decision {
+            StreamDecision::Transcode(target) if !slowed_reverb => target.content_type,
+            _ => "audio/mpeg",
+        };
+
         command.arg("-v").arg("error");
 
         let mut child = match command
@@ -494,7 +556,7 @@ async fn stream_song(
         let stream = ReaderStream::with_capacity(stdout, 131072);
 
         HttpResponse::PartialContent()
-            .append_header((header::CONTENT_TYPE, "audio/mpeg"))
+            .append_header((header::CONTENT_TYPE, content_type))
             .append_header((header::TRANSFER_ENCODING, "chunked"))
             .append_header((header::CACHE_CONTROL, "public, max-age=3600"))
             .streaming(stream)
@@ -512,10 +574,447 @@ async fn format_contributing_artists_route(artist: web::Path<String>) -> impl Re
         .body(json)
 }
 
+#[derive(Deserialize)]
+pub struct LibrarySettingsQuery {
+    pub path: String,
+}
+
+#[get("/settings")]
+pub async fn get_library_settings(query: web::Query<LibrarySettingsQuery>) -> impl Responder {
+    match read_libraries().by_path(&query.path) {
+        Some(library) => HttpResponse::Ok().json(&library.settings),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LibrarySettingsForm {
+    pub path: String,
+    pub settings: LibrarySettings,
+}
+
+#[post("/settings")]
+pub async fn set_library_settings(form: web::Json<LibrarySettingsForm>) -> impl Responder {
+    let LibrarySettingsForm { path, settings } = form.into_inner();
+
+    let mut libraries = read_libraries();
+    match libraries.by_path_mut(&path) {
+        Some(library) => library.settings = settings,
+        None => return HttpResponse::NotFound().finish(),
+    }
+
+    if let Err(e) = write_libraries(&libraries) {
+        error!("Failed to save library settings: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[derive(Serialize)]
+pub struct LibraryResponse {
+    #[serde(flatten)]
+    pub library: Library,
+    pub song_count: usize,
+}
+
+#[get("/libraries")]
+pub async fn list_libraries() -> impl Responder {
+    let catalog = fetch_library().await.unwrap_or_default();
+
+    let libraries: Vec<LibraryResponse> = read_libraries().libraries
+        .into_iter()
+        .map(|library| {
+            let song_count = catalog.iter()
+                .flat_map(|artist| artist.albums.iter())
+                .flat_map(|album| album.songs.iter())
+                .filter(|song| library.contains(&song.path))
+                .count();
+
+            LibraryResponse { library, song_count }
+        })
+        .collect();
+
+    HttpResponse::Ok().json(libraries)
+}
+
+#[derive(Deserialize)]
+pub struct AddLibraryForm {
+    pub path: String,
+    pub name: Option<String>,
+}
+
+/// Registers a library root without scanning it. `/library/index` or
+/// `/library/refresh` pick it up afterwards, and the watcher starts right away.
+#[post("/libraries")]
+pub async fn add_library(form: web::Json<AddLibraryForm>) -> impl Responder {
+    let AddLibraryForm { path, name } = form.into_inner();
+
+    if !std::path::Path::new(&path).is_dir() {
+        return HttpResponse::BadRequest().body(format!("{} is not a directory", path));
+    }
+
+    let mut libraries = read_libraries();
+    let library = Library::new(&path, name);
+    if !libraries.add(library.clone()) {
+        return HttpResponse::Conflict().body(format!("{} is already a library", path));
+    }
+
+    if let Err(e) = write_libraries(&libraries) {
+        error!("Failed to save library: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    sync_watched_libraries();
+
+    HttpResponse::Ok().json(library)
+}
+
+#[derive(Deserialize)]
+pub struct UpdateLibraryForm {
+    pub name: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+#[post("/libraries/{id}")]
+pub async fn update_library(id: web::Path<String>, form: web::Json<UpdateLibraryForm>) -> impl Responder {
+    let UpdateLibraryForm { name, enabled } = form.into_inner();
+
+    let mut libraries = read_libraries();
+    let library = match libraries.get_mut(&id) {
+        Some(library) => library,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    if let Some(name) = name.filter(|name| !name.trim().is_empty()) {
+        library.name = name;
+    }
+    if let Some(enabled) = enabled {
+        library.enabled = enabled;
+    }
+    let library = library.clone();
+
+    if let Err(e) = write_libraries(&libraries) {
+        error!("Failed to save library: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    sync_watched_libraries();
+
+    HttpResponse::Ok().json(library)
+}
+
+#[derive(Serialize)]
+pub struct LibraryRemoval {
+    pub library: Library,
+    #[serde(flatten)]
+    pub removed: RemovedSongs,
+    /// Rows that still point at the removed songs. They are left in place so
+    /// they resolve again if the songs come back.
+    pub references: SongReferences,
+}
+
+/// Removes a library root and purges its songs, and the albums and artists
+/// left empty, from the catalog and the search index.
+pub async fn remove_library_root(pool: &DbPool, id: &str) -> Result<Option<LibraryRemoval>, Box<dyn std::error::Error>> {
+    let mut libraries = read_libraries();
+    let library = match libraries.remove(id) {
+        Some(library) => library,
+        None => return Ok(None),
+    };
+    write_libraries(&libraries)?;
+    sync_watched_libraries();
+
+    let catalog_lock = lock_catalog().await;
+    let before = fetch_library().await?;
+    let mut catalog = (*before).clone();
+    let removed = remove_library_songs(&mut catalog, &library.path);
+
+    if removed.songs > 0 {
+        save_library(&Arc::new(catalog.clone())).await?;
+
+        if let Err(e) = update_search_data(&before, &catalog).await {
+            error!("Failed to update search data: {:?}", e);
+        }
+    }
+    drop(catalog_lock);
+
+    if let Err(e) = clear_library_scan_states(&library.path).await {
+        warn!("Failed to clear scan state for {}: {}", library.path, e);
+    }
+    if let Err(e) = remove_scan_report(&library.path).await {
+        warn!("Failed to remove the scan report for {}: {}", library.path, e);
+    }
+
+    let song_ids = removed.song_ids.clone();
+    let references = with_connection(pool, move |connection| count_song_references(connection, &song_ids)).await?;
+
+    let message = format!(
+        "Removed {}: {} songs, {} albums and {} artists",
+        library.path, removed.songs, removed.albums, removed.artists
+    );
+    info!("{}", message);
+    log_to_ws(message).await;
+
+    Ok(Some(LibraryRemoval { library, removed, references }))
+}
+
+#[delete("/libraries/{id}")]
+pub async fn remove_library(pool: web::Data<DbPool>, id: web::Path<String>) -> impl Responder {
+    match remove_library_root(&pool, &id).await {
+        Ok(Some(removal)) => HttpResponse::Ok().json(removal),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            error!("Failed to remove library {}: {:?}", id, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[get("/artist_splitting")]
+pub async fn get_artist_splitting() -> impl Responder {
+    HttpResponse::Ok().json(artist_split_settings())
+}
+
+#[post("/artist_splitting")]
+pub async fn set_artist_splitting(settings: web::Json<ArtistSplitSettings>) -> impl Responder {
+    if let Err(e) = save_artist_split_settings(settings.into_inner()) {
+        error!("Failed to save artist splitting settings: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    // Cached songs were split with the old settings, so the next scan reads every file again
+    if let Err(e) = clear_scan_states().await {
+        warn!("Failed to clear scan state: {}", e);
+    }
+
+    HttpResponse::Ok().json(artist_split_settings())
+}
+
+/// Looks up metadata again for albums without a MusicBrainz id, and for the
+/// artists and albums indexed offline. What is found is copied onto the catalog
+/// as it is when the lookups finish, so changes the watcher made in the
+/// meantime are kept.
+async fn refresh_missing_metadata() -> Result<String, Box<dyn std::error::Error>> {
+    let providers = MetadataProviders::from_settings();
+    if providers.is_offline() {
+        return Ok("Offline mode is on, so no metadata was looked up".to_string());
+    }
+
+    let progress = begin_scan()?;
+
+    let result = async {
+        let catalog = fetch_library().await?;
+        let mut artists: Vec<Artist> = catalog
+            .iter()
+            .filter(|artist| artist.pending_enrichment)
+            .cloned()
+            .collect();
+        let mut albums: Vec<(String, Album)> = catalog
+            .iter()
+            .flat_map(|artist| artist.albums.iter().map(move |album| (artist, album)))
+            .filter(|(_, album)| album.musicbrainz_id.is_empty() || album.pending_enrichment)
+            .map(|(artist, album)| (artist.name.clone(), album.clone()))
+            .collect();
+        let pending_album_ids: HashSet<String> = albums
+            .iter()
+            .filter(|(_, album)| album.pending_enrichment)
+            .map(|(_, album)| album.id.clone())
+            .collect();
+
+        let client = metadata_client()?;
+
+        progress.set_phase(ScanPhase::Metadata);
+        progress.metadata_queued(artists.len() + albums.len());
+
+        // Stopping early still saves what was found so far
+        let mut artists_done = 0;
+        for artist in artists.iter_mut() {
+            if progress.is_cancelled() {
+                break;
+            }
+            process_artist(&client, &providers, artist).await;
+            progress.metadata_finished();
+            artists_done += 1;
+        }
+        let mut albums_done = 0;
+        for (artist_name, album) in albums.iter_mut() {
+            if progress.is_cancelled() {
+                break;
+            }
+            process_album(&client, &providers, artist_name.clone(), album).await;
+            progress.metadata_finished();
+            albums_done += 1;
+        }
+
+        let enriched_artists: HashMap<&str, &Artist> = artists[..artists_done]
+            .iter()
+            .map(|artist| (artist.id.as_str(), artist))
+            .collect();
+        let found_albums = albums[..albums_done]
+            .iter()
+            .filter(|(_, album)| !album.musicbrainz_id.is_empty())
+            .count();
+        // Albums indexed offline have now been looked up online, even if nothing was found
+        let enriched_albums: HashMap<&str, &Album> = albums[..albums_done]
+            .iter()
+            .map(|(_, album)| album)
+            .filter(|album| !album.musicbrainz_id.is_empty() || pending_album_ids.contains(&album.id))
+            .map(|album| (album.id.as_str(), album))
+            .collect();
+
+        if !enriched_artists.is_empty() || !enriched_albums.is_empty() {
+            progress.set_phase(ScanPhase::Saving);
+
+            let _catalog_lock = lock_catalog().await;
+            let before = fetch_library().await?;
+            let mut catalog = (*before).clone();
+            for artist in catalog.iter_mut() {
+                if let Some(enriched) = enriched_artists.get(artist.id.as_str()) {
+                    if !enriched.description.is_empty() {
+                        artist.description = enriched.description.clone();
+                    }
+                    if !enriched.icon_url.is_empty() {
+                        artist.icon_url = enriched.icon_url.clone();
+                        artist.followers = enriched.followers;
+                    }
+                    if enriched.tadb_music_videos.is_some() {
+                        artist.tadb_music_videos = enriched.tadb_music_videos.clone();
+                    }
+                    artist.pending_enrichment = false;
+                }
+
+                for album in artist.albums.iter_mut() {
+                    let Some(found) = enriched_albums.get(album.id.as_str()) else {
+                        continue;
+                    };
+                    if album.cover_url.is_empty() {
+                        album.cover_url = found.cover_url.clone();
+                    }
+                    album.description = found.description.clone();
+                    album.first_release_date = found.first_release_date.clone();
+                    album.musicbrainz_id = found.musicbrainz_id.clone();
+                    album.wikidata_id = found.wikidata_id.clone();
+                    album.primary_type = found.primary_type.clone();
+                    album.release_album = found.release_album.clone();
+                    album.release_group_album = found.release_group_album.clone();
+                    album.pending_enrichment = false;
+                }
+
+                // Music videos are matched against the songs as they are now
+                if enriched_artists.contains_key(artist.id.as_str()) {
+                    refresh_audio_db_info(artist);
+                }
+            }
+
+            save_library(&Arc::new(catalog.clone())).await?;
+            if let Err(e) = update_search_data(&before, &catalog).await {
+                error!("Failed to update search data: {:?}", e);
+            }
+        }
+
+        progress.check_cancelled()?;
+        Ok(format!(
+            "Found metadata for {} of {} albums and looked up {} of {} artists",
+            found_albums,
+            albums.len(),
+            enriched_artists.len(),
+            artists.len()
+        ))
+    }.await;
+
+    progress.finish(&result).await;
+    result
+}
+
+#[post("/metadata/refresh")]
+pub async fn refresh_metadata_route() -> impl Responder {
+    enqueue_response(JobKind::MetadataRefresh).await
+}
+
+/// Runs a queued background job, returning the result stored with it.
+pub async fn run_job(kind: JobKind) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    match kind {
+        JobKind::Index { path } => Ok(serde_json::to_value(process_music_library(&path).await?)?),
+        JobKind::Refresh => Ok(serde_json::to_value(refresh_libraries().await?)?),
+        JobKind::Diff { path } => Ok(serde_json::to_value(preview_library(&path).await?)?),
+        JobKind::MetadataRefresh => Ok(refresh_missing_metadata().await?.into()),
+        JobKind::SearchPopulate => {
+            let items = populate_search_data().await.map_err(|e| e.to_string())?;
+            Ok(format!("Indexed {} search items", items.len()).into())
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ScheduleResponse {
+    #[serde(flatten)]
+    pub settings: ScheduleSettings,
+    /// When each enabled task runs next
+    pub next_runs: HashMap<ScheduledTask, DateTime<Local>>,
+}
+
+fn schedule_response() -> ScheduleResponse {
+    let settings = schedule_settings();
+    let now = Local::now();
+    let next_runs = ScheduledTask::ALL
+        .into_iter()
+        .filter(|task| settings.get(*task).enabled)
+        .filter_map(|task| settings.get(task).next_due(now).map(|next| (task, next)))
+        .collect();
+
+    ScheduleResponse { settings, next_runs }
+}
+
+#[get("/schedule")]
+pub async fn get_schedule() -> impl Responder {
+    HttpResponse::Ok().json(schedule_response())
+}
+
+#[post("/schedule")]
+pub async fn set_schedule(settings: web::Json<ScheduleSettings>) -> impl Responder {
+    if let Err(e) = settings.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    if let Err(e) = save_schedule_settings(settings.into_inner()) {
+        error!("Failed to save schedule settings: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().json(schedule_response())
+}
+
+#[derive(Deserialize)]
+pub struct TaskRunsQuery {
+    pub task: Option<ScheduledTask>,
+    pub limit: Option<i64>,
+}
+
+#[get("/schedule/runs")]
+pub async fn get_task_runs(query: web::Query<TaskRunsQuery>) -> impl Responder {
+    match task_runs(query.task, query.limit.unwrap_or(50)).await {
+        Ok(runs) => HttpResponse::Ok().json(runs),
+        Err(e) => {
+            error!("Failed to load the task run history: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
 #[get("/index/quick/{path}")]
 async fn index_library_no_cover_url(path: web::Path<String>) -> impl Responder {
     println!("Indexing");
-    let indexed_library = index_library(path.as_str()).await.unwrap();
+    let progress = match begin_scan() {
+        Ok(progress) => progress,
+        Err(e) => return HttpResponse::Conflict().body(e.to_string()),
+    };
+    progress.start_library(path.as_str());
+    let result = index_library(path.as_str(), progress.clone(), false).await;
+    progress.finish(&result).await;
+
+    let indexed_library = match result {
+        Ok(indexed) => indexed.library,
+        Err(e) => return scan_error_response(e.as_ref()),
+    };
 
     let json: String = serde_json::to_string(&*indexed_library.lock().unwrap()).unwrap();
 
@@ -6,7 +6,7 @@ use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::utils::database::{
-    database::establish_connection,
+    database::{with_connection, DbPool},
     models::{NewPlaylist, Playlist}
 };
 
@@ -17,41 +17,48 @@ struct AddSongToPlaylistRequest {
 }
 
 #[post("/add_song")]
-async fn add_song(item: web::Json<AddSongToPlaylistRequest>) -> Result<impl Responder, Box<dyn Error>> {
+async fn add_song(pool: web::Data<DbPool>, item: web::Json<AddSongToPlaylistRequest>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::playlist::dsl::playlist;
     use crate::utils::database::schema::song::dsl::song;
     use crate::utils::database::schema::_playlist_to_song::dsl::*;
 
-    let mut connection = establish_connection().get().unwrap();
-
-    let playlist_exists = playlist
-        .filter(crate::utils::database::schema::playlist::dsl::id.eq(item.playlist_id))
-        .select(crate::utils::database::schema::playlist::dsl::id)
-        .first::<i32>(&mut connection)
-        .optional()?
-        .is_some();
-
-    if !playlist_exists {
+    let item = item.into_inner();
+    let added = with_connection(&pool, move |connection| {
+        let playlist_exists = playlist
+            .filter(crate::utils::database::schema::playlist::dsl::id.eq(item.playlist_id))
+            .select(crate::utils::database::schema::playlist::dsl::id)
+            .first::<i32>(connection)
+            .optional()?
+            .is_some();
+
+        if !playlist_exists {
+            return Ok(false);
+        }
+
+        let song_exists = song
+            .filter(crate::utils::database::schema::song::dsl::id.eq(item.song_id.clone()))
+            .select(crate::utils::database::schema::song::dsl::id)
+            .first::<String>(connection)
+            .optional()?
+            .is_some();
+
+        if !song_exists {
+            diesel::insert_into(song)
+                .values(crate::utils::database::schema::song::dsl::id.eq(item.song_id.clone()))
+                .execute(connection)?;
+        }
+
+        diesel::insert_into(_playlist_to_song)
+            .values((a.eq(item.playlist_id), b.eq(item.song_id.clone())))
+            .execute(connection)?;
+
+        Ok(true)
+    }).await?;
+
+    if !added {
         return Ok(HttpResponse::BadRequest().body("Playlist does not exist"));
     }
 
-    let song_exists = song
-        .filter(crate::utils::database::schema::song::dsl::id.eq(item.song_id.clone()))
-        .select(crate::utils::database::schema::song::dsl::id)
-        .first::<String>(&mut connection)
-        .optional()?
-        .is_some();
-
-    if !song_exists {
-        diesel::insert_into(song)
-            .values(crate::utils::database::schema::song::dsl::id.eq(item.song_id.clone()))
-            .execute(&mut connection)?;
-    }
-
-    diesel::insert_into(_playlist_to_song)
-        .values((a.eq(item.playlist_id), b.eq(item.song_id.clone())))
-        .execute(&mut connection)?;
-
     Ok(HttpResponse::Ok().body("Song added to playlist successfully"))
 }
 
@@ -64,29 +71,30 @@ struct CreatePlaylistRequest {
 
 
 #[post("/create")]
-async fn create(item: web::Json<CreatePlaylistRequest>) -> Result<impl Responder, Box<dyn Error>> {
+async fn create(pool: web::Data<DbPool>, item: web::Json<CreatePlaylistRequest>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::playlist::dsl::*;
     use crate::utils::database::schema::_playlist_to_user::dsl::*;
 
-    let mut connection = establish_connection().get().unwrap();
-
     let new_playlist = NewPlaylist {
         name: item.name.clone(),
     };
+    let item_user_id = item.user_id;
+
+    with_connection(&pool, move |connection| {
+        diesel::insert_into(playlist)
+            .values(&new_playlist)
+            .execute(connection)?;
+        use diesel::SelectableHelper;
 
-    diesel::insert_into(playlist)
-        .values(&new_playlist)
-        .execute(&mut connection)?;
-    use diesel::SelectableHelper;
-    
-    let created_playlist = playlist
-        .select(Playlist::as_select())
-        .order(id.desc())
-        .first::<Playlist>(&mut connection)?;
+        let created_playlist = playlist
+            .select(Playlist::as_select())
+            .order(id.desc())
+            .first::<Playlist>(connection)?;
 
-    diesel::insert_into(_playlist_to_user)
-        .values((a.eq(created_playlist.id), b.eq(item.user_id)))
-        .execute(&mut connection)?;
+        diesel::insert_into(_playlist_to_user)
+            .values((a.eq(created_playlist.id), b.eq(item_user_id)))
+            .execute(connection)
+    }).await?;
 
     Ok(HttpResponse::Ok().body("Playlist created successfully"))
 }
@@ -98,19 +106,20 @@ struct DeletePlaylistRequest {
 }
 
 #[delete("/delete")]
-async fn delete(item: web::Json<DeletePlaylistRequest>) -> Result<impl Responder, Box<dyn Error>> {
+async fn delete(pool: web::Data<DbPool>, item: web::Json<DeletePlaylistRequest>) -> Result<impl Responder, Box<dyn Error>> {
 	use crate::utils::database::schema::playlist::dsl::*;
 
-	let mut connection = establish_connection().get().unwrap();
-
-	diesel::delete(playlist.filter(id.eq(item.playlist_id)))
-		.execute(&mut connection)?;
+	let playlist_id = item.playlist_id;
+	with_connection(&pool, move |connection| {
+		diesel::delete(playlist.filter(id.eq(playlist_id)))
+			.execute(connection)
+	}).await?;
 
 	Ok(HttpResponse::Ok().body("Playlist deleted successfully"))
 }
 
 #[get("/info/{playlist}")]
-async fn info(path: web::Path<i32>) -> Result<impl Responder, Box<dyn Error>> {
+async fn info(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::playlist::dsl::*;
     use crate::utils::database::schema::_playlist_to_song::dsl::{a as song_a, b as song_b, _playlist_to_song};
     use crate::utils::database::schema::_playlist_to_user::dsl::{a as user_a, b as user_b, _playlist_to_user};
@@ -118,28 +127,30 @@ async fn info(path: web::Path<i32>) -> Result<impl Responder, Box<dyn Error>> {
     use diesel::dsl::sql;
 
     let playlist_id = path.into_inner();
-    let mut connection = establish_connection().get().unwrap();
-
-    let result = playlist
-        .select(Playlist::as_select())
-        .filter(id.eq(playlist_id))
-        .first::<Playlist>(&mut connection)?;
-
-    let song_infos: Vec<SongInfo> = _playlist_to_song
-        .filter(song_a.eq(playlist_id))
-        .select((song_b, sql::<Timestamp>("date_added")))
-        .load::<(String, NaiveDateTime)>(&mut connection)?
-        .into_iter()
-        .map(|(song_id, other_date_added)| SongInfo {
-            song_id,
-            date_added: other_date_added,
-        })
-        .collect();
-
-    let user_ids: Vec<i32> = _playlist_to_user
-        .filter(user_a.eq(playlist_id))
-        .select(user_b)
-        .load::<i32>(&mut connection)?;
+    let (result, song_infos, user_ids) = with_connection(&pool, move |connection| {
+        let result = playlist
+            .select(Playlist::as_select())
+            .filter(id.eq(playlist_id))
+            .first::<Playlist>(connection)?;
+
+        let song_infos: Vec<SongInfo> = _playlist_to_song
+            .filter(song_a.eq(playlist_id))
+            .select((song_b, sql::<Timestamp>("date_added")))
+            .load::<(String, NaiveDateTime)>(connection)?
+            .into_iter()
+            .map(|(song_id, other_date_added)| SongInfo {
+                song_id,
+                date_added: other_date_added,
+            })
+            .collect();
+
+        let user_ids: Vec<i32> = _playlist_to_user
+            .filter(user_a.eq(playlist_id))
+            .select(user_b)
+            .load::<i32>(connection)?;
+
+        Ok((result, song_infos, user_ids))
+    }).await?;
 
     let response = PlaylistInfoResponse {
         id: result.id,
@@ -170,22 +181,22 @@ struct PlaylistInfoResponse {
 }
 
 #[get("/list/{id}")]
-async fn list(path: web::Path<i32>) -> Result<impl Responder, Box<dyn Error>> {
+async fn list(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::playlist::dsl::*;
     use crate::utils::database::schema::_playlist_to_user::dsl::*;
 
     let user_id = path.into_inner();
-    let mut connection = establish_connection().get().unwrap();
-
-    let playlist_ids: Vec<i32> = _playlist_to_user
-        .filter(b.eq(user_id))
-        .select(a)
-        .load::<i32>(&mut connection)?;
-
-    let results = playlist
-        .select(Playlist::as_select())
-        .filter(id.eq_any(playlist_ids))
-        .load::<Playlist>(&mut connection)?;
+    let results = with_connection(&pool, move |connection| {
+        let playlist_ids: Vec<i32> = _playlist_to_user
+            .filter(b.eq(user_id))
+            .select(a)
+            .load::<i32>(connection)?;
+
+        playlist
+            .select(Playlist::as_select())
+            .filter(id.eq_any(playlist_ids))
+            .load::<Playlist>(connection)
+    }).await?;
 
     Ok(HttpResponse::Ok().json(results))
 }
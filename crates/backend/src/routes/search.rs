@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::env;
 use std::error::Error;
 use std::fs;
@@ -15,7 +16,7 @@ use rayon::prelude::*;
 use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
 use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
-use serde_json::{self, from_str, json};
+use serde_json::{self, json};
 use tantivy::collector::TopDocs;
 use tantivy::query::{
     BooleanQuery, FuzzyTermQuery, Occur, PhraseQuery, Query, QueryParser, RegexQuery, TermQuery,
@@ -28,13 +29,16 @@ use url::Url;
 
 use crate::routes::album::fetch_album_info;
 use crate::routes::artist::fetch_artist_info;
+use crate::routes::jobs::enqueue_response;
 use crate::routes::song::fetch_song_info;
 use crate::structures::structures::Artist;
-use crate::utils::config::{get_config, is_docker};
-use crate::utils::database::database::establish_connection;
+use crate::utils::config::{fetch_library, is_docker};
+use crate::utils::database::database::{with_connection, DbPool};
 use crate::utils::database::models::{NewSearchItem, SearchItem};
+use crate::utils::jobs::JobKind;
+use crate::utils::metadata_providers::metadata_provider_settings;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct CombinedItem {
     pub item_type: String,
     pub name: String,
@@ -106,17 +110,15 @@ pub fn get_tantivy_index_path() -> PathBuf {
 
 pub async fn populate_search_data(
 ) -> Result<Vec<CombinedItem>, Box<dyn std::error::Error + Send + Sync>> {
-    let config = match get_config().await {
-        Ok(config) => config,
-        Err(_e) => {
-            let no_config = "Tantivy could not populate the search data! This is expected if the library has not been indexed yet or music.json file is not present.";
+    let library = match fetch_library().await {
+        Ok(library) if !library.is_empty() => library,
+        _ => {
+            let no_config = "Tantivy could not populate the search data! This is expected if the library has not been indexed yet.";
             error!("{}", no_config);
             return Err(no_config.to_string().into());
         }
     };
 
-    let library: Vec<Artist> = from_str(&config)?;
-
     let index_path = get_tantivy_index_path();
 
     if index_path.exists() {
@@ -133,48 +135,43 @@ pub async fn populate_search_data(
         *temp_dir_path = Some(index_path.clone());
     }
 
-    let mut schema_builder = Schema::builder();
-    schema_builder.add_text_field("item_type", TEXT | STORED);
-    schema_builder.add_text_field("name", TEXT | STORED);
-    schema_builder.add_text_field("id", TEXT | STORED);
-    schema_builder.add_text_field("description", TEXT | STORED);
-    schema_builder.add_text_field("acronym", TEXT | STORED);
-    let schema = schema_builder.build();
-
+    let schema = build_search_schema();
     let index = Index::create_in_dir(&index_path, schema.clone())?;
 
     let mut index_writer: IndexWriter = index.writer(100_000_000)?;
 
-    let mut combined_items = Vec::new();
+    let combined_items = search_items(&library);
+    for item in &combined_items {
+        index_writer.add_document(search_document(&schema, item))?;
+    }
 
-    for artist in library {
-        let artist_doc = doc!(
-            schema.get_field("item_type").unwrap() => "artist",
-            schema.get_field("name").unwrap() => artist.name.clone(),
-            schema.get_field("id").unwrap() => artist.id.clone(),
-            schema.get_field("description").unwrap() => artist.description.clone(),
-            schema.get_field("acronym").unwrap() => extract_acronym(&artist.name),
+    index_writer.commit()?;
+
+    let meta_path = index_path.join("meta.json");
+    if !meta_path.exists() {
+        error!(
+            "Index metadata file does not exist after commit at {:?}",
+            meta_path
         );
-        index_writer.add_document(artist_doc)?;
+        return Err("Index metadata file does not exist after commit".into());
+    }
+
+    Ok(combined_items)
+}
 
+fn search_items(library: &[Artist]) -> Vec<CombinedItem> {
+    let mut combined_items = Vec::new();
+
+    for artist in library {
         combined_items.push(CombinedItem {
             item_type: "artist".to_string(),
             name: artist.name.clone(),
             id: artist.id.clone(),
-            description: Some(artist.description),
+            description: Some(artist.description.clone()),
             acronym: extract_acronym(&artist.name),
         });
 
         for album in &artist.albums {
-            let album_doc = doc!(
-                schema.get_field("item_type").unwrap() => "album",
-                schema.get_field("name").unwrap() => album.name.clone(),
-                schema.get_field("id").unwrap() => album.id.clone(),
-                schema.get_field("description").unwrap() => album.description.clone(),
-                schema.get_field("acronym").unwrap() => extract_acronym(&album.name),
-            );
-            index_writer.add_document(album_doc)?;
-
             combined_items.push(CombinedItem {
                 item_type: "album".to_string(),
                 name: album.name.clone(),
@@ -184,15 +181,6 @@ pub async fn populate_search_data(
             });
 
             for song in &album.songs {
-                let song_doc = doc!(
-                    schema.get_field("item_type").unwrap() => "song",
-                    schema.get_field("name").unwrap() => song.name.clone(),
-                    schema.get_field("id").unwrap() => song.id.clone(),
-                    schema.get_field("description").unwrap() => "",
-                    schema.get_field("acronym").unwrap() => extract_acronym(&song.name),
-                );
-                index_writer.add_document(song_doc)?;
-
                 combined_items.push(CombinedItem {
                     item_type: "song".to_string(),
                     name: song.name.clone(),
@@ -204,30 +192,71 @@ pub async fn populate_search_data(
         }
     }
 
+    combined_items
+}
+
+fn search_key(item: &CombinedItem) -> String {
+    format!("{}:{}", item.item_type, item.id)
+}
+
+fn search_document(schema: &Schema, item: &CombinedItem) -> TantivyDocument {
+    doc!(
+        schema.get_field("item_type").unwrap() => item.item_type.clone(),
+        schema.get_field("name").unwrap() => item.name.clone(),
+        schema.get_field("id").unwrap() => item.id.clone(),
+        schema.get_field("description").unwrap() => item.description.clone().unwrap_or_default(),
+        schema.get_field("acronym").unwrap() => item.acronym.clone(),
+        schema.get_field("key").unwrap() => search_key(item),
+    )
+}
+
+/// Applies the difference between two versions of the catalog to the search
+/// index instead of rebuilding it, for small changes like a few edited files.
+pub async fn update_search_data(
+    before: &[Artist],
+    after: &[Artist],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let index_path = get_tantivy_index_path();
+    let index = match Index::open_in_dir(&index_path) {
+        Ok(index) if index.schema().get_field("key").is_ok() => index,
+        // No index yet, or one built before items had keys
+        _ => return populate_search_data().await.map(|_| ()),
+    };
+
+    let schema = index.schema();
+    let key_field = schema.get_field("key")?;
+
+    let before_items: HashMap<String, CombinedItem> = search_items(before).into_iter().map(|item| (search_key(&item), item)).collect();
+    let after_items: HashMap<String, CombinedItem> = search_items(after).into_iter().map(|item| (search_key(&item), item)).collect();
+
+    let mut index_writer: IndexWriter = index.writer(50_000_000)?;
+
+    for (key, item) in &before_items {
+        if after_items.get(key) != Some(item) {
+            index_writer.delete_term(Term::from_field_text(key_field, key));
+        }
+    }
+
+    for (key, item) in &after_items {
+        if before_items.get(key) != Some(item) {
+            index_writer.delete_term(Term::from_field_text(key_field, key));
+            index_writer.add_document(search_document(&schema, item))?;
+        }
+    }
+
     index_writer.commit()?;
 
-    let meta_path = index_path.join("meta.json");
-    if !meta_path.exists() {
-        error!(
-            "Index metadata file does not exist after commit at {:?}",
-            meta_path
-        );
-        return Err("Index metadata file does not exist after commit".into());
+    let mut temp_dir_path = TEMP_DIR_PATH.lock().unwrap();
+    if temp_dir_path.is_none() {
+        *temp_dir_path = Some(index_path);
     }
 
-    Ok(combined_items)
+    Ok(())
 }
 
 #[get("/populate")]
 async fn populate_search() -> HttpResponse {
-    match populate_search_data().await {
-        Ok(combined_items) => HttpResponse::Ok().json(combined_items),
-        Err(e) => {
-            error!("Failed to populate search data: {:?}", e);
-            HttpResponse::InternalServerError()
-                .body(format!("Failed to populate search data: {:?}", e))
-        }
-    }
+    enqueue_response(JobKind::SearchPopulate).await
 }
 
 #[derive(Serialize, Deserialize)]
@@ -350,6 +379,7 @@ fn build_search_schema() -> Schema {
     schema_builder.add_text_field("id", TEXT | STORED);
     schema_builder.add_text_field("description", TEXT | STORED);
     schema_builder.add_text_field("acronym", TEXT | STORED);
+    schema_builder.add_text_field("key", STRING);
     schema_builder.build()
 }
 
@@ -784,50 +814,55 @@ struct SearchItemResponse {
 
 #[post("/add_search_history")]
 async fn add_search_history(
+    pool: web::Data<DbPool>,
     item: web::Json<AddSearchHistoryRequest>,
 ) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::search_item::dsl::*;
 
-    let mut connection = establish_connection().get().unwrap();
-
     let new_search_item = NewSearchItem {
         user_id: item.user_id,
         search: item.search.clone(),
     };
 
-    diesel::insert_into(search_item)
-        .values(&new_search_item)
-        .execute(&mut connection)?;
+    with_connection(&pool, move |connection| {
+        diesel::insert_into(search_item)
+            .values(&new_search_item)
+            .execute(connection)
+    }).await?;
 
     Ok(HttpResponse::Ok().body("Search history added successfully"))
 }
 
 #[delete("/delete_item_from_search_history")]
 async fn delete_item_from_search_history(
+    pool: web::Data<DbPool>,
     item: web::Json<DeleteItemFromSearchHistoryRequest>,
 ) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::search_item::dsl::*;
 
-    let mut connection = establish_connection().get().unwrap();
-
-    diesel::delete(search_item.filter(id.eq(item.id))).execute(&mut connection)?;
+    let item_id = item.id;
+    with_connection(&pool, move |connection| {
+        diesel::delete(search_item.filter(id.eq(item_id))).execute(connection)
+    }).await?;
 
     Ok(HttpResponse::Ok().body("Search history item deleted successfully"))
 }
 
 #[get("/get_last_searched_queries")]
 async fn get_last_searched_queries(
+    pool: web::Data<DbPool>,
     query: web::Query<GetLastSearchedQueriesRequest>,
 ) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::search_item::dsl::*;
 
-    let mut connection = establish_connection().get().unwrap();
-
-    let results = search_item
-        .filter(user_id.eq(query.user_id))
-        .order(created_at.desc())
-        .limit(10)
-        .load::<SearchItem>(&mut connection)?;
+    let query_user_id = query.user_id;
+    let results = with_connection(&pool, move |connection| {
+        search_item
+            .filter(user_id.eq(query_user_id))
+            .order(created_at.desc())
+            .limit(10)
+            .load::<SearchItem>(connection)
+    }).await?;
 
     let response: Vec<SearchItemResponse> = results
         .into_iter()
@@ -869,6 +904,15 @@ struct InvidiousVideo {
     author: String,
 }
 
+/// Answers requests to outside services while the server is in offline mode.
+fn offline_response() -> Option<HttpResponse> {
+    metadata_provider_settings().offline.then(|| {
+        HttpResponse::ServiceUnavailable().json(json!({
+            "error": "The server is in offline mode"
+        }))
+    })
+}
+
 async fn get_working_invidious_instance(client: &reqwest::Client) -> String {
     // Return cached instance if checked recently
     if let Ok(cache) = WORKING_INSTANCE.lock() {
@@ -957,6 +1001,10 @@ async fn get_working_invidious_instance(client: &reqwest::Client) -> String {
 async fn search_youtube(
     query: web::Query<SearchRequest>,
 ) -> Result<impl Responder, Box<dyn Error>> {
+    if let Some(response) = offline_response() {
+        return Ok(response);
+    }
+
     let client = reqwest::Client::new();
     let search_query = &query.q;
 
@@ -1103,6 +1151,10 @@ struct Replies {
 async fn get_youtube_comments(
     query: web::Query<CommentsRequest>,
 ) -> Result<impl Responder, Box<dyn Error>> {
+    if let Some(response) = offline_response() {
+        return Ok(response);
+    }
+
     let client = reqwest::Client::builder()
         .timeout(Duration::from_secs(5))
         .build()?;
@@ -1227,6 +1279,10 @@ struct GeniusSongRequest {
 async fn search_genius_lyrics(
     query: web::Query<SearchRequest>,
 ) -> Result<impl Responder, Box<dyn Error>> {
+    if let Some(response) = offline_response() {
+        return Ok(response);
+    }
+
     let client = reqwest::Client::builder()
         .timeout(Duration::from_secs(10))
         .build()?;
@@ -1336,6 +1392,10 @@ async fn search_genius_lyrics(
 async fn get_genius_lyrics(
     query: web::Query<GeniusSongRequest>,
 ) -> Result<impl Responder, Box<dyn Error>> {
+    if let Some(response) = offline_response() {
+        return Ok(response);
+    }
+
     let client = reqwest::Client::builder()
         .timeout(Duration::from_secs(15))
         .build()?;
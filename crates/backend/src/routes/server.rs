@@ -2,29 +2,27 @@ use std::error::Error;
 
 use actix_web::{get, post, web, HttpResponse, Responder};
 use diesel::prelude::*;
-use diesel::result::Error as DieselError;
 
-use crate::utils::database::{database::establish_connection, models::ServerInfo};
+use crate::utils::database::{database::{with_connection, DbPool}, models::ServerInfo};
 
 #[get("/info")]
-async fn get_server_info() -> Result<impl Responder, Box<dyn Error>> {
+async fn get_server_info(pool: web::Data<DbPool>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::server_info::dsl::*;
 
-    let mut connection = establish_connection().get().unwrap();
+    let result = with_connection(&pool, |connection| {
+        server_info.select(ServerInfo::as_select()).first::<ServerInfo>(connection).optional()
+    }).await?;
 
-    match server_info.select(ServerInfo::as_select()).first::<ServerInfo>(&mut connection) {
-        Ok(server_info_data) => Ok(HttpResponse::Ok().json(server_info_data)),
-        Err(DieselError::NotFound) => Ok(HttpResponse::NoContent().finish()),
-        Err(e) => Err(Box::new(e)),
+    match result {
+        Some(server_info_data) => Ok(HttpResponse::Ok().json(server_info_data)),
+        None => Ok(HttpResponse::NoContent().finish()),
     }
 }
 
 #[post("/info")]
-async fn set_server_info(info: web::Json<ServerInfo>) -> Result<impl Responder, Box<dyn Error>> {
+async fn set_server_info(pool: web::Data<DbPool>, info: web::Json<ServerInfo>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::server_info::dsl::*;
 
-    let mut connection = establish_connection().get().unwrap();
-
     let new_info = ServerInfo {
         local_address: info.local_address.clone(),
         server_name: info.server_name.clone(),
@@ -34,12 +32,14 @@ async fn set_server_info(info: web::Json<ServerInfo>) -> Result<impl Responder,
         login_disclaimer: info.login_disclaimer.clone(),
     };
 
-    diesel::insert_into(server_info)
-        .values(&new_info)
-        .on_conflict((server_name, local_address))
-        .do_update()
-        .set(&new_info)
-        .execute(&mut connection)?;
+    with_connection(&pool, move |connection| {
+        diesel::insert_into(server_info)
+            .values(&new_info)
+            .on_conflict((server_name, local_address))
+            .do_update()
+            .set(&new_info)
+            .execute(connection)
+    }).await?;
 
     Ok(HttpResponse::Ok().body("Server info set successfully"))
 }
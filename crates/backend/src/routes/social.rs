@@ -4,7 +4,7 @@ use actix_web::{get, post, web, HttpResponse, Responder};
 use diesel::prelude::*;
 use serde::Deserialize;
 
-use crate::utils::database::database::establish_connection;
+use crate::utils::database::database::{with_connection, DbPool};
 
 #[derive(Deserialize)]
 struct FollowRequest {
@@ -13,44 +13,45 @@ struct FollowRequest {
 }
 
 #[post("/follow")]
-async fn follow(item: web::Json<FollowRequest>) -> Result<impl Responder, Box<dyn Error>> {
+async fn follow(pool: web::Data<DbPool>, item: web::Json<FollowRequest>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::follow::dsl::*;
 
-    let mut connection = establish_connection().get().unwrap();
-
-    diesel::insert_into(follow)
-        .values((follower_id.eq(item.follower_id), following_id.eq(item.following_id)))
-        .execute(&mut connection)?;
+    let item = item.into_inner();
+    with_connection(&pool, move |connection| {
+        diesel::insert_into(follow)
+            .values((follower_id.eq(item.follower_id), following_id.eq(item.following_id)))
+            .execute(connection)
+    }).await?;
 
     Ok(HttpResponse::Ok().body("Followed successfully"))
 }
 
 #[get("/followers/{user_id}")]
-async fn get_followers(path: web::Path<i32>) -> Result<impl Responder, Box<dyn Error>> {
+async fn get_followers(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::follow::dsl::*;
 
     let user_id = path.into_inner();
-    let mut connection = establish_connection().get().unwrap();
-
-    let results = follow
-        .filter(following_id.eq(user_id))
-        .select(follower_id)
-        .load::<i32>(&mut connection)?;
+    let results = with_connection(&pool, move |connection| {
+        follow
+            .filter(following_id.eq(user_id))
+            .select(follower_id)
+            .load::<i32>(connection)
+    }).await?;
 
     Ok(HttpResponse::Ok().json(results))
 }
 
 #[get("/following/{user_id}")]
-async fn get_following(path: web::Path<i32>) -> Result<impl Responder, Box<dyn Error>> {
+async fn get_following(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::follow::dsl::*;
 
     let user_id = path.into_inner();
-    let mut connection = establish_connection().get().unwrap();
-
-    let results = follow
-        .filter(follower_id.eq(user_id))
-        .select(following_id)
-        .load::<i32>(&mut connection)?;
+    let results = with_connection(&pool, move |connection| {
+        follow
+            .filter(follower_id.eq(user_id))
+            .select(following_id)
+            .load::<i32>(connection)
+    }).await?;
 
     Ok(HttpResponse::Ok().json(results))
 }
@@ -7,8 +7,8 @@ use serde::{Deserialize, Serialize};
 use tracing::error;
 
 use crate::routes::search::populate_search_data;
-use crate::structures::structures::{Album, Artist, MusicVideo, Song};
-use crate::utils::config::{fetch_library, get_config, refresh_cache, save_library};
+use crate::structures::structures::{Album, AlbumType, Artist, AudioProperties, MusicVideo, Song};
+use crate::utils::config::{fetch_catalog_index, fetch_library, lock_catalog, refresh_cache, save_library};
 use crate::utils::hash::{hash_album, hash_artist};
 
 use super::genres::fetch_albums_by_genres;
@@ -20,8 +20,11 @@ pub struct ResponseSong {
     pub artist: String,
     pub contributing_artists: Vec<String>,
     pub track_number: u16,
+    pub disc_number: u16,
+    pub disc_total: u16,
     pub path: String,
     pub duration: f64,
+    pub audio: Option<AudioProperties>,
     pub album_object: Album,
     pub artist_object: Artist,
     pub music_video: MusicVideo,
@@ -29,8 +32,8 @@ pub struct ResponseSong {
 
 
 pub async fn fetch_random_songs(amount: usize, genre: Option<String>) -> Result<Vec<ResponseSong>, ()> {
-    let config = get_config().await.map_err(|_| ())?;
-    let library: Vec<Artist> = serde_json::from_str(&config).map_err(|_| ())?;
+    let index = fetch_catalog_index().await.map_err(|_| ())?;
+    let library = index.library();
 
     let mut response_songs = Vec::new();
     let mut rng = rand::thread_rng();
@@ -61,10 +64,10 @@ pub async fn fetch_random_songs(amount: usize, genre: Option<String>) -> Result<
         }
 
         if let Some(song) = valid_song {
-            let artist_object = library.iter()
-                .find(|artist| artist.albums.iter().any(|a| a.id == valid_album.as_ref().unwrap().id))
-                .unwrap()
-                .clone();
+            let artist_object = match index.album(&valid_album.as_ref().unwrap().id) {
+                Some((_, artist)) => artist.clone(),
+                None => continue,
+            };
 
             let response_song = ResponseSong {
                 id: song.id.clone(),
@@ -72,8 +75,11 @@ pub async fn fetch_random_songs(amount: usize, genre: Option<String>) -> Result<
                 artist: song.artist.clone(),
                 contributing_artists: song.contributing_artists.clone(),
                 track_number: song.track_number,
+                disc_number: song.disc_number,
+                disc_total: song.disc_total,
                 path: song.path.clone(),
                 duration: song.duration,
+                audio: song.audio.clone(),
                 album_object: valid_album.clone().unwrap(),
                 artist_object,
                 music_video: song.music_video.unwrap_or_default(),
@@ -93,50 +99,47 @@ pub enum SongInfo {
 }
 
 pub async fn fetch_song_info(song_id: String, include: Option<HashSet<String>>, bare: Option<bool>) -> Result<SongInfo, ()> {
-    let library = fetch_library().await.map_err(|_| ())?;
+    let index = fetch_catalog_index().await.map_err(|_| ())?;
     let bare = bare.unwrap_or(false);
 
-    for artist in library.iter() {
-        for album in artist.albums.iter() {
-            for song in album.songs.iter() {
-                if song.id == song_id {
-                    if bare {
-                        return Ok(SongInfo::Bare(song.clone()));
-                    }
-
-                    let include_fields = include.unwrap_or_else(|| {
-                        let mut all_fields = HashSet::new();
-                        all_fields.insert("id".to_string());
-                        all_fields.insert("name".to_string());
-                        all_fields.insert("artist".to_string());
-                        all_fields.insert("contributing_artists".to_string());
-                        all_fields.insert("track_number".to_string());
-                        all_fields.insert("path".to_string());
-                        all_fields.insert("duration".to_string());
-                        all_fields.insert("album_object".to_string());
-                        all_fields.insert("artist_object".to_string());
-                        all_fields.insert("music_video".to_string());
-                        all_fields
-                    });
-
-                    return Ok(SongInfo::Full(ResponseSong {
-                        id: if include_fields.contains("id") { song.id.clone() } else { String::new() },
-                        name: if include_fields.contains("name") { song.name.clone() } else { String::new() },
-                        artist: if include_fields.contains("artist") { song.artist.clone() } else { String::new() },
-                        contributing_artists: if include_fields.contains("contributing_artists") { song.contributing_artists.clone() } else { vec![String::new()] },
-                        track_number: if include_fields.contains("track_number") { song.track_number } else { 0 },
-                        path: if include_fields.contains("path") { song.path.clone() } else { String::new() },
-                        duration: if include_fields.contains("duration") { song.duration } else { 0.0 },
-                        album_object: if include_fields.contains("album_object") { album.clone() } else { Album::default() },
-                        artist_object: if include_fields.contains("artist_object") { artist.clone() } else { Artist::default() },
-                        music_video: if include_fields.contains("music_video") { song.music_video.clone().unwrap_or_default() } else { MusicVideo::default() },
-                    }));
-                }
-            }
-        }
+    let (song, album, artist) = index.song(&song_id).ok_or(())?;
+    if bare {
+        return Ok(SongInfo::Bare(song.clone()));
     }
 
-    Err(())
+    let include_fields = include.unwrap_or_else(|| {
+        let mut all_fields = HashSet::new();
+        all_fields.insert("id".to_string());
+        all_fields.insert("name".to_string());
+        all_fields.insert("artist".to_string());
+        all_fields.insert("contributing_artists".to_string());
+        all_fields.insert("track_number".to_string());
+        all_fields.insert("disc_number".to_string());
+        all_fields.insert("disc_total".to_string());
+        all_fields.insert("path".to_string());
+        all_fields.insert("duration".to_string());
+        all_fields.insert("audio".to_string());
+        all_fields.insert("album_object".to_string());
+        all_fields.insert("artist_object".to_string());
+        all_fields.insert("music_video".to_string());
+        all_fields
+    });
+
+    Ok(SongInfo::Full(ResponseSong {
+        id: if include_fields.contains("id") { song.id.clone() } else { String::new() },
+        name: if include_fields.contains("name") { song.name.clone() } else { String::new() },
+        artist: if include_fields.contains("artist") { song.artist.clone() } else { String::new() },
+        contributing_artists: if include_fields.contains("contributing_artists") { song.contributing_artists.clone() } else { vec![String::new()] },
+        track_number: if include_fields.contains("track_number") { song.track_number } else { 0 },
+        disc_number: if include_fields.contains("disc_number") { song.disc_number } else { 0 },
+        disc_total: if include_fields.contains("disc_total") { song.disc_total } else { 0 },
+        path: if include_fields.contains("path") { song.path.clone() } else { String::new() },
+        duration: if include_fields.contains("duration") { song.duration } else { 0.0 },
+        audio: if include_fields.contains("audio") { song.audio.clone() } else { None },
+        album_object: if include_fields.contains("album_object") { album.clone() } else { Album::default() },
+        artist_object: if include_fields.contains("artist_object") { artist.clone() } else { Artist::default() },
+        music_video: if include_fields.contains("music_video") { song.music_video.clone().unwrap_or_default() } else { MusicVideo::default() },
+    }))
 }
 
 #[derive(Deserialize)]
@@ -170,12 +173,11 @@ async fn get_song_info(id: web::Path<String>, query: web::Query<SongQuery>) -> H
 }
 
 pub async fn fetch_songs_with_music_videos() -> Result<Vec<Song>, ()> {
-    let config = get_config().await.map_err(|_| ())?;
-    let library: Vec<Artist> = serde_json::from_str(&config).map_err(|_| ())?;
+    let library = fetch_library().await.map_err(|_| ())?;
 
     let mut response_songs = Vec::new();
 
-    for artist in &library {
+    for artist in library.iter() {
         for album in &artist.albums {
             for song in &album.songs {
                 if song.music_video.is_some() {
@@ -198,6 +200,7 @@ async fn get_songs_with_music_videos() -> HttpResponse {
 
 #[post("/edit/{id}")]
 async fn edit_song_metadata(form: web::Json<Song>) -> HttpResponse {
+    let _catalog_lock = lock_catalog().await;
     let mut library = match fetch_library().await {
         Ok(lib) => lib,
         Err(_) => return HttpResponse::InternalServerError().finish(),
@@ -244,6 +247,7 @@ pub async fn add_song(form: web::Json<AddSongForm>) -> HttpResponse {
     let artist_id = form.artist_id.clone();
     let album_id = form.album_id.clone();
 
+    let _catalog_lock = lock_catalog().await;
     let mut library = match fetch_library().await {
         Ok(lib) => lib,
         Err(_) => return HttpResponse::InternalServerError().finish(),
@@ -263,6 +267,8 @@ pub async fn add_song(form: web::Json<AddSongForm>) -> HttpResponse {
                     let new_album = Album {
                         id: hash_album(&new_song.name, &artist.name),
                         name: new_song.name.clone(),
+                        edition: None,
+                        album_type: AlbumType::Album,
                         cover_url: String::new(),
                         songs: vec![new_song.clone()],
                         first_release_date: String::new(),
@@ -274,6 +280,10 @@ pub async fn add_song(form: web::Json<AddSongForm>) -> HttpResponse {
                         contributing_artists_ids: vec![],
                         release_album: None,
                         release_group_album: None,
+                        genres: Vec::new(),
+                        pending_enrichment: false,
+                        musicbrainz_release_id: None,
+                        musicbrainz_release_group_id: None,
                     };
                     artist.albums.push(new_album);
                 }
@@ -298,6 +308,8 @@ pub async fn add_song(form: web::Json<AddSongForm>) -> HttpResponse {
                     let new_album = Album {
                         id: hash_album(&album_name, &artist_name),
                         name: album_name.clone(),
+                        edition: None,
+                        album_type: AlbumType::Album,
                         cover_url: String::new(),
                         songs: vec![new_song.clone()],
                         first_release_date: String::new(),
@@ -309,6 +321,10 @@ pub async fn add_song(form: web::Json<AddSongForm>) -> HttpResponse {
                         contributing_artists_ids: vec![],
                         release_album: None,
                         release_group_album: None,
+                        genres: Vec::new(),
+                        pending_enrichment: false,
+                        musicbrainz_release_id: None,
+                        musicbrainz_release_group_id: None,
                     };
                     artist.albums.push(new_album);
                 }
@@ -324,6 +340,8 @@ pub async fn add_song(form: web::Json<AddSongForm>) -> HttpResponse {
                 albums: vec![Album {
                     id: hash_album(&album_name, &artist_name),
                     name: album_name.clone(),
+                    edition: None,
+                    album_type: AlbumType::Album,
                     cover_url: String::new(),
                     songs: vec![new_song.clone()],
                     first_release_date: String::new(),
@@ -335,12 +353,18 @@ pub async fn add_song(form: web::Json<AddSongForm>) -> HttpResponse {
                     contributing_artists_ids: vec![],
                     release_album: None,
                     release_group_album: None,
+                    genres: Vec::new(),
+                    pending_enrichment: false,
+                    musicbrainz_release_id: None,
+                    musicbrainz_release_group_id: None,
                 }],
                 featured_on_album_ids: vec![],
                 icon_url: String::new(),
                 followers: 0,
                 description: String::new(),
                 tadb_music_videos: None,
+                pending_enrichment: false,
+                musicbrainz_id: None,
             };
             Arc::make_mut(&mut library).push(new_artist);
         }
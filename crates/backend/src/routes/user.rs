@@ -11,7 +11,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::routes::authentication::{hash_password, verify_password};
 use crate::utils::config::get_profile_picture_path;
-use crate::utils::database::database::establish_connection;
+use crate::utils::database::database::{with_connection, DbPool};
 use crate::utils::database::models::{ListenHistoryItem, NewListenHistoryItem, User};
 
 #[derive(Deserialize)]
@@ -22,19 +22,20 @@ pub struct AuthData {
 }
 
 #[post("/change_password")]
-pub async fn change_password(form: web::Json<AuthData>) -> Result<impl Responder, Box<dyn Error>> {
+pub async fn change_password(pool: web::Data<DbPool>, form: web::Json<AuthData>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::user::dsl::*;
     dotenv().ok();
 
-    let mut connection = establish_connection().get().unwrap();
-
-    let stored_password: String = match user
-        .filter(username.eq(&form.username))
-        .select(password)
-        .first::<String>(&mut connection) {
-            Ok(return_password) => return_password,
-            Err(_) => return Ok(HttpResponse::InternalServerError().body("User not found")),
-        };
+    let form_username = form.username.clone();
+    let stored_password: String = match with_connection(&pool, move |connection| {
+        user
+            .filter(username.eq(&form_username))
+            .select(password)
+            .first::<String>(connection)
+    }).await {
+        Ok(return_password) => return_password,
+        Err(_) => return Ok(HttpResponse::InternalServerError().body("User not found")),
+    };
 
     if !verify_password(&form.current_password, &stored_password) {
       return Ok(HttpResponse::Unauthorized().body("Current password is incorrect"));
@@ -45,9 +46,12 @@ pub async fn change_password(form: web::Json<AuthData>) -> Result<impl Responder
       Err(e) => return Ok(HttpResponse::InternalServerError().body(format!("Failed to hash new password: {}", e))),
     };
 
-    match diesel::update(user.filter(username.eq(&form.username)))
-      .set(password.eq(hashed_new_password))
-      .execute(&mut connection) {
+    let form_username = form.username.clone();
+    match with_connection(&pool, move |connection| {
+        diesel::update(user.filter(username.eq(&form_username)))
+            .set(password.eq(hashed_new_password))
+            .execute(connection)
+    }).await {
         Ok(_) => Ok(HttpResponse::Ok().body("Password changed")),
         Err(_) => Ok(HttpResponse::InternalServerError().body("There was an error updating the password!")),
     }
@@ -58,21 +62,21 @@ struct ListenHistoryQuery {
     user_id: i32,
 }
 
-pub async fn fetch_listen_history(user_id_param: i32) -> Result<Vec<ListenHistoryItem>, Box<dyn Error>> {
+pub async fn fetch_listen_history(pool: &DbPool, user_id_param: i32) -> Result<Vec<ListenHistoryItem>, Box<dyn Error>> {
     use crate::utils::database::schema::listen_history_item::dsl::*;
 
-    let mut connection = establish_connection().get()?;
-
-    let results = listen_history_item
-        .filter(user_id.eq(user_id_param))
-        .load::<ListenHistoryItem>(&mut connection)?;
+    let results = with_connection(pool, move |connection| {
+        listen_history_item
+            .filter(user_id.eq(user_id_param))
+            .load::<ListenHistoryItem>(connection)
+    }).await?;
 
     Ok(results)
 }
 
 #[get("/get_listen_history")]
-async fn get_listen_history(query: web::Query<ListenHistoryQuery>) -> Result<HttpResponse, Box<dyn Error>> {
-    let results = fetch_listen_history(query.user_id).await?;
+async fn get_listen_history(pool: web::Data<DbPool>, query: web::Query<ListenHistoryQuery>) -> Result<HttpResponse, Box<dyn Error>> {
+    let results = fetch_listen_history(&pool, query.user_id).await?;
     Ok(HttpResponse::Ok().json(results))
 }
 
@@ -84,20 +88,21 @@ struct AddSongRequest {
 
 #[post("/add_song_to_listen_history")]
 async fn add_song_to_listen_history(
+    pool: web::Data<DbPool>,
     item: web::Json<AddSongRequest>,
 ) -> Result<HttpResponse, Box<dyn Error>> {
     use crate::utils::database::schema::listen_history_item;
 
-    let mut connection = establish_connection().get().unwrap();
-
     let new_item = NewListenHistoryItem {
         user_id: item.user_id,
         song_id: item.song_id.clone(),
     };
 
-    diesel::insert_into(listen_history_item::table)
-        .values(&new_item)
-        .execute(&mut connection)?;
+    with_connection(&pool, move |connection| {
+        diesel::insert_into(listen_history_item::table)
+            .values(&new_item)
+            .execute(connection)
+    }).await?;
 
     Ok(HttpResponse::Ok().body("Song added to history"))
 }
@@ -110,16 +115,17 @@ struct SetBitrateRequest {
 
 #[post("/set_bitrate")]
 async fn set_bitrate(
+    pool: web::Data<DbPool>,
     item: web::Json<SetBitrateRequest>,
 ) -> impl Responder {
     use crate::utils::database::schema::user::dsl::{user, bitrate};
 
-    let mut connection = establish_connection().get().unwrap();
-
-    diesel::update(user.find(item.user_id))
-        .set(bitrate.eq(item.bitrate))
-        .execute(&mut connection)
-        .expect("Error updating bitrate");
+    let (item_user_id, item_bitrate) = (item.user_id, item.bitrate);
+    with_connection(&pool, move |connection| {
+        diesel::update(user.find(item_user_id))
+            .set(bitrate.eq(item_bitrate))
+            .execute(connection)
+    }).await.expect("Error updating bitrate");
 
     HttpResponse::Ok().body("Bitrate set")
 }
@@ -132,16 +138,17 @@ struct SetNowPlayingRequest {
 
 #[post("/set_now_playing")]
 async fn set_now_playing(
+    pool: web::Data<DbPool>,
     item: web::Json<SetNowPlayingRequest>,
 ) -> impl Responder {
     use crate::utils::database::schema::user::dsl::{user, now_playing};
 
-    let mut connection = establish_connection().get().unwrap();
-
-    diesel::update(user.find(item.user_id))
-        .set(now_playing.eq(item.now_playing.clone()))
-        .execute(&mut connection)
-        .expect("Error updating now playing");
+    let item = item.into_inner();
+    with_connection(&pool, move |connection| {
+        diesel::update(user.find(item.user_id))
+            .set(now_playing.eq(item.now_playing))
+            .execute(connection)
+    }).await.expect("Error updating now playing");
 
     HttpResponse::Ok().body("Now playing set")
 }
@@ -158,45 +165,46 @@ struct GetNowPlayingResponse {
 
 #[get("/get_now_playing")]
 async fn get_now_playing(
+    pool: web::Data<DbPool>,
     item: web::Query<GetNowPlayingRequest>,
 ) -> impl Responder {
     use crate::utils::database::schema::user::dsl::{user, now_playing};
 
-    let mut connection = establish_connection().get().unwrap();
-
-    let result = user
-        .find(item.user_id)
-        .select(now_playing)
-        .first::<Option<String>>(&mut connection)
-        .expect("Error loading now playing");
+    let item_user_id = item.user_id;
+    let result = with_connection(&pool, move |connection| {
+        user
+            .find(item_user_id)
+            .select(now_playing)
+            .first::<Option<String>>(connection)
+    }).await.expect("Error loading now playing");
 
     HttpResponse::Ok().json(GetNowPlayingResponse { now_playing: result })
 }
 
 #[get("/info_by_username/{username}")]
-async fn get_user_info(path: web::Path<String>) -> Result<impl Responder, Box<dyn Error>> {
+async fn get_user_info(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::user::dsl::*;
 
     let path_username = path.into_inner();
-    let mut connection = establish_connection().get().unwrap();
-
-    let recieved_user = user
-        .filter(username.eq(&path_username))
-        .first::<User>(&mut connection)?;
+    let recieved_user = with_connection(&pool, move |connection| {
+        user
+            .filter(username.eq(&path_username))
+            .first::<User>(connection)
+    }).await?;
 
     Ok(HttpResponse::Ok().json(recieved_user))
 }
 
 #[get("/info_by_id/{id}")]
-async fn get_user_info_by_id(path: web::Path<i32>) -> Result<impl Responder, Box<dyn Error>> {
+async fn get_user_info_by_id(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<impl Responder, Box<dyn Error>> {
     use crate::utils::database::schema::user::dsl::*;
 
     let path_id = path.into_inner();
-    let mut connection = establish_connection().get()?;
-
-    let recieved_user = user
-        .filter(id.eq(path_id))
-        .first::<User>(&mut connection)?;
+    let recieved_user = with_connection(&pool, move |connection| {
+        user
+            .filter(id.eq(path_id))
+            .first::<User>(connection)
+    }).await?;
 
     Ok(HttpResponse::Ok().json(recieved_user))
 }
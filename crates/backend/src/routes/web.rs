@@ -1,10 +1,10 @@
 use actix_web::{get, web, HttpResponse};
-use futures::future::join_all;
 use rand::seq::SliceRandom;
-use tokio::task;
 
 use crate::structures::structures::{Album, Artist, Genre};
-use crate::utils::config::fetch_library;
+use crate::utils::catalog_index::CatalogIndex;
+use crate::utils::config::{fetch_catalog_index, fetch_library};
+use crate::utils::database::database::DbPool;
 
 use super::album::ResponseAlbum;
 use super::user::fetch_listen_history;
@@ -39,36 +39,26 @@ struct AlbumCardProps {
     first_release_date: String,
 }
 
-async fn find_song_info_min(song_id: String) -> Result<SongInfo, ()> {
-    let library = fetch_library().await.map_err(|_| ())?;
-
-    for artist in library.iter() {
-        for album in artist.albums.iter() {
-            for song in album.songs.iter() {
-                if song.id == song_id {
-                    return Ok(SongInfo {
-                        song_name: song.name.clone(),
-                        song_id: song.id.clone(),
-                        song_path: song.path.clone(),
-                        artist_id: artist.id.clone(),
-                        artist_name: artist.name.clone(),
-                        album_id: album.id.clone(),
-                        album_name: album.name.clone(),
-                        album_cover: album.cover_url.clone(),
-                        release_date: album.first_release_date.clone(),
-                        album_songs_count: album.songs.len().clone(),
-                        item_type: "song".to_string(),
-                    });
-                }
-            }
-        }
-    }
-
-    Err(())
+fn find_song_info_min(index: &CatalogIndex, song_id: &str) -> Option<SongInfo> {
+    let (song, album, artist) = index.song(song_id)?;
+
+    Some(SongInfo {
+        song_name: song.name.clone(),
+        song_id: song.id.clone(),
+        song_path: song.path.clone(),
+        artist_id: artist.id.clone(),
+        artist_name: artist.name.clone(),
+        album_id: album.id.clone(),
+        album_name: album.name.clone(),
+        album_cover: album.cover_url.clone(),
+        release_date: album.first_release_date.clone(),
+        album_songs_count: album.songs.len(),
+        item_type: "song".to_string(),
+    })
 }
 
-async fn fetch_listen_history_songs(user_id: u32) -> Result<Vec<SongInfo>, ()> {
-    let listen_history_items = fetch_listen_history(user_id.try_into().unwrap()).await.unwrap();
+async fn fetch_listen_history_songs(pool: &DbPool, user_id: u32) -> Result<Vec<SongInfo>, ()> {
+    let listen_history_items = fetch_listen_history(pool, user_id.try_into().unwrap()).await.unwrap();
     
     let mut unique_listen_history_items: Vec<String> = Vec::new();
     let mut seen = std::collections::HashSet::new();
@@ -81,21 +71,10 @@ async fn fetch_listen_history_songs(user_id: u32) -> Result<Vec<SongInfo>, ()> {
 
     let unique_listen_history_items: Vec<String> = unique_listen_history_items.into_iter().take(30).collect();
 
-    let tasks: Vec<_> = unique_listen_history_items
-        .into_iter()
-        .map(|song_id| {
-            let song_id = song_id.clone();
-            task::spawn(async move {
-                find_song_info_min(song_id).await
-            })
-        })
-        .collect();
-
-    let songs_info: Vec<SongInfo> = join_all(tasks)
-        .await
-        .into_iter()
-        .filter_map(|res| res.ok())
-        .filter_map(|res| res.ok())
+    let index = fetch_catalog_index().await.map_err(|_| ())?;
+    let songs_info: Vec<SongInfo> = unique_listen_history_items
+        .iter()
+        .filter_map(|song_id| find_song_info_min(&index, song_id))
         .collect();
 
     let mut album_count: HashMap<String, Vec<SongInfo>> = HashMap::new();
@@ -129,8 +108,8 @@ async fn fetch_listen_history_songs(user_id: u32) -> Result<Vec<SongInfo>, ()> {
     Ok(result)
 }
 
-async fn fetch_similar_albums(user_id: u32) -> Result<(Vec<AlbumCardProps>, String), ()> {
-    let listen_history_result = fetch_albums_from_history(user_id).await;
+async fn fetch_similar_albums(pool: &DbPool, user_id: u32) -> Result<(Vec<AlbumCardProps>, String), ()> {
+    let listen_history_result = fetch_albums_from_history(pool, user_id).await;
     
     if let Ok((albums, genre)) = listen_history_result {
         if !albums.is_empty() {
@@ -141,8 +120,8 @@ async fn fetch_similar_albums(user_id: u32) -> Result<(Vec<AlbumCardProps>, Stri
     fetch_fallback_albums().await
 }
 
-async fn fetch_albums_from_history(user_id: u32) -> Result<(Vec<AlbumCardProps>, String), ()> {
-    let listen_history_songs = fetch_listen_history_songs(user_id).await?;
+async fn fetch_albums_from_history(pool: &DbPool, user_id: u32) -> Result<(Vec<AlbumCardProps>, String), ()> {
+    let listen_history_songs = fetch_listen_history_songs(pool, user_id).await?;
     
     let last_10_songs: Vec<_> = listen_history_songs.iter().take(10).collect();
     
@@ -241,9 +220,9 @@ async fn fetch_fallback_albums() -> Result<(Vec<AlbumCardProps>, String), ()> {
 }
 
 #[get("/similar_to/{user_id}")]
-async fn get_similar_albums(user_id: web::Path<u32>) -> HttpResponse {
+async fn get_similar_albums(pool: web::Data<DbPool>, user_id: web::Path<u32>) -> HttpResponse {
     let user_id = user_id.into_inner();
-    match fetch_similar_albums(user_id).await {
+    match fetch_similar_albums(&pool, user_id).await {
         Ok((albums, genre)) => {
             if albums.is_empty() {
                 HttpResponse::Ok().json((Vec::<AlbumCardProps>::new(), "Recommendations".to_string()))
@@ -261,18 +240,11 @@ async fn get_similar_albums(user_id: web::Path<u32>) -> HttpResponse {
 }
 
 async fn fetch_albums_info(album_ids: Vec<String>) -> Result<Vec<ResponseAlbum>, ()> {
-    let library = fetch_library().await.map_err(|_| ())?;
-    let mut album_map: HashMap<String, (&Artist, &Album)> = HashMap::new();
-
-    for artist in library.iter() {
-        for album in artist.albums.iter() {
-            album_map.insert(album.id.clone(), (artist, album));
-        }
-    }
+    let index = fetch_catalog_index().await.map_err(|_| ())?;
 
     let mut albums_info: Vec<ResponseAlbum> = Vec::new();
     for album_id in album_ids {
-        if let Some((artist, album)) = album_map.get(&album_id) {
+        if let Some((album, artist)) = index.album(&album_id) {
             albums_info.push(ResponseAlbum {
                 id: album.id.clone(),
                 name: album.name.clone(),
@@ -295,14 +267,14 @@ async fn fetch_albums_info(album_ids: Vec<String>) -> Result<Vec<ResponseAlbum>,
     Ok(albums_info)
 }
 
-async fn fetch_listen_again_songs(user_id: u32) -> Result<Vec<SongInfo>, ()> {
-    fetch_listen_history_songs(user_id).await
+async fn fetch_listen_again_songs(pool: &DbPool, user_id: u32) -> Result<Vec<SongInfo>, ()> {
+    fetch_listen_history_songs(pool, user_id).await
 }
 
 #[get("/listen_again/{user_id}")]
-async fn get_listen_again(user_id: web::Path<u32>) -> HttpResponse {
+async fn get_listen_again(pool: web::Data<DbPool>, user_id: web::Path<u32>) -> HttpResponse {
     let user_id = user_id.into_inner();
-    match fetch_listen_again_songs(user_id).await {
+    match fetch_listen_again_songs(&pool, user_id).await {
         Ok(songs) => HttpResponse::Ok().json(songs),
         Err(_) => HttpResponse::InternalServerError().json("Failed to fetch listen again songs"),
     }
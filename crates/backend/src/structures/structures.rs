@@ -4,6 +4,10 @@ use serde::{Deserialize, Serialize};
 pub struct Album {
     pub id: String,
     pub name: String,
+    #[serde(default)]
+    pub edition: Option<String>,
+    #[serde(default)]
+    pub album_type: AlbumType,
     pub cover_url: String,
     pub songs: Vec<Song>,
     pub first_release_date: String,
@@ -15,6 +19,17 @@ pub struct Album {
     pub contributing_artists_ids: Vec<String>,
     pub release_album: Option<ReleaseAlbum>,
     pub release_group_album: Option<ReleaseGroupAlbum>,
+    /// Genres read from the songs' tags, alongside those MusicBrainz has for the release
+    #[serde(default)]
+    pub genres: Vec<String>,
+    /// Indexed in offline mode, so only local metadata has been looked at yet
+    #[serde(default)]
+    pub pending_enrichment: bool,
+    /// The release the songs' tags name, looked up directly instead of searching by name
+    #[serde(default)]
+    pub musicbrainz_release_id: Option<String>,
+    #[serde(default)]
+    pub musicbrainz_release_group_id: Option<String>,
 }
 
 impl Default for Album {
@@ -22,6 +37,8 @@ impl Default for Album {
         Album {
             id: String::new(),
             name: String::new(),
+            edition: None,
+            album_type: AlbumType::Album,
             cover_url: String::new(),
             songs: Vec::new(),
             first_release_date: String::new(),
@@ -33,6 +50,36 @@ impl Default for Album {
             description: String::new(),
             release_album: None,
             release_group_album: None,
+            genres: Vec::new(),
+            pending_enrichment: false,
+            musicbrainz_release_id: None,
+            musicbrainz_release_group_id: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AlbumType {
+    #[default]
+    Album,
+    Compilation,
+}
+
+impl AlbumType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlbumType::Album => "album",
+            AlbumType::Compilation => "compilation",
+        }
+    }
+}
+
+impl From<&str> for AlbumType {
+    fn from(value: &str) -> Self {
+        match value {
+            "compilation" => AlbumType::Compilation,
+            _ => AlbumType::Album,
         }
     }
 }
@@ -47,6 +94,12 @@ pub struct Artist {
     pub featured_on_album_ids: Vec<String>,
     pub description: String,
     pub tadb_music_videos: Option<String>,
+    /// Indexed in offline mode, so only local metadata has been looked at yet
+    #[serde(default)]
+    pub pending_enrichment: bool,
+    /// From the songs' artist tags
+    #[serde(default)]
+    pub musicbrainz_id: Option<String>,
 }
 
 impl Default for Artist {
@@ -60,10 +113,16 @@ impl Default for Artist {
             featured_on_album_ids: vec![String::new()],
             description: String::new(),
             tadb_music_videos: None,
+            pending_enrichment: false,
+            musicbrainz_id: None,
         }
     }
 }
 
+fn default_disc() -> u16 {
+    1
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Song {
     pub id: String,
@@ -72,9 +131,33 @@ pub struct Song {
     pub contributing_artists: Vec<String>,
     pub contributing_artist_ids: Vec<String>,
     pub track_number: u16,
+    #[serde(default = "default_disc")]
+    pub disc_number: u16,
+    #[serde(default = "default_disc")]
+    pub disc_total: u16,
     pub path: String,
     pub duration: f64,
+    #[serde(default)]
+    pub audio: Option<AudioProperties>,
     pub music_video: Option<MusicVideo>,
+    /// The MusicBrainz recording and release track the file is tagged with
+    #[serde(default)]
+    pub musicbrainz_recording_id: Option<String>,
+    #[serde(default)]
+    pub musicbrainz_track_id: Option<String>,
+}
+
+/// Technical properties of a song's file, read when it is scanned.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct AudioProperties {
+    pub codec: String,
+    pub container: String,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u8>,
+    pub channels: Option<u8>,
+    /// Average audio bitrate in kbps
+    pub bitrate: Option<u32>,
+    pub file_size: u64,
 }
 
 impl Default for Song {
@@ -86,9 +169,14 @@ impl Default for Song {
             contributing_artists: Vec::new(),
             contributing_artist_ids: vec![String::new()],
             track_number: 0,
+            disc_number: default_disc(),
+            disc_total: default_disc(),
             path: String::new(),
             duration: 0.0,
+            audio: None,
             music_video: None,
+            musicbrainz_recording_id: None,
+            musicbrainz_track_id: None,
         }
     }
 }
@@ -0,0 +1,208 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use chrono::{DateTime, Days, Local, NaiveDate};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use crate::structures::structures::Artist;
+use crate::utils::config::{get_backup_config_path, get_config_path};
+
+const BACKUP_SUFFIX: &str = " (Backup).json";
+
+/// How many catalog backups are kept. Stored in `backups.json` and editable by
+/// admins through `/library/backups/retention`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct BackupRetention {
+    /// The newest backups, which are always kept
+    pub keep_last: usize,
+    /// For this many days, including today, the newest backup of each day is kept too
+    pub keep_daily_days: u64,
+}
+
+impl Default for BackupRetention {
+    fn default() -> Self {
+        BackupRetention {
+            keep_last: 10,
+            keep_daily_days: 14,
+        }
+    }
+}
+
+impl BackupRetention {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.keep_last == 0 {
+            return Err("keep_last must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref BACKUP_RETENTION: RwLock<BackupRetention> = RwLock::new(BackupRetention::default());
+}
+
+pub fn backup_retention() -> BackupRetention {
+    BACKUP_RETENTION.read().unwrap().clone()
+}
+
+/// Loads the saved retention policy into memory, keeping the defaults if none was saved.
+pub fn load_backup_retention() -> Result<(), Box<dyn Error>> {
+    let path = get_backup_config_path();
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let retention: BackupRetention = serde_json::from_str(&fs::read_to_string(path)?)?;
+    *BACKUP_RETENTION.write().unwrap() = retention;
+    Ok(())
+}
+
+pub fn save_backup_retention(retention: BackupRetention) -> Result<(), Box<dyn Error>> {
+    fs::write(get_backup_config_path(), serde_json::to_string_pretty(&retention)?)?;
+    *BACKUP_RETENTION.write().unwrap() = retention;
+    Ok(())
+}
+
+/// A copy of the catalog saved as `music_<id> (Backup).json` next to the config.
+#[derive(Serialize, Clone, Debug)]
+pub struct Backup {
+    pub id: u32,
+    pub file_name: String,
+    pub created_at: DateTime<Local>,
+    pub size: u64,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+fn backup_location() -> (PathBuf, String) {
+    let config_path = get_config_path();
+    let config_dir = config_path.parent().unwrap().to_path_buf();
+    let config_filename = config_path.file_stem().unwrap().to_string_lossy().to_string();
+    (config_dir, config_filename)
+}
+
+fn backup_id(file_name: &str, config_filename: &str) -> Option<u32> {
+    file_name
+        .strip_prefix(config_filename)?
+        .strip_prefix('_')?
+        .strip_suffix(BACKUP_SUFFIX)?
+        .parse()
+        .ok()
+}
+
+fn read_backup_entry(path: &Path, id: u32) -> io::Result<Backup> {
+    let metadata = fs::metadata(path)?;
+    Ok(Backup {
+        id,
+        file_name: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        created_at: metadata.modified()?.into(),
+        size: metadata.len(),
+        path: path.to_path_buf(),
+    })
+}
+
+/// Every catalog backup, newest first.
+pub fn list_backups() -> io::Result<Vec<Backup>> {
+    let (config_dir, config_filename) = backup_location();
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(&config_dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if let Some(id) = backup_id(&file_name, &config_filename) {
+            backups.push(read_backup_entry(&entry.path(), id)?);
+        }
+    }
+
+    backups.sort_by_key(|backup| Reverse(backup.id));
+    Ok(backups)
+}
+
+pub fn find_backup(id: u32) -> io::Result<Option<Backup>> {
+    Ok(list_backups()?.into_iter().find(|backup| backup.id == id))
+}
+
+pub fn read_backup(backup: &Backup) -> Result<Vec<Artist>, Box<dyn Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(&backup.path)?)?)
+}
+
+/// Writes to a temporary file in the same directory and renames it over `path`,
+/// so a crash leaves either the old file or the complete new one.
+pub async fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name));
+
+    let result = async {
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&temp_path, path).await
+    }.await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+/// Saves the catalog as a new backup, then prunes old ones by the retention policy.
+pub async fn backup_catalog(contents: &str) -> io::Result<Backup> {
+    let (config_dir, config_filename) = backup_location();
+
+    // Numbered after the newest backup rather than the first free number, so
+    // ids keep increasing once older backups are pruned
+    let id = list_backups()?.first().map_or(1, |newest| newest.id + 1);
+    let path = config_dir.join(format!("{}_{}{}", config_filename, id, BACKUP_SUFFIX));
+    write_atomically(&path, contents.as_bytes()).await?;
+
+    if let Err(e) = prune_backups(&backup_retention()) {
+        warn!("Failed to prune catalog backups: {}", e);
+    }
+
+    read_backup_entry(&path, id)
+}
+
+/// The backups the policy does not keep. `backups` must be sorted newest first.
+fn expired_backups<'a>(backups: &'a [Backup], retention: &BackupRetention, today: NaiveDate) -> Vec<&'a Backup> {
+    let oldest_daily = today.checked_sub_days(Days::new(retention.keep_daily_days)).unwrap_or(NaiveDate::MIN);
+
+    let mut days_kept: HashSet<NaiveDate> = HashSet::new();
+    backups
+        .iter()
+        .enumerate()
+        .filter(|(position, backup)| {
+            let day = backup.created_at.date_naive();
+            let newest_of_day = day > oldest_daily && days_kept.insert(day);
+            *position >= retention.keep_last && !newest_of_day
+        })
+        .map(|(_, backup)| backup)
+        .collect()
+}
+
+/// Deletes the backups the policy does not keep and returns them.
+pub fn prune_backups(retention: &BackupRetention) -> io::Result<Vec<Backup>> {
+    let backups = list_backups()?;
+    let expired: Vec<Backup> = expired_backups(&backups, retention, Local::now().date_naive())
+        .into_iter()
+        .cloned()
+        .collect();
+
+    for backup in &expired {
+        fs::remove_file(&backup.path)?;
+    }
+
+    if !expired.is_empty() {
+        info!("Pruned {} catalog backups", expired.len());
+    }
+    Ok(expired)
+}
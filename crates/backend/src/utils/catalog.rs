@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::info;
+
+use crate::structures::structures::{Album, AlbumType, Artist, AudioProperties, Song};
+use crate::utils::config::get_config_path;
+use crate::utils::database::database::establish_connection;
+use crate::utils::database::models::{AlbumRelease, CatalogAlbum, CatalogArtist, CatalogSong};
+use crate::utils::database::schema::{
+    _playlist_to_song, album, album_release, artist, catalog_meta, favorite_song, listen_history_item, song,
+};
+
+const CHUNK_SIZE: usize = 500;
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "[]".to_string())
+}
+
+fn from_json<T: DeserializeOwned + Default>(value: &str) -> T {
+    serde_json::from_str(value).unwrap_or_default()
+}
+
+fn to_catalog_artist(artist: &Artist, position: usize) -> CatalogArtist {
+    CatalogArtist {
+        id: artist.id.clone(),
+        name: artist.name.clone(),
+        icon_url: artist.icon_url.clone(),
+        followers: artist.followers as i64,
+        description: artist.description.clone(),
+        tadb_music_videos: artist.tadb_music_videos.clone(),
+        featured_on_album_ids: to_json(&artist.featured_on_album_ids),
+        position: position as i32,
+        pending_enrichment: artist.pending_enrichment,
+        musicbrainz_id: artist.musicbrainz_id.clone(),
+    }
+}
+
+fn to_catalog_album(album: &Album, artist_id: &str, position: usize) -> CatalogAlbum {
+    CatalogAlbum {
+        id: album.id.clone(),
+        artist_id: artist_id.to_string(),
+        name: album.name.clone(),
+        cover_url: album.cover_url.clone(),
+        first_release_date: album.first_release_date.clone(),
+        musicbrainz_id: album.musicbrainz_id.clone(),
+        wikidata_id: album.wikidata_id.clone(),
+        primary_type: album.primary_type.clone(),
+        description: album.description.clone(),
+        contributing_artists: to_json(&album.contributing_artists),
+        contributing_artists_ids: to_json(&album.contributing_artists_ids),
+        position: position as i32,
+        edition: album.edition.clone(),
+        album_type: album.album_type.as_str().to_string(),
+        pending_enrichment: album.pending_enrichment,
+        genres: to_json(&album.genres),
+        musicbrainz_release_id: album.musicbrainz_release_id.clone(),
+        musicbrainz_release_group_id: album.musicbrainz_release_group_id.clone(),
+    }
+}
+
+fn to_album_release(album: &Album) -> Option<AlbumRelease> {
+    if album.release_album.is_none() && album.release_group_album.is_none() {
+        return None;
+    }
+
+    Some(AlbumRelease {
+        album_id: album.id.clone(),
+        release_album: album.release_album.as_ref().map(to_json),
+        release_group_album: album.release_group_album.as_ref().map(to_json),
+    })
+}
+
+fn to_catalog_song(song: &Song, album_id: &str, position: usize) -> CatalogSong {
+    let audio = song.audio.as_ref();
+
+    CatalogSong {
+        id: song.id.clone(),
+        album_id: Some(album_id.to_string()),
+        name: song.name.clone(),
+        artist: song.artist.clone(),
+        contributing_artists: to_json(&song.contributing_artists),
+        contributing_artist_ids: to_json(&song.contributing_artist_ids),
+        track_number: song.track_number as i32,
+        path: song.path.clone(),
+        duration: song.duration,
+        music_video: song.music_video.as_ref().map(to_json),
+        position: position as i32,
+        disc_number: song.disc_number as i32,
+        disc_total: song.disc_total as i32,
+        codec: audio.map(|audio| audio.codec.clone()),
+        container: audio.map(|audio| audio.container.clone()),
+        sample_rate: audio.and_then(|audio| audio.sample_rate).map(|value| value as i32),
+        bit_depth: audio.and_then(|audio| audio.bit_depth).map(|value| value as i32),
+        channels: audio.and_then(|audio| audio.channels).map(|value| value as i32),
+        bitrate: audio.and_then(|audio| audio.bitrate).map(|value| value as i32),
+        file_size: audio.map(|audio| audio.file_size as i64),
+        musicbrainz_recording_id: song.musicbrainz_recording_id.clone(),
+        musicbrainz_track_id: song.musicbrainz_track_id.clone(),
+    }
+}
+
+fn into_artist(row: CatalogArtist) -> Artist {
+    Artist {
+        id: row.id,
+        name: row.name,
+        icon_url: row.icon_url,
+        followers: row.followers as u64,
+        albums: Vec::new(),
+        featured_on_album_ids: from_json(&row.featured_on_album_ids),
+        description: row.description,
+        tadb_music_videos: row.tadb_music_videos,
+        pending_enrichment: row.pending_enrichment,
+        musicbrainz_id: row.musicbrainz_id,
+    }
+}
+
+fn into_album(row: CatalogAlbum, release: Option<AlbumRelease>) -> Album {
+    let (release_album, release_group_album) = match release {
+        Some(release) => (
+            release.release_album.and_then(|json| serde_json::from_str(&json).ok()),
+            release.release_group_album.and_then(|json| serde_json::from_str(&json).ok()),
+        ),
+        None => (None, None),
+    };
+
+    Album {
+        id: row.id,
+        name: row.name,
+        edition: row.edition,
+        album_type: AlbumType::from(row.album_type.as_str()),
+        cover_url: row.cover_url,
+        songs: Vec::new(),
+        first_release_date: row.first_release_date,
+        musicbrainz_id: row.musicbrainz_id,
+        wikidata_id: row.wikidata_id,
+        primary_type: row.primary_type,
+        description: row.description,
+        contributing_artists: from_json(&row.contributing_artists),
+        contributing_artists_ids: from_json(&row.contributing_artists_ids),
+        release_album,
+        release_group_album,
+        genres: from_json(&row.genres),
+        pending_enrichment: row.pending_enrichment,
+        musicbrainz_release_id: row.musicbrainz_release_id,
+        musicbrainz_release_group_id: row.musicbrainz_release_group_id,
+    }
+}
+
+fn into_song(row: CatalogSong) -> Song {
+    // Songs scanned before audio properties were recorded have no codec
+    let audio = row.codec.map(|codec| AudioProperties {
+        codec,
+        container: row.container.unwrap_or_default(),
+        sample_rate: row.sample_rate.map(|value| value as u32),
+        bit_depth: row.bit_depth.map(|value| value as u8),
+        channels: row.channels.map(|value| value as u8),
+        bitrate: row.bitrate.map(|value| value as u32),
+        file_size: row.file_size.unwrap_or(0) as u64,
+    });
+
+    Song {
+        id: row.id,
+        name: row.name,
+        artist: row.artist,
+        contributing_artists: from_json(&row.contributing_artists),
+        contributing_artist_ids: from_json(&row.contributing_artist_ids),
+        track_number: row.track_number as u16,
+        disc_number: row.disc_number as u16,
+        disc_total: row.disc_total as u16,
+        path: row.path,
+        duration: row.duration,
+        audio,
+        music_video: row.music_video.and_then(|json| serde_json::from_str(&json).ok()),
+        musicbrainz_recording_id: row.musicbrainz_recording_id,
+        musicbrainz_track_id: row.musicbrainz_track_id,
+    }
+}
+
+fn assemble_albums(
+    connection: &mut SqliteConnection,
+    album_rows: Vec<CatalogAlbum>,
+) -> QueryResult<Vec<(String, Album)>> {
+    let album_ids: Vec<String> = album_rows.iter().map(|row| row.id.clone()).collect();
+
+    let mut releases: HashMap<String, AlbumRelease> = HashMap::new();
+    let mut songs: HashMap<String, Vec<Song>> = HashMap::new();
+
+    for chunk in album_ids.chunks(CHUNK_SIZE) {
+        for release in album_release::table
+            .filter(album_release::album_id.eq_any(chunk))
+            .select(AlbumRelease::as_select())
+            .load::<AlbumRelease>(connection)?
+        {
+            releases.insert(release.album_id.clone(), release);
+        }
+
+        for row in song::table
+            .filter(song::album_id.eq_any(chunk))
+            .order((song::album_id, song::position))
+            .select(CatalogSong::as_select())
+            .load::<CatalogSong>(connection)?
+        {
+            let album_id = row.album_id.clone().unwrap_or_default();
+            songs.entry(album_id).or_default().push(into_song(row));
+        }
+    }
+
+    Ok(album_rows
+        .into_iter()
+        .map(|row| {
+            let artist_id = row.artist_id.clone();
+            let release = releases.remove(&row.id);
+            let album_songs = songs.remove(&row.id).unwrap_or_default();
+            let mut album = into_album(row, release);
+            album.songs = album_songs;
+            (artist_id, album)
+        })
+        .collect())
+}
+
+pub fn load_catalog(connection: &mut SqliteConnection) -> QueryResult<Vec<Artist>> {
+    let artist_rows = artist::table
+        .order(artist::position)
+        .select(CatalogArtist::as_select())
+        .load::<CatalogArtist>(connection)?;
+
+    let album_rows = album::table
+        .order((album::artist_id, album::position))
+        .select(CatalogAlbum::as_select())
+        .load::<CatalogAlbum>(connection)?;
+
+    let mut albums: HashMap<String, Vec<Album>> = HashMap::new();
+    for (artist_id, album) in assemble_albums(connection, album_rows)? {
+        albums.entry(artist_id).or_default().push(album);
+    }
+
+    Ok(artist_rows
+        .into_iter()
+        .map(|row| {
+            let mut artist = into_artist(row);
+            artist.albums = albums.remove(&artist.id).unwrap_or_default();
+            artist
+        })
+        .collect())
+}
+
+/// The rows a catalog is stored as, keyed by id.
+#[derive(Default)]
+struct CatalogRows {
+    artists: HashMap<String, CatalogArtist>,
+    albums: HashMap<String, CatalogAlbum>,
+    releases: HashMap<String, AlbumRelease>,
+    songs: HashMap<String, CatalogSong>,
+}
+
+impl CatalogRows {
+    fn new(library: &[Artist]) -> Self {
+        let mut rows = CatalogRows::default();
+
+        for (artist_position, artist) in library.iter().enumerate() {
+            rows.artists.insert(artist.id.clone(), to_catalog_artist(artist, artist_position));
+
+            for (album_position, album) in artist.albums.iter().enumerate() {
+                rows.albums.insert(album.id.clone(), to_catalog_album(album, &artist.id, album_position));
+                if let Some(release) = to_album_release(album) {
+                    rows.releases.insert(album.id.clone(), release);
+                }
+
+                for (song_position, song) in album.songs.iter().enumerate() {
+                    rows.songs.insert(song.id.clone(), to_catalog_song(song, &album.id, song_position));
+                }
+            }
+        }
+
+        rows
+    }
+}
+
+/// Rows that are new or differ from the stored ones.
+fn changed_rows<T: PartialEq + Clone>(previous: &HashMap<String, T>, current: &HashMap<String, T>) -> Vec<T> {
+    current
+        .iter()
+        .filter(|(id, row)| previous.get(*id) != Some(*row))
+        .map(|(_, row)| row.clone())
+        .collect()
+}
+
+/// Ids of stored rows that are gone from the catalog.
+fn removed_ids<T>(previous: &HashMap<String, T>, current: &HashMap<String, T>) -> Vec<String> {
+    previous.keys().filter(|id| !current.contains_key(*id)).cloned().collect()
+}
+
+/// The rows that differ between the stored catalog and a new version of it.
+pub struct CatalogChanges {
+    artists: Vec<CatalogArtist>,
+    albums: Vec<CatalogAlbum>,
+    releases: Vec<AlbumRelease>,
+    songs: Vec<CatalogSong>,
+    removed_artists: Vec<String>,
+    removed_albums: Vec<String>,
+    removed_releases: Vec<String>,
+    removed_songs: Vec<String>,
+}
+
+impl CatalogChanges {
+    /// `previous` must be the catalog as it is stored.
+    pub fn new(previous: &[Artist], library: &[Artist]) -> Self {
+        let previous = CatalogRows::new(previous);
+        let current = CatalogRows::new(library);
+
+        CatalogChanges {
+            artists: changed_rows(&previous.artists, &current.artists),
+            albums: changed_rows(&previous.albums, &current.albums),
+            releases: changed_rows(&previous.releases, &current.releases),
+            songs: changed_rows(&previous.songs, &current.songs),
+            removed_artists: removed_ids(&previous.artists, &current.artists),
+            removed_albums: removed_ids(&previous.albums, &current.albums),
+            removed_releases: removed_ids(&previous.releases, &current.releases),
+            removed_songs: removed_ids(&previous.songs, &current.songs),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.artists.is_empty()
+            && self.albums.is_empty()
+            && self.releases.is_empty()
+            && self.songs.is_empty()
+            && self.removed_artists.is_empty()
+            && self.removed_albums.is_empty()
+            && self.removed_releases.is_empty()
+            && self.removed_songs.is_empty()
+    }
+}
+
+/// Writes the changed rows in a single transaction. Songs dropped from the
+/// catalog are detached from their album rather than deleted, so playlists
+/// and history keep pointing at them.
+pub fn save_catalog(connection: &mut SqliteConnection, changes: &CatalogChanges) -> QueryResult<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    connection.transaction(|connection| {
+        // Parents go in before their children and come out after them, so
+        // songs moving to another album never point at a deleted one
+        for row in &changes.artists {
+            diesel::insert_into(artist::table)
+                .values(row)
+                .on_conflict(artist::id)
+                .do_update()
+                .set(row)
+                .execute(connection)?;
+        }
+
+        for row in &changes.albums {
+            diesel::insert_into(album::table)
+                .values(row)
+                .on_conflict(album::id)
+                .do_update()
+                .set(row)
+                .execute(connection)?;
+        }
+
+        for chunk in changes.releases.chunks(CHUNK_SIZE) {
+            diesel::replace_into(album_release::table)
+                .values(chunk)
+                .execute(connection)?;
+        }
+
+        for row in &changes.songs {
+            diesel::insert_into(song::table)
+                .values(row)
+                .on_conflict(song::id)
+                .do_update()
+                .set(row)
+                .execute(connection)?;
+        }
+
+        for chunk in changes.removed_songs.chunks(CHUNK_SIZE) {
+            diesel::update(song::table.filter(song::id.eq_any(chunk)))
+                .set(song::album_id.eq(None::<String>))
+                .execute(connection)?;
+        }
+
+        for chunk in changes.removed_releases.chunks(CHUNK_SIZE) {
+            diesel::delete(album_release::table.filter(album_release::album_id.eq_any(chunk))).execute(connection)?;
+        }
+
+        for chunk in changes.removed_albums.chunks(CHUNK_SIZE) {
+            diesel::delete(album::table.filter(album::id.eq_any(chunk))).execute(connection)?;
+        }
+
+        for chunk in changes.removed_artists.chunks(CHUNK_SIZE) {
+            diesel::delete(artist::table.filter(artist::id.eq_any(chunk))).execute(connection)?;
+        }
+
+        Ok(())
+    })
+}
+
+pub fn catalog_is_empty(connection: &mut SqliteConnection) -> QueryResult<bool> {
+    let artists: i64 = artist::table.count().get_result(connection)?;
+    Ok(artists == 0)
+}
+
+/// How many user rows point at a set of songs, reported when songs are purged
+/// from the catalog. The rows themselves are kept.
+#[derive(Serialize, Debug, Default)]
+pub struct SongReferences {
+    pub playlist_entries: i64,
+    pub favorites: i64,
+    pub history: i64,
+}
+
+pub fn count_song_references(connection: &mut SqliteConnection, song_ids: &[String]) -> QueryResult<SongReferences> {
+    let mut references = SongReferences::default();
+
+    for chunk in song_ids.chunks(CHUNK_SIZE) {
+        references.playlist_entries += _playlist_to_song::table
+            .filter(_playlist_to_song::b.eq_any(chunk))
+            .count()
+            .get_result::<i64>(connection)?;
+        references.favorites += favorite_song::table
+            .filter(favorite_song::song_id.eq_any(chunk))
+            .count()
+            .get_result::<i64>(connection)?;
+        references.history += listen_history_item::table
+            .filter(listen_history_item::song_id.eq_any(chunk))
+            .count()
+            .get_result::<i64>(connection)?;
+    }
+
+    Ok(references)
+}
+
+pub fn get_catalog_meta(connection: &mut SqliteConnection, key: &str) -> QueryResult<Option<String>> {
+    catalog_meta::table
+        .filter(catalog_meta::key.eq(key))
+        .select(catalog_meta::value)
+        .first::<String>(connection)
+        .optional()
+}
+
+pub fn set_catalog_meta(connection: &mut SqliteConnection, key: &str, value: &str) -> QueryResult<()> {
+    diesel::replace_into(catalog_meta::table)
+        .values((catalog_meta::key.eq(key), catalog_meta::value.eq(value)))
+        .execute(connection)?;
+    Ok(())
+}
+
+pub fn import_catalog_from_json() -> Result<(), Box<dyn Error>> {
+    let config_path = get_config_path();
+    if !config_path.exists() {
+        return Ok(());
+    }
+
+    let mut connection = establish_connection().get()?;
+    if !catalog_is_empty(&mut connection)? {
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string(&config_path)?;
+    let library: Vec<Artist> = serde_json::from_str(&contents)?;
+    save_catalog(&mut connection, &CatalogChanges::new(&[], &library))?;
+
+    let imported_path = config_path.with_file_name("music (Imported).json");
+    fs::rename(&config_path, &imported_path)?;
+
+    info!("Imported {} artists from {} into the database", library.len(), config_path.display());
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::structures::structures::{Album, Artist, Song};
+
+/// Lookups by id into the cached catalog, built once each time the cache is
+/// refreshed. Entities are stored as positions in the catalog, so a song leads
+/// back to its album and artist without copying anything.
+pub struct CatalogIndex {
+    library: Arc<Vec<Artist>>,
+    artists: HashMap<String, usize>,
+    albums: HashMap<String, (usize, usize)>,
+    songs: HashMap<String, (usize, usize, usize)>,
+}
+
+impl CatalogIndex {
+    pub fn new(library: Arc<Vec<Artist>>) -> Self {
+        let mut artists = HashMap::with_capacity(library.len());
+        let mut albums = HashMap::new();
+        let mut songs = HashMap::new();
+
+        // The first occurrence wins, as it did when lookups walked the catalog
+        for (artist_position, artist) in library.iter().enumerate() {
+            artists.entry(artist.id.clone()).or_insert(artist_position);
+
+            for (album_position, album) in artist.albums.iter().enumerate() {
+                albums.entry(album.id.clone()).or_insert((artist_position, album_position));
+
+                for (song_position, song) in album.songs.iter().enumerate() {
+                    songs.entry(song.id.clone()).or_insert((artist_position, album_position, song_position));
+                }
+            }
+        }
+
+        CatalogIndex { library, artists, albums, songs }
+    }
+
+    pub fn library(&self) -> &Arc<Vec<Artist>> {
+        &self.library
+    }
+
+    pub fn artist(&self, artist_id: &str) -> Option<&Artist> {
+        self.artists.get(artist_id).map(|&artist| &self.library[artist])
+    }
+
+    /// The album and the artist it belongs to.
+    pub fn album(&self, album_id: &str) -> Option<(&Album, &Artist)> {
+        self.albums.get(album_id).map(|&(artist, album)| {
+            let artist = &self.library[artist];
+            (&artist.albums[album], artist)
+        })
+    }
+
+    /// The song with the album and artist it belongs to.
+    pub fn song(&self, song_id: &str) -> Option<(&Song, &Album, &Artist)> {
+        self.songs.get(song_id).map(|&(artist, album, song)| {
+            let artist = &self.library[artist];
+            let album = &artist.albums[album];
+            (&album.songs[song], album, artist)
+        })
+    }
+}
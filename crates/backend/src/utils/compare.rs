@@ -1,107 +1,878 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
 
-use rayon::iter::IntoParallelRefIterator;
-use rayon::prelude::*;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::structures::structures::{Album, Artist, Song};
-use crate::utils::config::get_config;
-
-fn flatten_data(library: &[Artist]) -> (HashMap<String, Song>, HashMap<String, ModifiedAlbum>, HashMap<String, Artist>) {
-  let flat_songs = Arc::new(Mutex::new(HashMap::new()));
-  let flat_albums = Arc::new(Mutex::new(HashMap::new()));
-  let flat_artists = Arc::new(Mutex::new(HashMap::new()));
-
-  library.par_iter().for_each(|artist| {
-      let mut artists = flat_artists.lock().unwrap();
-      artists.insert(artist.id.clone(), artist.clone());
-      drop(artists);
-      for album in &artist.albums {
-          let mut albums = flat_albums.lock().unwrap();
-          albums.insert(album.id.clone(), ModifiedAlbum{
-              album: album.clone(),
-              artist_name: artist.name.clone(),
-              artist_id: artist.id.clone()
-          });
-          drop(albums);
-          for song in &album.songs {
-              let mut songs = flat_songs.lock().unwrap();
-              songs.insert(song.id.clone(), song.clone());
-              drop(songs);
-          }
+use crate::utils::database::database::{establish_connection, with_connection};
+use crate::utils::database::models::{NewScanError, NewScanReport, ScanErrorRow, ScanReportRow};
+
+#[derive(Clone)]
+pub struct ModifiedAlbum {
+  pub album: Album,
+  pub artist_name: String,
+  pub artist_id: String
+}
+
+const MOVE_DURATION_TOLERANCE: f64 = 1.0;
+const CHUNK_SIZE: usize = 500;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SongChange {
+  pub id: String,
+  pub name: String,
+  pub artist: String,
+  pub path: String,
+  pub previous_path: Option<String>,
+}
+
+impl SongChange {
+  fn new(song: &Song, previous_path: Option<&str>) -> Self {
+    SongChange {
+      id: song.id.clone(),
+      name: song.name.clone(),
+      artist: song.artist.clone(),
+      path: song.path.clone(),
+      previous_path: previous_path.map(|p| p.to_string()),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanErrorKind {
+  /// The tags or audio properties couldn't be read; the song is indexed from its file name.
+  UnreadableTags,
+  /// The file was read but reports no duration.
+  ZeroDuration,
+  /// An embedded cover couldn't be written to the cover art directory.
+  CoverArt,
+  /// The path isn't valid UTF-8, so the file is skipped.
+  InvalidPath,
+}
+
+impl ScanErrorKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      ScanErrorKind::UnreadableTags => "unreadable_tags",
+      ScanErrorKind::ZeroDuration => "zero_duration",
+      ScanErrorKind::CoverArt => "cover_art",
+      ScanErrorKind::InvalidPath => "invalid_path",
+    }
+  }
+
+  fn parse(kind: &str) -> Option<ScanErrorKind> {
+    [ScanErrorKind::UnreadableTags, ScanErrorKind::ZeroDuration, ScanErrorKind::CoverArt, ScanErrorKind::InvalidPath]
+      .into_iter()
+      .find(|candidate| candidate.as_str() == kind)
+  }
+}
+
+/// A problem with a single file. Scans record these and carry on.
+#[derive(Serialize, Clone, Debug)]
+pub struct ScanFileError {
+  pub path: String,
+  pub kind: ScanErrorKind,
+  pub message: String,
+}
+
+impl ScanFileError {
+  pub fn new(path: &Path, kind: ScanErrorKind, message: impl Into<String>) -> Self {
+    ScanFileError {
+      path: path.to_string_lossy().to_string(),
+      kind,
+      message: message.into(),
+    }
+  }
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct ScanReport {
+  pub library_path: String,
+  pub added: Vec<SongChange>,
+  pub removed: Vec<SongChange>,
+  pub moved: Vec<SongChange>,
+  pub changed: Vec<SongChange>,
+  pub errors: Vec<ScanFileError>,
+  /// Artists and albums the catalog didn't have yet, whose metadata still needs looking up
+  #[serde(skip)]
+  pub new_artist_ids: Vec<String>,
+  #[serde(skip)]
+  pub new_album_ids: Vec<String>,
+}
+
+/// The size of each part of a scan report, for results that shouldn't carry
+/// every song.
+#[derive(Serialize, Clone)]
+pub struct ScanTotals {
+  pub library_path: String,
+  pub added: usize,
+  pub removed: usize,
+  pub moved: usize,
+  pub changed: usize,
+  pub errors: usize,
+}
+
+impl ScanReport {
+  pub fn totals(&self) -> ScanTotals {
+    ScanTotals {
+      library_path: self.library_path.clone(),
+      added: self.added.len(),
+      removed: self.removed.len(),
+      moved: self.moved.len(),
+      changed: self.changed.len(),
+      errors: self.errors.len(),
+    }
+  }
+
+  /// The new artists and albums in the scanned library, each album with its artist.
+  pub fn new_entries(&self, new_library: &[Artist]) -> (Vec<Artist>, Vec<ModifiedAlbum>) {
+    let new_artists = new_library
+      .iter()
+      .filter(|artist| self.new_artist_ids.contains(&artist.id))
+      .cloned()
+      .collect();
+    let new_albums = new_library
+      .iter()
+      .flat_map(|artist| artist.albums.iter().map(move |album| (artist, album)))
+      .filter(|(_, album)| self.new_album_ids.contains(&album.id))
+      .map(|(artist, album)| ModifiedAlbum {
+        album: album.clone(),
+        artist_name: artist.name.clone(),
+        artist_id: artist.id.clone(),
+      })
+      .collect();
+
+    (new_artists, new_albums)
+  }
+
+  pub fn summary(&self) -> String {
+    format!(
+      "Scan of {}: {} added, {} removed, {} moved, {} changed, {} errors",
+      self.library_path,
+      self.added.len(),
+      self.removed.len(),
+      self.moved.len(),
+      self.changed.len(),
+      self.errors.len()
+    )
+  }
+}
+
+fn new_scan_error(library_path: &str, error: &ScanFileError) -> NewScanError {
+  NewScanError {
+    library_path: library_path.to_string(),
+    path: error.path.clone(),
+    kind: error.kind.as_str().to_string(),
+    message: error.message.clone(),
+  }
+}
+
+impl TryFrom<ScanErrorRow> for ScanFileError {
+  type Error = Box<dyn Error>;
+
+  fn try_from(row: ScanErrorRow) -> Result<Self, Self::Error> {
+    Ok(ScanFileError {
+      kind: ScanErrorKind::parse(&row.kind).ok_or_else(|| format!("Unknown scan error kind {}", row.kind))?,
+      path: row.path,
+      message: row.message,
+    })
+  }
+}
+
+/// Saves a library's scan report in place of its previous one.
+pub async fn store_scan_report(report: ScanReport) -> Result<(), Box<dyn Error>> {
+  use crate::utils::database::schema::{scan_error, scan_report};
+
+  let new_report = NewScanReport {
+    library_path: report.library_path.clone(),
+    added: serde_json::to_string(&report.added)?,
+    removed: serde_json::to_string(&report.removed)?,
+    moved: serde_json::to_string(&report.moved)?,
+    changed: serde_json::to_string(&report.changed)?,
+  };
+  let errors: Vec<NewScanError> = report.errors.iter().map(|error| new_scan_error(&report.library_path, error)).collect();
+
+  with_connection(&establish_connection(), move |connection| {
+    connection.transaction(|connection| {
+      // Also deletes its errors
+      diesel::delete(scan_report::table.filter(scan_report::library_path.eq(&new_report.library_path)))
+        .execute(connection)?;
+      diesel::insert_into(scan_report::table).values(&new_report).execute(connection)?;
+
+      for chunk in errors.chunks(CHUNK_SIZE) {
+        diesel::insert_into(scan_error::table).values(chunk).execute(connection)?;
+      }
+      Ok(())
+    })
+  })
+  .await?;
+
+  Ok(())
+}
+
+/// Replaces the errors recorded for files under `changed_paths` in a library's
+/// report, for updates that only look at part of the library.
+pub async fn update_report_errors(library_path: &str, changed_paths: &[PathBuf], errors: Vec<ScanFileError>) -> Result<(), Box<dyn Error>> {
+  use crate::utils::database::schema::{scan_error, scan_report};
+
+  let library_path = library_path.to_string();
+  let changed_paths = changed_paths.to_vec();
+  let errors: Vec<NewScanError> = errors.iter().map(|error| new_scan_error(&library_path, error)).collect();
+
+  with_connection(&establish_connection(), move |connection| {
+    connection.transaction(|connection| {
+      diesel::insert_or_ignore_into(scan_report::table)
+        .values(NewScanReport {
+          library_path: library_path.clone(),
+          added: "[]".to_string(),
+          removed: "[]".to_string(),
+          moved: "[]".to_string(),
+          changed: "[]".to_string(),
+        })
+        .execute(connection)?;
+
+      let stale: Vec<i32> = scan_error::table
+        .filter(scan_error::library_path.eq(&library_path))
+        .select((scan_error::id, scan_error::path))
+        .load::<(i32, String)>(connection)?
+        .into_iter()
+        .filter(|(_, path)| changed_paths.iter().any(|changed_path| Path::new(path).starts_with(changed_path)))
+        .map(|(id, _)| id)
+        .collect();
+
+      for chunk in stale.chunks(CHUNK_SIZE) {
+        diesel::delete(scan_error::table.filter(scan_error::id.eq_any(chunk))).execute(connection)?;
       }
-  });
+      for chunk in errors.chunks(CHUNK_SIZE) {
+        diesel::insert_into(scan_error::table).values(chunk).execute(connection)?;
+      }
+      Ok(())
+    })
+  })
+  .await?;
+
+  Ok(())
+}
+
+/// The stored scan reports, optionally only the one for a library.
+pub async fn load_scan_reports(library_path: Option<String>) -> Result<Vec<ScanReport>, Box<dyn Error>> {
+  use crate::utils::database::schema::scan_report;
+
+  let rows = with_connection(&establish_connection(), move |connection| {
+    let mut query = scan_report::table.into_boxed();
+    if let Some(library_path) = library_path {
+      query = query.filter(scan_report::library_path.eq(library_path));
+    }
+    query
+      .order(scan_report::library_path.asc())
+      .select(ScanReportRow::as_select())
+      .load::<ScanReportRow>(connection)
+  })
+  .await?;
+
+  let mut reports = Vec::new();
+  for row in rows {
+    let errors = load_scan_errors(Some(row.library_path.clone()), None).await?;
+    reports.push(ScanReport {
+      library_path: row.library_path,
+      added: serde_json::from_str(&row.added)?,
+      removed: serde_json::from_str(&row.removed)?,
+      moved: serde_json::from_str(&row.moved)?,
+      changed: serde_json::from_str(&row.changed)?,
+      errors: errors.into_iter().map(|(_, error)| error).collect(),
+      ..Default::default()
+    });
+  }
+
+  Ok(reports)
+}
+
+/// The stored per-file errors with their library, filterable by library and kind.
+pub async fn load_scan_errors(
+  library_path: Option<String>,
+  kind: Option<ScanErrorKind>,
+) -> Result<Vec<(String, ScanFileError)>, Box<dyn Error>> {
+  use crate::utils::database::schema::scan_error;
 
-  let songs_clone = flat_songs.lock().unwrap().clone();
-  let albums_clone = flat_albums.lock().unwrap().clone();
-  let artists_clone = flat_artists.lock().unwrap().clone();
+  with_connection(&establish_connection(), move |connection| {
+    let mut query = scan_error::table.into_boxed();
+    if let Some(library_path) = library_path {
+      query = query.filter(scan_error::library_path.eq(library_path));
+    }
+    if let Some(kind) = kind {
+      query = query.filter(scan_error::kind.eq(kind.as_str()));
+    }
+    query
+      .order(scan_error::id.asc())
+      .select(ScanErrorRow::as_select())
+      .load::<ScanErrorRow>(connection)
+  })
+  .await?
+  .into_iter()
+  .map(|row| Ok((row.library_path.clone(), ScanFileError::try_from(row)?)))
+  .collect()
+}
+
+/// Forgets a library's scan report, for when the library is removed.
+pub async fn remove_scan_report(library_path: &str) -> Result<(), Box<dyn Error>> {
+  use crate::utils::database::schema::scan_report;
+
+  let library_path = library_path.to_string();
+  with_connection(&establish_connection(), move |connection| {
+    diesel::delete(scan_report::table.filter(scan_report::library_path.eq(library_path))).execute(connection)
+  })
+  .await?;
+
+  Ok(())
+}
 
-  (songs_clone, albums_clone, artists_clone)
+fn move_key(song: &Song) -> (String, String, u16, u16) {
+  (song.name.to_lowercase(), song.artist.to_lowercase(), song.disc_number, song.track_number)
 }
 
-fn find_new_artist_entries(
-  current_artists: &HashMap<String, Artist>, 
-  new_artists: &HashMap<String, Artist>,
-) -> Vec<Artist> {
-  new_artists
+fn song_differs(old: &Song, new: &Song) -> bool {
+  old.name != new.name
+    || old.artist != new.artist
+    || old.track_number != new.track_number
+    || old.disc_number != new.disc_number
+    || old.contributing_artists != new.contributing_artists
+    || (old.duration - new.duration).abs() > f64::EPSILON
+}
+
+pub fn diff_library(library_path: &str, current_library: &[Artist], new_library: &mut [Artist]) -> ScanReport {
+  let current_songs: HashMap<&str, &Song> = current_library
     .iter()
-    .filter(|(id, _)| !current_artists.contains_key(*id))
-    .map(|(_, artist)| artist.clone())
-    .collect()
+    .flat_map(|artist| artist.albums.iter())
+    .flat_map(|album| album.songs.iter())
+    .map(|song| (song.id.as_str(), song))
+    .collect();
+
+  let new_ids: HashSet<String> = new_library
+    .iter()
+    .flat_map(|artist| artist.albums.iter())
+    .flat_map(|album| album.songs.iter())
+    .map(|song| song.id.clone())
+    .collect();
+
+  let missing: Vec<&Song> = current_songs
+    .values()
+    .filter(|song| Path::new(&song.path).starts_with(library_path) && !new_ids.contains(&song.id))
+    .copied()
+    .collect();
+
+  let missing_by_path: HashMap<&str, &Song> = missing.iter().map(|song| (song.path.as_str(), *song)).collect();
+  let mut missing_by_key: HashMap<(String, String, u16, u16), Vec<&Song>> = HashMap::new();
+  for song in &missing {
+    if !Path::new(&song.path).exists() {
+      missing_by_key.entry(move_key(song)).or_default().push(song);
+    }
+  }
+
+  let mut report = ScanReport {
+    library_path: library_path.to_string(),
+    ..Default::default()
+  };
+  let mut claimed: HashSet<String> = HashSet::new();
+
+  for song in new_library
+    .iter_mut()
+    .flat_map(|artist| artist.albums.iter_mut())
+    .flat_map(|album| album.songs.iter_mut())
+  {
+    if let Some(old) = current_songs.get(song.id.as_str()) {
+      if old.path != song.path {
+        report.moved.push(SongChange::new(song, Some(&old.path)));
+      } else if song_differs(old, song) {
+        report.changed.push(SongChange::new(song, None));
+      }
+      continue;
+    }
+
+    if let Some(old) = missing_by_path.get(song.path.as_str()).filter(|old| !claimed.contains(&old.id)) {
+      claimed.insert(old.id.clone());
+      song.id = old.id.clone();
+      report.changed.push(SongChange::new(song, None));
+      continue;
+    }
+
+    let moved_from = missing_by_key.get(&move_key(song)).and_then(|candidates| {
+      candidates.iter().find(|old| {
+        !claimed.contains(&old.id) && (old.duration - song.duration).abs() <= MOVE_DURATION_TOLERANCE
+      })
+    });
+
+    match moved_from {
+      Some(old) => {
+        claimed.insert(old.id.clone());
+        song.id = old.id.clone();
+        report.moved.push(SongChange::new(song, Some(&old.path)));
+      }
+      None => report.added.push(SongChange::new(song, None)),
+    }
+  }
+
+  report.removed = missing
+    .iter()
+    .filter(|song| !claimed.contains(&song.id))
+    .map(|song| SongChange::new(song, None))
+    .collect();
+
+  let current_artist_ids: HashSet<&str> = current_library.iter().map(|artist| artist.id.as_str()).collect();
+  let current_album_ids: HashSet<&str> = current_library
+    .iter()
+    .flat_map(|artist| artist.albums.iter())
+    .map(|album| album.id.as_str())
+    .collect();
+  for artist in new_library.iter() {
+    if !current_artist_ids.contains(artist.id.as_str()) {
+      report.new_artist_ids.push(artist.id.clone());
+    }
+    for album in &artist.albums {
+      if !current_album_ids.contains(album.id.as_str()) {
+        report.new_album_ids.push(album.id.clone());
+      }
+    }
+  }
+
+  report
+}
+
+/// Merges a scan into the catalog. Songs keep their catalog record, looked up
+/// metadata included, but take every field read from their file, and songs
+/// retagged into another album move there, leaving empty albums behind.
+pub fn apply_scan_report(current_library: &mut Vec<Artist>, new_library: &[Artist], report: &ScanReport) {
+  let removed: HashSet<&str> = report.removed.iter().map(|change| change.id.as_str()).collect();
+
+  let new_artists: HashMap<&str, &Artist> = new_library.iter().map(|artist| (artist.id.as_str(), artist)).collect();
+  let new_albums: HashMap<&str, &Album> = new_library
+    .iter()
+    .flat_map(|artist| artist.albums.iter())
+    .map(|album| (album.id.as_str(), album))
+    .collect();
+
+  // Where each scanned song belongs: its song, album and album artist
+  let mut placements: HashMap<&str, (&Song, &Album, &Artist)> = HashMap::new();
+  for artist in new_library {
+    for album in &artist.albums {
+      for song in &album.songs {
+        placements.insert(song.id.as_str(), (song, album, artist));
+      }
+    }
+  }
+
+  let mut placed: HashSet<String> = HashSet::new();
+  for artist in current_library.iter_mut() {
+    if let Some(new_artist) = new_artists.get(artist.id.as_str()) {
+      merge_tagged_id(&mut artist.musicbrainz_id, &new_artist.musicbrainz_id);
+    }
+
+    for album in artist.albums.iter_mut() {
+      if let Some(new_album) = new_albums.get(album.id.as_str()) {
+        merge_tagged_id(&mut album.musicbrainz_release_id, &new_album.musicbrainz_release_id);
+        merge_tagged_id(&mut album.musicbrainz_release_group_id, &new_album.musicbrainz_release_group_id);
+      }
+
+      let album_id = album.id.as_str();
+      album.songs.retain_mut(|song| {
+        if removed.contains(song.id.as_str()) {
+          return false;
+        }
+        let Some((new_song, new_album, _)) = placements.get(song.id.as_str()) else {
+          // Not part of the scanned library
+          return placed.insert(song.id.clone());
+        };
+        // Songs retagged into another album are added to it below
+        if new_album.id != album_id {
+          return false;
+        }
+        merge_scanned_song(song, new_song);
+        placed.insert(song.id.clone())
+      });
+    }
+  }
+
+  for (song_id, (new_song, new_album, new_artist)) in &placements {
+    if placed.contains(*song_id) {
+      continue;
+    }
+
+    let artist_position = match current_library.iter().position(|artist| artist.id == new_artist.id) {
+      Some(position) => position,
+      None => {
+        current_library.push(Artist { albums: Vec::new(), ..(*new_artist).clone() });
+        current_library.len() - 1
+      }
+    };
+    let artist = &mut current_library[artist_position];
+    let album_position = match artist.albums.iter().position(|album| album.id == new_album.id) {
+      Some(position) => position,
+      None => {
+        artist.albums.push(Album { songs: Vec::new(), ..(*new_album).clone() });
+        artist.albums.len() - 1
+      }
+    };
+    artist.albums[album_position].songs.push((*new_song).clone());
+  }
+
+  for artist in current_library.iter_mut() {
+    for album in artist.albums.iter_mut() {
+      album.songs.sort_by_key(|song| (song.disc_number, song.track_number));
+    }
+    artist.albums.retain(|album| !album.songs.is_empty());
+  }
+
+  current_library.retain(|artist| !artist.albums.is_empty() || !artist.featured_on_album_ids.is_empty());
+}
+
+/// Copies the fields read from a song's file onto its catalog record.
+fn merge_scanned_song(song: &mut Song, scanned: &Song) {
+  song.name.clone_from(&scanned.name);
+  song.artist.clone_from(&scanned.artist);
+  song.contributing_artists.clone_from(&scanned.contributing_artists);
+  song.contributing_artist_ids.clone_from(&scanned.contributing_artist_ids);
+  song.track_number = scanned.track_number;
+  song.disc_number = scanned.disc_number;
+  song.disc_total = scanned.disc_total;
+  song.path.clone_from(&scanned.path);
+  song.duration = scanned.duration;
+  song.audio.clone_from(&scanned.audio);
+  song.musicbrainz_recording_id.clone_from(&scanned.musicbrainz_recording_id);
+  song.musicbrainz_track_id.clone_from(&scanned.musicbrainz_track_id);
+}
+
+/// MusicBrainz IDs read from tags replace the catalog's, but files that lost
+/// their tags don't clear them.
+fn merge_tagged_id(current: &mut Option<String>, scanned: &Option<String>) {
+  if scanned.is_some() {
+    current.clone_from(scanned);
+  }
+}
+
+/// Song fields `apply_scan_report` copies from the scanned files onto songs
+/// already in the catalog. Every other field keeps the catalog's value.
+const MERGED_SONG_FIELDS: [&str; 12] = [
+  "name",
+  "artist",
+  "contributing_artists",
+  "contributing_artist_ids",
+  "track_number",
+  "disc_number",
+  "disc_total",
+  "path",
+  "duration",
+  "audio",
+  "musicbrainz_recording_id",
+  "musicbrainz_track_id",
+];
+
+/// A field whose scanned value differs from the catalog's.
+#[derive(Serialize, Clone)]
+pub struct FieldDiff {
+  pub field: &'static str,
+  pub current: serde_json::Value,
+  pub scanned: serde_json::Value,
+  /// Whether merging the scan replaces the catalog's value
+  pub overwritten: bool,
+}
+
+fn field_diff<T: Serialize + PartialEq>(field: &'static str, current: &T, scanned: &T, overwritten: bool) -> Option<FieldDiff> {
+  (current != scanned).then(|| FieldDiff {
+    field,
+    current: serde_json::to_value(current).unwrap_or_default(),
+    scanned: serde_json::to_value(scanned).unwrap_or_default(),
+    overwritten,
+  })
+}
+
+fn song_field<T: Serialize + PartialEq>(field: &'static str, current: &T, scanned: &T) -> Option<FieldDiff> {
+  field_diff(field, current, scanned, MERGED_SONG_FIELDS.contains(&field))
+}
+
+fn song_field_diffs(current: &Song, scanned: &Song) -> Vec<FieldDiff> {
+  [
+    song_field("name", &current.name, &scanned.name),
+    song_field("artist", &current.artist, &scanned.artist),
+    song_field("contributing_artists", &current.contributing_artists, &scanned.contributing_artists),
+    song_field("track_number", &current.track_number, &scanned.track_number),
+    song_field("disc_number", &current.disc_number, &scanned.disc_number),
+    song_field("disc_total", &current.disc_total, &scanned.disc_total),
+    song_field("duration", &current.duration, &scanned.duration),
+    song_field("path", &current.path, &scanned.path),
+    song_field("audio", &current.audio, &scanned.audio),
+    song_field("musicbrainz_recording_id", &current.musicbrainz_recording_id, &scanned.musicbrainz_recording_id),
+    song_field("musicbrainz_track_id", &current.musicbrainz_track_id, &scanned.musicbrainz_track_id),
+  ]
+  .into_iter()
+  .flatten()
+  .collect()
+}
+
+/// Albums already in the catalog keep their record, looked up metadata included.
+fn album_field_diffs(current: &Album, scanned: &Album) -> Vec<FieldDiff> {
+  [
+    field_diff("name", &current.name, &scanned.name, false),
+    field_diff("edition", &current.edition, &scanned.edition, false),
+    field_diff("album_type", &current.album_type, &scanned.album_type, false),
+    field_diff("contributing_artists", &current.contributing_artists, &scanned.contributing_artists, false),
+    field_diff(
+      "musicbrainz_release_id",
+      &current.musicbrainz_release_id,
+      &scanned.musicbrainz_release_id,
+      scanned.musicbrainz_release_id.is_some(),
+    ),
+    field_diff(
+      "musicbrainz_release_group_id",
+      &current.musicbrainz_release_group_id,
+      &scanned.musicbrainz_release_group_id,
+      scanned.musicbrainz_release_group_id.is_some(),
+    ),
+  ]
+  .into_iter()
+  .flatten()
+  .collect()
 }
 
-fn find_new_album_entries(
-  current_albums: &HashMap<String, ModifiedAlbum>, 
-  new_albums: &HashMap<String, ModifiedAlbum>,
-) -> Vec<ModifiedAlbum> {
-  new_albums
+fn artist_field_diffs(current: &Artist, scanned: &Artist) -> Vec<FieldDiff> {
+  [
+    field_diff("name", &current.name, &scanned.name, false),
+    field_diff("musicbrainz_id", &current.musicbrainz_id, &scanned.musicbrainz_id, scanned.musicbrainz_id.is_some()),
+  ]
+  .into_iter()
+  .flatten()
+  .collect()
+}
+
+#[derive(Serialize, Clone)]
+pub struct SongDiff {
+  #[serde(flatten)]
+  pub song: SongChange,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub fields: Vec<FieldDiff>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct EntityDiff {
+  pub id: String,
+  pub name: String,
+  /// The album's artist; unset for artists
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub artist: Option<String>,
+  /// The name in the catalog, for renamed albums and artists
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub previous_name: Option<String>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub fields: Vec<FieldDiff>,
+}
+
+#[derive(Serialize)]
+pub struct Changes<T> {
+  pub added: Vec<T>,
+  pub removed: Vec<T>,
+  pub renamed: Vec<T>,
+  pub retagged: Vec<T>,
+}
+
+impl<T> Default for Changes<T> {
+  fn default() -> Self {
+    Changes { added: Vec::new(), removed: Vec::new(), renamed: Vec::new(), retagged: Vec::new() }
+  }
+}
+
+/// What rescanning a library would change in the catalog.
+#[derive(Serialize)]
+pub struct LibraryDiff {
+  /// Empty when the whole catalog is diffed, as for a backup
+  #[serde(skip_serializing_if = "String::is_empty")]
+  pub library_path: String,
+  pub songs: Changes<SongDiff>,
+  pub albums: Changes<EntityDiff>,
+  pub artists: Changes<EntityDiff>,
+  pub errors: Vec<ScanFileError>,
+}
+
+/// An album or artist with the ids of its songs inside the library being diffed.
+struct DiffEntry<'a, T> {
+  item: &'a T,
+  name: &'a str,
+  artist: Option<&'a str>,
+  song_ids: HashSet<&'a str>,
+  /// Whether all of its songs are in the library, so it goes when they do
+  only_in_library: bool,
+}
+
+fn album_entries<'a>(library: &'a [Artist], library_path: &str) -> HashMap<&'a str, DiffEntry<'a, Album>> {
+  library
     .iter()
-    .filter(|(id, _)| !current_albums.contains_key(*id))
-    .map(|(_, album)| album.clone())
+    .flat_map(|artist| artist.albums.iter().map(move |album| (artist, album)))
+    .map(|(artist, album)| {
+      let entry = DiffEntry {
+        item: album,
+        name: &album.name,
+        artist: Some(&artist.name),
+        song_ids: songs_in_library(album.songs.iter(), library_path),
+        only_in_library: album.songs.iter().all(|song| Path::new(&song.path).starts_with(library_path)),
+      };
+      (album.id.as_str(), entry)
+    })
     .collect()
 }
 
-fn find_new_song_entries(
-  current_songs: &HashMap<String, Song>, 
-  new_songs: &HashMap<String, Song>,
-) -> Vec<Song> {
-  new_songs
+fn artist_entries<'a>(library: &'a [Artist], library_path: &str) -> HashMap<&'a str, DiffEntry<'a, Artist>> {
+  library
     .iter()
-    .filter(|(id, _)| !current_songs.contains_key(*id))
-    .map(|(_, song)| song.clone())
+    .map(|artist| {
+      let songs = || artist.albums.iter().flat_map(|album| album.songs.iter());
+      let entry = DiffEntry {
+        item: artist,
+        name: &artist.name,
+        artist: None,
+        song_ids: songs_in_library(songs(), library_path),
+        only_in_library: artist.featured_on_album_ids.is_empty()
+          && songs().all(|song| Path::new(&song.path).starts_with(library_path)),
+      };
+      (artist.id.as_str(), entry)
+    })
     .collect()
 }
 
-#[derive(Clone)]
-pub struct ModifiedAlbum {
-  pub album: Album,
-  pub artist_name: String,
-  pub artist_id: String
+fn songs_in_library<'a>(songs: impl Iterator<Item = &'a Song>, library_path: &str) -> HashSet<&'a str> {
+  songs
+    .filter(|song| Path::new(&song.path).starts_with(library_path))
+    .map(|song| song.id.as_str())
+    .collect()
 }
 
-pub async fn compare(library: &Arc<Mutex<Vec<Artist>>>) -> Result<(Vec<Artist>, Vec<ModifiedAlbum>, Vec<Song>), &'static str> {
-  let current_library = match get_config().await {
-    Ok(config) => config,
-    Err(_) => return Err("Failed to get config because there's no config"),
-  };
+fn entity_diff<T>(id: &str, entry: &DiffEntry<T>, previous_name: Option<&str>, fields: Vec<FieldDiff>) -> EntityDiff {
+  EntityDiff {
+    id: id.to_string(),
+    name: entry.name.to_string(),
+    artist: entry.artist.map(|artist| artist.to_string()),
+    previous_name: previous_name.map(|name| name.to_string()),
+    fields,
+  }
+}
 
+/// Pairs up albums or artists between the catalog and a scan. A scanned one
+/// that isn't in the catalog but holds songs matched to one that disappears
+/// was renamed by retagging; the rest are added or removed.
+fn entity_changes<T>(
+  current: &HashMap<&str, DiffEntry<T>>,
+  scanned: &HashMap<&str, DiffEntry<T>>,
+  field_diffs: impl Fn(&T, &T) -> Vec<FieldDiff>,
+) -> Changes<EntityDiff> {
+  let mut changes = Changes::default();
+  let mut renamed_from: HashSet<&str> = HashSet::new();
+  let song_owners: HashMap<&str, &str> = current
+    .iter()
+    .flat_map(|(id, entry)| entry.song_ids.iter().map(move |song_id| (*song_id, *id)))
+    .collect();
 
-  let current_library: Vec<Artist> = match serde_json::from_str(&current_library) {
-    Ok(library) => library,
-    Err(_) => return Err("Failed to parse config"),
-  };
+  for (id, entry) in scanned {
+    if let Some(existing) = current.get(id) {
+      let fields = field_diffs(existing.item, entry.item);
+      if !fields.is_empty() {
+        changes.retagged.push(entity_diff(id, entry, None, fields));
+      }
+      continue;
+    }
 
+    let mut shared: HashMap<&str, usize> = HashMap::new();
+    for song_id in &entry.song_ids {
+      if let Some(current_id) = song_owners.get(song_id) {
+        *shared.entry(current_id).or_default() += 1;
+      }
+    }
+    let previous = shared
+      .into_iter()
+      .filter(|(current_id, _)| !scanned.contains_key(current_id) && !renamed_from.contains(current_id))
+      .max_by_key(|(_, count)| *count)
+      .and_then(|(current_id, _)| current.get_key_value(current_id));
 
-  let library_guard = library.lock().unwrap();
+    match previous {
+      Some((current_id, existing)) => {
+        renamed_from.insert(current_id);
+        changes.renamed.push(entity_diff(id, entry, Some(existing.name), field_diffs(existing.item, entry.item)));
+      }
+      None => changes.added.push(entity_diff(id, entry, None, Vec::new())),
+    }
+  }
 
-  let new_library: Vec<Artist> = (*library_guard.to_owned()).to_vec();
+  changes.removed = current
+    .iter()
+    .filter(|(id, entry)| {
+      !entry.song_ids.is_empty() && entry.only_in_library && !scanned.contains_key(*id) && !renamed_from.contains(*id)
+    })
+    .map(|(id, entry)| entity_diff(id, entry, None, Vec::new()))
+    .collect();
 
-  let (current_songs, current_albums, current_artists) = flatten_data(&current_library);
-  let (new_songs, new_albums, new_artists) = flatten_data(&new_library);
+  for list in [&mut changes.added, &mut changes.removed, &mut changes.renamed, &mut changes.retagged] {
+    list.sort_by_key(|diff| diff.name.to_lowercase());
+  }
+  changes
+}
 
-  let new_artist_entries = find_new_artist_entries(&current_artists, &new_artists);
-  let new_album_entries = find_new_album_entries(&current_albums, &new_albums);
-  let new_song_entries = find_new_song_entries(&current_songs, &new_songs);
+/// Diffs a freshly indexed library against the catalog without merging it,
+/// listing the fields the merge would overwrite and the ones it would keep.
+pub fn preview_library_diff(
+  library_path: &str,
+  current_library: &[Artist],
+  new_library: &mut [Artist],
+  errors: Vec<ScanFileError>,
+) -> LibraryDiff {
+  let report = diff_library(library_path, current_library, new_library);
 
-  Ok((new_artist_entries, new_album_entries, new_song_entries))
-}
\ No newline at end of file
+  let current_songs: HashMap<&str, &Song> = current_library
+    .iter()
+    .flat_map(|artist| artist.albums.iter())
+    .flat_map(|album| album.songs.iter())
+    .map(|song| (song.id.as_str(), song))
+    .collect();
+  let new_songs: HashMap<&str, &Song> = new_library
+    .iter()
+    .flat_map(|artist| artist.albums.iter())
+    .flat_map(|album| album.songs.iter())
+    .map(|song| (song.id.as_str(), song))
+    .collect();
+
+  let with_fields = |changes: Vec<SongChange>| -> Vec<SongDiff> {
+    changes
+      .into_iter()
+      .map(|change| {
+        let fields = match (current_songs.get(change.id.as_str()), new_songs.get(change.id.as_str())) {
+          (Some(current), Some(scanned)) => song_field_diffs(current, scanned),
+          _ => Vec::new(),
+        };
+        SongDiff { song: change, fields }
+      })
+      .collect()
+  };
+
+  let songs = Changes {
+    added: with_fields(report.added),
+    removed: with_fields(report.removed),
+    renamed: with_fields(report.moved),
+    retagged: with_fields(report.changed),
+  };
+
+  let albums = entity_changes(
+    &album_entries(current_library, library_path),
+    &album_entries(new_library, library_path),
+    album_field_diffs,
+  );
+  let artists = entity_changes(
+    &artist_entries(current_library, library_path),
+    &artist_entries(new_library, library_path),
+    artist_field_diffs,
+  );
+
+  LibraryDiff {
+    library_path: library_path.to_string(),
+    songs,
+    albums,
+    artists,
+    errors,
+  }
+}
@@ -0,0 +1,258 @@
+#[cfg(test)]
+mod tests {
+    use crate::structures::structures::{Album, Artist, Song};
+    use crate::utils::compare::{apply_scan_report, diff_library};
+    use crate::utils::hash::{hash_album, hash_artist, hash_song};
+
+    const LIBRARY: &str = "/test-library";
+
+    fn song(name: &str, artist: &str, album: &str, track_number: u16, path: &str) -> Song {
+        Song {
+            id: hash_song(name, artist, album, 1, track_number),
+            name: name.to_string(),
+            artist: artist.to_string(),
+            contributing_artists: Vec::new(),
+            contributing_artist_ids: Vec::new(),
+            track_number,
+            disc_number: 1,
+            disc_total: 1,
+            path: format!("{}/{}", LIBRARY, path),
+            duration: 180.0,
+            audio: None,
+            music_video: None,
+            musicbrainz_recording_id: None,
+            musicbrainz_track_id: None,
+        }
+    }
+
+    fn album(name: &str, artist: &str, songs: Vec<Song>) -> Album {
+        Album {
+            id: hash_album(name, artist),
+            name: name.to_string(),
+            songs,
+            ..Default::default()
+        }
+    }
+
+    fn artist(name: &str, albums: Vec<Album>) -> Artist {
+        Artist {
+            id: hash_artist(name),
+            name: name.to_string(),
+            albums,
+            featured_on_album_ids: Vec::new(),
+            ..Default::default()
+        }
+    }
+
+    fn catalog() -> Vec<Artist> {
+        vec![artist(
+            "Artist",
+            vec![
+                album(
+                    "First",
+                    "Artist",
+                    vec![
+                        song("One", "Artist", "First", 1, "Artist/First/01.flac"),
+                        song("Two", "Artist", "First", 2, "Artist/First/02.flac"),
+                    ],
+                ),
+                album("Second", "Artist", vec![song("Three", "Artist", "Second", 1, "Artist/Second/01.flac")]),
+            ],
+        )]
+    }
+
+    fn song_id(library: &[Artist], name: &str) -> String {
+        songs(library).into_iter().find(|(_, song)| song.name == name).unwrap().1.id.clone()
+    }
+
+    fn songs(library: &[Artist]) -> Vec<(&Album, &Song)> {
+        library
+            .iter()
+            .flat_map(|artist| artist.albums.iter())
+            .flat_map(|album| album.songs.iter().map(move |song| (album, song)))
+            .collect()
+    }
+
+    /// The names of the albums holding the song with this id.
+    fn albums_of(library: &[Artist], id: &str) -> Vec<String> {
+        songs(library)
+            .into_iter()
+            .filter(|(_, song)| song.id == id)
+            .map(|(album, _)| album.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_renamed_file_keeps_id() {
+        let current = catalog();
+        let mut scanned = catalog();
+        scanned[0].albums[0].songs[0].path = format!("{}/Artist/First/01 - One.flac", LIBRARY);
+
+        let report = diff_library(LIBRARY, &current, &mut scanned);
+        assert_eq!(report.moved.len(), 1);
+        assert!(report.added.is_empty() && report.removed.is_empty());
+        assert_eq!(song_id(&scanned, "One"), song_id(&current, "One"));
+
+        let mut merged = current.clone();
+        apply_scan_report(&mut merged, &scanned, &report);
+        let (_, one) = songs(&merged).into_iter().find(|(_, song)| song.name == "One").unwrap();
+        assert_eq!(one.path, format!("{}/Artist/First/01 - One.flac", LIBRARY));
+    }
+
+    #[test]
+    fn test_moved_file_keeps_id() {
+        let current = catalog();
+        let mut scanned = catalog();
+        // Moved to another folder and filed under a retagged album, so only the move key matches
+        let moved = song("Three", "Artist", "Second (Remaster)", 1, "Elsewhere/01.flac");
+        scanned[0].albums[1] = album("Second (Remaster)", "Artist", vec![moved]);
+
+        let report = diff_library(LIBRARY, &current, &mut scanned);
+        assert_eq!(report.moved.len(), 1);
+        assert!(report.added.is_empty() && report.removed.is_empty());
+        let id = song_id(&current, "Three");
+        assert_eq!(song_id(&scanned, "Three"), id);
+
+        let mut merged = current.clone();
+        apply_scan_report(&mut merged, &scanned, &report);
+        assert_eq!(albums_of(&merged, &id), vec!["Second (Remaster)"]);
+        assert!(merged[0].albums.iter().all(|album| album.name != "Second"));
+    }
+
+    #[test]
+    fn test_retagged_title_keeps_id() {
+        let current = catalog();
+        let mut scanned = catalog();
+        scanned[0].albums[0].songs[1] = song("Two (Live)", "Artist", "First", 2, "Artist/First/02.flac");
+
+        let report = diff_library(LIBRARY, &current, &mut scanned);
+        assert_eq!(report.changed.len(), 1);
+        assert!(report.added.is_empty() && report.removed.is_empty());
+        let id = song_id(&current, "Two");
+        assert_eq!(song_id(&scanned, "Two (Live)"), id);
+
+        let mut merged = current.clone();
+        apply_scan_report(&mut merged, &scanned, &report);
+        let (album, two) = songs(&merged).into_iter().find(|(_, song)| song.id == id).unwrap();
+        assert_eq!(two.name, "Two (Live)");
+        assert_eq!(album.name, "First");
+    }
+
+    #[test]
+    fn test_retagged_track_number_reaches_catalog() {
+        let current = catalog();
+        let mut scanned = catalog();
+        scanned[0].albums[0].songs[0] = song("One", "Artist", "First", 3, "Artist/First/01.flac");
+
+        let report = diff_library(LIBRARY, &current, &mut scanned);
+        let mut merged = current.clone();
+        apply_scan_report(&mut merged, &scanned, &report);
+
+        let first = &merged[0].albums[0];
+        assert_eq!(first.songs.iter().map(|song| song.name.as_str()).collect::<Vec<_>>(), vec!["Two", "One"]);
+        assert_eq!(first.songs[1].track_number, 3);
+    }
+
+    #[test]
+    fn test_retagged_album_moves_song_to_new_album() {
+        let current = catalog();
+        let mut scanned = catalog();
+        let retagged = song("Three", "Artist", "Third", 1, "Artist/Second/01.flac");
+        scanned[0].albums[1] = album("Third", "Artist", vec![retagged]);
+
+        let report = diff_library(LIBRARY, &current, &mut scanned);
+        assert_eq!(report.changed.len(), 1);
+        let id = song_id(&current, "Three");
+        assert_eq!(song_id(&scanned, "Three"), id);
+
+        let mut merged = current.clone();
+        apply_scan_report(&mut merged, &scanned, &report);
+        assert_eq!(albums_of(&merged, &id), vec!["Third"]);
+        assert!(merged[0].albums.iter().all(|album| album.name != "Second"));
+    }
+
+    #[test]
+    fn test_retagged_album_already_added_by_metadata_lookup() {
+        let current = catalog();
+        let mut scanned = catalog();
+        let retagged = song("Three", "Artist", "Third", 1, "Artist/Second/01.flac");
+        scanned[0].albums[1] = album("Third", "Artist", vec![retagged]);
+
+        let report = diff_library(LIBRARY, &current, &mut scanned);
+        let id = song_id(&current, "Three");
+
+        // New albums are added to the catalog with their songs before the merge
+        let mut merged = current.clone();
+        merged[0].albums.push(scanned[0].albums[1].clone());
+        apply_scan_report(&mut merged, &scanned, &report);
+        assert_eq!(albums_of(&merged, &id), vec!["Third"]);
+    }
+
+    #[test]
+    fn test_retagged_album_moves_song_to_existing_album() {
+        let current = catalog();
+        let mut scanned = catalog();
+        let retagged = song("Three", "Artist", "First", 3, "Artist/Second/01.flac");
+        scanned[0].albums.pop();
+        scanned[0].albums[0].songs.push(retagged);
+
+        let report = diff_library(LIBRARY, &current, &mut scanned);
+        let id = song_id(&current, "Three");
+        assert_eq!(song_id(&scanned, "Three"), id);
+
+        let mut merged = current.clone();
+        apply_scan_report(&mut merged, &scanned, &report);
+        assert_eq!(albums_of(&merged, &id), vec!["First"]);
+        assert_eq!(merged[0].albums.len(), 1);
+        assert_eq!(merged[0].albums[0].songs.len(), 3);
+    }
+
+    #[test]
+    fn test_new_and_deleted_files() {
+        let current = catalog();
+        let mut scanned = catalog();
+        scanned[0].albums[1].songs = vec![song("Four", "Artist", "Second", 2, "Artist/Second/02.flac")];
+
+        let report = diff_library(LIBRARY, &current, &mut scanned);
+        assert_eq!(report.added.len(), 1);
+        assert_eq!(report.removed.len(), 1);
+        assert_eq!(report.removed[0].id, song_id(&current, "Three"));
+        assert_eq!(song_id(&scanned, "Four"), hash_song("Four", "Artist", "Second", 1, 2));
+
+        let mut merged = current.clone();
+        apply_scan_report(&mut merged, &scanned, &report);
+        let second = merged[0].albums.iter().find(|album| album.name == "Second").unwrap();
+        assert_eq!(second.songs.iter().map(|song| song.name.as_str()).collect::<Vec<_>>(), vec!["Four"]);
+    }
+
+    #[test]
+    fn test_new_artists_and_albums() {
+        // The catalog already holds this artist from another library
+        let current = catalog();
+        let mut scanned = catalog();
+        scanned[0].albums.push(album("Third", "Artist", vec![song("Five", "Artist", "Third", 1, "Artist/Third/01.flac")]));
+        scanned.push(artist("Other", vec![album("Debut", "Other", vec![song("Six", "Other", "Debut", 1, "Other/Debut/01.flac")])]));
+
+        let report = diff_library(LIBRARY, &current, &mut scanned);
+        let (new_artists, new_albums) = report.new_entries(&scanned);
+        assert_eq!(new_artists.iter().map(|artist| artist.name.as_str()).collect::<Vec<_>>(), vec!["Other"]);
+        let album_names: Vec<(&str, &str)> =
+            new_albums.iter().map(|entry| (entry.artist_name.as_str(), entry.album.name.as_str())).collect();
+        assert_eq!(album_names, vec![("Artist", "Third"), ("Other", "Debut")]);
+    }
+
+    #[test]
+    fn test_songs_outside_library_are_kept() {
+        let mut current = catalog();
+        current[0].albums[0].songs.push(song("Elsewhere", "Artist", "First", 9, "x.flac"));
+        current[0].albums[0].songs[2].path = "/other-library/x.flac".to_string();
+        let mut scanned = catalog();
+
+        let report = diff_library(LIBRARY, &current, &mut scanned);
+        assert!(report.removed.is_empty());
+
+        let mut merged = current.clone();
+        apply_scan_report(&mut merged, &scanned, &report);
+        assert_eq!(merged[0].albums[0].songs.len(), 3);
+    }
+}
@@ -7,13 +7,15 @@ use actix_web::{get, HttpResponse, Responder};
 use dotenvy::dotenv;
 use rand::distributions::Alphanumeric;
 use rand::Rng;
-use serde_json::{from_str, json, to_string, Value};
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use serde_json::{json, to_string, Value};
 use lazy_static::lazy_static;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard, RwLock};
 
 use crate::structures::structures::Artist;
+use crate::utils::backups::{backup_catalog, Backup};
+use crate::utils::catalog::{catalog_is_empty, load_catalog, save_catalog, CatalogChanges};
+use crate::utils::catalog_index::CatalogIndex;
+use crate::utils::database::database::{establish_connection, with_connection, DbPool};
 
 pub fn is_docker() -> bool {
     if env::var("RUNNING_IN_DOCKER").is_ok() {
@@ -71,36 +73,49 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 }
 
 lazy_static! {
-    pub static ref LIBRARY_CACHE: RwLock<Option<Arc<Vec<Artist>>>> = RwLock::new(None);
-}
+    pub static ref LIBRARY_CACHE: RwLock<Option<Arc<CatalogIndex>>> = RwLock::new(None);
 
-pub async fn get_config() -> Result<String, Box<dyn Error>> {
-    let config_path = get_config_path();
+    /// Held from reading the catalog to saving a changed copy of it, so writers
+    /// can't overwrite each other's changes. Scans hold it for their whole run,
+    /// which queues watcher updates until they finish.
+    static ref CATALOG_WRITE_LOCK: AsyncMutex<()> = AsyncMutex::new(());
+}
 
-    let mut file = File::open(config_path).await?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).await?;
+/// Takes the catalog write lock. Anything that saves a catalog it read earlier
+/// must hold it from the read to the save.
+pub async fn lock_catalog() -> AsyncMutexGuard<'static, ()> {
+    CATALOG_WRITE_LOCK.lock().await
+}
 
-    Ok(contents)
+pub async fn get_config() -> Result<String, Box<dyn Error>> {
+    let library = fetch_library().await?;
+    Ok(to_string(&*library)?)
 }
 
 pub async fn fetch_library() -> Result<Arc<Vec<Artist>>, Box<dyn Error>> {
+    Ok(fetch_catalog_index().await?.library().clone())
+}
+
+/// The cached catalog with its id lookups, loading both if the cache is empty.
+pub async fn fetch_catalog_index() -> Result<Arc<CatalogIndex>, Box<dyn Error>> {
+    if let Some(index) = &*LIBRARY_CACHE.read().await {
+        return Ok(index.clone());
+    }
+
+    // Another task may have loaded the catalog while this one waited to write
     let mut cache = LIBRARY_CACHE.write().await;
-    if let Some(library) = &*cache {
-        return Ok(library.clone());
+    if let Some(index) = &*cache {
+        return Ok(index.clone());
     }
 
-    let config = get_config().await?;
-    let library: Vec<Artist> = from_str(&config)?;
-    let library = Arc::new(library);
-    *cache = Some(library.clone());
-    Ok(library)
+    let library = with_connection(&establish_connection(), load_catalog).await?;
+    let index = Arc::new(CatalogIndex::new(Arc::new(library)));
+    *cache = Some(index.clone());
+    Ok(index)
 }
 
 pub async fn save_library(library: &Arc<Vec<Artist>>) -> Result<(), Box<dyn Error>> {
-    let config = to_string(&**library)?;
-    save_config(&config, false).await?;
-    refresh_cache().await?;
+    save_config(library, false).await?;
     Ok(())
 }
 
@@ -154,52 +169,126 @@ pub fn get_libraries_config_path() -> PathBuf {
     path
 }
 
-#[get("/has_config")]
-async fn has_config() -> impl Responder {
-    let config_path = get_config_path();
-
-    if Path::new(&config_path).exists() {
-        HttpResponse::Ok().body("Config file exists")
+pub fn get_artist_splitting_config_path() -> PathBuf {
+    let path = if is_docker() {
+        Path::new("/ParsonLabsMusic/Config/artist_splitting.json").to_path_buf()
     } else {
-        HttpResponse::NotFound().body("Config file not found")
+        let mut path = dirs::data_local_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("ParsonLabs");
+        path.push("Music");
+        path.push("Config");
+        path.push("artist_splitting.json");
+        path
+    };
+
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            eprintln!("Failed to create directories: {}", e);
+        }
     }
-}
 
-pub async fn save_config(indexed_json: &String, create_backup: bool) -> std::io::Result<()> {
-    let config_path = get_config_path();
+    path
+}
 
-    let current_content = if config_path.exists() {
-        fs::read_to_string(&config_path).ok()
+pub fn get_schedule_config_path() -> PathBuf {
+    let path = if is_docker() {
+        Path::new("/ParsonLabsMusic/Config/schedule.json").to_path_buf()
     } else {
-        None
+        let mut path = dirs::data_local_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("ParsonLabs");
+        path.push("Music");
+        path.push("Config");
+        path.push("schedule.json");
+        path
     };
 
-    if current_content.as_ref() == Some(indexed_json) {
-        return Ok(());
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            eprintln!("Failed to create directories: {}", e);
+        }
     }
 
-    let config_dir = config_path.parent().unwrap();
-    let config_filename = config_path.file_stem().unwrap().to_str().unwrap();
-    let config_extension = config_path.extension().unwrap().to_str().unwrap();
+    path
+}
 
-    if create_backup {
-        let mut backup_number = 1;
-        let mut backup_path = config_dir.join(format!("{}_{} (Backup).{}", config_filename, backup_number, config_extension));
+pub fn get_backup_config_path() -> PathBuf {
+    let path = if is_docker() {
+        Path::new("/ParsonLabsMusic/Config/backups.json").to_path_buf()
+    } else {
+        let mut path = dirs::data_local_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("ParsonLabs");
+        path.push("Music");
+        path.push("Config");
+        path.push("backups.json");
+        path
+    };
 
-        while backup_path.exists() {
-            backup_number += 1;
-            backup_path = config_dir.join(format!("{}_{} (Backup).{}", config_filename, backup_number, config_extension));
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            eprintln!("Failed to create directories: {}", e);
         }
+    }
 
-        if config_path.exists() {
-            fs::rename(&config_path, &backup_path)?;
+    path
+}
+
+pub fn get_metadata_providers_config_path() -> PathBuf {
+    let path = if is_docker() {
+        Path::new("/ParsonLabsMusic/Config/metadata_providers.json").to_path_buf()
+    } else {
+        let mut path = dirs::data_local_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("ParsonLabs");
+        path.push("Music");
+        path.push("Config");
+        path.push("metadata_providers.json");
+        path
+    };
+
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            eprintln!("Failed to create directories: {}", e);
         }
     }
 
-    let mut file = File::create(&config_path).await?;
-    file.write_all(indexed_json.as_bytes()).await?;
+    path
+}
+
+#[get("/has_config")]
+async fn has_config(pool: web::Data<DbPool>) -> impl Responder {
+    let has_catalog = matches!(with_connection(&pool, catalog_is_empty).await, Ok(false));
 
-    Ok(())
+    if has_catalog {
+        HttpResponse::Ok().body("Library catalog exists")
+    } else {
+        HttpResponse::NotFound().body("Library catalog not found")
+    }
+}
+
+/// Saves the catalog, writing only what changed since the stored version, and
+/// first backs the stored one up when asked to. Returns the backup that was
+/// made, if any.
+pub async fn save_config(library: &[Artist], create_backup: bool) -> std::io::Result<Option<Backup>> {
+    let current = fetch_library().await.map_err(|e| std::io::Error::other(e.to_string()))?;
+    let changes = CatalogChanges::new(&current, library);
+
+    if changes.is_empty() {
+        return Ok(None);
+    }
+
+    let backup = if create_backup && !current.is_empty() {
+        Some(backup_catalog(&to_string(&*current)?).await?)
+    } else {
+        None
+    };
+
+    with_connection(&establish_connection(), move |connection| save_catalog(connection, &changes))
+        .await
+        .map_err(std::io::Error::other)?;
+
+    // The saved catalog is what a reload would give, so there's no need to read it back
+    *LIBRARY_CACHE.write().await = Some(Arc::new(CatalogIndex::new(Arc::new(library.to_vec()))));
+
+    Ok(backup)
 }
 
 pub fn get_icon_art_path() -> PathBuf {
@@ -254,6 +343,25 @@ pub fn get_cover_art_path() -> PathBuf {
     path
 }
 
+/// Where metadata provider responses are cached between lookups.
+pub fn get_metadata_cache_path() -> PathBuf {
+    let path = if is_docker() {
+        Path::new("/ParsonLabsMusic/Metadata Cache").to_path_buf()
+    } else {
+        let mut path = dirs::data_local_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("ParsonLabs");
+        path.push("Music");
+        path.push("Metadata Cache");
+        path
+    };
+
+    if let Err(e) = fs::create_dir_all(&path) {
+        eprintln!("Failed to create metadata cache directory: {}", e);
+    }
+
+    path
+}
+
 pub fn get_profile_picture_path() -> PathBuf {
     let path = if is_docker() {
         Path::new("/ParsonLabsMusic/Profile Pictures").to_path_buf()
@@ -1,12 +1,18 @@
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+use actix_web::error::BlockingError;
+use actix_web::web;
 use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
+use diesel::r2d2::event::{AcquireEvent, CheckinEvent, CheckoutEvent, HandleEvent, ReleaseEvent, TimeoutEvent};
 use diesel::r2d2::{self, ConnectionManager};
-use diesel::RunQueryDsl;
+use lazy_static::lazy_static;
+use serde::Serialize;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use dotenvy::dotenv;
 use diesel::sqlite::SqliteConnection;
@@ -41,24 +47,154 @@ impl diesel::r2d2::CustomizeConnection<SqliteConnection, diesel::r2d2::Error> fo
 
 pub type DbPool = Arc<r2d2::Pool<ConnectionManager<SqliteConnection>>>;
 
-pub fn establish_connection() -> DbPool {
+const POOL_SIZE: u32 = 16;
+
+lazy_static! {
+    static ref POOL: DbPool = build_pool();
+    static ref POOL_METRICS: PoolMetrics = PoolMetrics::default();
+}
+
+/// Counters fed by the pool's event hooks. Times are kept in microseconds.
+#[derive(Debug, Default)]
+struct PoolMetrics {
+    checkouts: AtomicU64,
+    timeouts: AtomicU64,
+    wait_micros: AtomicU64,
+    max_wait_micros: AtomicU64,
+    held_micros: AtomicU64,
+    connections_opened: AtomicU64,
+    connections_closed: AtomicU64,
+}
+
+#[derive(Debug)]
+struct PoolMetricsHandler;
+
+impl HandleEvent for PoolMetricsHandler {
+    fn handle_acquire(&self, _: AcquireEvent) {
+        POOL_METRICS.connections_opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handle_release(&self, _: ReleaseEvent) {
+        POOL_METRICS.connections_closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        let wait = event.duration().as_micros() as u64;
+        POOL_METRICS.checkouts.fetch_add(1, Ordering::Relaxed);
+        POOL_METRICS.wait_micros.fetch_add(wait, Ordering::Relaxed);
+        POOL_METRICS.max_wait_micros.fetch_max(wait, Ordering::Relaxed);
+    }
+
+    fn handle_timeout(&self, _: TimeoutEvent) {
+        POOL_METRICS.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handle_checkin(&self, event: CheckinEvent) {
+        POOL_METRICS.held_micros.fetch_add(event.duration().as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+fn build_pool() -> DbPool {
     dotenv().ok();
 
     let database_url = get_database_path().to_str().unwrap().to_string();
     let manager = ConnectionManager::<SqliteConnection>::new(database_url);
     let pool = r2d2::Pool::builder()
-        .max_size(16)
+        .max_size(POOL_SIZE)
         .connection_customizer(Box::new(ConnectionOptions {
             enable_wal: true,
             enable_foreign_keys: true,
             busy_timeout: Some(Duration::from_secs(30)),
         }))
+        .event_handler(Box::new(PoolMetricsHandler))
         .build(manager)
         .expect("Failed to create pool.");
 
     Arc::new(pool)
 }
 
+/// The connection pool shared by the whole server. It is created on first use,
+/// which `main` makes happen at startup, and handed to routes as `web::Data`.
+pub fn establish_connection() -> DbPool {
+    POOL.clone()
+}
+
+#[derive(Debug)]
+pub enum DbError {
+    Pool(r2d2::PoolError),
+    Query(diesel::result::Error),
+    Blocking(BlockingError),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Pool(e) => write!(f, "Failed to get a database connection: {}", e),
+            DbError::Query(e) => write!(f, "Database query failed: {}", e),
+            DbError::Blocking(e) => write!(f, "Database task failed: {}", e),
+        }
+    }
+}
+
+impl Error for DbError {}
+
+impl From<diesel::result::Error> for DbError {
+    fn from(e: diesel::result::Error) -> Self {
+        DbError::Query(e)
+    }
+}
+
+/// Runs diesel work with a pooled connection on the blocking thread pool, so
+/// slow queries and lock waits don't stall the actix workers.
+pub async fn with_connection<T, F>(pool: &DbPool, query: F) -> Result<T, DbError>
+where
+    F: FnOnce(&mut SqliteConnection) -> QueryResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = pool.clone();
+    web::block(move || {
+        let mut connection = pool.get().map_err(DbError::Pool)?;
+        query(&mut connection).map_err(DbError::Query)
+    })
+    .await
+    .map_err(DbError::Blocking)?
+}
+
+/// The pool's current size and what its event hooks counted since startup.
+#[derive(Serialize, Debug)]
+pub struct PoolStats {
+    pub max_size: u32,
+    pub connections: u32,
+    pub idle_connections: u32,
+    pub in_use: u32,
+    pub checkouts: u64,
+    pub timeouts: u64,
+    pub average_wait_ms: f64,
+    pub max_wait_ms: f64,
+    pub average_hold_ms: f64,
+    pub connections_opened: u64,
+    pub connections_closed: u64,
+}
+
+pub fn pool_stats(pool: &DbPool) -> PoolStats {
+    let state = pool.state();
+    let checkouts = POOL_METRICS.checkouts.load(Ordering::Relaxed);
+    let per_checkout = |micros: u64| if checkouts == 0 { 0.0 } else { micros as f64 / checkouts as f64 / 1000.0 };
+
+    PoolStats {
+        max_size: pool.max_size(),
+        connections: state.connections,
+        idle_connections: state.idle_connections,
+        in_use: state.connections - state.idle_connections,
+        checkouts,
+        timeouts: POOL_METRICS.timeouts.load(Ordering::Relaxed),
+        average_wait_ms: per_checkout(POOL_METRICS.wait_micros.load(Ordering::Relaxed)),
+        max_wait_ms: POOL_METRICS.max_wait_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+        average_hold_ms: per_checkout(POOL_METRICS.held_micros.load(Ordering::Relaxed)),
+        connections_opened: POOL_METRICS.connections_opened.load(Ordering::Relaxed),
+        connections_closed: POOL_METRICS.connections_closed.load(Ordering::Relaxed),
+    }
+}
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
 
@@ -82,14 +218,6 @@ pub fn redo_migrations() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
     Ok(())
 }
 
-pub fn migrations_ran() -> bool {
-    let pool = establish_connection();
-    let mut conn = pool.get().expect("Failed to get a connection from the pool");
-    diesel::sql_query("SELECT 1 FROM diesel_schema_migrations LIMIT 1")
-        .execute(&mut conn)
-        .is_ok()
-}
-
 pub fn get_database_path() -> PathBuf {
     let path = if is_docker() {
         Path::new("/ParsonLabsMusic/Database").to_path_buf()
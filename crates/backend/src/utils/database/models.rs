@@ -3,8 +3,8 @@ use diesel::{prelude::*, sqlite::Sqlite};
 use serde::{Deserialize, Serialize};
 
 use super::schema::{
-    follow, listen_history_item, playlist, search_item, server_info, song,
-    user, _playlist_to_song, _playlist_to_user,
+    album, album_release, artist, follow, job, job_log, listen_history_item, playlist, scan_error, scan_report,
+    scan_state, search_item, server_info, song, task_run, user, _playlist_to_song, _playlist_to_user,
 };
 
 #[derive(Insertable, Queryable, Selectable, Debug, Serialize, Deserialize)]
@@ -130,4 +130,225 @@ pub struct ServerInfo {
     pub product_name: String,
     pub startup_wizard_completed: bool,
     pub login_disclaimer: Option<String>,
-}
\ No newline at end of file
+}
+
+#[derive(Queryable, Selectable, Debug, Clone, Serialize)]
+#[diesel(table_name = scan_state)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ScanState {
+    pub path: String,
+    pub library_path: String,
+    pub size: i64,
+    pub mtime: i64,
+    pub song_id: String,
+    pub album_name: String,
+    pub song: String,
+    pub scanned_at: NaiveDateTime,
+    pub album_artist: Option<String>,
+    pub compilation: bool,
+    pub release_date: Option<String>,
+    pub genres: String,
+    pub musicbrainz_release_id: Option<String>,
+    pub musicbrainz_release_group_id: Option<String>,
+    pub musicbrainz_artist_id: Option<String>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = scan_state)]
+pub struct NewScanState {
+    pub path: String,
+    pub library_path: String,
+    pub size: i64,
+    pub mtime: i64,
+    pub song_id: String,
+    pub album_name: String,
+    pub song: String,
+    pub album_artist: Option<String>,
+    pub compilation: bool,
+    pub release_date: Option<String>,
+    pub genres: String,
+    pub musicbrainz_release_id: Option<String>,
+    pub musicbrainz_release_group_id: Option<String>,
+    pub musicbrainz_artist_id: Option<String>,
+}
+
+#[derive(Insertable, Queryable, Selectable, Identifiable, AsChangeset, Debug, Clone, PartialEq)]
+#[diesel(table_name = artist)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct CatalogArtist {
+    pub id: String,
+    pub name: String,
+    pub icon_url: String,
+    pub followers: i64,
+    pub description: String,
+    pub tadb_music_videos: Option<String>,
+    pub featured_on_album_ids: String,
+    pub position: i32,
+    pub pending_enrichment: bool,
+    pub musicbrainz_id: Option<String>,
+}
+
+#[derive(Insertable, Queryable, Selectable, Identifiable, AsChangeset, Debug, Clone, PartialEq)]
+#[diesel(table_name = album)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct CatalogAlbum {
+    pub id: String,
+    pub artist_id: String,
+    pub name: String,
+    pub cover_url: String,
+    pub first_release_date: String,
+    pub musicbrainz_id: String,
+    pub wikidata_id: Option<String>,
+    pub primary_type: String,
+    pub description: String,
+    pub contributing_artists: String,
+    pub contributing_artists_ids: String,
+    pub position: i32,
+    pub edition: Option<String>,
+    pub album_type: String,
+    pub pending_enrichment: bool,
+    pub genres: String,
+    pub musicbrainz_release_id: Option<String>,
+    pub musicbrainz_release_group_id: Option<String>,
+}
+
+#[derive(Insertable, Queryable, Selectable, Debug, Clone, PartialEq)]
+#[diesel(table_name = album_release)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct AlbumRelease {
+    pub album_id: String,
+    pub release_album: Option<String>,
+    pub release_group_album: Option<String>,
+}
+
+#[derive(Insertable, Queryable, Selectable, AsChangeset, Debug, Clone, PartialEq)]
+#[diesel(table_name = song)]
+#[diesel(treat_none_as_null = true)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct CatalogSong {
+    pub id: String,
+    pub album_id: Option<String>,
+    pub name: String,
+    pub artist: String,
+    pub contributing_artists: String,
+    pub contributing_artist_ids: String,
+    pub track_number: i32,
+    pub path: String,
+    pub duration: f64,
+    pub music_video: Option<String>,
+    pub position: i32,
+    pub disc_number: i32,
+    pub disc_total: i32,
+    pub codec: Option<String>,
+    pub container: Option<String>,
+    pub sample_rate: Option<i32>,
+    pub bit_depth: Option<i32>,
+    pub channels: Option<i32>,
+    pub bitrate: Option<i32>,
+    pub file_size: Option<i64>,
+    pub musicbrainz_recording_id: Option<String>,
+    pub musicbrainz_track_id: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Debug, Clone, Serialize)]
+#[diesel(table_name = task_run)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct TaskRun {
+    pub id: i32,
+    pub task: String,
+    pub status: String,
+    pub message: Option<String>,
+    pub started_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    pub job_id: Option<i32>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = task_run)]
+pub struct NewTaskRun {
+    pub task: String,
+    pub status: String,
+    pub job_id: Option<i32>,
+}
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = job)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct JobRow {
+    pub id: i32,
+    pub payload: String,
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub progress: Option<String>,
+    pub result: Option<String>,
+    pub error: Option<String>,
+    pub run_after: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = job)]
+pub struct NewJob {
+    pub kind: String,
+    pub payload: String,
+    pub status: String,
+    pub max_attempts: i32,
+}
+
+#[derive(Queryable, Selectable, Debug, Clone, Serialize)]
+#[diesel(table_name = job_log)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct JobLog {
+    pub message: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = job_log)]
+pub struct NewJobLog {
+    pub job_id: i32,
+    pub message: String,
+}
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = scan_report)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ScanReportRow {
+    pub library_path: String,
+    pub added: String,
+    pub removed: String,
+    pub moved: String,
+    pub changed: String,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = scan_report)]
+pub struct NewScanReport {
+    pub library_path: String,
+    pub added: String,
+    pub removed: String,
+    pub moved: String,
+    pub changed: String,
+}
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = scan_error)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ScanErrorRow {
+    pub library_path: String,
+    pub path: String,
+    pub kind: String,
+    pub message: String,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = scan_error)]
+pub struct NewScanError {
+    pub library_path: String,
+    pub path: String,
+    pub kind: String,
+    pub message: String,
+}
@@ -29,6 +29,59 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    album (id) {
+        id -> Text,
+        artist_id -> Text,
+        name -> Text,
+        cover_url -> Text,
+        first_release_date -> Text,
+        musicbrainz_id -> Text,
+        wikidata_id -> Nullable<Text>,
+        primary_type -> Text,
+        description -> Text,
+        contributing_artists -> Text,
+        contributing_artists_ids -> Text,
+        position -> Integer,
+        edition -> Nullable<Text>,
+        album_type -> Text,
+        pending_enrichment -> Bool,
+        genres -> Text,
+        musicbrainz_release_id -> Nullable<Text>,
+        musicbrainz_release_group_id -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    album_release (album_id) {
+        album_id -> Text,
+        release_album -> Nullable<Text>,
+        release_group_album -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    artist (id) {
+        id -> Text,
+        name -> Text,
+        icon_url -> Text,
+        followers -> BigInt,
+        description -> Text,
+        tadb_music_videos -> Nullable<Text>,
+        featured_on_album_ids -> Text,
+        position -> Integer,
+        pending_enrichment -> Bool,
+        musicbrainz_id -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    catalog_meta (key) {
+        key -> Text,
+        value -> Text,
+    }
+}
+
 diesel::table! {
     favorite_song (user_id, song_id) {
         user_id -> Integer,
@@ -53,6 +106,33 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    job (id) {
+        id -> Integer,
+        kind -> Text,
+        payload -> Text,
+        status -> Text,
+        attempts -> Integer,
+        max_attempts -> Integer,
+        progress -> Nullable<Text>,
+        result -> Nullable<Text>,
+        error -> Nullable<Text>,
+        run_after -> Timestamp,
+        created_at -> Timestamp,
+        started_at -> Nullable<Timestamp>,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    job_log (id) {
+        id -> Integer,
+        job_id -> Integer,
+        message -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     listen_history_item (id) {
         id -> Integer,
@@ -120,6 +200,47 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    scan_error (id) {
+        id -> Integer,
+        library_path -> Text,
+        path -> Text,
+        kind -> Text,
+        message -> Text,
+    }
+}
+
+diesel::table! {
+    scan_report (library_path) {
+        library_path -> Text,
+        added -> Text,
+        removed -> Text,
+        moved -> Text,
+        changed -> Text,
+        scanned_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    scan_state (path) {
+        path -> Text,
+        library_path -> Text,
+        size -> BigInt,
+        mtime -> BigInt,
+        song_id -> Text,
+        album_name -> Text,
+        song -> Text,
+        scanned_at -> Timestamp,
+        album_artist -> Nullable<Text>,
+        compilation -> Bool,
+        release_date -> Nullable<Text>,
+        genres -> Text,
+        musicbrainz_release_id -> Nullable<Text>,
+        musicbrainz_release_group_id -> Nullable<Text>,
+        musicbrainz_artist_id -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     search_item (id) {
         id -> Integer,
@@ -145,6 +266,39 @@ diesel::table! {
     song (id) {
         id -> Text,
         added_at -> Timestamp,
+        album_id -> Nullable<Text>,
+        name -> Text,
+        artist -> Text,
+        contributing_artists -> Text,
+        contributing_artist_ids -> Text,
+        track_number -> Integer,
+        path -> Text,
+        duration -> Double,
+        music_video -> Nullable<Text>,
+        position -> Integer,
+        disc_number -> Integer,
+        disc_total -> Integer,
+        codec -> Nullable<Text>,
+        container -> Nullable<Text>,
+        sample_rate -> Nullable<Integer>,
+        bit_depth -> Nullable<Integer>,
+        channels -> Nullable<Integer>,
+        bitrate -> Nullable<Integer>,
+        file_size -> Nullable<BigInt>,
+        musicbrainz_recording_id -> Nullable<Text>,
+        musicbrainz_track_id -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    task_run (id) {
+        id -> Integer,
+        task -> Text,
+        status -> Text,
+        message -> Nullable<Text>,
+        started_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+        job_id -> Nullable<Integer>,
     }
 }
 
@@ -170,8 +324,11 @@ diesel::joinable!(_playlist_to_user -> playlist (a));
 diesel::joinable!(_playlist_to_user -> user (b));
 diesel::joinable!(_song_to_genre -> genre (genre_id));
 diesel::joinable!(_song_to_genre -> song (song_id));
+diesel::joinable!(album -> artist (artist_id));
+diesel::joinable!(album_release -> album (album_id));
 diesel::joinable!(favorite_song -> song (song_id));
 diesel::joinable!(favorite_song -> user (user_id));
+diesel::joinable!(job_log -> job (job_id));
 diesel::joinable!(listen_history_item -> user (user_id));
 diesel::joinable!(lyrics -> song (song_id));
 diesel::joinable!(lyrics_contribution -> lyrics (lyrics_id));
@@ -179,23 +336,35 @@ diesel::joinable!(lyrics_contribution -> user (user_id));
 diesel::joinable!(lyrics_view_history -> song (song_id));
 diesel::joinable!(lyrics_view_history -> user (user_id));
 diesel::joinable!(playlist_stats -> playlist (playlist_id));
+diesel::joinable!(scan_error -> scan_report (library_path));
 diesel::joinable!(search_item -> user (user_id));
+diesel::joinable!(task_run -> job (job_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     _playlist_to_song,
     _playlist_to_user,
     _song_to_genre,
+    album,
+    album_release,
+    artist,
+    catalog_meta,
     favorite_song,
     follow,
     genre,
+    job,
+    job_log,
     listen_history_item,
     lyrics,
     lyrics_contribution,
     lyrics_view_history,
     playlist,
     playlist_stats,
+    scan_error,
+    scan_report,
+    scan_state,
     search_item,
     server_info,
     song,
+    task_run,
     user,
 );
@@ -1,60 +1,210 @@
-use std::collections::HashSet;
-use regex::Regex;
+use std::fs;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::config::get_artist_splitting_config_path;
+
+/// Delimiters and protected names used when an artist tag holds a single
+/// string that has to be split. Stored in `artist_splitting.json` and editable
+/// by admins through `/library/artist_splitting`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ArtistSplitSettings {
+    pub delimiters: Vec<String>,
+    pub exceptions: Vec<String>,
+}
+
+impl Default for ArtistSplitSettings {
+    fn default() -> Self {
+        ArtistSplitSettings {
+            delimiters: [
+                " & ", " featuring ", " ft. ", " with ", " feat. ", " and ", " presents ", ", ", " vs. ", " x ", ";"
+            ].iter().map(|s| s.to_string()).collect(),
+            exceptions: [
+                "Simon & Garfunkel", "Earth, Wind & Fire", "Charli XCX", "Crosby, Stills, Nash & Young",
+                "Hall & Oates", "Florence + the Machine", "Tyler, the Creator", "Mumford & Sons"
+            ].iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref ARTIST_SPLIT_SETTINGS: RwLock<ArtistSplitSettings> = RwLock::new(ArtistSplitSettings::default());
+}
+
+pub fn artist_split_settings() -> ArtistSplitSettings {
+    ARTIST_SPLIT_SETTINGS.read().unwrap().clone()
+}
+
+/// Loads the saved settings into memory, keeping the defaults if none were saved.
+pub fn load_artist_split_settings() -> Result<(), Box<dyn std::error::Error>> {
+    let path = get_artist_splitting_config_path();
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let settings: ArtistSplitSettings = serde_json::from_str(&fs::read_to_string(path)?)?;
+    *ARTIST_SPLIT_SETTINGS.write().unwrap() = settings;
+    Ok(())
+}
+
+pub fn save_artist_split_settings(settings: ArtistSplitSettings) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(get_artist_splitting_config_path(), serde_json::to_string_pretty(&settings)?)?;
+    *ARTIST_SPLIT_SETTINGS.write().unwrap() = settings;
+    Ok(())
+}
 
 pub fn format_contributing_artists(artists: Vec<String>) -> Vec<(String, Vec<String>)> {
-    let delimiters = vec![
-        " & ", " featuring ", " ft. ", " with ", " feat. ", " and ", " presents ", ", ", " vs. ", " x ", "X", ";", "\0"
-    ];
+    format_contributing_artists_with(artists, &artist_split_settings())
+}
+
+// Exception names are swapped for single-word placeholders before splitting, so
+// no delimiter can match inside them while word boundaries around them still do.
+fn protect_exceptions(artist: &str, exceptions: &[String]) -> (String, Vec<String>) {
+    let mut protected = artist.to_string();
+    let mut originals = Vec::new();
+
+    for exception in exceptions.iter().filter(|e| !e.trim().is_empty()) {
+        let re = Regex::new(&format!(r"(?i){}", regex::escape(exception.trim()))).unwrap();
+        protected = re.replace_all(&protected, |captures: &Captures| {
+            originals.push(captures[0].to_string());
+            format!("artistexception{}", originals.len() - 1)
+        }).to_string();
+    }
+
+    (protected, originals)
+}
+
+fn restore_exceptions(artist: &str, originals: &[String]) -> String {
+    let re_placeholder = Regex::new(r"artistexception(\d+)").unwrap();
+    re_placeholder.replace_all(artist, |captures: &Captures| {
+        captures[1].parse::<usize>().ok()
+            .and_then(|index| originals.get(index))
+            .cloned()
+            .unwrap_or_default()
+    }).to_string()
+}
+
+// Words and spaced delimiters only match between words, so " x " doesn't split
+// "X Ambassadors". A delimiter that starts or ends with punctuation, like ";",
+// takes the spaces next to it instead, so "A; B" and "A;B" both split.
+fn delimiter_pattern(delimiter: &str) -> String {
+    let edge = |c: Option<char>| match c {
+        Some(c) if c.is_alphanumeric() || c.is_whitespace() => r"\b",
+        _ => r"\s*",
+    };
+
+    format!(
+        "(?i){}{}{}",
+        edge(delimiter.chars().next()),
+        regex::escape(delimiter),
+        edge(delimiter.chars().last())
+    )
+}
+
+pub fn format_contributing_artists_with(artists: Vec<String>, settings: &ArtistSplitSettings) -> Vec<(String, Vec<String>)> {
+    let delimiters: Vec<Regex> = settings.delimiters.iter()
+        .filter(|delimiter| !delimiter.is_empty())
+        .map(|delimiter| Regex::new(&delimiter_pattern(delimiter)).unwrap())
+        .collect();
+    let re_parentheses = Regex::new(r"\(([^)]+)\)").unwrap();
 
     let mut formatted_artists = Vec::new();
 
     for artist in &artists {
+        let (artist, originals) = protect_exceptions(artist, &settings.exceptions);
         let mut main_artist = artist.clone();
-        let mut contributing_artists = Vec::new();
 
-        let re_parentheses = Regex::new(r"\(([^)]+)\)").unwrap();
         if let Some(captures) = re_parentheses.captures(&artist) {
             let inside_parentheses = captures.get(1).unwrap().as_str();
             main_artist = artist.replace(&format!("({})", inside_parentheses), "").trim().to_string();
             main_artist = format!("{} {}", main_artist, inside_parentheses).trim().to_string();
         }
 
-        let mut split_artists = vec![main_artist.clone()];
-        for delimiter in &delimiters {
-            let re = Regex::new(&format!(r"(?i)\b{}\b", regex::escape(delimiter))).unwrap();
-            let mut temp_split = Vec::new();
-            for part in split_artists {
-                let parts: Vec<String> = re.split(&part)
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-                    .collect();
-                temp_split.extend(parts);
-            }
-            split_artists = temp_split;
+        let mut split_artists = vec![main_artist];
+        for re in &delimiters {
+            split_artists = split_artists.iter()
+                .flat_map(|part| re.split(part).map(|s| s.trim().to_string()).collect::<Vec<_>>())
+                .filter(|s| !s.is_empty())
+                .collect();
         }
 
+        let split_artists: Vec<String> = split_artists.iter()
+            .map(|artist| restore_exceptions(artist, &originals))
+            .collect();
+
         if let Some((main_artist, additional_contributing_artists)) = split_artists.split_first() {
             let main_artist = main_artist.to_string();
-            let additional_contributing_artists: Vec<String> = additional_contributing_artists.iter()
-                .flat_map(|artist| {
-                    delimiters.iter().fold(vec![artist.clone()], |acc, delim| {
-                        let re = Regex::new(&format!(r"(?i)\b{}\b", regex::escape(delim))).unwrap();
-                        acc.into_iter().flat_map(|s| re.split(&s).map(|s| s.trim().to_string()).collect::<Vec<_>>()).collect()
-                    })
-                })
-                .filter(|artist| !artist.is_empty() && artist.to_lowercase() != main_artist.to_lowercase())
-                .collect();
-
-            contributing_artists.extend(additional_contributing_artists);
 
-            let contributing_artists: HashSet<String> = contributing_artists.into_iter().map(|artist| {
-                artist.replace("'", "").replace("  ", " ")
-            }).collect();
+            // Keep tag order so the same file always produces the same credits
+            let mut contributing_artists: Vec<String> = Vec::new();
+            for artist in additional_contributing_artists {
+                let artist = artist.replace("'", "").replace("  ", " ");
+                if !artist.is_empty()
+                    && artist.to_lowercase() != main_artist.to_lowercase()
+                    && !contributing_artists.contains(&artist)
+                {
+                    contributing_artists.push(artist);
+                }
+            }
 
-            let contributing_artists: Vec<String> = contributing_artists.into_iter().collect();
             formatted_artists.push((main_artist, contributing_artists));
         }
     }
 
     formatted_artists
-}
\ No newline at end of file
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AlbumTitle {
+    pub name: String,
+    pub disc_number: Option<u16>,
+    pub edition: Option<String>,
+}
+
+pub fn parse_disc_number(text: &str) -> Option<u16> {
+    let re_disc = Regex::new(r"(?i)^(?:cd|dis[ck])\s*(\d+)(?:\s*(?:of|/)\s*\d+)?$").unwrap();
+
+    re_disc.captures(text.trim())
+        .and_then(|captures| captures[1].parse().ok())
+}
+
+pub fn parse_album_title(album_name: &str) -> AlbumTitle {
+    let re_suffix = Regex::new(r"\s*[(\[]([^)\]]+)[)\]]").unwrap();
+    let re_trailing_disc = Regex::new(r"(?i)[\s\-–:_,]+((?:cd|dis[ck])\s*\d+)\s*$").unwrap();
+    let re_edition = Regex::new(r"(?i)\b(edition|deluxe|remaster(ed)?|expanded|anniversary|special|collector'?s|bonus|reissue|version)\b").unwrap();
+
+    let mut disc_number = None;
+    let mut edition = None;
+
+    let name = re_suffix.replace_all(album_name, |captures: &Captures| {
+        let inside = captures[1].trim();
+
+        if let Some(disc) = parse_disc_number(inside) {
+            disc_number = Some(disc);
+            return String::new();
+        }
+
+        if edition.is_none() && re_edition.is_match(inside) {
+            edition = Some(inside.to_string());
+        }
+        captures[0].to_string()
+    }).to_string();
+
+    let name = match re_trailing_disc.captures(&name) {
+        Some(captures) => {
+            disc_number = disc_number.or_else(|| parse_disc_number(&captures[1]));
+            name[..captures.get(0).unwrap().start()].to_string()
+        }
+        None => name,
+    };
+
+    AlbumTitle {
+        name: name.trim().to_string(),
+        disc_number,
+        edition,
+    }
+}
@@ -1,6 +1,8 @@
 #[cfg(test)]
 mod tests {
-    use crate::utils::format::format_contributing_artists;
+    use crate::utils::format::{
+        format_contributing_artists, format_contributing_artists_with, parse_album_title, AlbumTitle, ArtistSplitSettings,
+    };
 
     #[test]
     fn test_single_artist() {
@@ -188,4 +190,125 @@ mod tests {
         let result = format_contributing_artists(artists);
         assert_eq!(result, vec![]);
     }
+
+    #[test]
+    fn test_exception_not_split() {
+        let artists = vec!["Simon & Garfunkel".to_string(), "Earth, Wind & Fire".to_string()];
+        let result = format_contributing_artists(artists);
+        assert_eq!(
+            result,
+            vec![
+                ("Simon & Garfunkel".to_string(), vec![]),
+                ("Earth, Wind & Fire".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exception_with_featuring() {
+        let artists = vec!["Earth, Wind & Fire featuring The Emotions".to_string()];
+        let result = format_contributing_artists(artists);
+        assert_eq!(
+            result,
+            vec![("Earth, Wind & Fire".to_string(), vec!["The Emotions".to_string()])]
+        );
+    }
+
+    #[test]
+    fn test_exception_as_contributor() {
+        let artists = vec!["Troye Sivan & Charli XCX".to_string()];
+        let result = format_contributing_artists(artists);
+        assert_eq!(
+            result,
+            vec![("Troye Sivan".to_string(), vec!["Charli XCX".to_string()])]
+        );
+    }
+
+    #[test]
+    fn test_bare_x_not_split() {
+        let artists = vec!["X Ambassadors".to_string()];
+        let result = format_contributing_artists(artists);
+        assert_eq!(result, vec![("X Ambassadors".to_string(), vec![])]);
+    }
+
+    #[test]
+    fn test_semicolon_separated() {
+        let artists = vec!["Drake; Future;21 Savage".to_string()];
+        let result = format_contributing_artists(artists);
+        assert_eq!(
+            result,
+            vec![("Drake".to_string(), vec!["Future".to_string(), "21 Savage".to_string()])]
+        );
+    }
+
+    #[test]
+    fn test_custom_exception() {
+        let settings = ArtistSplitSettings {
+            exceptions: vec!["Belle and Sebastian".to_string()],
+            ..ArtistSplitSettings::default()
+        };
+        let artists = vec!["Belle and Sebastian".to_string()];
+        let result = format_contributing_artists_with(artists, &settings);
+        assert_eq!(result, vec![("Belle and Sebastian".to_string(), vec![])]);
+    }
+
+    #[test]
+    fn test_custom_delimiters() {
+        let settings = ArtistSplitSettings {
+            delimiters: vec![" / ".to_string()],
+            exceptions: vec![],
+        };
+        let artists = vec!["Daft Punk / Pharrell Williams & Nile Rodgers".to_string()];
+        let result = format_contributing_artists_with(artists, &settings);
+        assert_eq!(
+            result,
+            vec![("Daft Punk".to_string(), vec!["Pharrell Williams & Nile Rodgers".to_string()])]
+        );
+    }
+
+    #[test]
+    fn test_album_title_disc_suffix() {
+        let result = parse_album_title("Mellon Collie and the Infinite Sadness (Disc 2)");
+        assert_eq!(
+            result,
+            AlbumTitle {
+                name: "Mellon Collie and the Infinite Sadness".to_string(),
+                disc_number: Some(2),
+                edition: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_album_title_edition_preserved() {
+        let result = parse_album_title("Random Access Memories (10th Anniversary Edition) [CD1]");
+        assert_eq!(
+            result,
+            AlbumTitle {
+                name: "Random Access Memories (10th Anniversary Edition)".to_string(),
+                disc_number: Some(1),
+                edition: Some("10th Anniversary Edition".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_album_title_trailing_disc() {
+        let result = parse_album_title("The Wall - CD 2");
+        assert_eq!(result.name, "The Wall");
+        assert_eq!(result.disc_number, Some(2));
+    }
+
+    #[test]
+    fn test_album_title_other_parentheses_kept() {
+        let result = parse_album_title("Unplugged (Live)");
+        assert_eq!(
+            result,
+            AlbumTitle {
+                name: "Unplugged (Live)".to_string(),
+                disc_number: None,
+                edition: None,
+            }
+        );
+    }
 }
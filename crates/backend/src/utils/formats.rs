@@ -0,0 +1,168 @@
+use std::fs::File;
+use std::path::Path;
+
+use lofty::mp4::{Mp4Codec, Mp4File};
+use lofty::{AudioFile, ParseOptions};
+
+pub struct AudioFormat {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    pub codec: &'static str,
+    pub container: &'static str,
+    pub mime_type: &'static str,
+    pub lossless: bool,
+    /// Whether lofty can read tags and audio properties from the format.
+    /// Other formats are indexed from their file and directory names.
+    pub readable: bool,
+}
+
+const fn entry(
+    name: &'static str,
+    extensions: &'static [&'static str],
+    codec: &'static str,
+    container: &'static str,
+    mime_type: &'static str,
+    lossless: bool,
+    readable: bool,
+) -> AudioFormat {
+    AudioFormat { name, extensions, codec, container, mime_type, lossless, readable }
+}
+
+pub const AUDIO_FORMATS: &[AudioFormat] = &[
+    entry("mp3", &["mp3"], "MP3", "MPEG", "audio/mpeg", false, true),
+    entry("aac", &["aac"], "AAC", "ADTS", "audio/aac", false, true),
+    entry("m4a", &["m4a", "m4b"], "AAC", "MP4", "audio/mp4", false, true),
+    // ALAC shares the .m4a extension and is told apart by `detect_format`
+    entry("alac", &[], "ALAC", "MP4", "audio/mp4", true, true),
+    entry("flac", &["flac"], "FLAC", "FLAC", "audio/flac", true, true),
+    entry("ogg", &["ogg", "oga"], "Vorbis", "Ogg", "audio/ogg", false, true),
+    entry("opus", &["opus"], "Opus", "Ogg", "audio/ogg; codecs=opus", false, true),
+    entry("wav", &["wav", "wave"], "PCM", "WAV", "audio/wav", true, true),
+    entry("aiff", &["aif", "aiff", "aifc"], "PCM", "AIFF", "audio/aiff", true, true),
+    entry("ape", &["ape"], "APE", "APE", "audio/x-ape", true, true),
+    entry("wavpack", &["wv"], "WavPack", "WavPack", "audio/x-wavpack", true, true),
+    entry("wma", &["wma"], "WMA", "ASF", "audio/x-ms-wma", false, false),
+    entry("dsf", &["dsf"], "DSD", "DSF", "audio/x-dsf", true, false),
+    entry("dff", &["dff"], "DSD", "DSDIFF", "audio/x-dff", true, false),
+];
+
+pub fn format_by_name(name: &str) -> Option<&'static AudioFormat> {
+    AUDIO_FORMATS.iter().find(|format| format.name == name)
+}
+
+/// Looks a file's format up by its extension alone.
+pub fn audio_format(path: &Path) -> Option<&'static AudioFormat> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    AUDIO_FORMATS.iter().find(|format| format.extensions.contains(&extension.as_str()))
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+    audio_format(path).is_some()
+}
+
+/// MP4 files can hold AAC, ALAC, MP3 or FLAC streams, which only the MP4
+/// specific properties tell apart.
+pub fn read_mp4_codec(path: &Path) -> Option<&'static str> {
+    let mut file = File::open(path).ok()?;
+    let mp4_file = Mp4File::read_from(&mut file, ParseOptions::new()).ok()?;
+
+    match mp4_file.properties().codec() {
+        Mp4Codec::AAC => Some("AAC"),
+        Mp4Codec::ALAC => Some("ALAC"),
+        Mp4Codec::MP3 => Some("MP3"),
+        Mp4Codec::FLAC => Some("FLAC"),
+        _ => None,
+    }
+}
+
+/// Like `audio_format`, but opens MP4 files to tell ALAC from AAC.
+pub fn detect_format(path: &Path) -> Option<&'static AudioFormat> {
+    let format = audio_format(path)?;
+
+    if format.container == "MP4" && read_mp4_codec(path) == Some("ALAC") {
+        return format_by_name("alac");
+    }
+
+    Some(format)
+}
+
+/// Formats a client can play without transcoding. Anything not listed for a
+/// client is transcoded before it is streamed.
+pub struct ClientProfile {
+    pub name: &'static str,
+    pub formats: &'static [&'static str],
+}
+
+pub const CLIENT_PROFILES: &[ClientProfile] = &[
+    ClientProfile { name: "web", formats: &["mp3", "aac", "m4a", "flac", "ogg", "opus", "wav"] },
+    ClientProfile { name: "safari", formats: &["mp3", "aac", "m4a", "alac", "flac", "wav", "aiff"] },
+    ClientProfile { name: "ios", formats: &["mp3", "aac", "m4a", "alac", "flac", "wav", "aiff"] },
+    ClientProfile { name: "android", formats: &["mp3", "aac", "m4a", "flac", "ogg", "opus", "wav"] },
+];
+
+/// Picks the client profile from an explicit name, falling back to the
+/// User-Agent and then to the generic web profile.
+pub fn client_profile(name: Option<&str>, user_agent: Option<&str>) -> &'static ClientProfile {
+    let name = name.map(|name| name.to_lowercase()).unwrap_or_else(|| {
+        let user_agent = user_agent.unwrap_or_default();
+
+        if user_agent.contains("iPhone") || user_agent.contains("iPad") {
+            "ios".to_string()
+        } else if user_agent.contains("Android") {
+            "android".to_string()
+        } else if user_agent.contains("Safari") && !user_agent.contains("Chrome") && !user_agent.contains("Chromium") {
+            "safari".to_string()
+        } else {
+            "web".to_string()
+        }
+    });
+
+    CLIENT_PROFILES.iter()
+        .find(|profile| profile.name == name)
+        .unwrap_or(&CLIENT_PROFILES[0])
+}
+
+pub struct TranscodeTarget {
+    pub ffmpeg_format: &'static str,
+    pub codec: &'static str,
+    pub bitrate: Option<u32>,
+    pub content_type: &'static str,
+}
+
+pub enum StreamDecision {
+    Direct(&'static str),
+    Transcode(TranscodeTarget),
+}
+
+pub fn mp3_target(bitrate: u32) -> TranscodeTarget {
+    TranscodeTarget {
+        ffmpeg_format: "mp3",
+        codec: "libmp3lame",
+        bitrate: Some(bitrate),
+        content_type: "audio/mpeg",
+    }
+}
+
+/// Decides how `stream_song` serves a format to a client. A requested bitrate
+/// always transcodes to MP3; otherwise formats the client can't play are
+/// transcoded to FLAC when the source is lossless, or to 320k MP3.
+pub fn stream_decision(format: &AudioFormat, client: &ClientProfile, bitrate: u32) -> StreamDecision {
+    if bitrate > 0 {
+        return StreamDecision::Transcode(mp3_target(bitrate));
+    }
+
+    if client.formats.contains(&format.name) {
+        return StreamDecision::Direct(format.mime_type);
+    }
+
+    if format.lossless && client.formats.contains(&"flac") {
+        StreamDecision::Transcode(TranscodeTarget {
+            ffmpeg_format: "flac",
+            codec: "flac",
+            bitrate: None,
+            content_type: "audio/flac",
+        })
+    } else {
+        StreamDecision::Transcode(mp3_target(320))
+    }
+}
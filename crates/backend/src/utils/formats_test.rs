@@ -0,0 +1,113 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    use crate::utils::formats::{
+        audio_format, client_profile, format_by_name, is_audio_file, stream_decision, StreamDecision, AUDIO_FORMATS,
+        CLIENT_PROFILES,
+    };
+
+    /// The content type a decision serves, and the MP3 bitrate or the
+    /// transcoding codec when it transcodes.
+    fn describe(decision: StreamDecision) -> (&'static str, &'static str, Option<u32>) {
+        match decision {
+            StreamDecision::Direct(content_type) => (content_type, "direct", None),
+            StreamDecision::Transcode(target) => (target.content_type, target.codec, target.bitrate),
+        }
+    }
+
+    #[test]
+    fn test_formats_by_extension() {
+        let cases = [
+            // path, format, lossless, readable
+            ("song.mp3", Some("mp3"), false, true),
+            ("song.M4A", Some("m4a"), false, true),
+            ("book.m4b", Some("m4a"), false, true),
+            ("song.oga", Some("ogg"), false, true),
+            ("song.opus", Some("opus"), false, true),
+            ("song.wma", Some("wma"), false, false),
+            ("song.flac", Some("flac"), true, true),
+            ("song.wave", Some("wav"), true, true),
+            ("song.aifc", Some("aiff"), true, true),
+            ("song.wv", Some("wavpack"), true, true),
+            ("song.dsf", Some("dsf"), true, false),
+            ("song.mka", None, false, false),
+            ("cover.jpg", None, false, false),
+            ("no_extension", None, false, false),
+        ];
+
+        for (path, name, lossless, readable) in cases {
+            let format = audio_format(Path::new(path));
+            assert_eq!(format.map(|format| format.name), name, "{}", path);
+            assert_eq!(is_audio_file(Path::new(path)), name.is_some(), "{}", path);
+            if let Some(format) = format {
+                assert_eq!(format.lossless, lossless, "{}", path);
+                assert_eq!(format.readable, readable, "{}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_table_is_consistent() {
+        let mut extensions = HashSet::new();
+        for format in AUDIO_FORMATS {
+            assert_eq!(format_by_name(format.name).map(|found| found.name), Some(format.name));
+            for extension in format.extensions {
+                assert_eq!(extension.to_lowercase(), *extension, "{}", format.name);
+                assert!(extensions.insert(*extension), "{} is listed twice", extension);
+            }
+        }
+
+        for profile in CLIENT_PROFILES {
+            for name in profile.formats {
+                assert!(format_by_name(name).is_some(), "{} lists unknown format {}", profile.name, name);
+            }
+        }
+        assert!(format_by_name("mka").is_none());
+    }
+
+    #[test]
+    fn test_client_profiles() {
+        let cases = [
+            // explicit name, User-Agent, profile
+            (Some("iOS"), None, "ios"),
+            (Some("unknown"), None, "web"),
+            (None, Some("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0) Safari/604.1"), "ios"),
+            (None, Some("Mozilla/5.0 (Linux; Android 14) Chrome/120.0 Mobile Safari/537.36"), "android"),
+            (None, Some("Mozilla/5.0 (Macintosh) Version/17.0 Safari/605.1.15"), "safari"),
+            (None, Some("Mozilla/5.0 (Windows NT 10.0) Chrome/120.0 Safari/537.36"), "web"),
+            (None, None, "web"),
+        ];
+
+        for (name, user_agent, expected) in cases {
+            assert_eq!(client_profile(name, user_agent).name, expected, "{:?} {:?}", name, user_agent);
+        }
+    }
+
+    #[test]
+    fn test_stream_decisions() {
+        let cases = [
+            // format, client, requested bitrate, expected decision
+            ("flac", "web", 0, ("audio/flac", "direct", None)),
+            ("mp3", "ios", 0, ("audio/mpeg", "direct", None)),
+            ("opus", "android", 0, ("audio/ogg; codecs=opus", "direct", None)),
+            ("alac", "safari", 0, ("audio/mp4", "direct", None)),
+            // Lossless sources the client can't play stay lossless
+            ("alac", "web", 0, ("audio/flac", "flac", None)),
+            ("aiff", "android", 0, ("audio/flac", "flac", None)),
+            ("dsf", "ios", 0, ("audio/flac", "flac", None)),
+            // Lossy ones become MP3
+            ("ogg", "safari", 0, ("audio/mpeg", "libmp3lame", Some(320))),
+            ("wma", "web", 0, ("audio/mpeg", "libmp3lame", Some(320))),
+            // A requested bitrate always transcodes
+            ("mp3", "web", 128, ("audio/mpeg", "libmp3lame", Some(128))),
+            ("flac", "ios", 256, ("audio/mpeg", "libmp3lame", Some(256))),
+        ];
+
+        for (format, client, bitrate, expected) in cases {
+            let decision = stream_decision(format_by_name(format).unwrap(), client_profile(Some(client), None), bitrate);
+            assert_eq!(describe(decision), expected, "{} on {} at {}", format, client, bitrate);
+        }
+    }
+}
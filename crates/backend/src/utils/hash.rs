@@ -1,23 +1,47 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use sha2::{Digest, Sha256};
 
-pub fn hash_artist(name: &String) -> String {
-  let mut hasher = DefaultHasher::new();
+/// Version of the ID scheme below. Bump it whenever the hashed fields or their
+/// encoding change, and teach `id_migration` how to remap the previous version.
+pub const ID_VERSION: u32 = 3;
 
-  name.to_owned().hash(&mut hasher);
-  hasher.finish().to_string()
+/// Hashes `fields` into a stable ID. Every field is length-prefixed so values
+/// can't bleed into each other ("AB" + "C" and "A" + "BC" hash differently),
+/// and the kind keeps artists, albums and songs with equal fields apart.
+fn stable_id(kind: &str, fields: &[&str]) -> String {
+  let mut hasher = Sha256::new();
+
+  for field in std::iter::once(kind).chain(fields.iter().copied()) {
+    hasher.update((field.len() as u64).to_le_bytes());
+    hasher.update(field.as_bytes());
+  }
+
+  let digest = hasher.finalize();
+  let hex: String = digest[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+
+  format!("v{}-{}", ID_VERSION, hex)
 }
 
-pub fn hash_song(name: &String, artist: &String, album: &String, track_number: u16) -> String {
-  let mut hasher = DefaultHasher::new();
+/// Returns the ID scheme version an ID was generated with. IDs without a
+/// version prefix come from the original `DefaultHasher` scheme (version 1).
+pub fn id_version(id: &str) -> u32 {
+  id.strip_prefix('v')
+    .and_then(|rest| rest.split_once('-'))
+    .and_then(|(version, _)| version.parse().ok())
+    .unwrap_or(1)
+}
 
-  (name.to_owned() + artist + album + track_number.to_string().as_str()).hash(&mut hasher);
-  hasher.finish().to_string()
+pub fn hash_artist(name: &str) -> String {
+  stable_id("artist", &[&name.to_lowercase()])
 }
 
-pub fn hash_album(name: &String, artist: &String) -> String {
-  let mut hasher = DefaultHasher::new();
+pub fn hash_song(name: &str, artist: &str, album: &str, disc_number: u16, track_number: u16) -> String {
+  stable_id("song", &[name, &artist.to_lowercase(), album, &disc_number.to_string(), &track_number.to_string()])
+}
+
+pub fn hash_album(name: &str, artist: &str) -> String {
+  stable_id("album", &[name, &artist.to_lowercase()])
+}
 
-  (name.to_owned() + artist).hash(&mut hasher);
-  hasher.finish().to_string()
+pub fn hash_library(path: &str) -> String {
+  stable_id("library", &[path])
 }
@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::structures::structures::{Album, Artist, Song};
+    use crate::utils::hash::{hash_album, hash_artist, hash_library, hash_song, id_version, ID_VERSION};
+    use crate::utils::id_migration::{build_remap, TaggedAlbum};
+
+    // Every stored playlist, favorite and listen history row refers to these
+    // values, so they must never change without bumping `ID_VERSION`.
+    #[test]
+    fn test_golden_ids() {
+        assert_eq!(ID_VERSION, 3);
+        assert_eq!(hash_artist("Daft Punk"), "v3-a0f0f69724c0f96d");
+        assert_eq!(hash_album("Discovery", "Daft Punk"), "v3-fac18710749e6875");
+        assert_eq!(hash_song("One More Time", "Daft Punk", "Discovery", 1, 1), "v3-5ca60918e611a050");
+        assert_eq!(hash_library("/music"), "v3-74533bcf0fcd5918");
+    }
+
+    #[test]
+    fn test_artist_names_ignore_case() {
+        assert_eq!(hash_artist("Daft Punk"), hash_artist("DAFT PUNK"));
+        assert_eq!(hash_album("Discovery", "Daft Punk"), hash_album("Discovery", "daft punk"));
+        assert_ne!(hash_album("Discovery", "Daft Punk"), hash_album("discovery", "Daft Punk"));
+    }
+
+    #[test]
+    fn test_fields_dont_run_together() {
+        assert_ne!(hash_album("AB", "C"), hash_album("A", "BC"));
+        assert_ne!(hash_song("A", "B", "C", 1, 1), hash_song("AB", "", "C", 1, 1));
+    }
+
+    #[test]
+    fn test_disc_number_keeps_songs_apart() {
+        // Multi-disc albums often share one album title and restart track numbers
+        assert_ne!(hash_song("Intro", "Artist", "Album", 1, 1), hash_song("Intro", "Artist", "Album", 2, 1));
+    }
+
+    #[test]
+    fn test_kinds_dont_collide() {
+        assert_ne!(hash_artist("Name"), hash_library("name"));
+    }
+
+    #[test]
+    fn test_id_version() {
+        assert_eq!(id_version(&hash_artist("Daft Punk")), ID_VERSION);
+        assert_eq!(id_version("11862823438465387227"), 1);
+        assert_eq!(id_version("v2-5d28650d495aa995"), 2);
+    }
+
+    fn song(id: &str, name: &str, artist: &str, track_number: u16, path: &str) -> Song {
+        Song {
+            id: id.to_string(),
+            name: name.to_string(),
+            artist: artist.to_string(),
+            contributing_artists: Vec::new(),
+            contributing_artist_ids: Vec::new(),
+            track_number,
+            disc_number: 1,
+            disc_total: 1,
+            path: path.to_string(),
+            duration: 180.0,
+            audio: None,
+            music_video: None,
+            musicbrainz_recording_id: None,
+            musicbrainz_track_id: None,
+        }
+    }
+
+    fn tagged(name: &str, album_artist: Option<&str>, compilation: bool) -> TaggedAlbum {
+        TaggedAlbum {
+            name: name.to_string(),
+            album_artist: album_artist.map(str::to_string),
+            compilation,
+        }
+    }
+
+    /// A catalog with version 1 ids: an album that holds songs the scanner
+    /// merged in from another artist, and a compilation.
+    fn version_1_catalog() -> Vec<Artist> {
+        vec![
+            Artist {
+                id: "101".to_string(),
+                name: "Daft Punk".to_string(),
+                albums: vec![Album {
+                    id: "201".to_string(),
+                    name: "Discovery".to_string(),
+                    songs: vec![
+                        song("301", "Digital Love", "Romanthony", 3, "/music/03.flac"),
+                        song("302", "One More Time", "Daft Punk", 1, "/music/01.flac"),
+                    ],
+                    ..Default::default()
+                }],
+                featured_on_album_ids: Vec::new(),
+                ..Default::default()
+            },
+            Artist {
+                id: "102".to_string(),
+                name: "Various Artists".to_string(),
+                albums: vec![Album {
+                    id: "202".to_string(),
+                    name: "Hits".to_string(),
+                    songs: vec![song("303", "Song", "Someone", 1, "/music/hits/01.flac")],
+                    ..Default::default()
+                }],
+                featured_on_album_ids: Vec::new(),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_remap_uses_scanner_keys() {
+        let tagged_albums = HashMap::from([
+            ("/music/03.flac".to_string(), tagged("Discovery (Deluxe Edition) (Disc 1)", None, false)),
+            ("/music/01.flac".to_string(), tagged("Discovery (Deluxe Edition) (Disc 1)", None, false)),
+            ("/music/hits/01.flac".to_string(), tagged("Hits", None, true)),
+        ]);
+        let remap = build_remap(&version_1_catalog(), &tagged_albums);
+
+        assert_eq!(remap.artists["101"], hash_artist("Daft Punk"));
+        // Keyed by the owning artist's songs, not the merged-in ones, and by the
+        // name the scanner gives the album rather than the version 1 one
+        assert_eq!(remap.albums["201"], hash_album("Discovery (Deluxe Edition)", "Daft Punk"));
+        assert_eq!(remap.albums["202"], hash_album("Hits", "Various Artists"));
+        // Songs keep the album name from their tags, disc suffix included
+        assert_eq!(remap.songs["302"], hash_song("One More Time", "Daft Punk", "Discovery (Deluxe Edition) (Disc 1)", 1, 1));
+        assert_eq!(remap.songs["301"], "v3-74a4cc7d9a3b351c");
+    }
+
+    #[test]
+    fn test_remap_without_scan_state() {
+        let remap = build_remap(&version_1_catalog(), &HashMap::new());
+
+        assert_eq!(remap.albums["201"], hash_album("Discovery", "Daft Punk"));
+        assert_eq!(remap.songs["302"], hash_song("One More Time", "Daft Punk", "Discovery", 1, 1));
+    }
+
+    #[test]
+    fn test_remap_skips_current_ids() {
+        let mut library = version_1_catalog();
+        library[0].id = hash_artist("Daft Punk");
+        library[0].albums[0].songs[1].id = hash_song("One More Time", "Daft Punk", "Discovery", 1, 1);
+
+        let remap = build_remap(&library, &HashMap::new());
+        assert!(!remap.artists.contains_key(&library[0].id));
+        assert!(!remap.songs.contains_key(&library[0].albums[0].songs[1].id));
+        assert!(remap.songs.contains_key("301"));
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use tracing::{info, warn};
+
+use crate::structures::structures::{Album, Artist, Song};
+use crate::utils::catalog::{get_catalog_meta, load_catalog, save_catalog, set_catalog_meta, CatalogChanges};
+use crate::utils::config::{get_cover_art_path, get_icon_art_path};
+use crate::utils::database::database::establish_connection;
+use crate::utils::database::models::ScanState;
+use crate::utils::database::schema::{listen_history_item, scan_state, song, user};
+use crate::utils::format::parse_album_title;
+use crate::utils::hash::{hash_album, hash_artist, hash_song, id_version, ID_VERSION};
+use crate::utils::library::album_owner;
+
+const ID_VERSION_KEY: &str = "id_version";
+
+/// What a song's tags say about its album, as kept in `scan_state`.
+pub(crate) struct TaggedAlbum {
+    pub name: String,
+    pub album_artist: Option<String>,
+    pub compilation: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct IdRemap {
+    pub artists: HashMap<String, String>,
+    pub albums: HashMap<String, String>,
+    pub songs: HashMap<String, String>,
+}
+
+impl IdRemap {
+    fn is_empty(&self) -> bool {
+        self.artists.is_empty() && self.albums.is_empty() && self.songs.is_empty()
+    }
+
+    fn artist(&self, id: &str) -> String {
+        self.artists.get(id).cloned().unwrap_or_else(|| id.to_string())
+    }
+
+    fn album(&self, id: &str) -> String {
+        self.albums.get(id).cloned().unwrap_or_else(|| id.to_string())
+    }
+
+    fn song(&self, id: &str) -> String {
+        self.songs.get(id).cloned().unwrap_or_else(|| id.to_string())
+    }
+}
+
+/// The id the scanner files an album under. Albums are keyed by the album name
+/// and owner their songs' tags give, and albums merged across artists by the
+/// scanner keep the key of the songs owned by the artist they ended up with.
+/// Without scan state, the catalog's names are the best guess.
+fn album_id(artist: &Artist, album: &Album, tagged_albums: &HashMap<String, TaggedAlbum>) -> String {
+    let keys: Vec<(String, String)> = album
+        .songs
+        .iter()
+        .filter_map(|song| {
+            let tagged = tagged_albums.get(&song.path)?;
+            let owner = album_owner(tagged.album_artist.as_deref(), tagged.compilation, &song.artist);
+            Some((parse_album_title(&tagged.name).name, owner))
+        })
+        .collect();
+
+    match keys.iter().find(|(_, owner)| owner.eq_ignore_ascii_case(&artist.name)).or(keys.first()) {
+        Some((name, owner)) => hash_album(name, owner),
+        None => hash_album(&album.name, &artist.name),
+    }
+}
+
+/// Works out the current-version ID of every outdated artist, album and song.
+/// Songs are hashed with the album name read from their tags (kept in
+/// `scan_state`), since the catalog only stores it with disc suffixes removed.
+pub(crate) fn build_remap(library: &[Artist], tagged_albums: &HashMap<String, TaggedAlbum>) -> IdRemap {
+    let mut remap = IdRemap::default();
+    let mut taken_song_ids = HashSet::new();
+
+    for artist in library {
+        if id_version(&artist.id) < ID_VERSION {
+            remap.artists.insert(artist.id.clone(), hash_artist(&artist.name));
+        }
+
+        for album in &artist.albums {
+            if id_version(&album.id) < ID_VERSION {
+                remap.albums.insert(album.id.clone(), album_id(artist, album, tagged_albums));
+            }
+
+            for song in &album.songs {
+                if id_version(&song.id) >= ID_VERSION {
+                    taken_song_ids.insert(song.id.clone());
+                    continue;
+                }
+
+                let album_name = tagged_albums.get(&song.path).map_or(&album.name, |tagged| &tagged.name);
+                let new_id = hash_song(&song.name, &song.artist, album_name, song.disc_number, song.track_number);
+
+                if taken_song_ids.insert(new_id.clone()) {
+                    remap.songs.insert(song.id.clone(), new_id);
+                } else {
+                    warn!("Keeping ID {} for {} because its new ID is already taken", song.id, song.path);
+                }
+            }
+        }
+    }
+
+    remap
+}
+
+fn remap_song(song: &mut Song, remap: &IdRemap) {
+    song.id = remap.song(&song.id);
+    song.contributing_artist_ids = song.contributing_artist_ids.iter().map(|id| remap.artist(id)).collect();
+}
+
+/// Points artwork stored under the old ID at the new file name and returns the
+/// renames to carry out once the database changes have been committed.
+fn remap_artwork(url: &mut String, directory: &Path, old_id: &str, new_id: &str, renames: &mut Vec<(String, String)>) {
+    let old_file_name = format!("{}.jpg", old_id);
+    let new_file_name = format!("{}.jpg", new_id);
+    let old_path = directory.join(&old_file_name);
+
+    let url_path = Path::new(url.as_str());
+    if url_path.file_name().and_then(|name| name.to_str()) == Some(old_file_name.as_str()) {
+        *url = url_path.with_file_name(&new_file_name).to_string_lossy().to_string();
+    }
+
+    if old_path.exists() {
+        let new_path = directory.join(&new_file_name);
+        renames.push((old_path.to_string_lossy().to_string(), new_path.to_string_lossy().to_string()));
+    }
+}
+
+fn apply_remap(library: &mut [Artist], remap: &IdRemap) -> Vec<(String, String)> {
+    let cover_art_path = get_cover_art_path();
+    let icon_art_path = get_icon_art_path();
+    let mut renames = Vec::new();
+
+    for artist in library.iter_mut() {
+        let new_artist_id = remap.artist(&artist.id);
+        if new_artist_id != artist.id {
+            remap_artwork(&mut artist.icon_url, &icon_art_path, &artist.id, &new_artist_id, &mut renames);
+            artist.id = new_artist_id;
+        }
+        artist.featured_on_album_ids = artist.featured_on_album_ids.iter().map(|id| remap.album(id)).collect();
+
+        for album in artist.albums.iter_mut() {
+            let new_album_id = remap.album(&album.id);
+            if new_album_id != album.id {
+                remap_artwork(&mut album.cover_url, &cover_art_path, &album.id, &new_album_id, &mut renames);
+                album.id = new_album_id;
+            }
+            album.contributing_artists_ids = album.contributing_artists_ids.iter().map(|id| remap.artist(id)).collect();
+
+            for song in album.songs.iter_mut() {
+                remap_song(song, remap);
+            }
+        }
+    }
+
+    renames
+}
+
+fn remap_song_references(connection: &mut SqliteConnection, remap: &IdRemap) -> QueryResult<()> {
+    // Playlists, favorites, genres and lyrics follow through ON UPDATE CASCADE.
+    for (old_id, new_id) in &remap.songs {
+        diesel::update(song::table.filter(song::id.eq(old_id)))
+            .set(song::id.eq(new_id))
+            .execute(connection)?;
+
+        diesel::update(listen_history_item::table.filter(listen_history_item::song_id.eq(old_id)))
+            .set(listen_history_item::song_id.eq(new_id))
+            .execute(connection)?;
+
+        diesel::update(user::table.filter(user::now_playing.eq(old_id)))
+            .set(user::now_playing.eq(new_id))
+            .execute(connection)?;
+    }
+
+    let states = scan_state::table
+        .select(ScanState::as_select())
+        .load::<ScanState>(connection)?;
+
+    for state in states {
+        let Some(new_id) = remap.songs.get(&state.song_id) else {
+            continue;
+        };
+
+        let Ok(mut cached) = serde_json::from_str::<Song>(&state.song) else {
+            continue;
+        };
+        remap_song(&mut cached, remap);
+
+        diesel::update(scan_state::table.filter(scan_state::path.eq(&state.path)))
+            .set((
+                scan_state::song_id.eq(new_id),
+                scan_state::song.eq(serde_json::to_string(&cached).unwrap_or(state.song.clone())),
+            ))
+            .execute(connection)?;
+    }
+
+    Ok(())
+}
+
+/// Rewrites catalog IDs generated by an older ID scheme, along with every table
+/// that refers to songs by ID, so upgrading doesn't orphan playlists,
+/// favorites or listen history. Runs once per ID version bump.
+pub fn migrate_ids() -> Result<(), Box<dyn Error>> {
+    let mut connection = establish_connection().get()?;
+
+    let stored_version = get_catalog_meta(&mut connection, ID_VERSION_KEY)?
+        .and_then(|version| version.parse::<u32>().ok())
+        .unwrap_or(1);
+
+    if stored_version >= ID_VERSION {
+        return Ok(());
+    }
+
+    let previous = load_catalog(&mut connection)?;
+    let mut library = previous.clone();
+
+    let tagged_albums: HashMap<String, TaggedAlbum> = scan_state::table
+        .select((scan_state::path, scan_state::album_name, scan_state::album_artist, scan_state::compilation))
+        .load::<(String, String, Option<String>, bool)>(&mut connection)?
+        .into_iter()
+        .map(|(path, name, album_artist, compilation)| (path, TaggedAlbum { name, album_artist, compilation }))
+        .collect();
+
+    let remap = build_remap(&library, &tagged_albums);
+    let renames = apply_remap(&mut library, &remap);
+
+    connection.transaction::<_, diesel::result::Error, _>(|connection| {
+        if !remap.is_empty() {
+            remap_song_references(connection, &remap)?;
+            save_catalog(connection, &CatalogChanges::new(&previous, &library))?;
+        }
+        set_catalog_meta(connection, ID_VERSION_KEY, &ID_VERSION.to_string())
+    })?;
+
+    for (old_path, new_path) in renames {
+        if let Err(e) = fs::rename(&old_path, &new_path) {
+            warn!("Failed to rename artwork {} to {}: {}", old_path, new_path, e);
+        }
+    }
+
+    if !remap.is_empty() {
+        info!(
+            "Migrated catalog IDs to version {} ({} artists, {} albums, {} songs)",
+            ID_VERSION,
+            remap.artists.len(),
+            remap.albums.len(),
+            remap.songs.len()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,575 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex as AsyncMutex, Notify};
+use tracing::{error, info, warn};
+
+use crate::utils::database::database::{establish_connection, with_connection};
+use crate::utils::database::models::{JobLog, JobRow, NewJob, NewJobLog};
+use crate::utils::progress::{cancel_scan, current_scan_progress, take_finished_scan_progress, ScanError};
+
+/// Jobs that don't touch the catalog can run next to a scan.
+const WORKER_COUNT: usize = 2;
+/// How often idle workers look for jobs whose retry delay has passed.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the progress of a running scan job is saved.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_ATTEMPTS: i32 = 3;
+/// Doubled after every failed attempt.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+/// How long a scan job waits when a scan started outside the queue is running.
+const BUSY_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobKind {
+    /// Scans one library into the catalog
+    Index { path: String },
+    /// Rescans every enabled library
+    Refresh,
+    /// Previews what rescanning a library would change
+    Diff { path: String },
+    /// Looks up metadata again for albums without a MusicBrainz id
+    MetadataRefresh,
+    /// Rebuilds the search index from the catalog
+    SearchPopulate,
+}
+
+impl JobKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobKind::Index { .. } => "index",
+            JobKind::Refresh => "refresh",
+            JobKind::Diff { .. } => "diff",
+            JobKind::MetadataRefresh => "metadata_refresh",
+            JobKind::SearchPopulate => "search_populate",
+        }
+    }
+
+    /// Jobs that scan libraries or write the catalog run one at a time.
+    fn exclusive(&self) -> bool {
+        !matches!(self, JobKind::SearchPopulate)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(status: &str) -> Option<JobStatus> {
+        [JobStatus::Queued, JobStatus::Running, JobStatus::Succeeded, JobStatus::Failed, JobStatus::Cancelled]
+            .into_iter()
+            .find(|candidate| candidate.as_str() == status)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Succeeded | JobStatus::Failed | JobStatus::Cancelled)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Job {
+    pub id: i32,
+    #[serde(flatten)]
+    pub kind: JobKind,
+    pub status: JobStatus,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    /// The last progress event of a scan job
+    pub progress: Option<serde_json::Value>,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    /// When a queued job may start, later than `created_at` while waiting to retry
+    pub run_after: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+impl TryFrom<JobRow> for Job {
+    type Error = Box<dyn Error>;
+
+    fn try_from(row: JobRow) -> Result<Self, Self::Error> {
+        Ok(Job {
+            id: row.id,
+            kind: serde_json::from_str(&row.payload)?,
+            status: JobStatus::parse(&row.status).ok_or_else(|| format!("Unknown job status {}", row.status))?,
+            attempts: row.attempts,
+            max_attempts: row.max_attempts,
+            progress: row.progress.and_then(|progress| serde_json::from_str(&progress).ok()),
+            result: row.result.and_then(|result| serde_json::from_str(&result).ok()),
+            error: row.error,
+            run_after: row.run_after,
+            created_at: row.created_at,
+            started_at: row.started_at,
+            finished_at: row.finished_at,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    NotFound,
+    /// The job already finished, so there is nothing to cancel
+    Finished(JobStatus),
+}
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobError::NotFound => write!(f, "Job not found"),
+            JobError::Finished(status) => write!(f, "The job already {}", status.as_str()),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+struct RunningJob {
+    exclusive: bool,
+    cancelled: AtomicBool,
+}
+
+lazy_static! {
+    static ref JOB_WAKER: Notify = Notify::new();
+    static ref RUNNING_JOBS: Mutex<HashMap<i32, Arc<RunningJob>>> = Mutex::new(HashMap::new());
+    static ref CLAIM_LOCK: AsyncMutex<()> = AsyncMutex::new(());
+}
+
+tokio::task_local! {
+    static CURRENT_JOB: i32;
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+/// Queues a job, or returns the id of an identical one that hasn't started
+/// yet. The flag tells whether a new job was queued.
+pub async fn enqueue_job(kind: JobKind) -> Result<(i32, bool), Box<dyn Error>> {
+    use crate::utils::database::schema::job::dsl;
+
+    let name = kind.name();
+    let payload = serde_json::to_string(&kind)?;
+
+    // Taking the write lock up front keeps two requests from both queuing the same job
+    let (job_id, queued) = with_connection(&establish_connection(), move |connection| {
+        connection.immediate_transaction(|connection| {
+            let queued = dsl::job
+                .filter(dsl::status.eq(JobStatus::Queued.as_str()))
+                .filter(dsl::payload.eq(&payload))
+                .select(dsl::id)
+                .first::<i32>(connection)
+                .optional()?;
+            if let Some(job_id) = queued {
+                return Ok((job_id, false));
+            }
+
+            let job_id = diesel::insert_into(dsl::job)
+                .values(NewJob {
+                    kind: name.to_string(),
+                    payload: payload.clone(),
+                    status: JobStatus::Queued.as_str().to_string(),
+                    max_attempts: MAX_ATTEMPTS,
+                })
+                .returning(dsl::id)
+                .get_result::<i32>(connection)?;
+            Ok((job_id, true))
+        })
+    })
+    .await?;
+    if !queued {
+        return Ok((job_id, false));
+    }
+
+    info!("Queued {} job {}", name, job_id);
+    JOB_WAKER.notify_waiters();
+
+    Ok((job_id, true))
+}
+
+pub async fn get_job(job_id: i32) -> Result<Option<Job>, Box<dyn Error>> {
+    use crate::utils::database::schema::job::dsl;
+
+    with_connection(&establish_connection(), move |connection| {
+        dsl::job
+            .filter(dsl::id.eq(job_id))
+            .select(JobRow::as_select())
+            .first::<JobRow>(connection)
+            .optional()
+    })
+    .await?
+    .map(Job::try_from)
+    .transpose()
+}
+
+/// The most recent jobs, newest first.
+pub async fn list_jobs(status: Option<JobStatus>, kind: Option<String>, limit: i64) -> Result<Vec<Job>, Box<dyn Error>> {
+    use crate::utils::database::schema::job::dsl;
+
+    with_connection(&establish_connection(), move |connection| {
+        let mut query = dsl::job.into_boxed();
+        if let Some(status) = status {
+            query = query.filter(dsl::status.eq(status.as_str()));
+        }
+        if let Some(kind) = kind {
+            query = query.filter(dsl::kind.eq(kind));
+        }
+
+        query
+            .order(dsl::id.desc())
+            .limit(limit)
+            .select(JobRow::as_select())
+            .load::<JobRow>(connection)
+    })
+    .await?
+    .into_iter()
+    .map(Job::try_from)
+    .collect()
+}
+
+pub async fn job_logs(job_id: i32) -> Result<Vec<JobLog>, Box<dyn Error>> {
+    use crate::utils::database::schema::job_log::dsl;
+
+    Ok(with_connection(&establish_connection(), move |connection| {
+        dsl::job_log
+            .filter(dsl::job_id.eq(job_id))
+            .order(dsl::id.asc())
+            .select(JobLog::as_select())
+            .load::<JobLog>(connection)
+    })
+    .await?)
+}
+
+fn insert_job_log(connection: &mut SqliteConnection, job_id: i32, message: &str) -> QueryResult<()> {
+    use crate::utils::database::schema::job_log::dsl;
+
+    diesel::insert_into(dsl::job_log)
+        .values(NewJobLog { job_id, message: message.to_string() })
+        .execute(connection)?;
+
+    Ok(())
+}
+
+async fn add_job_log(job_id: i32, message: &str) -> Result<(), Box<dyn Error>> {
+    let message = message.to_string();
+    with_connection(&establish_connection(), move |connection| insert_job_log(connection, job_id, &message)).await?;
+
+    Ok(())
+}
+
+/// Adds a line to the log of the job running the current task, if any.
+pub async fn log_to_current_job(message: &str) {
+    if let Ok(job_id) = CURRENT_JOB.try_with(|job_id| *job_id) {
+        if let Err(e) = add_job_log(job_id, message).await {
+            warn!("Failed to add to the log of job {}: {}", job_id, e);
+        }
+    }
+}
+
+/// Cancels a queued job straight away. A running scan job is asked to stop
+/// and ends as cancelled once it has; search population can't be stopped.
+pub async fn cancel_job(job_id: i32) -> Result<Job, Box<dyn Error>> {
+    use crate::utils::database::schema::job::dsl;
+
+    let job = get_job(job_id).await?.ok_or(JobError::NotFound)?;
+    if job.status.is_finished() {
+        return Err(Box::new(JobError::Finished(job.status)));
+    }
+
+    let running = RUNNING_JOBS.lock().unwrap().get(&job_id).cloned();
+    if let Some(running) = &running {
+        running.cancelled.store(true, Ordering::Relaxed);
+        if running.exclusive {
+            cancel_scan();
+        }
+    }
+    let queued = running.is_none();
+    with_connection(&establish_connection(), move |connection| {
+        if queued {
+            diesel::update(dsl::job.filter(dsl::id.eq(job_id)).filter(dsl::status.eq(JobStatus::Queued.as_str())))
+                .set((dsl::status.eq(JobStatus::Cancelled.as_str()), dsl::finished_at.eq(now())))
+                .execute(connection)?;
+        }
+        insert_job_log(connection, job_id, "Cancellation requested")
+    })
+    .await?;
+
+    Ok(get_job(job_id).await?.unwrap_or(job))
+}
+
+/// Waits for a job to finish, checking on it every few seconds.
+pub async fn wait_for_job(job_id: i32) -> Result<Job, Box<dyn Error>> {
+    loop {
+        let job = get_job(job_id).await?.ok_or(JobError::NotFound)?;
+        if job.status.is_finished() {
+            return Ok(job);
+        }
+        actix_web::rt::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Puts jobs left running by a previous server process back in the queue.
+pub fn requeue_interrupted_jobs() -> Result<(), Box<dyn Error>> {
+    use crate::utils::database::schema::job::dsl;
+
+    let mut connection = establish_connection().get()?;
+    let running: Vec<i32> = RUNNING_JOBS.lock().unwrap().keys().copied().collect();
+
+    let interrupted = dsl::job
+        .filter(dsl::status.eq(JobStatus::Running.as_str()))
+        .filter(dsl::id.ne_all(&running))
+        .select(dsl::id)
+        .load::<i32>(&mut connection)?;
+
+    for job_id in interrupted {
+        diesel::update(dsl::job.filter(dsl::id.eq(job_id)))
+            .set((dsl::status.eq(JobStatus::Queued.as_str()), dsl::run_after.eq(now())))
+            .execute(&mut connection)?;
+        insert_job_log(&mut connection, job_id, "Requeued after the server restarted")?;
+    }
+    JOB_WAKER.notify_waiters();
+
+    Ok(())
+}
+
+/// Marks the next job that may start as running and returns it. Workers
+/// claim one at a time, so two can't claim the same job or start two
+/// exclusive ones together.
+async fn claim_next_job() -> Result<Option<Job>, Box<dyn Error>> {
+    use crate::utils::database::schema::job::dsl;
+
+    let _claim_lock = CLAIM_LOCK.lock().await;
+    let exclusive_running = RUNNING_JOBS.lock().unwrap().values().any(|running| running.exclusive);
+
+    let queued = with_connection(&establish_connection(), |connection| {
+        dsl::job
+            .filter(dsl::status.eq(JobStatus::Queued.as_str()))
+            .filter(dsl::run_after.le(now()))
+            .order(dsl::id.asc())
+            .select(JobRow::as_select())
+            .load::<JobRow>(connection)
+    })
+    .await?;
+
+    for row in queued {
+        let job = match Job::try_from(row) {
+            Ok(job) => job,
+            Err(e) => {
+                warn!("Skipping unreadable job: {}", e);
+                continue;
+            }
+        };
+        if exclusive_running && job.kind.exclusive() {
+            continue;
+        }
+
+        let (job_id, attempts) = (job.id, job.attempts + 1);
+        // Cancelling may have finished the job since it was loaded
+        let claimed = with_connection(&establish_connection(), move |connection| {
+            diesel::update(dsl::job.filter(dsl::id.eq(job_id)).filter(dsl::status.eq(JobStatus::Queued.as_str())))
+                .set((
+                    dsl::status.eq(JobStatus::Running.as_str()),
+                    dsl::attempts.eq(attempts),
+                    dsl::started_at.eq(now()),
+                    dsl::error.eq(None::<String>),
+                ))
+                .execute(connection)
+        })
+        .await?;
+        if claimed == 0 {
+            continue;
+        }
+
+        RUNNING_JOBS.lock().unwrap().insert(job.id, Arc::new(RunningJob {
+            exclusive: job.kind.exclusive(),
+            cancelled: AtomicBool::new(false),
+        }));
+
+        return Ok(Some(Job { attempts, ..job }));
+    }
+
+    Ok(None)
+}
+
+async fn save_progress(job_id: i32, progress: &impl Serialize) -> Result<(), Box<dyn Error>> {
+    use crate::utils::database::schema::job::dsl;
+
+    let progress = serde_json::to_string(progress)?;
+    with_connection(&establish_connection(), move |connection| {
+        diesel::update(dsl::job.filter(dsl::id.eq(job_id)))
+            .set(dsl::progress.eq(progress))
+            .execute(connection)
+    })
+    .await?;
+
+    Ok(())
+}
+
+async fn finish_job(job_id: i32, status: JobStatus, result: Option<String>, error: Option<String>) -> Result<(), Box<dyn Error>> {
+    use crate::utils::database::schema::job::dsl;
+
+    with_connection(&establish_connection(), move |connection| {
+        diesel::update(dsl::job.filter(dsl::id.eq(job_id)))
+            .set((
+                dsl::status.eq(status.as_str()),
+                dsl::result.eq(result),
+                dsl::error.eq(error),
+                dsl::finished_at.eq(now()),
+            ))
+            .execute(connection)
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Puts a job back in the queue to start again after `delay`. Attempts that
+/// never got to run are given back.
+async fn retry_job(job_id: i32, attempts: i32, delay: Duration, error: &str) -> Result<(), Box<dyn Error>> {
+    use crate::utils::database::schema::job::dsl;
+
+    let run_after = now() + chrono::Duration::from_std(delay)?;
+    let error = error.to_string();
+
+    with_connection(&establish_connection(), move |connection| {
+        diesel::update(dsl::job.filter(dsl::id.eq(job_id)))
+            .set((
+                dsl::status.eq(JobStatus::Queued.as_str()),
+                dsl::attempts.eq(attempts),
+                dsl::error.eq(error),
+                dsl::run_after.eq(run_after),
+            ))
+            .execute(connection)
+    })
+    .await?;
+
+    Ok(())
+}
+
+async fn run_job<F, Fut>(job: Job, run: &F) -> Result<(), Box<dyn Error>>
+where
+    F: Fn(JobKind) -> Fut,
+    Fut: Future<Output = Result<serde_json::Value, Box<dyn Error>>>,
+{
+    info!("Running {} job {} (attempt {} of {})", job.kind.name(), job.id, job.attempts, job.max_attempts);
+    add_job_log(job.id, &format!("Attempt {} of {} started", job.attempts, job.max_attempts)).await?;
+
+    let exclusive = job.kind.exclusive();
+    let work = CURRENT_JOB.scope(job.id, run(job.kind.clone()));
+    let record_progress = async {
+        loop {
+            actix_web::rt::time::sleep(PROGRESS_INTERVAL).await;
+            if let Some(progress) = current_scan_progress().filter(|_| exclusive) {
+                if let Err(e) = save_progress(job.id, &progress).await {
+                    warn!("Failed to save the progress of job {}: {}", job.id, e);
+                }
+            }
+        }
+    };
+    let outcome = tokio::select! {
+        outcome = work => outcome,
+        _ = record_progress => unreachable!(),
+    };
+
+    let running = RUNNING_JOBS.lock().unwrap().remove(&job.id);
+    let cancelled = running.is_some_and(|running| running.cancelled.load(Ordering::Relaxed));
+    let busy = matches!(&outcome, Err(e) if matches!(e.downcast_ref::<ScanError>(), Some(ScanError::AlreadyRunning)));
+    if let Some(progress) = take_finished_scan_progress().filter(|_| exclusive && !busy) {
+        save_progress(job.id, &progress).await?;
+    }
+
+    match outcome {
+        Ok(result) => {
+            add_job_log(job.id, "Finished").await?;
+            finish_job(job.id, JobStatus::Succeeded, Some(serde_json::to_string(&result)?), None).await
+        }
+        Err(e) if cancelled || matches!(e.downcast_ref::<ScanError>(), Some(ScanError::Cancelled)) => {
+            add_job_log(job.id, "Cancelled").await?;
+            finish_job(job.id, JobStatus::Cancelled, None, None).await
+        }
+        // A scan started outside the queue is running, which isn't the job's fault
+        Err(e) if busy => {
+            retry_job(job.id, job.attempts - 1, BUSY_RETRY_DELAY, &e.to_string()).await
+        }
+        Err(e) if job.attempts < job.max_attempts => {
+            let delay = RETRY_BASE_DELAY * 2u32.pow(job.attempts as u32 - 1);
+            warn!("{} job {} failed, retrying in {}s: {}", job.kind.name(), job.id, delay.as_secs(), e);
+            add_job_log(job.id, &format!("Failed: {}. Retrying in {} seconds", e, delay.as_secs())).await?;
+            retry_job(job.id, job.attempts, delay, &e.to_string()).await
+        }
+        Err(e) => {
+            error!("{} job {} failed: {}", job.kind.name(), job.id, e);
+            add_job_log(job.id, &format!("Failed: {}", e)).await?;
+            finish_job(job.id, JobStatus::Failed, None, Some(e.to_string())).await
+        }
+    }
+}
+
+async fn worker<F, Fut>(run: Rc<F>)
+where
+    F: Fn(JobKind) -> Fut,
+    Fut: Future<Output = Result<serde_json::Value, Box<dyn Error>>>,
+{
+    loop {
+        match claim_next_job().await {
+            Ok(Some(job)) => {
+                let job_id = job.id;
+                if let Err(e) = run_job(job, &*run).await {
+                    error!("Failed to record the outcome of job {}: {}", job_id, e);
+                    RUNNING_JOBS.lock().unwrap().remove(&job_id);
+                }
+                // A finished scan job may let another one start
+                JOB_WAKER.notify_waiters();
+            }
+            Ok(None) => {
+                tokio::select! {
+                    _ = JOB_WAKER.notified() => {}
+                    _ = actix_web::rt::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+            Err(e) => {
+                warn!("Failed to claim a job: {}", e);
+                actix_web::rt::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Starts the workers that run queued jobs with `run`.
+pub fn start_job_workers<F, Fut>(run: F)
+where
+    F: Fn(JobKind) -> Fut + 'static,
+    Fut: Future<Output = Result<serde_json::Value, Box<dyn Error>>> + 'static,
+{
+    let run = Rc::new(run);
+    for _ in 0..WORKER_COUNT {
+        actix_web::rt::spawn(worker(run.clone()));
+    }
+}
@@ -1,330 +1,141 @@
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use audiotags::Tag;
-use lofty::{AudioFile, Probe};
+use lofty::{Accessor, AudioFile, FileType, ItemKey, Picture, PictureType, Probe, Tag, TaggedFile, TaggedFileExt};
 use rayon::prelude::*;
-use regex::Regex;
+use serde::Serialize;
 use tracing::warn;
 use walkdir::WalkDir;
 
-use crate::structures::structures::{Album, Artist, Song};
+use crate::structures::structures::{Album, AlbumType, Artist, AudioProperties, Song};
 use super::config::get_cover_art_path;
-use super::format::format_contributing_artists;
+use super::database::models::ScanState;
+use super::format::{format_contributing_artists, parse_album_title, parse_disc_number};
+use super::formats::{audio_format, is_audio_file, read_mp4_codec};
 use super::hash::{hash_album, hash_artist, hash_song};
+use super::library_settings::{library_settings, LibraryFilter};
+use super::compare::{ScanErrorKind, ScanFileError};
+use super::progress::{ScanError, ScanPhase, ScanProgress};
+use super::scan_state::{cached_song, file_signature, load_scan_states, new_scan_state, save_scan_states};
 
-pub async fn index_library(path_to_library: &str) -> Result<Arc<Mutex<Vec<Artist>>>, Box<dyn Error>> {
+const VARIOUS_ARTISTS: &str = "Various Artists";
+
+pub struct IndexedLibrary {
+    pub library: Arc<Mutex<Vec<Artist>>>,
+    /// Problems with individual files, which don't stop the scan.
+    pub errors: Vec<ScanFileError>,
+}
+
+/// Reads a library into a catalog of its own. A `dry_run` scan writes nothing:
+/// neither the file read cache nor embedded covers are stored.
+pub async fn index_library(path_to_library: &str, progress: Arc<ScanProgress>, dry_run: bool) -> Result<IndexedLibrary, Box<dyn Error>> {
+    let path_to_library = path_to_library.to_string();
+
+    // Reading tags blocks, so it runs off the async workers to keep progress
+    // events and other requests flowing during a scan
+    let library = tokio::task::spawn_blocking(move || scan_library_files(&path_to_library, &progress, dry_run)).await??;
+    Ok(library)
+}
+
+fn scan_library_files(path_to_library: &str, progress: &ScanProgress, dry_run: bool) -> Result<IndexedLibrary, ScanError> {
     let library = Arc::new(Mutex::new(Vec::<Artist>::new()));
     let library_clone = Arc::clone(&library);
 
+    let settings = library_settings(path_to_library);
+    let mut filter = LibraryFilter::new(Path::new(path_to_library), &settings);
+
     let files: Vec<_> = WalkDir::new(&*path_to_library)
+        .follow_links(settings.follow_symlinks)
         .into_iter()
+        .filter_entry(|e| !filter.is_ignored(e.path()))
         .filter_map(|e| e.ok())
         .filter(|e| {
             e.file_type().is_file()
-                && matches!(e.path().extension().and_then(|s| s.to_str()), Some("mp3") | Some("flac") | Some("ogg") | Some("m4a"))
+                && is_audio_file(e.path())
         })
         .collect();
 
-    files.par_iter().for_each(|entry| {
-        let path = entry.path();
-        
-        let tag = match Tag::new().read_from_path(&path) {
-            Ok(t) => t,
-            Err(e) => {
-                warn!("Failed to read tags from {}: {}", path.display(), e);
-                Tag::new().read_from_path(&path).ok().unwrap()
-            }
-        };
-
-        let artists: Vec<String> = tag.artists()
-            .unwrap_or_default()
-            .iter()
-            .map(|&s| s.to_string())
-            .collect();
-
-        let song_name = tag.title()
-            .unwrap_or_else(|| {
-                path.file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("Unknown Title")
-            })
-            .to_string();
-
-        let album_name = tag.album_title()
-            .unwrap_or_else(|| {
-                // Use parent directory name as fallback
-                path.parent()
-                    .and_then(|p| p.file_name())
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("Unknown Album")
-            })
-            .to_string();
-
-        let track_number = tag.track_number().unwrap_or(0);
-
-        let duration = match Probe::open(path) {
-            Ok(probe) => match probe.read() {
-                Ok(tagged_file) => tagged_file.properties().duration().as_secs_f64(),
-                Err(e) => {
-                    warn!("Failed to read audio properties from {}: {}", path.display(), e);
-                    0.0
-                }
-            },
-            Err(e) => {
-                warn!("Failed to probe audio file {}: {}", path.display(), e);
-                0.0
-            }
-        };
-
-        let formatted_artists = if artists.is_empty() {
-            vec![(String::from("Unknown Artist"), Vec::new())]
-        } else {
-            format_contributing_artists(artists)
-        };
-
-        let artist_name = formatted_artists.get(0).map_or(String::new(), |a| a.0.clone());
-        let contributing_artists = formatted_artists.get(0).map_or(Vec::new(), |a| a.1.clone());
-
-        let re = Regex::new(r"\(.*\)").unwrap();
-        let album_name_without_cd = re.replace_all(&album_name, "").trim().to_string();
+    progress.files_discovered(files.len());
+    progress.set_phase(ScanPhase::Reading);
 
-        let id = hash_song(&song_name, &artist_name, &album_name, track_number);
+    let previous_states = load_scan_states(path_to_library).unwrap_or_else(|e| {
+        warn!("Failed to load scan state for {}, every file will be read: {}", path_to_library, e);
+        HashMap::new()
+    });
+    let seen_paths = Mutex::new(HashSet::new());
+    let changed_states = Mutex::new(Vec::new());
+    let owned_album_ids = Mutex::new(HashSet::new());
+    let errors = Mutex::new(Vec::new());
+    let record_error = |error: ScanFileError| {
+        progress.error();
+        errors.lock().unwrap().push(error);
+    };
 
-        let album_id;
-        {
-            let mut library = library.lock().unwrap();
+    files.par_iter().for_each(|entry| {
+        if progress.is_cancelled() {
+            return;
+        }
 
-            let artist_name_lowercase = artist_name.to_lowercase();
-            let artist_position = library.iter().position(|a| a.name.to_lowercase() == artist_name_lowercase);
+        let path = entry.path();
+        let Some(path_string) = path.to_str().map(|path| path.to_string()) else {
+            record_error(ScanFileError::new(path, ScanErrorKind::InvalidPath, "The path is not valid UTF-8"));
+            return;
+        };
+        progress.file_processed(&path_string);
+        let (size, mtime) = std::fs::metadata(path)
+            .map(|metadata| file_signature(&metadata))
+            .unwrap_or((0, 0));
 
-            let artist = if let Some(artist_position) = artist_position {
-                &mut library[artist_position]
-            } else {
-                let new_artist = Artist {
-                    id: hash_artist(&artist_name),
-                    name: artist_name.clone(),
-                    albums: Vec::new(),
-                    featured_on_album_ids: Vec::new(),
-                    icon_url: String::new(),
-                    followers: 0,
-                    description: String::new(),
-                    tadb_music_videos: None,
-                };
-                library.push(new_artist);
-                library.last_mut().unwrap()
-            };
+        if (size as u64) < settings.min_file_size {
+            return;
+        }
 
-            let album_position = artist.albums.iter().position(|a| a.name == album_name_without_cd && a.id == hash_album(&album_name_without_cd.clone(), &artist_name));
+        let cached = previous_states
+            .get(&path_string)
+            .and_then(|state| cached_song(state, size, mtime));
 
-            let album = if let Some(album_position) = album_position {
-                &mut artist.albums[album_position]
-            } else {
-                let mut new_album = Album {
-                    id: hash_album(&album_name_without_cd.clone(), &artist_name),
-                    name: album_name_without_cd.clone(),
-                    songs: Vec::new(),
-                    cover_url: String::new(),
-                    primary_type: String::new(),
-                    description: String::new(),
-                    first_release_date: String::new(),
-                    musicbrainz_id: String::new(),
-                    wikidata_id: None,
-                    contributing_artists: Vec::new(),
-                    contributing_artists_ids: Vec::new(),
-                    release_album: None,
-                    release_group_album: None,
-                };
-
-                let mut cover_found = false;
-                
-                let base_cover_art_path = get_cover_art_path();
-                let cover_art_path = base_cover_art_path.join(format!("{}.jpg", new_album.id));
-                
-                if cover_art_path.exists() {
-                    new_album.cover_url = cover_art_path.to_str().unwrap().to_string();
-                    cover_found = true;
-                } else {
-                    if let Ok(tag) = Tag::new().read_from_path(&path) {
-                        if let Some(picture) = tag.album_cover() {
-                            let mut file = File::create(&cover_art_path).unwrap();
-                            file.write_all(picture.data).unwrap();
-                            new_album.cover_url = cover_art_path.to_str().unwrap().to_string();
-                            cover_found = true;
-                        }
-                    }
-                }                
-                
-                if !cover_found {
-                    if let Some(parent_path) = path.parent() {
-                        for image_path in WalkDir::new(parent_path)
-                            .max_depth(1)
-                            .into_iter()
-                            .filter_map(|e| e.ok())
-                            .filter(|e| {
-                                e.file_type().is_file()
-                                    && matches!(
-                                        e.path().extension().and_then(|s| s.to_str()),
-                                        Some("jpg")
-                                            | Some("jpeg")
-                                            | Some("png")
-                                            | Some("gif")
-                                            | Some("bmp")
-                                            | Some("ico")
-                                            | Some("tif")
-                                            | Some("tiff")
-                                            | Some("webp")
-                                    )
-                            })
-                        {
-                            new_album.cover_url = image_path.path().to_str().unwrap().to_string();
-                            cover_found = true;
-                            break;
-                        }
-
-                        if !cover_found
-                            && parent_path.read_dir().unwrap().any(|e| {
-                                if let Ok(entry) = e {
-                                    let path = entry.path();
-                                    let path_file_name = path.file_name().unwrap().to_str().unwrap();
-                                    path.is_dir()
-                                        && (path_file_name.starts_with("CD")
-                                            || path_file_name.starts_with("Disc")
-                                            || path.file_name().unwrap() == "Covers")
-                                } else {
-                                    false
-                                }
-                            })
-                        {
-                            if let Some(grandparent_path) = parent_path.parent() {
-                                for image_path in WalkDir::new(grandparent_path)
-                                    .max_depth(1)
-                                    .into_iter()
-                                    .filter_map(|e| e.ok())
-                                    .filter(|e| {
-                                        e.file_type().is_file()
-                                            && matches!(
-                                                e.path().extension().and_then(|s| s.to_str()),
-                                                Some("jpg")
-                                                    | Some("jpeg")
-                                                    | Some("png")
-                                                    | Some("gif")
-                                                    | Some("bmp")
-                                                    | Some("ico")
-                                                    | Some("tif")
-                                                    | Some("tiff")
-                                                    | Some("webp")
-                                            )
-                                    })
-                                {
-                                    new_album.cover_url = image_path.path().to_str().unwrap().to_string();
-                                    break;
-                                }
-                            }
+        let scanned = match cached {
+            Some(cached) => cached,
+            None => {
+                let (scanned, error) = read_song(path);
+                match error {
+                    // Left out of the cache so the file is read and reported again
+                    Some(error) => record_error(error),
+                    None => {
+                        if let Some(state) = new_scan_state(path_to_library, &scanned, size, mtime) {
+                            changed_states.lock().unwrap().push(state);
                         }
                     }
                 }
+                scanned
+            }
+        };
 
-                artist.albums.push(new_album);
-                artist.albums.sort_by(|a, b| a.name.cmp(&b.name));
-                artist.albums.last_mut().unwrap()
-            };
+        seen_paths.lock().unwrap().insert(path_string);
 
-            album_id = album.id.clone();
+        // Files with an unknown duration (0) are kept, since the threshold can't be checked
+        if scanned.song.duration > 0.0 && scanned.song.duration < settings.min_duration {
+            return;
         }
 
-        let mut contributing_artist_ids = Vec::new();
-        let mut new_artists = Vec::new();
-        let mut featured_on_album_updates = Vec::new();
-        
-        for contributing_artist_name in &contributing_artists {
-            let contributing_artist_name_lowercase = contributing_artist_name.to_lowercase();
-            let contributing_artist_position = {
-                let library = library.lock().unwrap();
-                library.iter().position(|a| a.name.to_lowercase() == contributing_artist_name_lowercase)
-            };
-        
-            if let Some(contributing_artist_position) = contributing_artist_position {
-                let contributing_artist = {
-                    let library = library.lock().unwrap();
-                    &library[contributing_artist_position].clone()
-                };
-                contributing_artist_ids.push(contributing_artist.id.clone());
-        
-                if !contributing_artist.featured_on_album_ids.contains(&album_id) {
-                    featured_on_album_updates.push(contributing_artist_position);
-                }
-            } else {
-                let new_artist = Artist {
-                    id: hash_artist(&contributing_artist_name),
-                    name: contributing_artist_name.clone(),
-                    albums: Vec::new(),
-                    featured_on_album_ids: vec![album_id.clone()],
-                    icon_url: String::new(),
-                    followers: 0,
-                    description: String::new(),
-                    tadb_music_videos: None,
-                };
-                contributing_artist_ids.push(new_artist.id.clone());
-                new_artists.push(new_artist);
-            }
+        let has_album_owner = scanned.album_artist.is_some() || scanned.compilation;
+        let (album_id, cover_error) = add_song_to_library(&library, scanned, path, dry_run);
+        if let Some(error) = cover_error {
+            record_error(error);
         }
-        
-        {
-            let mut library = library.lock().unwrap();
-        
-            for new_artist in new_artists {
-                library.push(new_artist);
-            }
-        
-            for artist_position in featured_on_album_updates {
-                if let Some(contributing_artist) = library.get_mut(artist_position) {
-                    contributing_artist.featured_on_album_ids.push(album_id.clone());
-                }
-            }
+        if has_album_owner {
+            owned_album_ids.lock().unwrap().insert(album_id);
         }
-        
-        let song = Song {
-            id,
-            name: song_name,
-            artist: artist_name.clone(),
-            contributing_artists: contributing_artists.clone(),
-            contributing_artist_ids: contributing_artist_ids.clone(),
-            track_number,
-            path: path.to_str().unwrap().to_string(),
-            duration,
-            music_video: None,
-        };
+    });
 
-        {
-            let mut library = library.lock().unwrap();
-            let artist_name_clone = artist_name.clone();
-            let artist_name_lowercase = artist_name_clone.to_lowercase();
-            let artist_position = library.iter().position(|a| a.name.to_lowercase() == artist_name_lowercase).unwrap();
-            let artist = &mut library[artist_position];
-            let album_name_without_cd_clone = album_name_without_cd.clone();
-            let album_position = artist.albums.iter().position(|a| a.name == album_name_without_cd_clone && a.id == hash_album(&album_name_without_cd_clone, &artist_name)).unwrap();
-            let album = &mut artist.albums[album_position];
-
-            if !album.songs.iter().any(|s| s.id == song.id) {
-                album.songs.push(song);
-                album.songs.sort_by(|a, b| a.track_number.cmp(&b.track_number));
-            }
+    // Files that weren't read would look removed to `save_scan_states`
+    progress.check_cancelled()?;
 
-            for contributing_artist_name in &contributing_artists {
-                if !album.contributing_artists.contains(contributing_artist_name) {
-                    album.contributing_artists.push(contributing_artist_name.clone());
-                }
-            }
-            for contributing_artist_id in &contributing_artist_ids {
-                if !album.contributing_artists_ids.contains(contributing_artist_id) {
-                    album.contributing_artists_ids.push(contributing_artist_id.clone());
-                }
-            }
-        }
-    });
+    let owned_album_ids = owned_album_ids.into_inner().unwrap();
 
     let mut library = library.lock().unwrap();
     
@@ -342,7 +153,7 @@ pub async fn index_library(path_to_library: &str) -> Result<Arc<Mutex<Vec<Artist
 
         for album in artist.albums.iter_mut() {
             album.songs.sort_by(|a, b| {
-                match a.track_number.cmp(&b.track_number) {
+                match (a.disc_number, a.track_number).cmp(&(b.disc_number, b.track_number)) {
                     std::cmp::Ordering::Equal => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
                     other => other
                 }
@@ -368,7 +179,13 @@ pub async fn index_library(path_to_library: &str) -> Result<Arc<Mutex<Vec<Artist
         }
     }
     
+    // Albums tagged with an album artist or as a compilation already have the
+    // right owner, so only untagged albums are merged across artists.
     for (_album_name, locations) in album_map.iter().filter(|(_, v)| v.len() > 1) {
+        if locations.iter().any(|&(artist_idx, album_idx)| owned_album_ids.contains(&library[artist_idx].albums[album_idx].id)) {
+            continue;
+        }
+
         let mut best_location = locations[0];
         let mut max_songs = library[locations[0].0].albums[locations[0].1].songs.len();
         
@@ -421,11 +238,16 @@ pub async fn index_library(path_to_library: &str) -> Result<Arc<Mutex<Vec<Artist
         
         for album in artist.albums.iter_mut() {
             album.songs.sort_by(|a, b| {
-                match a.track_number.cmp(&b.track_number) {
+                match (a.disc_number, a.track_number).cmp(&(b.disc_number, b.track_number)) {
                     std::cmp::Ordering::Equal => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
                     other => other
                 }
             });
+
+            let disc_total = album.songs.iter().map(|s| s.disc_number.max(s.disc_total)).max().unwrap_or(1);
+            for song in album.songs.iter_mut() {
+                song.disc_total = disc_total;
+            }
             
             album.contributing_artists.sort();
             album.contributing_artists.dedup();
@@ -435,5 +257,811 @@ pub async fn index_library(path_to_library: &str) -> Result<Arc<Mutex<Vec<Artist
         }
     }
 
-    Ok(Arc::clone(&library_clone))
-}
\ No newline at end of file
+    if !dry_run {
+        if let Err(e) = save_scan_states(&previous_states, &seen_paths.into_inner().unwrap(), changed_states.into_inner().unwrap()) {
+            warn!("Failed to save scan state for {}: {}", path_to_library, e);
+        }
+    }
+
+    Ok(IndexedLibrary {
+        library: Arc::clone(&library_clone),
+        errors: errors.into_inner().unwrap(),
+    })
+}
+
+#[derive(Default, Debug)]
+pub struct FileChanges {
+    /// Files that were read (or taken from the scan cache) and added again
+    pub updated: usize,
+    /// Songs whose files no longer exist or are now ignored
+    pub removed: usize,
+    pub errors: Vec<ScanFileError>,
+}
+
+impl FileChanges {
+    pub fn is_empty(&self) -> bool {
+        self.updated == 0 && self.removed == 0
+    }
+}
+
+/// Brings the songs under `changed_paths` up to date without rescanning the
+/// whole library. Songs whose files are gone are removed, and files that were
+/// created, modified or moved in are read again. Used by the filesystem watcher,
+/// so unlike `index_library` it works on the full catalog and doesn't merge
+/// albums across artists.
+pub fn update_library_files(catalog: &mut Vec<Artist>, library_path: &str, changed_paths: &[PathBuf]) -> FileChanges {
+    let settings = library_settings(library_path);
+    let mut filter = LibraryFilter::new(Path::new(library_path), &settings);
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    for changed_path in changed_paths {
+        if filter.is_ignored_with_parents(changed_path) {
+            continue;
+        }
+
+        if changed_path.is_dir() {
+            files.extend(
+                WalkDir::new(changed_path)
+                    .follow_links(settings.follow_symlinks)
+                    .into_iter()
+                    .filter_entry(|e| !filter.is_ignored(e.path()))
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_file() && is_audio_file(e.path()))
+                    .map(|e| e.path().to_path_buf()),
+            );
+        } else if changed_path.is_file() && is_audio_file(changed_path) {
+            files.push(changed_path.clone());
+        }
+    }
+    files.sort();
+    files.dedup();
+
+    let is_stale = |song_path: &str| changed_paths.iter().any(|changed_path| Path::new(song_path).starts_with(changed_path));
+
+    // Ids of the songs taken out, so files that are read again keep theirs
+    let mut removed_paths = HashMap::new();
+    for artist in catalog.iter_mut() {
+        for album in artist.albums.iter_mut() {
+            album.songs.retain(|song| {
+                if is_stale(&song.path) {
+                    removed_paths.insert(song.path.clone(), song.id.clone());
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+
+    let previous_states: HashMap<String, ScanState> = load_scan_states(library_path)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(path, _)| is_stale(path))
+        .collect();
+    let mut seen_paths = HashSet::new();
+    let mut changed_states = Vec::new();
+    let mut changes = FileChanges::default();
+
+    let library = Mutex::new(std::mem::take(catalog));
+    for path in &files {
+        let Some(path_string) = path.to_str().map(|path| path.to_string()) else {
+            changes.errors.push(ScanFileError::new(path, ScanErrorKind::InvalidPath, "The path is not valid UTF-8"));
+            continue;
+        };
+        let (size, mtime) = fs::metadata(path)
+            .map(|metadata| file_signature(&metadata))
+            .unwrap_or((0, 0));
+
+        if (size as u64) < settings.min_file_size {
+            continue;
+        }
+
+        let mut scanned = match previous_states.get(&path_string).and_then(|state| cached_song(state, size, mtime)) {
+            Some(cached) => cached,
+            None => {
+                let (scanned, error) = read_song(path);
+                match error {
+                    Some(error) => changes.errors.push(error),
+                    None => changed_states.extend(new_scan_state(library_path, &scanned, size, mtime)),
+                }
+                scanned
+            }
+        };
+        seen_paths.insert(path_string.clone());
+
+        if scanned.song.duration > 0.0 && scanned.song.duration < settings.min_duration {
+            continue;
+        }
+
+        if let Some(id) = removed_paths.remove(&path_string) {
+            scanned.song.id = id;
+        }
+        let (_, cover_error) = add_song_to_library(&library, scanned, path, false);
+        changes.errors.extend(cover_error);
+        changes.updated += 1;
+    }
+    *catalog = library.into_inner().unwrap();
+    changes.removed = removed_paths.len();
+
+    tidy_catalog(catalog);
+
+    if let Err(e) = save_scan_states(&previous_states, &seen_paths, changed_states) {
+        warn!("Failed to save scan state for {}: {}", library_path, e);
+    }
+
+    changes
+}
+
+/// Counts of what `remove_library_songs` took out of the catalog.
+#[derive(Serialize, Debug, Default)]
+pub struct RemovedSongs {
+    #[serde(skip)]
+    pub song_ids: Vec<String>,
+    pub songs: usize,
+    pub albums: usize,
+    pub artists: usize,
+}
+
+/// Removes every song stored under `library_path`, along with the albums and
+/// artists that are left empty.
+pub fn remove_library_songs(catalog: &mut Vec<Artist>, library_path: &str) -> RemovedSongs {
+    let album_count = |catalog: &Vec<Artist>| catalog.iter().map(|artist| artist.albums.len()).sum::<usize>();
+    let albums_before = album_count(catalog);
+    let artists_before = catalog.len();
+
+    let mut song_ids = Vec::new();
+    for artist in catalog.iter_mut() {
+        for album in artist.albums.iter_mut() {
+            album.songs.retain(|song| {
+                if Path::new(&song.path).starts_with(library_path) {
+                    song_ids.push(song.id.clone());
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+
+    tidy_catalog(catalog);
+
+    RemovedSongs {
+        songs: song_ids.len(),
+        song_ids,
+        albums: albums_before - album_count(catalog),
+        artists: artists_before - catalog.len(),
+    }
+}
+
+/// Drops albums left without songs, and artists left without albums or
+/// features, then restores song order and disc totals.
+fn tidy_catalog(catalog: &mut Vec<Artist>) {
+    for artist in catalog.iter_mut() {
+        artist.albums.retain(|album| !album.songs.is_empty());
+    }
+
+    let album_ids: HashSet<String> = catalog.iter()
+        .flat_map(|artist| artist.albums.iter().map(|album| album.id.clone()))
+        .collect();
+
+    for artist in catalog.iter_mut() {
+        artist.featured_on_album_ids.retain(|id| album_ids.contains(id));
+
+        for album in artist.albums.iter_mut() {
+            album.songs.sort_by(|a, b| {
+                match (a.disc_number, a.track_number).cmp(&(b.disc_number, b.track_number)) {
+                    std::cmp::Ordering::Equal => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+                    other => other
+                }
+            });
+
+            let disc_total = album.songs.iter().map(|s| s.disc_number.max(s.disc_total)).max().unwrap_or(1);
+            for song in album.songs.iter_mut() {
+                song.disc_total = disc_total;
+            }
+        }
+    }
+
+    catalog.retain(|artist| !artist.albums.is_empty() || !artist.featured_on_album_ids.is_empty());
+    catalog.sort_by_key(|artist| artist.name.to_lowercase());
+}
+
+pub struct ScannedSong {
+    pub song: Song,
+    pub album_name: String,
+    pub album_artist: Option<String>,
+    pub compilation: bool,
+    /// The earliest release date in the tags, as `YYYY`, `YYYY-MM` or `YYYY-MM-DD`
+    pub release_date: Option<String>,
+    pub genres: Vec<String>,
+    /// MusicBrainz IDs for the album and its artist, as Picard tags them
+    pub musicbrainz_release_id: Option<String>,
+    pub musicbrainz_release_group_id: Option<String>,
+    pub musicbrainz_artist_id: Option<String>,
+}
+
+/// Returns the artist credits when the file stores them as separate values: an
+/// ARTISTS tag (Vorbis, APE, ID3v2 TXXX or MP4 freeform), repeated Vorbis ARTIST
+/// fields, or an ID3v2.4 TPE1 frame holding several null-separated values.
+/// Returns `None` when there is only a single artist string to split.
+fn read_artist_values(tag: &Tag) -> Option<Vec<String>> {
+    let collect = |key: &ItemKey| -> Vec<String> {
+        let mut values: Vec<String> = Vec::new();
+        for value in tag.get_strings(key).flat_map(|value| value.split('\0')) {
+            let value = value.trim().to_string();
+            if !value.is_empty() && !values.contains(&value) {
+                values.push(value);
+            }
+        }
+        values
+    };
+
+    let artists_keys = [
+        ItemKey::Unknown("ARTISTS".to_string()),
+        ItemKey::Unknown("----:com.apple.iTunes:ARTISTS".to_string()),
+    ];
+    if let Some(values) = artists_keys.iter().map(collect).find(|values| !values.is_empty()) {
+        return Some(values);
+    }
+
+    let values = collect(&ItemKey::TrackArtist);
+    if values.len() > 1 {
+        Some(values)
+    } else {
+        None
+    }
+}
+
+/// Cuts a tag date down to `YYYY`, `YYYY-MM` or `YYYY-MM-DD`, dropping any time.
+fn normalize_tag_date(value: &str) -> Option<String> {
+    let value = value.trim();
+    let year = value.get(..4).filter(|year| year.bytes().all(|b| b.is_ascii_digit()))?;
+
+    let mut date = year.to_string();
+    let mut rest = &value[4..];
+    for _ in 0..2 {
+        let Some(part) = rest.strip_prefix('-').and_then(|rest| rest.get(..2)) else {
+            break;
+        };
+        if !part.bytes().all(|b| b.is_ascii_digit()) {
+            break;
+        }
+        date.push('-');
+        date.push_str(part);
+        rest = &rest[3..];
+    }
+    Some(date)
+}
+
+/// The earliest of the original release, release, recording and year tags.
+fn read_release_date(tag: &Tag) -> Option<String> {
+    let keys = [ItemKey::OriginalReleaseDate, ItemKey::ReleaseDate, ItemKey::RecordingDate, ItemKey::Year];
+    keys.iter()
+        .filter_map(|key| tag.get_string(key))
+        .filter_map(normalize_tag_date)
+        .chain(tag.year().map(|year| year.to_string()).and_then(|year| normalize_tag_date(&year)))
+        .min()
+}
+
+/// Genre values, split where a file stores several in one field.
+fn read_genres(tag: &Tag) -> Vec<String> {
+    let mut genres: Vec<String> = Vec::new();
+    for genre in tag.get_strings(&ItemKey::Genre).flat_map(|value| value.split(['\0', ';'])) {
+        let genre = genre.trim();
+        if !genre.is_empty() && !genres.iter().any(|existing| existing.eq_ignore_ascii_case(genre)) {
+            genres.push(genre.to_string());
+        }
+    }
+    genres
+}
+
+/// The first MusicBrainz ID in a tag. Files credited to several artists hold
+/// one ID each, separated like other multi-value tags.
+fn read_musicbrainz_id(tag: &Tag, key: &ItemKey) -> Option<String> {
+    tag.get_strings(key)
+        .flat_map(|value| value.split(['\0', ';', '/']))
+        .map(|id| id.trim().to_lowercase())
+        .find(|id| id.len() == 36 && id.chars().all(|c| c.is_ascii_hexdigit() || c == '-'))
+}
+
+fn read_cover_picture(path: &Path) -> Option<Picture> {
+    let tagged_file = Probe::open(path).and_then(|probe| probe.read()).ok()?;
+    let tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag())?;
+
+    tag.pictures().iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or_else(|| tag.pictures().first())
+        .cloned()
+}
+
+fn read_audio_properties(tagged_file: &TaggedFile, path: &Path) -> AudioProperties {
+    let (codec, container) = match tagged_file.file_type() {
+        FileType::Aac => ("AAC", "ADTS"),
+        FileType::Aiff => ("PCM", "AIFF"),
+        FileType::Ape => ("APE", "APE"),
+        FileType::Flac => ("FLAC", "FLAC"),
+        FileType::Mpeg => ("MP3", "MPEG"),
+        FileType::Mp4 => (read_mp4_codec(path).unwrap_or("Unknown"), "MP4"),
+        FileType::Mpc => ("Musepack", "MPC"),
+        FileType::Opus => ("Opus", "Ogg"),
+        FileType::Vorbis => ("Vorbis", "Ogg"),
+        FileType::Speex => ("Speex", "Ogg"),
+        FileType::Wav => ("PCM", "WAV"),
+        FileType::WavPack => ("WavPack", "WavPack"),
+        FileType::Custom(name) => (name, name),
+        _ => ("Unknown", "Unknown"),
+    };
+
+    let properties = tagged_file.properties();
+
+    AudioProperties {
+        codec: codec.to_string(),
+        container: container.to_string(),
+        sample_rate: properties.sample_rate(),
+        bit_depth: properties.bit_depth(),
+        channels: properties.channels(),
+        bitrate: properties.audio_bitrate().or_else(|| properties.overall_bitrate()),
+        file_size: fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0),
+    }
+}
+
+/// Reads a file's tags and audio properties. Files whose tags can't be read
+/// are still indexed from their names; the error is returned alongside, as it
+/// is for files that were read but report no duration.
+fn read_song(path: &Path) -> (ScannedSong, Option<ScanFileError>) {
+    let format = audio_format(path);
+
+    let mut error = None;
+    let tagged_file = match Probe::open(path).and_then(|probe| probe.read()) {
+        Ok(tagged_file) => Some(tagged_file),
+        Err(e) => {
+            if format.is_none_or(|format| format.readable) {
+                warn!("Failed to read tags from {}: {}", path.display(), e);
+                error = Some(ScanFileError::new(path, ScanErrorKind::UnreadableTags, e.to_string()));
+            }
+            None
+        }
+    };
+    let tag = tagged_file.as_ref().and_then(|file| file.primary_tag().or_else(|| file.first_tag()));
+
+    let artists: Vec<String> = tag
+        .and_then(|t| t.artist())
+        .map(|artist| vec![artist.to_string()])
+        .unwrap_or_default();
+
+    let song_name = tag.and_then(|t| t.title())
+        .map(|title| title.to_string())
+        .unwrap_or_else(|| {
+            path.file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("Unknown Title")
+                .to_string()
+        });
+
+    let album_name = tag.and_then(|t| t.album())
+        .map(|album| album.to_string())
+        .unwrap_or_else(|| {
+            // Use parent directory name as fallback
+            path.parent()
+                .and_then(|p| p.file_name())
+                .and_then(|s| s.to_str())
+                .unwrap_or("Unknown Album")
+                .to_string()
+        });
+
+    let track_number = tag.and_then(|t| t.track()).unwrap_or(0) as u16;
+
+    let disc_number = tag.and_then(|t| t.disk()).map(|disc| disc as u16)
+        .or_else(|| parse_album_title(&album_name).disc_number)
+        .or_else(|| {
+            path.parent()
+                .and_then(|p| p.file_name())
+                .and_then(|s| s.to_str())
+                .and_then(parse_disc_number)
+        })
+        .unwrap_or(1);
+    let disc_total = tag.and_then(|t| t.disk_total()).map(|total| total as u16).unwrap_or(disc_number).max(disc_number);
+
+    // ALBUMARTIST (Vorbis/APE), TPE2 (ID3v2) and aART (MP4) all map to ItemKey::AlbumArtist
+    let album_artist = tag
+        .and_then(|t| t.get_string(&ItemKey::AlbumArtist))
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    let compilation = tag
+        .and_then(|t| t.get_string(&ItemKey::FlagCompilation))
+        .map(|s| s.trim() == "1" || s.trim().eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+
+    let artist_values = tag.and_then(read_artist_values);
+    let release_date = tag.and_then(read_release_date);
+    let genres = tag.map(read_genres).unwrap_or_default();
+
+    let musicbrainz_id = |key: ItemKey| tag.and_then(|t| read_musicbrainz_id(t, &key));
+    // The album artist's ID, unless the album belongs to the track artist
+    let musicbrainz_artist_id = if album_artist.is_some() || compilation {
+        musicbrainz_id(ItemKey::MusicBrainzReleaseArtistId)
+    } else {
+        musicbrainz_id(ItemKey::MusicBrainzArtistId)
+    };
+
+    let (duration, audio) = match (&tagged_file, format) {
+        (Some(tagged_file), _) => (
+            tagged_file.properties().duration().as_secs_f64(),
+            Some(read_audio_properties(tagged_file, path)),
+        ),
+        // Formats lofty can't parse still get their codec and size from the format table
+        (None, Some(format)) => (0.0, Some(AudioProperties {
+            codec: format.codec.to_string(),
+            container: format.container.to_string(),
+            file_size: fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0),
+            ..AudioProperties::default()
+        })),
+        (None, None) => (0.0, None),
+    };
+    if tagged_file.is_some() && duration <= 0.0 {
+        error = Some(ScanFileError::new(path, ScanErrorKind::ZeroDuration, "The file reports no duration"));
+    }
+
+    // Separate tag values are already one artist each, so they are never split
+    let formatted_artists = match artist_values.as_deref() {
+        Some([main_artist, contributing_artists @ ..]) => vec![(
+            main_artist.clone(),
+            contributing_artists.iter()
+                .filter(|artist| artist.to_lowercase() != main_artist.to_lowercase())
+                .cloned()
+                .collect(),
+        )],
+        _ if artists.is_empty() => vec![(String::from("Unknown Artist"), Vec::new())],
+        _ => format_contributing_artists(artists),
+    };
+
+    let artist_name = formatted_artists.get(0).map_or(String::new(), |a| a.0.clone());
+    let contributing_artists = formatted_artists.get(0).map_or(Vec::new(), |a| a.1.clone());
+
+    let id = hash_song(&song_name, &artist_name, &album_name, disc_number, track_number);
+
+    let song = Song {
+        id,
+        name: song_name,
+        artist: artist_name,
+        contributing_artists,
+        contributing_artist_ids: Vec::new(),
+        track_number,
+        disc_number,
+        disc_total,
+        path: path.to_string_lossy().to_string(),
+        duration,
+        audio,
+        music_video: None,
+        musicbrainz_recording_id: musicbrainz_id(ItemKey::MusicBrainzRecordingId),
+        musicbrainz_track_id: musicbrainz_id(ItemKey::MusicBrainzTrackId),
+    };
+
+    let scanned = ScannedSong {
+        song,
+        album_name,
+        album_artist,
+        compilation,
+        release_date,
+        genres,
+        musicbrainz_release_id: musicbrainz_id(ItemKey::MusicBrainzReleaseId),
+        musicbrainz_release_group_id: musicbrainz_id(ItemKey::MusicBrainzReleaseGroupId),
+        musicbrainz_artist_id,
+    };
+
+    (scanned, error)
+}
+
+/// The artist a song's album is filed under, and hashed with: the album artist
+/// tag, Various Artists for untagged compilations, or the track artist.
+pub(crate) fn album_owner(album_artist: Option<&str>, compilation: bool, song_artist: &str) -> String {
+    match album_artist {
+        Some(album_artist) => album_artist.to_string(),
+        None if compilation => VARIOUS_ARTISTS.to_string(),
+        None => song_artist.to_string(),
+    }
+}
+
+/// Adds a song to the catalog, creating its artist and album as needed, and
+/// returns the album id along with any failure to store an embedded cover.
+/// Embedded covers aren't stored when `dry_run` is set.
+fn add_song_to_library(library: &Mutex<Vec<Artist>>, scanned: ScannedSong, path: &Path, dry_run: bool) -> (String, Option<ScanFileError>) {
+    let ScannedSong {
+        mut song,
+        album_name,
+        album_artist,
+        compilation,
+        release_date,
+        genres,
+        musicbrainz_release_id,
+        musicbrainz_release_group_id,
+        musicbrainz_artist_id,
+    } = scanned;
+
+    let artist_name = album_owner(album_artist.as_deref(), compilation, &song.artist);
+    let compilation = compilation || artist_name.eq_ignore_ascii_case(VARIOUS_ARTISTS);
+
+    // The track artist is credited on the album when someone else owns it.
+    let mut contributing_artists = song.contributing_artists.clone();
+    if !song.artist.eq_ignore_ascii_case(&artist_name) {
+        contributing_artists.push(song.artist.clone());
+    }
+
+    let album_title = parse_album_title(&album_name);
+    let album_name_without_cd = album_title.name.clone();
+
+    let album_id;
+    let mut cover_error = None;
+    {
+        let mut library = library.lock().unwrap();
+
+        let artist_name_lowercase = artist_name.to_lowercase();
+        let artist_position = library.iter().position(|a| a.name.to_lowercase() == artist_name_lowercase);
+
+        let artist = if let Some(artist_position) = artist_position {
+            &mut library[artist_position]
+        } else {
+            let new_artist = Artist {
+                id: hash_artist(&artist_name),
+                name: artist_name.clone(),
+                albums: Vec::new(),
+                featured_on_album_ids: Vec::new(),
+                icon_url: String::new(),
+                followers: 0,
+                description: String::new(),
+                tadb_music_videos: None,
+                pending_enrichment: false,
+                musicbrainz_id: None,
+            };
+            library.push(new_artist);
+            library.last_mut().unwrap()
+        };
+
+        let album_position = artist.albums.iter().position(|a| a.name == album_name_without_cd && a.id == hash_album(&album_name_without_cd.clone(), &artist_name));
+
+        let album = if let Some(album_position) = album_position {
+            &mut artist.albums[album_position]
+        } else {
+            let mut new_album = Album {
+                id: hash_album(&album_name_without_cd.clone(), &artist_name),
+                name: album_name_without_cd.clone(),
+                edition: album_title.edition.clone(),
+                album_type: if compilation { AlbumType::Compilation } else { AlbumType::Album },
+                songs: Vec::new(),
+                cover_url: String::new(),
+                primary_type: String::new(),
+                description: String::new(),
+                first_release_date: String::new(),
+                musicbrainz_id: String::new(),
+                wikidata_id: None,
+                contributing_artists: Vec::new(),
+                contributing_artists_ids: Vec::new(),
+                release_album: None,
+                release_group_album: None,
+                genres: Vec::new(),
+                pending_enrichment: false,
+                musicbrainz_release_id: None,
+                musicbrainz_release_group_id: None,
+            };
+
+            let mut cover_found = false;
+
+            let base_cover_art_path = get_cover_art_path();
+            let cover_art_path = base_cover_art_path.join(format!("{}.jpg", new_album.id));
+
+            if cover_art_path.exists() {
+                new_album.cover_url = cover_art_path.to_string_lossy().to_string();
+                cover_found = true;
+            } else {
+                if let Some(picture) = read_cover_picture(path).filter(|_| !dry_run) {
+                    match File::create(&cover_art_path).and_then(|mut file| file.write_all(picture.data())) {
+                        Ok(()) => {
+                            new_album.cover_url = cover_art_path.to_string_lossy().to_string();
+                            cover_found = true;
+                        }
+                        Err(e) => {
+                            cover_error = Some(ScanFileError::new(
+                                path,
+                                ScanErrorKind::CoverArt,
+                                format!("Failed to write {}: {}", cover_art_path.display(), e),
+                            ));
+                        }
+                    }
+                }
+            }                
+
+            if !cover_found {
+                if let Some(parent_path) = path.parent() {
+                    for image_path in WalkDir::new(parent_path)
+                        .max_depth(1)
+                        .into_iter()
+                        .filter_map(|e| e.ok())
+                        .filter(|e| {
+                            e.file_type().is_file()
+                                && matches!(
+                                    e.path().extension().and_then(|s| s.to_str()),
+                                    Some("jpg")
+                                        | Some("jpeg")
+                                        | Some("png")
+                                        | Some("gif")
+                                        | Some("bmp")
+                                        | Some("ico")
+                                        | Some("tif")
+                                        | Some("tiff")
+                                        | Some("webp")
+                                )
+                        })
+                    {
+                        new_album.cover_url = image_path.path().to_string_lossy().to_string();
+                        cover_found = true;
+                        break;
+                    }
+
+                    if !cover_found
+                        && parent_path.read_dir().is_ok_and(|mut entries| entries.any(|e| {
+                            if let Ok(entry) = e {
+                                let path = entry.path();
+                                let path_file_name = entry.file_name().to_string_lossy().to_string();
+                                path.is_dir()
+                                    && (path_file_name.starts_with("CD")
+                                        || path_file_name.starts_with("Disc")
+                                        || path_file_name == "Covers")
+                            } else {
+                                false
+                            }
+                        }))
+                    {
+                        if let Some(grandparent_path) = parent_path.parent() {
+                            for image_path in WalkDir::new(grandparent_path)
+                                .max_depth(1)
+                                .into_iter()
+                                .filter_map(|e| e.ok())
+                                .filter(|e| {
+                                    e.file_type().is_file()
+                                        && matches!(
+                                            e.path().extension().and_then(|s| s.to_str()),
+                                            Some("jpg")
+                                                | Some("jpeg")
+                                                | Some("png")
+                                                | Some("gif")
+                                                | Some("bmp")
+                                                | Some("ico")
+                                                | Some("tif")
+                                                | Some("tiff")
+                                                | Some("webp")
+                                        )
+                                })
+                            {
+                                new_album.cover_url = image_path.path().to_string_lossy().to_string();
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+
+            artist.albums.push(new_album);
+            artist.albums.sort_by(|a, b| a.name.cmp(&b.name));
+            artist.albums.last_mut().unwrap()
+        };
+
+        album_id = album.id.clone();
+    }
+
+    let mut contributing_artist_ids = Vec::new();
+    let mut new_artists = Vec::new();
+    let mut featured_on_album_updates = Vec::new();
+
+    for contributing_artist_name in &contributing_artists {
+        let contributing_artist_name_lowercase = contributing_artist_name.to_lowercase();
+        let contributing_artist_position = {
+            let library = library.lock().unwrap();
+            library.iter().position(|a| a.name.to_lowercase() == contributing_artist_name_lowercase)
+        };
+
+        if let Some(contributing_artist_position) = contributing_artist_position {
+            let contributing_artist = {
+                let library = library.lock().unwrap();
+                &library[contributing_artist_position].clone()
+            };
+            contributing_artist_ids.push(contributing_artist.id.clone());
+
+            if !contributing_artist.featured_on_album_ids.contains(&album_id) {
+                featured_on_album_updates.push(contributing_artist_position);
+            }
+        } else {
+            let new_artist = Artist {
+                id: hash_artist(&contributing_artist_name),
+                name: contributing_artist_name.clone(),
+                albums: Vec::new(),
+                featured_on_album_ids: vec![album_id.clone()],
+                icon_url: String::new(),
+                followers: 0,
+                description: String::new(),
+                tadb_music_videos: None,
+                pending_enrichment: false,
+                musicbrainz_id: None,
+            };
+            contributing_artist_ids.push(new_artist.id.clone());
+            new_artists.push(new_artist);
+        }
+    }
+
+    {
+        let mut library = library.lock().unwrap();
+
+        for new_artist in new_artists {
+            let new_artist_name_lowercase = new_artist.name.to_lowercase();
+            match library.iter_mut().find(|a| a.name.to_lowercase() == new_artist_name_lowercase) {
+                Some(existing_artist) => {
+                    if !existing_artist.featured_on_album_ids.contains(&album_id) {
+                        existing_artist.featured_on_album_ids.push(album_id.clone());
+                    }
+                }
+                None => library.push(new_artist),
+            }
+        }
+
+        for artist_position in featured_on_album_updates {
+            if let Some(contributing_artist) = library.get_mut(artist_position) {
+                contributing_artist.featured_on_album_ids.push(album_id.clone());
+            }
+        }
+    }
+
+    song.contributing_artist_ids = contributing_artist_ids[..song.contributing_artists.len()].to_vec();
+
+    {
+        let mut library = library.lock().unwrap();
+        let artist_name_clone = artist_name.clone();
+        let artist_name_lowercase = artist_name_clone.to_lowercase();
+        let artist_position = library.iter().position(|a| a.name.to_lowercase() == artist_name_lowercase).unwrap();
+        let artist = &mut library[artist_position];
+        if artist.musicbrainz_id.is_none() {
+            artist.musicbrainz_id = musicbrainz_artist_id;
+        }
+        let album_name_without_cd_clone = album_name_without_cd.clone();
+        let album_position = artist.albums.iter().position(|a| a.name == album_name_without_cd_clone && a.id == hash_album(&album_name_without_cd_clone, &artist_name)).unwrap();
+        let album = &mut artist.albums[album_position];
+
+        if !album.songs.iter().any(|s| s.id == song.id) {
+            album.songs.push(song);
+            album.songs.sort_by_key(|s| (s.disc_number, s.track_number));
+        }
+
+        for contributing_artist_name in &contributing_artists {
+            if !album.contributing_artists.contains(contributing_artist_name) {
+                album.contributing_artists.push(contributing_artist_name.clone());
+            }
+        }
+        for contributing_artist_id in &contributing_artist_ids {
+            if !album.contributing_artists_ids.contains(contributing_artist_id) {
+                album.contributing_artists_ids.push(contributing_artist_id.clone());
+            }
+        }
+
+        if compilation {
+            album.album_type = AlbumType::Compilation;
+        }
+
+        // Looked up dates win over the tags, which only fill in what is missing
+        if album.first_release_date.is_empty() {
+            if let Some(release_date) = release_date {
+                album.first_release_date = release_date;
+            }
+        }
+        for genre in genres {
+            if !album.genres.iter().any(|existing| existing.eq_ignore_ascii_case(&genre)) {
+                album.genres.push(genre);
+            }
+        }
+        if album.musicbrainz_release_id.is_none() {
+            album.musicbrainz_release_id = musicbrainz_release_id;
+        }
+        if album.musicbrainz_release_group_id.is_none() {
+            album.musicbrainz_release_group_id = musicbrainz_release_group_id;
+        }
+    }
+
+    (album_id, cover_error)
+}
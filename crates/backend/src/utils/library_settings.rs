@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::utils::config::get_libraries_config_path;
+use crate::utils::hash::hash_library;
+
+pub const IGNORE_FILE_NAME: &str = ".musicignore";
+
+/// A library root. The id is derived from the path when the root is added and
+/// stays the same if the root is renamed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Library {
+    pub id: String,
+    pub path: String,
+    /// Display name, defaulting to the root's directory name.
+    pub name: String,
+    /// Disabled roots keep their songs but aren't scanned or watched.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub settings: LibrarySettings,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Library {
+    pub fn new(path: &str, name: Option<String>) -> Self {
+        let name = name.filter(|name| !name.trim().is_empty()).unwrap_or_else(|| {
+            Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.to_string())
+        });
+
+        Library {
+            id: hash_library(path),
+            path: path.to_string(),
+            name,
+            enabled: true,
+            settings: LibrarySettings::default(),
+        }
+    }
+
+    /// Whether a song path lies under this root.
+    pub fn contains(&self, song_path: &str) -> bool {
+        Path::new(song_path).starts_with(&self.path)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Libraries {
+    #[serde(default)]
+    pub libraries: Vec<Library>,
+    /// Older files only listed root paths, with scan settings keyed by path.
+    /// `read_libraries` turns them into `libraries` entries.
+    #[serde(default, skip_serializing)]
+    paths: Vec<String>,
+    #[serde(default, skip_serializing)]
+    settings: HashMap<String, LibrarySettings>,
+}
+
+impl Libraries {
+    /// Paths of the roots that are scanned and watched.
+    pub fn enabled_paths(&self) -> Vec<String> {
+        self.libraries.iter()
+            .filter(|library| library.enabled)
+            .map(|library| library.path.clone())
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Library> {
+        self.libraries.iter().find(|library| library.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut Library> {
+        self.libraries.iter_mut().find(|library| library.id == id)
+    }
+
+    pub fn by_path(&self, path: &str) -> Option<&Library> {
+        self.libraries.iter().find(|library| library.path == path)
+    }
+
+    pub fn by_path_mut(&mut self, path: &str) -> Option<&mut Library> {
+        self.libraries.iter_mut().find(|library| library.path == path)
+    }
+
+    /// Adds a root unless its path is already configured. Returns whether it was added.
+    pub fn add(&mut self, library: Library) -> bool {
+        if self.by_path(&library.path).is_some() {
+            return false;
+        }
+
+        self.libraries.push(library);
+        true
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<Library> {
+        let index = self.libraries.iter().position(|library| library.id == id)?;
+        Some(self.libraries.remove(index))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LibrarySettings {
+    /// Patterns in `.musicignore` syntax, relative to the library root.
+    pub exclude: Vec<String>,
+    /// Songs shorter than this many seconds are left out of the catalog.
+    pub min_duration: f64,
+    /// Files smaller than this many bytes are skipped without being read.
+    pub min_file_size: u64,
+    pub follow_symlinks: bool,
+}
+
+impl Default for LibrarySettings {
+    fn default() -> Self {
+        LibrarySettings {
+            exclude: ["@eaDir", ".AppleDouble", "#recycle", "._*"].iter().map(|s| s.to_string()).collect(),
+            min_duration: 0.0,
+            min_file_size: 0,
+            follow_symlinks: false,
+        }
+    }
+}
+
+pub fn read_libraries() -> Libraries {
+    let mut libraries: Libraries = fs::read_to_string(get_libraries_config_path())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+
+    for path in std::mem::take(&mut libraries.paths) {
+        let mut library = Library::new(&path, None);
+        if let Some(settings) = libraries.settings.remove(&path) {
+            library.settings = settings;
+        }
+        libraries.add(library);
+    }
+
+    libraries
+}
+
+pub fn write_libraries(libraries: &Libraries) -> Result<(), Box<dyn Error>> {
+    fs::write(get_libraries_config_path(), serde_json::to_string_pretty(libraries)?)?;
+    Ok(())
+}
+
+pub fn library_settings(library_path: &str) -> LibrarySettings {
+    read_libraries()
+        .by_path(library_path)
+        .map(|library| library.settings.clone())
+        .unwrap_or_default()
+}
+
+/// Turns `.musicignore` patterns into globs relative to the directory they
+/// apply to. Like `.gitignore`, a pattern without a slash matches at any depth
+/// and one with a slash is anchored to that directory. Negations aren't supported.
+fn build_glob_set(patterns: &[String], source: &str) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+
+    for line in patterns {
+        let pattern = line.trim();
+        if pattern.is_empty() || pattern.starts_with('!') {
+            continue;
+        }
+
+        let pattern = pattern.trim_end_matches('/');
+        let pattern = if pattern.contains('/') {
+            pattern.trim_start_matches('/').to_string()
+        } else {
+            format!("**/{}", pattern)
+        };
+
+        match GlobBuilder::new(&pattern).literal_separator(true).build() {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => warn!("Ignoring invalid pattern {:?} in {}: {}", line, source, e),
+        }
+    }
+
+    builder.build().unwrap_or_else(|e| {
+        warn!("Failed to build ignore patterns from {}: {}", source, e);
+        GlobSet::empty()
+    })
+}
+
+/// Decides which paths under a library root are scanned, combining the
+/// library's exclude patterns with any `.musicignore` files on the way down.
+pub struct LibraryFilter {
+    root: PathBuf,
+    exclude: GlobSet,
+    ignore_files: HashMap<PathBuf, Option<GlobSet>>,
+}
+
+impl LibraryFilter {
+    pub fn new(root: &Path, settings: &LibrarySettings) -> Self {
+        LibraryFilter {
+            root: root.to_path_buf(),
+            exclude: build_glob_set(&settings.exclude, "library settings"),
+            ignore_files: HashMap::new(),
+        }
+    }
+
+    fn ignore_file(&mut self, directory: &Path) -> Option<&GlobSet> {
+        self.ignore_files
+            .entry(directory.to_path_buf())
+            .or_insert_with(|| {
+                let ignore_path = directory.join(IGNORE_FILE_NAME);
+                let content = fs::read_to_string(&ignore_path).ok()?;
+                // Comments only exist in files, so a `#recycle` exclude in the settings still matches
+                let patterns: Vec<String> = content
+                    .lines()
+                    .filter(|line| !line.trim_start().starts_with('#'))
+                    .map(|line| line.to_string())
+                    .collect();
+                Some(build_glob_set(&patterns, &ignore_path.to_string_lossy()))
+            })
+            .as_ref()
+    }
+
+    pub fn is_ignored(&mut self, path: &Path) -> bool {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative.to_path_buf(),
+            _ => return false,
+        };
+
+        if self.exclude.is_match(&relative) {
+            return true;
+        }
+
+        let directories: Vec<PathBuf> = path.ancestors()
+            .skip(1)
+            .take_while(|ancestor| ancestor.starts_with(&self.root))
+            .map(|ancestor| ancestor.to_path_buf())
+            .collect();
+
+        for directory in directories {
+            let relative = path.strip_prefix(&directory).unwrap().to_path_buf();
+            if self.ignore_file(&directory).is_some_and(|ignore| ignore.is_match(&relative)) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Like `is_ignored`, but also checks every directory between the path and
+    /// the library root, for paths that didn't come from walking the library.
+    pub fn is_ignored_with_parents(&mut self, path: &Path) -> bool {
+        let paths: Vec<PathBuf> = path.ancestors()
+            .take_while(|ancestor| ancestor.starts_with(&self.root))
+            .map(|ancestor| ancestor.to_path_buf())
+            .collect();
+
+        paths.iter().any(|path| self.is_ignored(path))
+    }
+}
@@ -0,0 +1,126 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::utils::library_settings::{LibraryFilter, LibrarySettings, IGNORE_FILE_NAME};
+
+    const ROOT: &str = "/music-library-settings-test";
+
+    fn filter(exclude: &[&str]) -> LibraryFilter {
+        let settings = LibrarySettings {
+            exclude: exclude.iter().map(|pattern| pattern.to_string()).collect(),
+            ..LibrarySettings::default()
+        };
+        LibraryFilter::new(Path::new(ROOT), &settings)
+    }
+
+    fn is_ignored(filter: &mut LibraryFilter, path: &str) -> bool {
+        filter.is_ignored(&Path::new(ROOT).join(path))
+    }
+
+    #[test]
+    fn test_default_excludes() {
+        let mut filter = LibraryFilter::new(Path::new(ROOT), &LibrarySettings::default());
+        let cases = [
+            ("@eaDir", true),
+            ("Artist/Album/@eaDir", true),
+            ("Artist/.AppleDouble", true),
+            ("#recycle", true),
+            ("Artist/Album/._01.flac", true),
+            ("Artist/Album/01.flac", false),
+            ("Artist/Album/_01.flac", false),
+        ];
+
+        for (path, ignored) in cases {
+            assert_eq!(is_ignored(&mut filter, path), ignored, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_patterns_without_slash_match_at_any_depth() {
+        let mut filter = filter(&["*.m4b", "Live"]);
+        let cases = [
+            ("book.m4b", true),
+            ("Artist/Audiobooks/book.m4b", true),
+            ("Live", true),
+            ("Artist/Live", true),
+            ("Artist/Live Album", false),
+            ("Artist/Album/01.m4a", false),
+        ];
+
+        for (path, ignored) in cases {
+            assert_eq!(is_ignored(&mut filter, path), ignored, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_patterns_with_slash_are_anchored() {
+        let mut filter = filter(&["/Podcasts", "Artist/Demos/", "Various/*/bonus"]);
+        let cases = [
+            ("Podcasts", true),
+            ("Artist/Podcasts", false),
+            ("Artist/Demos", true),
+            ("Other/Artist/Demos", false),
+            ("Various/Hits/bonus", true),
+            // `*` doesn't cross directories
+            ("Various/Hits/Disc 1/bonus", false),
+        ];
+
+        for (path, ignored) in cases {
+            assert_eq!(is_ignored(&mut filter, path), ignored, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_negations_and_invalid_patterns_are_skipped() {
+        let mut filter = filter(&["!keep.flac", "[", "  ", "*.cue"]);
+
+        assert!(!is_ignored(&mut filter, "keep.flac"));
+        assert!(is_ignored(&mut filter, "Album/disc.cue"));
+    }
+
+    #[test]
+    fn test_root_is_never_ignored() {
+        let mut filter = filter(&["*"]);
+
+        assert!(!filter.is_ignored(Path::new(ROOT)));
+        assert!(!filter.is_ignored(Path::new("/elsewhere/song.flac")));
+        assert!(is_ignored(&mut filter, "song.flac"));
+    }
+
+    #[test]
+    fn test_parents_are_checked() {
+        let mut filter = filter(&["/Podcasts"]);
+        let path = Path::new(ROOT).join("Podcasts/Show/episode.mp3");
+
+        assert!(!filter.is_ignored(&path));
+        assert!(filter.is_ignored_with_parents(&path));
+    }
+
+    #[test]
+    fn test_ignore_files_apply_below_their_directory() {
+        let root = std::env::temp_dir().join(format!("library-settings-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("Artist/Album")).unwrap();
+        fs::write(root.join("Artist").join(IGNORE_FILE_NAME), "# Leftovers\nScans\n/bonus.flac\n").unwrap();
+
+        let mut filter = LibraryFilter::new(&root, &LibrarySettings::default());
+        let cases = [
+            ("Artist/Album/Scans", true),
+            ("Artist/bonus.flac", true),
+            // Comment lines aren't patterns
+            ("Artist/# Leftovers", false),
+            // Anchored to the directory holding the ignore file
+            ("Artist/Album/bonus.flac", false),
+            // Outside the directory holding the ignore file
+            ("Scans", false),
+            ("Artist/Album/01.flac", false),
+        ];
+        let results: Vec<bool> = cases.iter().map(|(path, _)| filter.is_ignored(&root.join(path))).collect();
+        fs::remove_dir_all(&root).unwrap();
+
+        for ((path, ignored), result) in cases.iter().zip(results) {
+            assert_eq!(result, *ignored, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::structures::structures::{Album, Artist};
+use super::format::parse_disc_number;
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "gif"];
+
+/// Artist images looked for in the artist's folder, by file name without extension.
+const ARTIST_IMAGE_NAMES: [&str; 2] = ["artist", "folder"];
+
+/// Plain text sidecars, checked after the Kodi-style `.nfo` file.
+const ALBUM_DESCRIPTION_FILES: [&str; 2] = ["description.txt", "review.txt"];
+const ARTIST_DESCRIPTION_FILES: [&str; 2] = ["biography.txt", "bio.txt"];
+
+/// The folder holding an album's songs. Songs split into `CD1`, `Disc 2` and
+/// similar folders lead back to the folder above them.
+pub fn album_directory(album: &Album) -> Option<PathBuf> {
+    let song_directory = Path::new(&album.songs.first()?.path).parent()?;
+
+    let is_disc_directory = song_directory
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse_disc_number)
+        .is_some();
+    if is_disc_directory {
+        song_directory.parent().map(Path::to_path_buf)
+    } else {
+        Some(song_directory.to_path_buf())
+    }
+}
+
+/// The folders above the artist's album folders, most common first.
+pub fn artist_directories(artist: &Artist) -> Vec<PathBuf> {
+    let mut directories: Vec<(PathBuf, usize)> = Vec::new();
+    for directory in artist.albums.iter().filter_map(album_directory).filter_map(|path| path.parent().map(Path::to_path_buf)) {
+        match directories.iter_mut().find(|(existing, _)| *existing == directory) {
+            Some((_, count)) => *count += 1,
+            None => directories.push((directory, 1)),
+        }
+    }
+
+    directories.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    directories.into_iter().map(|(directory, _)| directory).collect()
+}
+
+/// An image in `directory` named one of `names`, ignoring case.
+fn find_named_image(directory: &Path, names: &[&str]) -> Option<PathBuf> {
+    let mut images: Vec<PathBuf> = fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default().to_lowercase();
+            let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default().to_lowercase();
+            IMAGE_EXTENSIONS.contains(&extension.as_str()) && names.contains(&stem.as_str())
+        })
+        .collect();
+
+    // Earlier names in the list win
+    images.sort_by_key(|path| {
+        let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default().to_lowercase();
+        names.iter().position(|name| *name == stem)
+    });
+    images.into_iter().next()
+}
+
+/// The text of the first `element` in an `.nfo` file, unescaped.
+fn read_nfo_element(path: &Path, element: &str) -> Option<String> {
+    let contents = fs::read_to_string(path).ok()?;
+    let pattern = Regex::new(&format!(r"(?s)<{0}>(.*?)</{0}>", element)).ok()?;
+    let text = pattern.captures(&contents)?.get(1)?.as_str();
+
+    let text = text
+        .trim()
+        .trim_start_matches("<![CDATA[")
+        .trim_end_matches("]]>")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+fn read_description(directory: &Path, nfo_file: &str, nfo_element: &str, text_files: &[&str]) -> Option<String> {
+    read_nfo_element(&directory.join(nfo_file), nfo_element).or_else(|| {
+        text_files
+            .iter()
+            .filter_map(|file| fs::read_to_string(directory.join(file)).ok())
+            .map(|text| text.trim().to_string())
+            .find(|text| !text.is_empty())
+    })
+}
+
+/// An `artist.jpg` or `folder.jpg` (or another image format) in the artist's folder.
+pub fn find_artist_image(artist: &Artist) -> Option<PathBuf> {
+    artist_directories(artist)
+        .iter()
+        .find_map(|directory| find_named_image(directory, &ARTIST_IMAGE_NAMES))
+}
+
+/// The `<biography>` of an `artist.nfo`, or a `biography.txt` or `bio.txt`, in the artist's folder.
+pub fn read_artist_description(artist: &Artist) -> Option<String> {
+    artist_directories(artist)
+        .iter()
+        .find_map(|directory| read_description(directory, "artist.nfo", "biography", &ARTIST_DESCRIPTION_FILES))
+}
+
+/// The `<review>` of an `album.nfo`, or a `description.txt` or `review.txt`, in the album's folder.
+pub fn read_album_description(album: &Album) -> Option<String> {
+    let directory = album_directory(album)?;
+    read_description(&directory, "album.nfo", "review", &ALBUM_DESCRIPTION_FILES)
+}
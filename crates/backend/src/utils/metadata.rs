@@ -1,4 +1,4 @@
-use std::{collections::HashMap, error::Error, path::Path, sync::{Arc, Mutex}, time::Duration};
+use std::{collections::HashMap, error::Error};
 
 use regex::Regex;
 use reqwest::{
@@ -9,7 +9,7 @@ use levenshtein::levenshtein;
 use scraper::{Html, Selector};
 use serde::Deserialize;
 use serde_json::Value;
-use tokio::{fs, io, time::sleep};
+use tokio::{fs, io};
 use tracing::{info, warn};
 use std::error::Error as StdError;
 
@@ -21,6 +21,9 @@ use crate::{
 };
 
 use super::config::{get_cover_art_path, get_icon_art_path};
+use super::metadata_http::{get_cached, send_uncached};
+use super::metadata_providers::{MetadataField, MetadataProviders, ProviderKind};
+use super::progress::ScanProgress;
 
 #[derive(Debug, Deserialize)]
 struct SearchResponse {
@@ -54,22 +57,19 @@ struct TokenResponse {
     accessToken: String,
 }
 
-pub async fn get_access_token() -> Result<String, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let token_res: TokenResponse = client
-        .get("https://open.spotify.com/get_access_token?reason=transport&productType=web_player")
-        .header(reqwest::header::USER_AGENT, "MyApp/1.0")
-        .send()
-        .await?
-        .json()
-        .await?;
+pub(crate) async fn get_access_token(client: &Client, base_url: &str) -> Result<String, Box<dyn StdError + Send + Sync>> {
+    let request = client
+        .get(format!("{}/get_access_token?reason=transport&productType=web_player", base_url))
+        .header(reqwest::header::USER_AGENT, "MyApp/1.0");
+    let token_res: TokenResponse = serde_json::from_str(&send_uncached(ProviderKind::Spotify, request).await?.body)?;
     Ok(token_res.accessToken)
 }
 
-async fn get_artist_metadata(
+pub(crate) async fn get_artist_metadata(
     client: &Client,
+    base_url: &str,
     artist_name: &str,
-    access_token: String,
+    access_token: &str,
 ) -> Option<(String, u64)> {
     let mut headers = HeaderMap::new();
 
@@ -80,24 +80,16 @@ async fn get_artist_metadata(
     headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
     headers.insert(USER_AGENT, "MyApp/1.0".parse().unwrap());
 
-    let url = format!("https://api.spotify.com/v1/search?type=artist&q={}&decorate_restrictions=false&best_match=true&include_external=audio&limit=1", artist_name);
+    let url = format!("{}/v1/search?type=artist&q={}&decorate_restrictions=false&best_match=true&include_external=audio&limit=1", base_url, artist_name);
 
-    let response = match client.get(&url).headers(headers).send().await {
-        Ok(response) => response,
+    let body = match get_cached(ProviderKind::Spotify, client.get(&url).headers(headers)).await {
+        Ok(response) => response.body,
         Err(_) => {
             warn!("Failed to send request for artist: {}", artist_name);
             return None;
         }
     };
 
-    let body = match response.text().await {
-        Ok(body) => body,
-        Err(_) => {
-            warn!("Failed to read response body for artist: {}", artist_name);
-            return None;
-        }
-    };
-
     let res: SearchResponse = match serde_json::from_str(&body) {
         Ok(res) => res,
         Err(_) => {
@@ -116,103 +108,91 @@ async fn get_artist_metadata(
     None
 }
 
-pub async fn process_artist(
-    client: &Client,
-    artist: &mut Artist,
-    access_token: Option<String>,
-    store_audio_db_image: bool,
-) {
-    let client1 = client.clone();
-    let artist_name1 = artist.name.clone();
-    let wikipedia_future =
-        tokio::spawn(async move {
-            fetch_wikipedia_extract(&client1, &artist_name1, None, None).await
-        });
-
-    let wikipedia_extract = wikipedia_future.await.unwrap();
-    if let Some(wikipedia_extract) = wikipedia_extract {
-        artist.description = wikipedia_extract;
-        let log = format!("Wikipedia extract downloaded for Artist: {}", artist.name);
-        info!(log);
-        log_to_ws(log).await;
-    }
+/// Fills in the artist's description, icon and music videos from the first
+/// provider in each field's priority order that has them. Offline, only local
+/// files are looked at and the artist is left pending enrichment.
+pub async fn process_artist(client: &Client, providers: &MetadataProviders, artist: &mut Artist) {
+    update_artist_description(client, providers, artist, &ArtistMetadata::default()).await;
 
-    if let Some(token) = access_token.clone() {
-        let client2 = client.clone();
-        let artist_name2 = artist.name.clone();
-        let metadata_future = tokio::spawn(async move {
-            get_artist_metadata(&client2, &artist_name2, token).await
-        });
+    let mut icon_found = false;
+    for provider in providers.for_field(MetadataField::ArtistImage) {
+        let Some(image) = provider.artist_image(client, artist).await else {
+            continue;
+        };
 
-        if let Some((img_url, followers)) = metadata_future.await.unwrap() {
-            match download_and_store_icon_art(client, &img_url, &artist.id.to_string()).await {
-                Ok(path) => {
-                    artist.icon_url = path;
-                    artist.followers = followers;
+        // Local images are used where they are rather than copied
+        let stored = if provider.kind().is_remote() {
+            download_and_store_icon_art(client, &image.url, &artist.id.to_string()).await
+        } else {
+            Ok(image.url)
+        };
 
-                    let log = format!("Icon art downloaded and stored for Artist: {}", artist.name);
-                    info!(log);
-                    log_to_ws(log).await;
+        match stored {
+            Ok(path) => {
+                artist.icon_url = path;
+                if let Some(followers) = image.followers {
+                    artist.followers = followers;
                 }
-                Err(e) => warn!(
-                    "Failed to store icon art for Artist: {}. Error: {}",
-                    artist.name, e
-                ),
-            }
-        } else {
-            warn!("No Spotify icon for {}", artist.name);
-        }
-    } else {
-        warn!("Skipping Spotify metadata (no token) for {}", artist.name);
-    }
 
-    let client3 = client.clone();
-    let artist_clone = Arc::new(Mutex::new(artist.clone()));
-    let artist_clone_for_task = Arc::clone(&artist_clone);
-    
-    let audio_db_future = tokio::task::spawn_blocking(move || {
-        tokio::runtime::Handle::current().block_on(async move {
-            fetch_audio_db_info(&client3, &mut artist_clone_for_task.lock().unwrap(), store_audio_db_image).await
-        })
-    });
-
-    match audio_db_future.await {
-        Ok(inner_result) => match inner_result {
-            Ok(()) => {
-                let artist_clone_locked = artist_clone.lock().unwrap();
-                *artist = artist_clone_locked.clone();
-                let log = format!("AudioDB info done for {}", artist.name);
+                let log = format!("{} icon art downloaded and stored for Artist: {}", provider.kind().display_name(), artist.name);
                 info!(log);
                 log_to_ws(log).await;
+                icon_found = true;
+                break;
             }
-            Err(e) => {
-                warn!("AudioDB task error for {}: {}", artist.name, e);
-                log_to_ws(format!("AudioDB error for {}: {}", artist.name, e)).await;
-            }
-        },
-        Err(e) => {
-            warn!("Failed to join AudioDB task for {}: {:?}", artist.name, e);
-            log_to_ws(format!("Join AudioDB task failed: {:?}", e)).await;
+            Err(e) => warn!(
+                "Failed to store icon art for Artist: {}. Error: {}",
+                artist.name, e
+            ),
         }
     }
+    if !icon_found {
+        warn!("No icon art found for {}", artist.name);
+    }
 
-    sleep(Duration::from_secs(1)).await;
+    for provider in providers.for_field(MetadataField::MusicVideos) {
+        if let Some(music_videos) = provider.music_videos(client, artist).await {
+            artist.tadb_music_videos = Some(music_videos);
+            refresh_audio_db_info(artist);
+            let log = format!("{} music videos done for {}", provider.kind().display_name(), artist.name);
+            info!(log);
+            log_to_ws(log).await;
+            break;
+        }
+    }
+
+    artist.pending_enrichment = providers.is_offline();
 }
 
-pub async fn process_artists(client: &Client, library: &mut Vec<Artist>) {
-    match get_access_token().await {
-        Ok(tok) => {
-            for artist in library.iter_mut() {
-                process_artist(client, artist, Some(tok.clone()), false).await;
-            }
+/// Sets the artist's description from the first provider that has one,
+/// returning whether any did.
+pub(crate) async fn update_artist_description(
+    client: &Client,
+    providers: &MetadataProviders,
+    artist: &mut Artist,
+    metadata: &ArtistMetadata,
+) -> bool {
+    for provider in providers.for_field(MetadataField::ArtistDescription) {
+        if let Some(description) = provider.artist_description(client, artist, metadata).await {
+            artist.description = description;
+            let log = format!("{} description downloaded for Artist: {}", provider.kind().display_name(), artist.name);
+            info!(log);
+            log_to_ws(log).await;
+            return true;
         }
-        Err(e) => {
-            warn!("Failed to get Spotify token: {}", e);
-            log_to_ws(format!("Spotify token error, using AudioDB fallback: {}", e)).await;
-            for artist in library.iter_mut() {
-                process_artist(client, artist, None, true).await;
-            }
+    }
+    false
+}
+
+pub async fn process_artists(client: &Client, providers: &MetadataProviders, library: &mut Vec<Artist>, progress: &ScanProgress) {
+    progress.metadata_queued(library.len());
+    for artist in library.iter_mut() {
+        if progress.is_cancelled() {
+            break;
         }
+
+        process_artist(client, providers, artist).await;
+        progress.metadata_finished();
     }
 }
 
@@ -237,41 +217,66 @@ async fn download_and_store_icon_art(
     Ok(clean_path.to_string())
 }
 
-async fn fetch_album_metadata(
+/// The album's name as MusicBrainz would have it, without the edition.
+pub(crate) fn release_name(album: &Album) -> String {
+    match &album.edition {
+        Some(edition) => album.name.replace(&format!("({})", edition), "").trim().to_string(),
+        None => album.name.clone(),
+    }
+}
+
+/// The album's metadata, looked up by the release group or release its tags
+/// name when it has them and searched for by name otherwise.
+pub(crate) async fn fetch_album_metadata(
     client: &Client,
-    artist_name: &String,
-    mut album: &mut Album,
-) -> AlbumMetadata {
+    base_url: &str,
+    artist_name: &str,
+    album: &Album,
+) -> Option<AlbumMetadata> {
+    let tagged_ids = [
+        (&album.musicbrainz_release_group_id, true),
+        (&album.musicbrainz_release_id, false),
+    ];
+    for (id, is_release_group) in tagged_ids {
+        let Some(id) = id else {
+            continue;
+        };
+
+        match fetch_musicbrainz_release(client, base_url, id, is_release_group).await {
+            Ok(Some(metadata)) => return Some(metadata),
+            Ok(None) => {
+                let log_message = format!(
+                    "MusicBrainz has nothing for the ID {} tagged on Album: {}",
+                    id, album.name
+                );
+                info!("{}", log_message);
+                log_to_ws(log_message).await;
+            }
+            Err(e) => warn!("MusicBrainz lookup of {} failed for Album: {}. Error: {}", id, album.name, e),
+        }
+    }
+
+    let release_name = release_name(album);
+
     let status = "AND (status:\"Official\" OR status:\"Promotion\")";
     let query_with_status = format!(
         "artist:\"{}\" AND release:\"{}\" {}",
-        artist_name, album.name, status
+        artist_name, release_name, status
     );
-    let query_without_status = format!("artist:\"{}\" AND release:\"{}\"", artist_name, album.name);
-
-    let cover_art_path = get_cover_art_path();
-    let cover_url_path = cover_art_path.join(format!("{}.jpg", album.id));
-    let cover_url_path = Path::new(&cover_url_path);
-    let cover_art_already_downloaded = cover_url_path.exists();
+    let query_without_status = format!("artist:\"{}\" AND release:\"{}\"", artist_name, release_name);
 
     let url_with_status = format!(
-        "https://musicbrainz.org/ws/2/release-group/?query={}&fmt=json&limit=10",
-        query_with_status
+        "{}/ws/2/release-group/?query={}&fmt=json&limit=10",
+        base_url, query_with_status
     );
     let url_without_status = format!(
-        "https://musicbrainz.org/ws/2/release-group/?query={}&fmt=json&limit=10",
-        query_without_status
+        "{}/ws/2/release-group/?query={}&fmt=json&limit=10",
+        base_url, query_without_status
     );
 
-    let mut album_metadata = fetch_musicbrainz_metadata(
-        client,
-        &url_with_status,
-        cover_art_already_downloaded,
-        &mut album,
-    )
-    .await;
+    let mut album_metadata = fetch_musicbrainz_metadata(client, base_url, &url_with_status).await;
 
-    if album_metadata.is_err() || album_metadata.as_ref().unwrap().cover_url.is_empty() {
+    if !matches!(album_metadata, Ok(Some(_))) {
         let log_message = format!(
             "Didn't find the album: {} in release group with status, trying without status...",
             album.name
@@ -279,17 +284,10 @@ async fn fetch_album_metadata(
         info!("{}", log_message);
         log_to_ws(log_message).await;
 
-        sleep(Duration::from_secs(1)).await;
-        album_metadata = fetch_musicbrainz_metadata(
-            client,
-            &url_without_status,
-            cover_art_already_downloaded,
-            &mut album,
-        )
-        .await;
+        album_metadata = fetch_musicbrainz_metadata(client, base_url, &url_without_status).await;
     }
 
-    if album_metadata.is_err() {
+    if !matches!(album_metadata, Ok(Some(_))) {
         let log_message = format!(
             "Didn't find the album: {} in release group, trying release...",
             album.name
@@ -298,36 +296,40 @@ async fn fetch_album_metadata(
         log_to_ws(log_message).await;
 
         let url = format!(
-            "https://musicbrainz.org/ws/2/release/?query={}&fmt=json&limit=10",
-            query_without_status
+            "{}/ws/2/release/?query={}&fmt=json&limit=10",
+            base_url, query_without_status
         );
-        album_metadata =
-            fetch_musicbrainz_metadata(client, &url, cover_art_already_downloaded, &mut album)
-                .await;
+        album_metadata = fetch_musicbrainz_metadata(client, base_url, &url).await;
     }
 
-    album_metadata.unwrap_or_default()
+    match album_metadata {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            warn!("MusicBrainz lookup failed for Album: {}. Error: {}", album.name, e);
+            None
+        }
+    }
 }
 
-async fn fetch_wikipedia_extract(
+pub(crate) async fn fetch_wikipedia_extract(
     client: &Client,
+    wikidata_base_url: &str,
+    wikipedia_base_url: &str,
     artist_name: &str,
     album_name: Option<&str>,
     wikidata_id: Option<&str>,
 ) -> Option<String> {
     if let Some(wikidata_id) = wikidata_id {
         let wikidata_url = format!(
-            "https://www.wikidata.org/wiki/Special:EntityData/{}.json",
-            wikidata_id
+            "{}/wiki/Special:EntityData/{}.json",
+            wikidata_base_url, wikidata_id
         );
-        if let Ok(response) = client.get(&wikidata_url).send().await {
-            if let Ok(body) = response.text().await {
-                let v: Value = serde_json::from_str(&body).unwrap_or_else(|_| "[]".into());
-                if let Some(wikipedia_title) =
-                    v["entities"][wikidata_id]["sitelinks"]["enwiki"]["title"].as_str()
-                {
-                    return fetch_wikipedia_page_extract(client, wikipedia_title).await;
-                }
+        if let Ok(response) = get_cached(ProviderKind::Wikipedia, client.get(&wikidata_url)).await {
+            let v: Value = serde_json::from_str(&response.body).unwrap_or_else(|_| "[]".into());
+            if let Some(wikipedia_title) =
+                v["entities"][wikidata_id]["sitelinks"]["enwiki"]["title"].as_str()
+            {
+                return fetch_wikipedia_page_extract(client, wikipedia_base_url, wikipedia_title).await;
             }
         }
     }
@@ -336,45 +338,40 @@ async fn fetch_wikipedia_extract(
         Some(album) => format!("{} (Album)", album),
         None => format!("{} Artist", artist_name),
     };
-    let search_url = format!("https://en.wikipedia.org/w/api.php?action=query&format=json&list=search&srsearch={}&srlimit=1", query);
-    if let Ok(response) = client.get(&search_url).send().await {
-        if let Ok(body) = response.text().await {
-            let v: Value = serde_json::from_str(&body).unwrap_or_else(|_| "[]".into());
-            if let Some(page_title) = v["query"]["search"][0]["title"].as_str() {
-                return fetch_wikipedia_page_extract(client, page_title).await;
-            }
+    let search_url = format!("{}/w/api.php?action=query&format=json&list=search&srsearch={}&srlimit=1", wikipedia_base_url, query);
+    if let Ok(response) = get_cached(ProviderKind::Wikipedia, client.get(&search_url)).await {
+        let v: Value = serde_json::from_str(&response.body).unwrap_or_else(|_| "[]".into());
+        if let Some(page_title) = v["query"]["search"][0]["title"].as_str() {
+            return fetch_wikipedia_page_extract(client, wikipedia_base_url, page_title).await;
         }
     }
 
     None
 }
 
-async fn fetch_wikipedia_page_extract(client: &Client, page_title: &str) -> Option<String> {
-    sleep(Duration::from_secs(1)).await;
-    let extract_url = format!("https://en.wikipedia.org/w/api.php?action=query&prop=extracts&exintro=&format=json&titles={}", page_title);
-    if let Ok(response) = client.get(&extract_url).send().await {
-        if let Ok(body) = response.text().await {
-            let v: Value = serde_json::from_str(&body).unwrap_or_else(|_| "[]".into());
-            if let Some(pages) = v["query"]["pages"].as_object() {
-                for page in pages.values() {
-                    if let Some(extract) = page["extract"].as_str() {
-                        let re = Regex::new(r"<.*?>|\n").unwrap();
-                        let clean_extract = re.replace_all(extract, "").to_string();
-
-                        let re_amp = Regex::new(r"&amp;").unwrap();
-                        let without_amp = re_amp.replace_all(&clean_extract, "&").to_string();
-                        let re_lt = Regex::new(r"&lt;").unwrap();
-                        let without_lt = re_lt.replace_all(&without_amp, "<").to_string();
-                        let re_gt = Regex::new(r"&gt;").unwrap();
-                        let without_gt = re_gt.replace_all(&without_lt, ">").to_string();
-                        let re_quot = Regex::new(r"&quot;").unwrap();
-                        let without_quot = re_quot.replace_all(&without_gt, "\"").to_string();
-
-                        let re_backticks = Regex::new(r"(?m)^`|`$").unwrap();
-                        let final_extract = re_backticks.replace_all(&without_quot, "").to_string();
-
-                        return Some(final_extract);
-                    }
+async fn fetch_wikipedia_page_extract(client: &Client, base_url: &str, page_title: &str) -> Option<String> {
+    let extract_url = format!("{}/w/api.php?action=query&prop=extracts&exintro=&format=json&titles={}", base_url, page_title);
+    if let Ok(response) = get_cached(ProviderKind::Wikipedia, client.get(&extract_url)).await {
+        let v: Value = serde_json::from_str(&response.body).unwrap_or_else(|_| "[]".into());
+        if let Some(pages) = v["query"]["pages"].as_object() {
+            for page in pages.values() {
+                if let Some(extract) = page["extract"].as_str() {
+                    let re = Regex::new(r"<.*?>|\n").unwrap();
+                    let clean_extract = re.replace_all(extract, "").to_string();
+
+                    let re_amp = Regex::new(r"&amp;").unwrap();
+                    let without_amp = re_amp.replace_all(&clean_extract, "&").to_string();
+                    let re_lt = Regex::new(r"&lt;").unwrap();
+                    let without_lt = re_lt.replace_all(&without_amp, "<").to_string();
+                    let re_gt = Regex::new(r"&gt;").unwrap();
+                    let without_gt = re_gt.replace_all(&without_lt, ">").to_string();
+                    let re_quot = Regex::new(r"&quot;").unwrap();
+                    let without_quot = re_quot.replace_all(&without_gt, "\"").to_string();
+
+                    let re_backticks = Regex::new(r"(?m)^`|`$").unwrap();
+                    let final_extract = re_backticks.replace_all(&without_quot, "").to_string();
+
+                    return Some(final_extract);
                 }
             }
         }
@@ -382,137 +379,156 @@ async fn fetch_wikipedia_page_extract(client: &Client, page_title: &str) -> Opti
     None
 }
 
+#[derive(Default)]
 pub struct AlbumMetadata {
-    pub cover_url: String,
     pub first_release_date: String,
     pub musicbrainz_id: String,
+    /// Whether `musicbrainz_id` names a release group rather than a single release
+    pub is_release_group: bool,
     pub wikidata_id: Option<String>,
     pub primary_type: String,
+    pub release_album: Option<ReleaseAlbum>,
+    pub release_group_album: Option<ReleaseGroupAlbum>,
 }
 
-impl Default for AlbumMetadata {
-    fn default() -> Self {
-        Self {
-            cover_url: String::from(""),
-            first_release_date: String::from(""),
-            musicbrainz_id: String::from(""),
-            wikidata_id: None,
-            primary_type: String::from(""),
-        }
-    }
-}
-
+/// The first release or release group a MusicBrainz search returns, or None
+/// when the search found nothing.
 async fn fetch_musicbrainz_metadata(
     client: &Client,
+    base_url: &str,
     url: &str,
-    cover_art_already_downloaded: bool,
-    album: &mut Album,
-) -> Result<AlbumMetadata, Box<dyn std::error::Error>> {
-    let response = client.get(url).send().await?;
-    let body = response.text().await?;
-    let v: serde_json::Value = serde_json::from_str(&body)?;
-
-    let empty_vec = Vec::new();
-    let release_groups = if v["release-groups"].is_array() {
-        v["release-groups"].as_array().unwrap()
-    } else if v["releases"].is_array() {
-        v["releases"].as_array().unwrap()
+) -> Result<Option<AlbumMetadata>, Box<dyn StdError + Send + Sync>> {
+    let response = get_cached(ProviderKind::MusicBrainz, client.get(url)).await?;
+    let v: serde_json::Value = serde_json::from_str(&response.body)?;
+
+    let is_release_group = v["release-groups"].is_array();
+    let Some(release) = v["release-groups"]
+        .as_array()
+        .or_else(|| v["releases"].as_array())
+        .and_then(|releases| releases.first())
+    else {
+        return Ok(None);
+    };
+    let id = release["id"].as_str().unwrap_or("");
+
+    fetch_musicbrainz_release(client, base_url, id, is_release_group).await
+}
+
+/// Looks up a release or release group by its MusicBrainz ID, returning None
+/// when MusicBrainz doesn't know it.
+pub(crate) async fn fetch_musicbrainz_release(
+    client: &Client,
+    base_url: &str,
+    id: &str,
+    is_release_group: bool,
+) -> Result<Option<AlbumMetadata>, Box<dyn StdError + Send + Sync>> {
+    let release_url = if is_release_group {
+        format!("{}/ws/2/release-group/{}?inc=aliases+artist-credits+releases+annotation+tags+genres+ratings+url-rels&fmt=json", base_url, id)
     } else {
-        &empty_vec
+        format!("{}/ws/2/release/{}?inc=aliases+artist-credits+annotation+labels+recordings+tags+url-rels+release-groups+media+genres+tags+ratings+discids&fmt=json", base_url, id)
     };
+    let release_response = get_cached(ProviderKind::MusicBrainz, client.get(&release_url)).await?;
+    if !release_response.status.is_success() {
+        return Ok(None);
+    }
+    let release: serde_json::Value = serde_json::from_str(&release_response.body)?;
 
-    for release in release_groups {
-        let id = release["id"].as_str().unwrap_or("");
-        let is_release_group = url.contains("release-group");
-
-        let cover_url = if !cover_art_already_downloaded {
-            let cover_art_url = if is_release_group {
-                format!("http://coverartarchive.org/release-group/{}", id)
-            } else {
-                format!("http://coverartarchive.org/release/{}", id)
-            };
-
-            sleep(Duration::from_secs(1)).await;
-
-            let cover_art_response = client.get(&cover_art_url).send().await?;
-            let cover_art_body = if cover_art_response.status().is_success() {
-                cover_art_response.text().await?
-            } else {
-                "".to_string()
-            };
-            let cover_art: serde_json::Value = serde_json::from_str(&cover_art_body)?;
-            let images = cover_art["images"].as_array().unwrap_or(&empty_vec);
-            let first_image = images.get(0).unwrap_or(&serde_json::Value::Null).clone();
-            first_image["image"].as_str().unwrap_or("").to_string()
-        } else {
-            let cover_art_path = get_icon_art_path();
-            let cover_url_path = cover_art_path.join(format!("{}.jpg", album.id));
-            cover_url_path
-                .to_str()
-                .map(|s| s.to_owned())
-                .unwrap_or_else(|| "".to_string())
-        };
+    let (release_album, release_group_album) = if is_release_group {
+        (None, Some(map_to_release_group_album(&release).unwrap_or_default()))
+    } else {
+        (Some(map_to_release_album(&release).unwrap_or_default()), None)
+    };
 
-        let release_url = if is_release_group {
-            format!("https://musicbrainz.org/ws/2/release-group/{}?inc=aliases+artist-credits+releases+annotation+tags+genres+ratings+url-rels&fmt=json", id)
-        } else {
-            format!("https://musicbrainz.org/ws/2/release/{}?inc=aliases+artist-credits+annotation+labels+recordings+tags+url-rels+release-groups+media+genres+tags+ratings+discids&fmt=json", id)
-        };
-        let release_response = client.get(&release_url).send().await?;
-        let release_body = release_response.text().await?;
-        let release: serde_json::Value = serde_json::from_str(&release_body)?;
+    let first_release_date = if is_release_group {
+        release["first-release-date"].as_str().unwrap_or("")
+    } else {
+        release["date"].as_str().unwrap_or("")
+    };
+    let primary_type = if is_release_group {
+        release["primary-type"].as_str().unwrap_or("")
+    } else {
+        release["status"].as_str().unwrap_or("")
+    };
+
+    let wikidata_id = wikidata_relation(&release).unwrap_or_default();
+
+    Ok(Some(AlbumMetadata {
+        first_release_date: first_release_date.to_string(),
+        musicbrainz_id: id.to_string(),
+        is_release_group,
+        wikidata_id: Some(wikidata_id),
+        primary_type: primary_type.to_string(),
+        release_album,
+        release_group_album,
+    }))
+}
 
-        if release_url.contains("release-group") {
-            let mapped_release_group = map_to_release_group_album(&release).unwrap_or_default();
-            album.release_group_album = Some(mapped_release_group);
+/// The Wikidata ID among a MusicBrainz entity's URL relationships.
+fn wikidata_relation(entity: &Value) -> Option<String> {
+    entity["relations"].as_array()?.iter().find_map(|relation| {
+        if relation["type"].as_str() == Some("wikidata") {
+            relation["url"]["resource"]
+                .as_str()
+                .and_then(|wikidata_url| wikidata_url.split('/').last())
+                .map(|id| id.to_string())
         } else {
-            let mapped_release = map_to_release_album(&release).unwrap_or_default();
-            album.release_album = Some(mapped_release);
+            None
         }
+    })
+}
 
-        let first_release_date = if is_release_group {
-            release["first-release-date"].as_str().unwrap_or("")
-        } else {
-            release["date"].as_str().unwrap_or("")
-        };
-        let primary_type = if is_release_group {
-            release["primary-type"].as_str().unwrap_or("")
-        } else {
-            release["status"].as_str().unwrap_or("")
-        };
+/// What MusicBrainz knows about an artist that helps find their other metadata.
+#[derive(Default)]
+pub struct ArtistMetadata {
+    pub musicbrainz_id: String,
+    pub wikidata_id: Option<String>,
+}
 
-        let wikidata_id = release["relations"]
-            .as_array()
-            .and_then(|relations| {
-                relations.iter().find_map(|relation| {
-                    if relation["type"].as_str() == Some("wikidata") {
-                        relation["url"]["resource"]
-                            .as_str()
-                            .and_then(|wikidata_url| wikidata_url.split('/').last())
-                            .map(|id| id.to_string())
-                    } else {
-                        None
-                    }
-                })
-            })
-            .unwrap_or("".to_string());
-
-        return Ok(AlbumMetadata {
-            cover_url: cover_url.to_string(),
-            first_release_date: first_release_date.to_string(),
-            musicbrainz_id: id.to_string(),
-            wikidata_id: Some(wikidata_id),
-            primary_type: primary_type.to_string(),
-        });
+/// Looks up an artist by their MusicBrainz ID, returning None when
+/// MusicBrainz doesn't know them.
+pub(crate) async fn fetch_musicbrainz_artist(
+    client: &Client,
+    base_url: &str,
+    id: &str,
+) -> Result<Option<ArtistMetadata>, Box<dyn StdError + Send + Sync>> {
+    let url = format!("{}/ws/2/artist/{}?inc=url-rels&fmt=json", base_url, id);
+    let response = get_cached(ProviderKind::MusicBrainz, client.get(&url)).await?;
+    if !response.status.is_success() {
+        return Ok(None);
     }
+    let artist: Value = serde_json::from_str(&response.body)?;
 
-    Ok(AlbumMetadata {
-        cover_url: "".to_string(),
-        first_release_date: "".to_string(),
-        musicbrainz_id: "".to_string(),
-        wikidata_id: Some("".to_string()),
-        primary_type: "".to_string(),
-    })
+    Ok(Some(ArtistMetadata {
+        musicbrainz_id: artist["id"].as_str().unwrap_or(id).to_string(),
+        wikidata_id: wikidata_relation(&artist),
+    }))
+}
+
+/// The front cover the Cover Art Archive has for a MusicBrainz release or release group.
+pub(crate) async fn fetch_cover_art_url(
+    client: &Client,
+    base_url: &str,
+    musicbrainz_id: &str,
+    is_release_group: bool,
+) -> Result<Option<String>, Box<dyn StdError + Send + Sync>> {
+    let cover_art_url = if is_release_group {
+        format!("{}/release-group/{}", base_url, musicbrainz_id)
+    } else {
+        format!("{}/release/{}", base_url, musicbrainz_id)
+    };
+
+    let cover_art_response = get_cached(ProviderKind::CoverArtArchive, client.get(&cover_art_url)).await?;
+    if !cover_art_response.status.is_success() {
+        return Ok(None);
+    }
+    let cover_art: serde_json::Value = serde_json::from_str(&cover_art_response.body)?;
+
+    Ok(cover_art["images"]
+        .as_array()
+        .and_then(|images| images.first())
+        .and_then(|image| image["image"].as_str())
+        .filter(|image| !image.is_empty())
+        .map(|image| image.to_string()))
 }
 
 async fn download_and_store_cover_art(
@@ -524,7 +540,8 @@ async fn download_and_store_cover_art(
     let cover_url_path = cover_art_path.join(format!("{}.jpg", album_id));
     let cover_url_file_path = cover_url_path.to_str().map(|s| s.to_owned()).unwrap();
 
-    if cover_url_path.exists() {
+    // A new image URL replaces the stored cover; without one the stored cover is kept
+    if cover_url_path.exists() && image_url.is_empty() {
         // warn!("Cover art already downloaded for album: {}", album_id);
         let absolute_path = fs::canonicalize(&cover_url_file_path).await?;
         let clean_path = absolute_path.to_str().ok_or_else(|| {
@@ -577,49 +594,90 @@ async fn download_and_store_cover_art(
     Ok(clean_path.to_string())
 }
 
-pub async fn process_albums(client: &Client, library: &mut Vec<Artist>) {
+pub async fn process_albums(client: &Client, providers: &MetadataProviders, library: &mut Vec<Artist>, progress: &ScanProgress) {
+    progress.metadata_queued(library.iter().map(|artist| artist.albums.len()).sum());
     for artist in library.iter_mut() {
         for album in &mut artist.albums {
-            process_album(client, artist.name.clone(), album).await;
+            if progress.is_cancelled() {
+                return;
+            }
+
+            process_album(client, providers, artist.name.clone(), album).await;
+            progress.metadata_finished();
         }
     }
 }
 
-pub async fn process_album(client: &Client, artist_name: String, album: &mut Album) {
-    let re = Regex::new(r"(?i)\b.*cd.*\b").unwrap();
-    let album_name = album.name.to_lowercase();
-    let album_name = re.replace_all(&album_name, "").trim().to_string();
-
-    let metadata = fetch_album_metadata(client, &artist_name, album).await;
+/// Fills in the album's release metadata, description and cover from the first
+/// provider in each field's priority order that has them. Offline, only local
+/// files are looked at and the album is left pending enrichment.
+pub async fn process_album(client: &Client, providers: &MetadataProviders, artist_name: String, album: &mut Album) {
+    let mut metadata = None;
+    for provider in providers.for_field(MetadataField::AlbumMetadata) {
+        metadata = provider.album_metadata(client, &artist_name, album).await;
+        if metadata.is_some() {
+            break;
+        }
+    }
+    apply_album_metadata(client, providers, &artist_name, album, metadata.unwrap_or_default(), false).await;
+}
 
-    if let Some(wikidata_id) = metadata.wikidata_id.clone() {
-        if let Some(wikipedia_extract) =
-            fetch_wikipedia_extract(client, &album_name, Some(&artist_name), Some(&wikidata_id))
-                .await
-        {
-            album.description = wikipedia_extract;
-        } else {
-            warn!(
-                "Failed to fetch Wikipedia extract for Album: {}",
-                album.name
-            );
+/// Fills in the album's description, cover and release details from what the
+/// album metadata providers found. `replace_cover` looks the cover up again
+/// even when the album has one, as after pinning a different release.
+pub(crate) async fn apply_album_metadata(
+    client: &Client,
+    providers: &MetadataProviders,
+    artist_name: &str,
+    album: &mut Album,
+    metadata: AlbumMetadata,
+    replace_cover: bool,
+) {
+    let mut description_found = false;
+    for provider in providers.for_field(MetadataField::AlbumDescription) {
+        if let Some(description) = provider.album_description(client, artist_name, album, &metadata).await {
+            album.description = description;
+            description_found = true;
+            break;
         }
     }
+    if !description_found {
+        warn!(
+            "Failed to fetch a description for Album: {}",
+            album.name
+        );
+    }
 
-    if album.cover_url.is_empty() {
-        match download_and_store_cover_art(client, &metadata.cover_url, &album.id.to_string()).await
-        {
-            Ok(path) => {
-                album.cover_url = path;
-                let log = format!("Cover art downloaded and stored for Album: {}", album.name);
-                info!(log);
-                log_to_ws(log)
-                    .await
+    if album.cover_url.is_empty() || replace_cover {
+        // A cover downloaded by an earlier scan is reused without asking the providers again
+        let reuse_cover = !replace_cover && get_cover_art_path().join(format!("{}.jpg", album.id)).exists();
+        let mut cover_url = String::new();
+        if !reuse_cover {
+            for provider in providers.for_field(MetadataField::AlbumCover) {
+                if let Some(url) = provider.album_cover(client, artist_name, album, &metadata).await {
+                    cover_url = url;
+                    break;
+                }
+            }
+        }
+
+        if cover_url.is_empty() && !reuse_cover {
+            warn!("No cover art found for Album: {}", album.name);
+        } else {
+            match download_and_store_cover_art(client, &cover_url, &album.id.to_string()).await
+            {
+                Ok(path) => {
+                    album.cover_url = path;
+                    let log = format!("Cover art downloaded and stored for Album: {}", album.name);
+                    info!(log);
+                    log_to_ws(log)
+                        .await
+                }
+                Err(e) => warn!(
+                    "Failed to store cover art for Album: {}. Error: {}",
+                    album.name, e
+                ),
             }
-            Err(e) => warn!(
-                "Failed to store cover art for Album: {}. Error: {}",
-                album.name, e
-            ),
         }
     } else {
         let log = format!("Cover art already found for Album: {}", album.name);
@@ -628,17 +686,25 @@ pub async fn process_album(client: &Client, artist_name: String, album: &mut Alb
             .await
     }
 
-    album.first_release_date = metadata.first_release_date;
-    album.musicbrainz_id = metadata.musicbrainz_id;
-    album.wikidata_id = metadata.wikidata_id;
-    album.primary_type = metadata.primary_type;
+    if !metadata.musicbrainz_id.is_empty() {
+        album.first_release_date = metadata.first_release_date;
+        album.musicbrainz_id = metadata.musicbrainz_id;
+        album.wikidata_id = metadata.wikidata_id;
+        album.primary_type = metadata.primary_type;
+        if metadata.release_album.is_some() {
+            album.release_album = metadata.release_album;
+        }
+        if metadata.release_group_album.is_some() {
+            album.release_group_album = metadata.release_group_album;
+        }
+    }
 
     let log = format!("Metadata updated for Album: {}", album.name);
     info!(log);
     log_to_ws(log)
         .await;
 
-    sleep(Duration::from_secs(1)).await;
+    album.pending_enrichment = providers.is_offline();
 }
 
 fn map_to_release_album(release_album_json: &Value) -> Result<ReleaseAlbum, Box<dyn Error>> {
@@ -1683,34 +1749,32 @@ fn map_to_release_group_album(
     })
 }
 
-async fn fetch_audio_db_info(client: &Client, artist: &mut Artist, store_image: bool) -> Result<(), Box<dyn StdError + Send + Sync>> {
+/// TheAudioDB's search page for an artist. There is no search API without a
+/// key, so the page is scraped for the artist's link and image.
+async fn search_audio_db(client: &Client, base_url: &str, artist_name: &str) -> Result<String, Box<dyn StdError + Send + Sync>> {
     let mut params = HashMap::new();
-    params.insert("search", &artist.name);
-
-    let res = client.post("https://www.theaudiodb.com/browse.php")
-        .form(&params)
-        .send()
-        .await?;
-
-    let body = res.text().await?;
-
-    if store_image {
-        let document = Html::parse_document(&body);
-        if let Ok(img_sel) = Selector::parse("div.col-sm-3 img") {
-            if let Some(img_el) = document.select(&img_sel).next() {
-                if let Some(src) = img_el.value().attr("src") {
-                    if let Ok(path) = download_and_store_icon_art(client, src, &artist.id.to_string()).await {
-                        artist.icon_url = path;
-                        let log = format!("AudioDB fallback icon stored for Artist: {}", artist.name);
-                        info!("{}", log.clone());
-                        log_to_ws(log).await;
-                    }
-                }
-            }
-        }
-    }
-        
+    params.insert("search", artist_name);
+
+    let request = client.post(format!("{}/browse.php", base_url)).form(&params);
+    Ok(get_cached(ProviderKind::TheAudioDb, request).await?.body)
+}
+
+pub(crate) async fn fetch_audio_db_image(client: &Client, base_url: &str, artist_name: &str) -> Result<Option<String>, Box<dyn StdError + Send + Sync>> {
+    let body = search_audio_db(client, base_url, artist_name).await?;
+
     let document = Html::parse_document(&body);
+    let img_sel = Selector::parse("div.col-sm-3 img")
+        .map_err(|e: scraper::error::SelectorErrorKind| Box::<dyn StdError + Send + Sync>::from(e.to_string()))?;
+
+    Ok(document
+        .select(&img_sel)
+        .next()
+        .and_then(|img_el| img_el.value().attr("src"))
+        .map(|src| src.to_string()))
+}
+
+fn audio_db_artist_id(body: &str) -> Result<Option<String>, Box<dyn StdError + Send + Sync>> {
+    let document = Html::parse_document(body);
     let selector = Selector::parse("div.col-sm-3 a")
         .map_err(|e: scraper::error::SelectorErrorKind| Box::<dyn StdError + Send + Sync>::from(e.to_string()))?;
 
@@ -1719,42 +1783,29 @@ async fn fetch_audio_db_info(client: &Client, artist: &mut Artist, store_image:
             if href.contains("/artist/") {
                 let parts: Vec<&str> = href.split('/').collect();
                 if parts.len() > 2 {
-                    let artist_id = parts[2].split('-').next().unwrap();
-
-                    let api_url = format!("https://www.theaudiodb.com/api/v1/json/2/mvid.php?i={}", artist_id);
-                    let api_res = client.get(&api_url).send().await?;
-
-                    let api_body = api_res.text().await?;
-                    artist.tadb_music_videos = Some(api_body.clone());
-
-                    let root: Value = serde_json::from_str(&api_body)?;
-
-                    for album in &mut artist.albums {
-                        for song in &mut album.songs {
-                            if let Some(mvids) = root["mvids"].as_array() {
-                                for mvid in mvids {
-                                    let distance = levenshtein(&song.name, mvid["strTrack"].as_str().unwrap_or(""));
-                                    if distance < 3 {
-                                        song.music_video = Some(MusicVideo {
-                                            url: mvid["strMusicVid"].as_str().unwrap_or("").to_string(),
-                                            thumbnail_url: mvid["strTrackThumb"].as_str().map(|s| s.to_string()),
-                                            tadb_track_id: mvid["idTrack"].as_str().unwrap_or("").to_string(),
-                                            tadb_album_id: mvid["idAlbum"].as_str().unwrap_or("").to_string(),
-                                            description: mvid["strDescriptionEN"].as_str().unwrap_or("").to_string(),
-                                            musicbrainz_recording_id: mvid["strMusicBrainzID"].as_str().unwrap_or("").to_string(),
-                                        });
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                    }
+                    return Ok(parts[2].split('-').next().map(|id| id.to_string()));
                 }
             }
         }
     }
 
-    Ok(())
+    Ok(None)
+}
+
+/// The artist's music videos as TheAudioDB's `mvid.php` returns them, which is
+/// the form `Artist::tadb_music_videos` stores.
+pub(crate) async fn fetch_audio_db_music_videos(client: &Client, base_url: &str, artist_name: &str) -> Result<Option<String>, Box<dyn StdError + Send + Sync>> {
+    let body = search_audio_db(client, base_url, artist_name).await?;
+    let Some(artist_id) = audio_db_artist_id(&body)? else {
+        return Ok(None);
+    };
+
+    let api_url = format!("{}/api/v1/json/2/mvid.php?i={}", base_url, artist_id);
+    let api_body = get_cached(ProviderKind::TheAudioDb, client.get(&api_url)).await?.body;
+
+    // Checked here so refresh_audio_db_info can rely on it parsing
+    serde_json::from_str::<Value>(&api_body)?;
+    Ok(Some(api_body))
 }
 
 pub fn refresh_audio_db_info(artist: &mut Artist) {
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER};
+use reqwest::{Client, Request, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{fs, time::sleep};
+use tracing::warn;
+
+use super::config::get_metadata_cache_path;
+use super::metadata_providers::{provider_cache_ttl, ProviderKind};
+
+/// Attempts made at a request the server keeps answering with 429 or 503.
+const MAX_ATTEMPTS: u32 = 4;
+
+/// The wait after a 429 or 503 that came without a usable `Retry-After`.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(2);
+
+/// Longer `Retry-After`s still pause the host, but the request gives up
+/// rather than holding a metadata refresh for that long.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// The client metadata lookups are made with. MusicBrainz asks for a user
+/// agent that identifies the application.
+pub fn metadata_client() -> reqwest::Result<Client> {
+    Client::builder()
+        .user_agent("ParsonLabsMusic/0.1 (will@parsonlabs.com)")
+        .build()
+}
+
+pub struct MetadataResponse {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+/// A response as stored in the metadata cache, one JSON file per request.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct CacheEntry {
+    pub(crate) url: String,
+    pub(crate) status: u16,
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+    /// Seconds since the Unix epoch at which the response was fetched or last revalidated
+    pub(crate) fetched_at: u64,
+    pub(crate) body: String,
+}
+
+impl CacheEntry {
+    pub(crate) fn is_fresh(&self, ttl: Duration) -> bool {
+        unix_now().saturating_sub(self.fetched_at) < ttl.as_secs()
+    }
+
+    fn response(&self) -> MetadataResponse {
+        MetadataResponse {
+            status: StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK),
+            body: self.body.clone(),
+        }
+    }
+}
+
+/// Allows `per_second` requests a second on average, with bursts of up to
+/// `capacity`. A 429 or 503 pauses it until the server's `Retry-After` has passed.
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    per_second: f64,
+    refilled_at: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(per_second: f64, now: Instant) -> Self {
+        let capacity = per_second.clamp(1.0, 10.0);
+        TokenBucket {
+            tokens: capacity,
+            capacity,
+            per_second,
+            refilled_at: now,
+            paused_until: None,
+        }
+    }
+
+    /// Takes a token, or says how long to wait before one is available.
+    pub(crate) fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        if let Some(paused_until) = self.paused_until {
+            if paused_until > now {
+                return Err(paused_until - now);
+            }
+            self.paused_until = None;
+        }
+
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.per_second))
+        }
+    }
+
+    /// Tokens refill from the end of the pause, so one request goes through
+    /// then and the rest follow at the usual rate instead of in a burst.
+    pub(crate) fn pause(&mut self, until: Instant) {
+        let until = self.paused_until.map_or(until, |paused_until| paused_until.max(until));
+        self.tokens = 1.0;
+        self.refilled_at = until;
+        self.paused_until = Some(until);
+    }
+}
+
+lazy_static! {
+    /// One bucket per host, shared by every provider and job talking to it.
+    static ref RATE_LIMITS: Mutex<HashMap<String, TokenBucket>> = Mutex::new(HashMap::new());
+}
+
+/// Waits until the host's bucket has a token for this request.
+async fn acquire(host: &str, kind: ProviderKind) {
+    loop {
+        let wait = {
+            let now = Instant::now();
+            let mut buckets = RATE_LIMITS.lock().unwrap();
+            let bucket = buckets
+                .entry(host.to_string())
+                .or_insert_with(|| TokenBucket::new(kind.requests_per_second(), now));
+            match bucket.take(now) {
+                Ok(()) => return,
+                Err(wait) => wait,
+            }
+        };
+        sleep(wait).await;
+    }
+}
+
+fn pause_host(host: &str, kind: ProviderKind, duration: Duration) {
+    let now = Instant::now();
+    RATE_LIMITS
+        .lock()
+        .unwrap()
+        .entry(host.to_string())
+        .or_insert_with(|| TokenBucket::new(kind.requests_per_second(), now))
+        .pause(now + duration);
+}
+
+/// How long a 429 or 503 response asks clients to wait, given either in
+/// seconds or as an HTTP date.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Duration {
+    let Some(value) = headers.get(RETRY_AFTER).and_then(|value| value.to_str().ok()) else {
+        return DEFAULT_RETRY_AFTER;
+    };
+
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Duration::from_secs(seconds);
+    }
+
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .and_then(|date| (date.with_timezone(&Utc) - Utc::now()).to_std().ok())
+        .unwrap_or(DEFAULT_RETRY_AFTER)
+}
+
+fn header_string(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers.get(name).and_then(|value| value.to_str().ok()).map(str::to_string)
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default()
+}
+
+/// The host and port requests are rate limited by.
+fn host_key(request: &Request) -> String {
+    let url = request.url();
+    format!("{}:{}", url.host_str().unwrap_or_default(), url.port_or_known_default().unwrap_or_default())
+}
+
+/// The cache file for a request, named by a hash of its method, URL and body
+/// so form posts to the same URL are cached apart.
+fn cache_file(request: &Request) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(request.method().as_str().as_bytes());
+    hasher.update([0]);
+    hasher.update(request.url().as_str().as_bytes());
+    hasher.update([0]);
+    if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+        hasher.update(body);
+    }
+
+    let hex: String = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+    get_metadata_cache_path().join(format!("{}.json", hex))
+}
+
+async fn read_cache(path: &PathBuf) -> Option<CacheEntry> {
+    let contents = fs::read_to_string(path).await.ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Writes through a temporary file so a concurrent reader never sees half an entry.
+async fn write_cache(path: &PathBuf, entry: &CacheEntry) {
+    let temporary = path.with_extension("json.tmp");
+    let result = async {
+        fs::write(&temporary, serde_json::to_vec(entry)?).await?;
+        fs::rename(&temporary, path).await
+    };
+
+    if let Err(e) = result.await {
+        warn!("Failed to write metadata cache entry for {}: {}", entry.url, e);
+    }
+}
+
+/// Sends a metadata request, answering from the on-disk cache while the
+/// provider's TTL allows. Stale entries are revalidated with their ETag or
+/// Last-Modified date. Successful and 404 responses are cached; anything else
+/// is returned without being stored.
+pub async fn get_cached(kind: ProviderKind, request: RequestBuilder) -> Result<MetadataResponse, Box<dyn StdError + Send + Sync>> {
+    send(kind, request, true).await
+}
+
+/// Sends a request through the provider's rate limiter without caching it,
+/// for responses that are only good once, like access tokens.
+pub async fn send_uncached(kind: ProviderKind, request: RequestBuilder) -> Result<MetadataResponse, Box<dyn StdError + Send + Sync>> {
+    send(kind, request, false).await
+}
+
+async fn send(kind: ProviderKind, request: RequestBuilder, use_cache: bool) -> Result<MetadataResponse, Box<dyn StdError + Send + Sync>> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let host = host_key(&request);
+
+    let cache_path = use_cache.then(|| cache_file(&request));
+    let cached = match &cache_path {
+        Some(path) => read_cache(path).await,
+        None => None,
+    };
+
+    if let Some(entry) = &cached {
+        if entry.is_fresh(provider_cache_ttl(kind)) {
+            return Ok(entry.response());
+        }
+    }
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+
+        let mut attempt_request = request.try_clone().ok_or("Metadata requests can't have streamed bodies")?;
+        if let Some(entry) = &cached {
+            let headers = attempt_request.headers_mut();
+            if let Some(etag) = entry.etag.as_deref().and_then(|etag| HeaderValue::from_str(etag).ok()) {
+                headers.insert(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = entry.last_modified.as_deref().and_then(|date| HeaderValue::from_str(date).ok()) {
+                headers.insert(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        acquire(&host, kind).await;
+        let response = client.execute(attempt_request).await?;
+        let status = response.status();
+
+        if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+            let wait = retry_after(response.headers());
+            pause_host(&host, kind, wait);
+
+            if attempt < MAX_ATTEMPTS && wait <= MAX_RETRY_AFTER {
+                warn!(
+                    "{} answered {} for {}, retrying in {:.1}s",
+                    kind.display_name(), status, request.url(), wait.as_secs_f64()
+                );
+                continue;
+            }
+
+            // A stale answer beats none while the provider is overloaded
+            if let Some(entry) = &cached {
+                return Ok(entry.response());
+            }
+            return Ok(MetadataResponse { status, body: response.text().await? });
+        }
+
+        if let (StatusCode::NOT_MODIFIED, Some(entry), Some(path)) = (status, &cached, &cache_path) {
+            let entry = CacheEntry { fetched_at: unix_now(), ..entry.clone() };
+            write_cache(path, &entry).await;
+            return Ok(entry.response());
+        }
+
+        let etag = header_string(response.headers(), ETAG);
+        let last_modified = header_string(response.headers(), LAST_MODIFIED);
+        let body = response.text().await?;
+
+        if let Some(path) = &cache_path {
+            if status.is_success() || status == StatusCode::NOT_FOUND {
+                let entry = CacheEntry {
+                    url: request.url().to_string(),
+                    status: status.as_u16(),
+                    etag,
+                    last_modified,
+                    fetched_at: unix_now(),
+                    body: body.clone(),
+                };
+                write_cache(path, &entry).await;
+            }
+        }
+
+        return Ok(MetadataResponse { status, body });
+    }
+}
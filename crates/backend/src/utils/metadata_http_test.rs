@@ -0,0 +1,118 @@
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+    use crate::utils::metadata_http::{retry_after, unix_now, CacheEntry, TokenBucket};
+
+    fn seconds(seconds: f64) -> Duration {
+        Duration::from_secs_f64(seconds)
+    }
+
+    #[test]
+    fn test_bucket_allows_a_burst_then_waits() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, start);
+
+        assert_eq!(bucket.take(start), Ok(()));
+        assert_eq!(bucket.take(start), Ok(()));
+        assert_eq!(bucket.take(start), Err(seconds(0.5)));
+    }
+
+    #[test]
+    fn test_bucket_refills_up_to_capacity() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1.0, start);
+
+        assert_eq!(bucket.take(start), Ok(()));
+        assert_eq!(bucket.take(start + seconds(0.25)), Err(seconds(0.75)));
+        assert_eq!(bucket.take(start + seconds(1.0)), Ok(()));
+
+        // A long idle spell only refills the one token a bucket this slow holds
+        let later = start + seconds(60.0);
+        assert_eq!(bucket.take(later), Ok(()));
+        assert_eq!(bucket.take(later), Err(seconds(1.0)));
+    }
+
+    #[test]
+    fn test_bucket_capacity_is_clamped() {
+        let start = Instant::now();
+
+        let mut slow = TokenBucket::new(0.5, start);
+        assert_eq!(slow.take(start), Ok(()));
+        assert_eq!(slow.take(start), Err(seconds(2.0)));
+
+        let mut fast = TokenBucket::new(50.0, start);
+        assert_eq!((0..10).filter(|_| fast.take(start).is_ok()).count(), 10);
+        assert!(fast.take(start).is_err());
+    }
+
+    #[test]
+    fn test_bucket_pause() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, start);
+
+        bucket.pause(start + seconds(5.0));
+        assert_eq!(bucket.take(start + seconds(1.0)), Err(seconds(4.0)));
+        // A shorter pause doesn't cut a longer one short
+        bucket.pause(start + seconds(2.0));
+        assert_eq!(bucket.take(start + seconds(3.0)), Err(seconds(2.0)));
+
+        // Only one request goes through when the pause ends
+        assert_eq!(bucket.take(start + seconds(5.0)), Ok(()));
+        assert_eq!(bucket.take(start + seconds(5.0)), Err(seconds(0.1)));
+    }
+
+    fn entry(age: u64) -> CacheEntry {
+        CacheEntry {
+            url: "https://musicbrainz.org/ws/2/release/1".to_string(),
+            status: 200,
+            etag: None,
+            last_modified: None,
+            fetched_at: unix_now() - age,
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_cache_freshness() {
+        let ttl = Duration::from_secs(3600);
+        let cases = [
+            // age in seconds, fresh
+            (0, true),
+            (3599, true),
+            (3600, false),
+            (86400, false),
+        ];
+
+        for (age, fresh) in cases {
+            assert_eq!(entry(age).is_fresh(ttl), fresh, "{}s old", age);
+        }
+        assert!(!entry(0).is_fresh(Duration::ZERO));
+
+        // Clock changes can leave an entry fetched in the future, which counts as fresh
+        let future = CacheEntry { fetched_at: unix_now() + 60, ..entry(0) };
+        assert!(future.is_fresh(ttl));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let cases = [
+            // header, wait
+            (None, Duration::from_secs(2)),
+            (Some("30"), Duration::from_secs(30)),
+            (Some(" 0 "), Duration::ZERO),
+            (Some("Wed, 21 Oct 2015 07:28:00 GMT"), Duration::from_secs(2)),
+            (Some("soon"), Duration::from_secs(2)),
+        ];
+
+        for (header, wait) in cases {
+            let mut headers = HeaderMap::new();
+            if let Some(header) = header {
+                headers.insert(RETRY_AFTER, HeaderValue::from_static(header));
+            }
+            assert_eq!(retry_after(&headers), wait, "{:?}", header);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::error::Error as StdError;
+
+use levenshtein::levenshtein;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::structures::structures::{Album, Artist};
+use super::metadata::{
+    apply_album_metadata, fetch_musicbrainz_artist, fetch_musicbrainz_release, release_name, update_artist_description,
+};
+use super::metadata_http::get_cached;
+use super::metadata_providers::{MetadataProviders, ProviderKind};
+
+/// Results asked of each MusicBrainz search.
+const SEARCH_LIMIT: &str = "10";
+
+/// How much MusicBrainz's own search score counts towards a candidate's score,
+/// next to how well the candidate fits what the catalog knows.
+const SEARCH_SCORE_WEIGHT: f64 = 0.2;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ReleaseKind {
+    ReleaseGroup,
+    Release,
+}
+
+impl ReleaseKind {
+    /// The search path and the key its results are listed under.
+    fn search(&self) -> (&'static str, &'static str) {
+        match self {
+            ReleaseKind::ReleaseGroup => ("release-group", "release-groups"),
+            ReleaseKind::Release => ("release", "releases"),
+        }
+    }
+}
+
+/// A MusicBrainz release or release group an album could be matched to.
+#[derive(Serialize)]
+pub struct AlbumCandidate {
+    pub musicbrainz_id: String,
+    pub kind: ReleaseKind,
+    pub title: String,
+    pub artist: String,
+    pub date: String,
+    pub primary_type: String,
+    /// Only releases have a track count
+    pub track_count: Option<u64>,
+    pub country: Option<String>,
+    /// Whether the album is already matched to it or tagged with it
+    pub current: bool,
+    /// MusicBrainz's own search score, from 0 to 100
+    pub search_score: u8,
+    /// How well the candidate fits the album, from 0 to 100
+    pub score: u8,
+}
+
+/// A MusicBrainz artist an artist could be matched to.
+#[derive(Serialize)]
+pub struct ArtistCandidate {
+    pub musicbrainz_id: String,
+    pub name: String,
+    pub sort_name: String,
+    pub disambiguation: String,
+    pub artist_type: String,
+    pub country: Option<String>,
+    /// Whether the artist is already matched to it or tagged with it
+    pub current: bool,
+    pub search_score: u8,
+    pub score: u8,
+}
+
+/// 1 for equal strings down to 0 for nothing in common, ignoring case.
+fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (a.trim().to_lowercase(), b.trim().to_lowercase());
+    let longest = a.chars().count().max(b.chars().count());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(&a, &b) as f64 / longest as f64
+}
+
+/// Averages the `(value, weight)` signals that apply to a candidate and blends
+/// in MusicBrainz's search score.
+fn combined_score(signals: &[(f64, f64)], search_score: u8) -> u8 {
+    let total_weight: f64 = signals.iter().map(|(_, weight)| weight).sum();
+    let fit = signals.iter().map(|(value, weight)| value * weight).sum::<f64>() / total_weight;
+    let score = fit * (1.0 - SEARCH_SCORE_WEIGHT) + f64::from(search_score) / 100.0 * SEARCH_SCORE_WEIGHT;
+    (score * 100.0).round() as u8
+}
+
+fn search_score(entity: &Value) -> u8 {
+    entity["score"]
+        .as_u64()
+        .or_else(|| entity["score"].as_str().and_then(|score| score.parse().ok()))
+        .unwrap_or(0)
+        .min(100) as u8
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+/// The credited artists joined the way MusicBrainz displays them.
+fn artist_credit(entity: &Value) -> String {
+    entity["artist-credit"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|credit| format!("{}{}", credit["name"].as_str().unwrap_or_default(), credit["joinphrase"].as_str().unwrap_or_default()))
+        .collect()
+}
+
+/// Quotes would end the search term early.
+fn search_term(value: &str) -> String {
+    value.replace('"', "")
+}
+
+async fn search_musicbrainz(client: &Client, base_url: &str, entity: &str, query: &str) -> Result<Value, Box<dyn StdError + Send + Sync>> {
+    let request = client
+        .get(format!("{}/ws/2/{}/", base_url, entity))
+        .query(&[("query", query), ("fmt", "json"), ("limit", SEARCH_LIMIT)]);
+    let response = get_cached(ProviderKind::MusicBrainz, request).await?;
+    if !response.status.is_success() {
+        return Err(format!("MusicBrainz answered {} to the {} search", response.status, entity).into());
+    }
+    Ok(serde_json::from_str(&response.body)?)
+}
+
+fn album_candidate(album: &Album, artist_name: &str, title: &str, kind: ReleaseKind, entity: &Value) -> AlbumCandidate {
+    let musicbrainz_id = text(&entity["id"]);
+    let candidate_title = text(&entity["title"]);
+    let artist = artist_credit(entity);
+    let (date, primary_type) = match kind {
+        ReleaseKind::ReleaseGroup => (text(&entity["first-release-date"]), text(&entity["primary-type"])),
+        ReleaseKind::Release => (text(&entity["date"]), text(&entity["release-group"]["primary-type"])),
+    };
+    let track_count = entity["track-count"].as_u64();
+
+    let mut signals = vec![(similarity(title, &candidate_title), 0.5), (similarity(artist_name, &artist), 0.25)];
+    if let Some(track_count) = track_count {
+        let songs = album.songs.len() as f64;
+        let tracks = track_count as f64;
+        signals.push((1.0 - (songs - tracks).abs() / songs.max(tracks).max(1.0), 0.15));
+    }
+    if let (Some(year), Some(candidate_year)) = (album.first_release_date.get(..4), date.get(..4)) {
+        signals.push((if year == candidate_year { 1.0 } else { 0.0 }, 0.1));
+    }
+
+    let current = album.musicbrainz_id == musicbrainz_id
+        || album.musicbrainz_release_id.as_deref() == Some(musicbrainz_id.as_str())
+        || album.musicbrainz_release_group_id.as_deref() == Some(musicbrainz_id.as_str());
+    let search_score = search_score(entity);
+
+    AlbumCandidate {
+        musicbrainz_id,
+        kind,
+        title: candidate_title,
+        artist,
+        date,
+        primary_type,
+        track_count,
+        country: entity["country"].as_str().map(str::to_string),
+        current,
+        search_score,
+        score: combined_score(&signals, search_score),
+    }
+}
+
+/// Scores the release group and release search results against the album,
+/// best match first.
+pub(crate) fn rank_album_candidates(
+    album: &Album,
+    artist_name: &str,
+    title: &str,
+    results: &[(ReleaseKind, Value)],
+) -> Vec<AlbumCandidate> {
+    let mut candidates: Vec<AlbumCandidate> = results
+        .iter()
+        .flat_map(|(kind, results)| {
+            let (_, key) = kind.search();
+            results[key].as_array().into_iter().flatten().map(move |result| (*kind, result))
+        })
+        .map(|(kind, result)| album_candidate(album, artist_name, title, kind, result))
+        .collect();
+
+    candidates.sort_by(|a, b| b.score.cmp(&a.score).then(b.search_score.cmp(&a.search_score)));
+    candidates
+}
+
+/// Release groups and releases MusicBrainz finds for the album, best match
+/// first. `title` and `artist_name` replace the album's own for the search.
+pub async fn album_candidates(
+    client: &Client,
+    base_url: &str,
+    artist_name: &str,
+    album: &Album,
+    title: Option<&str>,
+) -> Result<Vec<AlbumCandidate>, Box<dyn StdError + Send + Sync>> {
+    let title = title.map(str::to_string).unwrap_or_else(|| release_name(album));
+    let query = format!("artist:\"{}\" AND release:\"{}\"", search_term(artist_name), search_term(&title));
+
+    let mut results = Vec::new();
+    for kind in [ReleaseKind::ReleaseGroup, ReleaseKind::Release] {
+        let (entity, _) = kind.search();
+        results.push((kind, search_musicbrainz(client, base_url, entity, &query).await?));
+    }
+
+    Ok(rank_album_candidates(album, artist_name, &title, &results))
+}
+
+/// Scores the artist search results against the artist, best match first.
+pub(crate) fn rank_artist_candidates(artist: &Artist, name: &str, results: &Value) -> Vec<ArtistCandidate> {
+    let mut candidates: Vec<ArtistCandidate> = results["artists"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|result| {
+            let musicbrainz_id = text(&result["id"]);
+            let candidate_name = text(&result["name"]);
+            let sort_name = text(&result["sort-name"]);
+            let search_score = search_score(result);
+            let name_similarity = similarity(name, &candidate_name).max(similarity(name, &sort_name));
+
+            ArtistCandidate {
+                current: artist.musicbrainz_id.as_deref() == Some(musicbrainz_id.as_str()),
+                musicbrainz_id,
+                name: candidate_name,
+                sort_name,
+                disambiguation: text(&result["disambiguation"]),
+                artist_type: text(&result["type"]),
+                country: result["country"].as_str().map(str::to_string),
+                search_score,
+                score: combined_score(&[(name_similarity, 1.0)], search_score),
+            }
+        })
+        .collect();
+
+    candidates.sort_by(|a, b| b.score.cmp(&a.score).then(b.search_score.cmp(&a.search_score)));
+    candidates
+}
+
+/// Artists MusicBrainz finds for the artist, best match first. `name`
+/// replaces the artist's own for the search.
+pub async fn artist_candidates(
+    client: &Client,
+    base_url: &str,
+    artist: &Artist,
+    name: Option<&str>,
+) -> Result<Vec<ArtistCandidate>, Box<dyn StdError + Send + Sync>> {
+    let name = name.unwrap_or(&artist.name);
+    let query = format!("artist:\"{}\"", search_term(name));
+    let results = search_musicbrainz(client, base_url, "artist", &query).await?;
+
+    Ok(rank_artist_candidates(artist, name, &results))
+}
+
+/// Matches the album to a release or release group, replacing its release
+/// details, cover and description with those of the chosen one. Returns false
+/// when MusicBrainz doesn't know the ID.
+pub async fn pin_album(
+    client: &Client,
+    providers: &MetadataProviders,
+    base_url: &str,
+    artist_name: &str,
+    album: &mut Album,
+    musicbrainz_id: &str,
+    kind: ReleaseKind,
+) -> Result<bool, Box<dyn StdError + Send + Sync>> {
+    let is_release_group = kind == ReleaseKind::ReleaseGroup;
+    let Some(metadata) = fetch_musicbrainz_release(client, base_url, musicbrainz_id, is_release_group).await? else {
+        return Ok(false);
+    };
+
+    // Details of the release matched before must not outlive the new match
+    album.release_album = None;
+    album.release_group_album = None;
+    apply_album_metadata(client, providers, artist_name, album, metadata, true).await;
+    Ok(true)
+}
+
+/// Matches the artist to a MusicBrainz artist and looks their description up
+/// again through it. Returns false when MusicBrainz doesn't know the ID.
+pub async fn pin_artist(
+    client: &Client,
+    providers: &MetadataProviders,
+    base_url: &str,
+    artist: &mut Artist,
+    musicbrainz_id: &str,
+) -> Result<bool, Box<dyn StdError + Send + Sync>> {
+    let Some(metadata) = fetch_musicbrainz_artist(client, base_url, musicbrainz_id).await? else {
+        return Ok(false);
+    };
+
+    artist.musicbrainz_id = Some(metadata.musicbrainz_id.clone());
+    update_artist_description(client, providers, artist, &metadata).await;
+    artist.pending_enrichment = false;
+    Ok(true)
+}
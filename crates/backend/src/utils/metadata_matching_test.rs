@@ -0,0 +1,149 @@
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::structures::structures::{Album, Artist, Song};
+    use crate::utils::metadata_matching::{rank_album_candidates, rank_artist_candidates, ReleaseKind};
+
+    fn song(track_number: u16) -> Song {
+        Song {
+            id: track_number.to_string(),
+            name: format!("Track {}", track_number),
+            artist: "Daft Punk".to_string(),
+            contributing_artists: Vec::new(),
+            contributing_artist_ids: Vec::new(),
+            track_number,
+            disc_number: 1,
+            disc_total: 1,
+            path: format!("/music/{:02}.flac", track_number),
+            duration: 180.0,
+            audio: None,
+            music_video: None,
+            musicbrainz_recording_id: None,
+            musicbrainz_track_id: None,
+        }
+    }
+
+    fn discovery() -> Album {
+        Album {
+            id: "album".to_string(),
+            name: "Discovery".to_string(),
+            songs: (1..=14).map(song).collect(),
+            first_release_date: "2001-03-07".to_string(),
+            musicbrainz_release_group_id: Some("rg-discovery".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn credit(name: &str) -> Value {
+        json!([{ "name": name, "joinphrase": "" }])
+    }
+
+    fn album_results() -> Vec<(ReleaseKind, Value)> {
+        vec![
+            (
+                ReleaseKind::ReleaseGroup,
+                json!({ "release-groups": [
+                    { "id": "rg-live", "title": "Discovery Live", "artist-credit": credit("Daft Punk"),
+                      "first-release-date": "2001", "primary-type": "Live", "score": 100 },
+                    { "id": "rg-discovery", "title": "Discovery", "artist-credit": credit("Daft Punk"),
+                      "first-release-date": "2001-03-12", "primary-type": "Album", "score": 90 },
+                ]}),
+            ),
+            (
+                ReleaseKind::Release,
+                json!({ "releases": [
+                    { "id": "r-anniversary", "title": "Discovery", "artist-credit": credit("Daft Punk"), "date": "2021-02-26",
+                      "track-count": 16, "release-group": { "primary-type": "Album" }, "score": 100 },
+                    { "id": "r-original", "title": "Discovery", "artist-credit": credit("Daft Punk"), "date": "2001-03-07",
+                      "track-count": 14, "country": "GB", "release-group": { "primary-type": "Album" }, "score": "95" },
+                    { "id": "r-cover", "title": "Discovery", "artist-credit": credit("Tribute Band"), "date": "2001",
+                      "track-count": 14, "release-group": { "primary-type": "Album" }, "score": 100 },
+                ]}),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_album_candidates_rank_by_fit_over_search_score() {
+        let album = discovery();
+        let candidates = rank_album_candidates(&album, "Daft Punk", &album.name, &album_results());
+
+        let ids: Vec<&str> = candidates.iter().map(|candidate| candidate.musicbrainz_id.as_str()).collect();
+        assert_eq!(ids, vec!["r-original", "rg-discovery", "r-anniversary", "r-cover", "rg-live"]);
+
+        let original = &candidates[0];
+        assert_eq!(original.kind, ReleaseKind::Release);
+        assert_eq!(original.track_count, Some(14));
+        assert_eq!(original.country.as_deref(), Some("GB"));
+        assert_eq!(original.search_score, 95);
+        assert_eq!(original.score, 99);
+
+        // Release groups have no track count, so title, artist and year decide
+        let group = &candidates[1];
+        assert_eq!(group.kind, ReleaseKind::ReleaseGroup);
+        assert_eq!(group.score, 98);
+    }
+
+    #[test]
+    fn test_album_candidates_mark_current_match() {
+        let album = discovery();
+        let candidates = rank_album_candidates(&album, "Daft Punk", &album.name, &album_results());
+
+        let current: Vec<&str> = candidates
+            .iter()
+            .filter(|candidate| candidate.current)
+            .map(|candidate| candidate.musicbrainz_id.as_str())
+            .collect();
+        assert_eq!(current, vec!["rg-discovery"]);
+    }
+
+    #[test]
+    fn test_album_candidates_without_results() {
+        let album = discovery();
+        let results = vec![(ReleaseKind::ReleaseGroup, json!({})), (ReleaseKind::Release, json!({ "releases": [] }))];
+
+        assert!(rank_album_candidates(&album, "Daft Punk", &album.name, &results).is_empty());
+    }
+
+    fn beatles() -> Artist {
+        Artist {
+            id: "artist".to_string(),
+            name: "The Beatles".to_string(),
+            albums: Vec::new(),
+            featured_on_album_ids: Vec::new(),
+            musicbrainz_id: Some("a-beatles".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn artist_results() -> Value {
+        json!({ "artists": [
+            { "id": "a-revival", "name": "The Beatles Revival Band", "sort-name": "Beatles Revival Band, The", "score": 100 },
+            { "id": "a-beatles", "name": "The Beatles", "sort-name": "Beatles, The", "type": "Group", "country": "GB", "score": 100 },
+            { "id": "a-tribute", "name": "Beatles", "sort-name": "Beatles", "score": 60 },
+        ]})
+    }
+
+    #[test]
+    fn test_artist_candidates_rank_by_name() {
+        let artist = beatles();
+        let candidates = rank_artist_candidates(&artist, &artist.name, &artist_results());
+
+        let ranked: Vec<(&str, u8)> = candidates.iter().map(|candidate| (candidate.musicbrainz_id.as_str(), candidate.score)).collect();
+        assert_eq!(ranked, vec![("a-beatles", 100), ("a-tribute", 63), ("a-revival", 57)]);
+
+        assert!(candidates[0].current);
+        assert_eq!(candidates[0].artist_type, "Group");
+        assert!(candidates[1..].iter().all(|candidate| !candidate.current));
+    }
+
+    #[test]
+    fn test_artist_candidates_match_sort_name() {
+        let artist = beatles();
+        let candidates = rank_artist_candidates(&artist, "beatles, the", &artist_results());
+
+        assert_eq!(candidates[0].musicbrainz_id, "a-beatles");
+        assert_eq!(candidates[0].score, 100);
+    }
+}
@@ -0,0 +1,635 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use tracing::warn;
+use url::Url;
+
+use crate::structures::structures::{Album, Artist};
+use crate::utils::config::get_metadata_providers_config_path;
+use crate::utils::local_metadata::{find_artist_image, read_album_description, read_artist_description};
+use crate::utils::metadata::{
+    fetch_album_metadata, fetch_audio_db_image, fetch_audio_db_music_videos, fetch_cover_art_url, fetch_wikipedia_extract,
+    get_access_token, get_artist_metadata, AlbumMetadata, ArtistMetadata,
+};
+
+/// The kinds of metadata looked up for artists and albums.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataField {
+    ArtistImage,
+    ArtistDescription,
+    /// Release dates, types and the MusicBrainz and Wikidata ids
+    AlbumMetadata,
+    AlbumCover,
+    AlbumDescription,
+    MusicVideos,
+}
+
+impl MetadataField {
+    pub const ALL: [MetadataField; 6] = [
+        MetadataField::ArtistImage,
+        MetadataField::ArtistDescription,
+        MetadataField::AlbumMetadata,
+        MetadataField::AlbumCover,
+        MetadataField::AlbumDescription,
+        MetadataField::MusicVideos,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetadataField::ArtistImage => "artist_image",
+            MetadataField::ArtistDescription => "artist_description",
+            MetadataField::AlbumMetadata => "album_metadata",
+            MetadataField::AlbumCover => "album_cover",
+            MetadataField::AlbumDescription => "album_description",
+            MetadataField::MusicVideos => "music_videos",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    Spotify,
+    #[serde(rename = "musicbrainz")]
+    MusicBrainz,
+    CoverArtArchive,
+    /// Wikipedia extracts, found through Wikidata when the album has a Wikidata id
+    Wikipedia,
+    #[serde(rename = "theaudiodb")]
+    TheAudioDb,
+    /// Sidecar files and images next to the music, the only provider used offline
+    Local,
+}
+
+impl ProviderKind {
+    pub const ALL: [ProviderKind; 6] = [
+        ProviderKind::Spotify,
+        ProviderKind::MusicBrainz,
+        ProviderKind::CoverArtArchive,
+        ProviderKind::Wikipedia,
+        ProviderKind::TheAudioDb,
+        ProviderKind::Local,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::Spotify => "spotify",
+            ProviderKind::MusicBrainz => "musicbrainz",
+            ProviderKind::CoverArtArchive => "cover_art_archive",
+            ProviderKind::Wikipedia => "wikipedia",
+            ProviderKind::TheAudioDb => "theaudiodb",
+            ProviderKind::Local => "local",
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            ProviderKind::Spotify => "Spotify",
+            ProviderKind::MusicBrainz => "MusicBrainz",
+            ProviderKind::CoverArtArchive => "Cover Art Archive",
+            ProviderKind::Wikipedia => "Wikipedia",
+            ProviderKind::TheAudioDb => "TheAudioDB",
+            ProviderKind::Local => "Local files",
+        }
+    }
+
+    /// The fields the provider can fill in.
+    pub fn fields(&self) -> &'static [MetadataField] {
+        match self {
+            ProviderKind::Spotify => &[MetadataField::ArtistImage],
+            ProviderKind::MusicBrainz => &[MetadataField::AlbumMetadata],
+            ProviderKind::CoverArtArchive => &[MetadataField::AlbumCover],
+            ProviderKind::Wikipedia => &[MetadataField::ArtistDescription, MetadataField::AlbumDescription],
+            ProviderKind::TheAudioDb => &[MetadataField::ArtistImage, MetadataField::MusicVideos],
+            ProviderKind::Local => &[MetadataField::ArtistImage, MetadataField::ArtistDescription, MetadataField::AlbumDescription],
+        }
+    }
+
+    /// Whether the provider makes network requests, which offline mode skips.
+    pub fn is_remote(&self) -> bool {
+        *self != ProviderKind::Local
+    }
+
+    /// How long responses are served from the metadata cache before being
+    /// revalidated, unless the provider's settings say otherwise. Follower
+    /// counts change daily; encyclopedic data rarely does.
+    pub fn default_cache_ttl_hours(&self) -> u64 {
+        match self {
+            ProviderKind::Spotify => 24,
+            ProviderKind::MusicBrainz | ProviderKind::TheAudioDb => 24 * 7,
+            ProviderKind::CoverArtArchive | ProviderKind::Wikipedia => 24 * 30,
+            ProviderKind::Local => 0,
+        }
+    }
+
+    /// The steady request rate allowed to each of the provider's hosts.
+    /// MusicBrainz asks clients to stay at one request a second.
+    pub fn requests_per_second(&self) -> f64 {
+        match self {
+            ProviderKind::MusicBrainz => 1.0,
+            ProviderKind::TheAudioDb => 2.0,
+            ProviderKind::Spotify | ProviderKind::CoverArtArchive | ProviderKind::Wikipedia => 5.0,
+            ProviderKind::Local => f64::INFINITY,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ProviderSettings {
+    pub enabled: bool,
+    /// Replaces the scheme and host of every request the provider makes, e.g.
+    /// to point it at a local stub server. Providers that talk to more than one
+    /// host send all of their requests to it.
+    pub base_url: Option<String>,
+    /// Overrides `ProviderKind::default_cache_ttl_hours`. Zero revalidates every
+    /// cached response before using it.
+    pub cache_ttl_hours: Option<u64>,
+}
+
+impl Default for ProviderSettings {
+    fn default() -> Self {
+        ProviderSettings {
+            enabled: true,
+            base_url: None,
+            cache_ttl_hours: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct ProviderToggles {
+    pub spotify: ProviderSettings,
+    pub musicbrainz: ProviderSettings,
+    pub cover_art_archive: ProviderSettings,
+    pub wikipedia: ProviderSettings,
+    pub theaudiodb: ProviderSettings,
+    pub local: ProviderSettings,
+}
+
+impl ProviderToggles {
+    pub fn get(&self, kind: ProviderKind) -> &ProviderSettings {
+        match kind {
+            ProviderKind::Spotify => &self.spotify,
+            ProviderKind::MusicBrainz => &self.musicbrainz,
+            ProviderKind::CoverArtArchive => &self.cover_art_archive,
+            ProviderKind::Wikipedia => &self.wikipedia,
+            ProviderKind::TheAudioDb => &self.theaudiodb,
+            ProviderKind::Local => &self.local,
+        }
+    }
+}
+
+/// For each field, the providers asked in turn until one has it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct FieldPriority {
+    pub artist_image: Vec<ProviderKind>,
+    pub artist_description: Vec<ProviderKind>,
+    pub album_metadata: Vec<ProviderKind>,
+    pub album_cover: Vec<ProviderKind>,
+    pub album_description: Vec<ProviderKind>,
+    pub music_videos: Vec<ProviderKind>,
+}
+
+impl Default for FieldPriority {
+    fn default() -> Self {
+        FieldPriority {
+            artist_image: vec![ProviderKind::Local, ProviderKind::Spotify, ProviderKind::TheAudioDb],
+            artist_description: vec![ProviderKind::Local, ProviderKind::Wikipedia],
+            album_metadata: vec![ProviderKind::MusicBrainz],
+            album_cover: vec![ProviderKind::CoverArtArchive],
+            album_description: vec![ProviderKind::Local, ProviderKind::Wikipedia],
+            music_videos: vec![ProviderKind::TheAudioDb],
+        }
+    }
+}
+
+impl FieldPriority {
+    pub fn get(&self, field: MetadataField) -> &[ProviderKind] {
+        match field {
+            MetadataField::ArtistImage => &self.artist_image,
+            MetadataField::ArtistDescription => &self.artist_description,
+            MetadataField::AlbumMetadata => &self.album_metadata,
+            MetadataField::AlbumCover => &self.album_cover,
+            MetadataField::AlbumDescription => &self.album_description,
+            MetadataField::MusicVideos => &self.music_videos,
+        }
+    }
+}
+
+/// Which metadata providers are used and in what order. Stored in
+/// `metadata_providers.json` and editable by admins through `/library/metadata/providers`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct MetadataProviderSettings {
+    /// Skips every remote provider. Artists and albums indexed meanwhile are
+    /// marked as pending enrichment and looked up by the next metadata refresh
+    /// made online.
+    pub offline: bool,
+    pub providers: ProviderToggles,
+    pub priority: FieldPriority,
+}
+
+impl MetadataProviderSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        for kind in ProviderKind::ALL {
+            if self.providers.get(kind).cache_ttl_hours.is_some() && !kind.is_remote() {
+                return Err(format!("{}: cache_ttl_hours can only be set for remote providers", kind.as_str()));
+            }
+            if let Some(base_url) = &self.providers.get(kind).base_url {
+                if !kind.is_remote() {
+                    return Err(format!("{}: base_url can only be set for remote providers", kind.as_str()));
+                }
+                match Url::parse(base_url) {
+                    Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                    _ => return Err(format!("{}: base_url must be an http or https URL, got {}", kind.as_str(), base_url)),
+                }
+            }
+        }
+
+        for field in MetadataField::ALL {
+            let mut seen = HashSet::new();
+            for kind in self.priority.get(field) {
+                if !kind.fields().contains(&field) {
+                    return Err(format!("{}: {} does not provide this field", field.as_str(), kind.as_str()));
+                }
+                if !seen.insert(kind) {
+                    return Err(format!("{}: {} is listed more than once", field.as_str(), kind.as_str()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref METADATA_PROVIDER_SETTINGS: RwLock<MetadataProviderSettings> = RwLock::new(MetadataProviderSettings::default());
+}
+
+pub fn metadata_provider_settings() -> MetadataProviderSettings {
+    METADATA_PROVIDER_SETTINGS.read().unwrap().clone()
+}
+
+/// How long the provider's responses stay fresh in the metadata cache.
+pub fn provider_cache_ttl(kind: ProviderKind) -> Duration {
+    let hours = METADATA_PROVIDER_SETTINGS
+        .read()
+        .unwrap()
+        .providers
+        .get(kind)
+        .cache_ttl_hours
+        .unwrap_or_else(|| kind.default_cache_ttl_hours());
+    Duration::from_secs(hours.saturating_mul(60 * 60))
+}
+
+/// Loads the saved provider settings into memory, keeping the defaults if none were saved.
+pub fn load_metadata_provider_settings() -> Result<(), Box<dyn Error>> {
+    let path = get_metadata_providers_config_path();
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let settings: MetadataProviderSettings = serde_json::from_str(&fs::read_to_string(path)?)?;
+    *METADATA_PROVIDER_SETTINGS.write().unwrap() = settings;
+    Ok(())
+}
+
+pub fn save_metadata_provider_settings(settings: MetadataProviderSettings) -> Result<(), Box<dyn Error>> {
+    fs::write(get_metadata_providers_config_path(), serde_json::to_string_pretty(&settings)?)?;
+    *METADATA_PROVIDER_SETTINGS.write().unwrap() = settings;
+    Ok(())
+}
+
+pub struct ArtistImage {
+    pub url: String,
+    pub followers: Option<u64>,
+}
+
+/// A source of artist and album metadata. Each lookup returns None when the
+/// provider has nothing for it, and the next provider in the field's priority
+/// order is asked instead. Providers only implement the fields they list in
+/// `ProviderKind::fields`.
+#[async_trait]
+pub trait MetadataProvider: Send + Sync {
+    fn kind(&self) -> ProviderKind;
+
+    async fn artist_image(&self, _client: &Client, _artist: &Artist) -> Option<ArtistImage> {
+        None
+    }
+
+    /// `metadata` is what MusicBrainz has for the artist, which is empty unless
+    /// an admin matched the artist to a MusicBrainz entry.
+    async fn artist_description(&self, _client: &Client, _artist: &Artist, _metadata: &ArtistMetadata) -> Option<String> {
+        None
+    }
+
+    async fn album_metadata(&self, _client: &Client, _artist_name: &str, _album: &Album) -> Option<AlbumMetadata> {
+        None
+    }
+
+    /// The URL of the album's cover image. `metadata` is what the album metadata
+    /// providers found, which is empty if none of them found the album.
+    async fn album_cover(&self, _client: &Client, _artist_name: &str, _album: &Album, _metadata: &AlbumMetadata) -> Option<String> {
+        None
+    }
+
+    async fn album_description(&self, _client: &Client, _artist_name: &str, _album: &Album, _metadata: &AlbumMetadata) -> Option<String> {
+        None
+    }
+
+    /// The artist's music videos in the form `Artist::tadb_music_videos` stores.
+    async fn music_videos(&self, _client: &Client, _artist: &Artist) -> Option<String> {
+        None
+    }
+}
+
+/// The base URL override with any trailing slash removed, or the provider's default.
+fn base_url(settings: &ProviderSettings, default: &str) -> String {
+    settings.base_url.as_deref().unwrap_or(default).trim_end_matches('/').to_string()
+}
+
+const MUSICBRAINZ_BASE_URL: &str = "https://musicbrainz.org";
+
+/// Where MusicBrainz is reached for manual matching, or None when it is
+/// disabled or the server is offline.
+pub fn musicbrainz_base_url(settings: &MetadataProviderSettings) -> Option<String> {
+    let musicbrainz = &settings.providers.musicbrainz;
+    (musicbrainz.enabled && !settings.offline).then(|| base_url(musicbrainz, MUSICBRAINZ_BASE_URL))
+}
+
+pub struct SpotifyProvider {
+    token_base_url: String,
+    api_base_url: String,
+    /// Fetched on first use and shared by every lookup with this provider; None
+    /// once fetching it has failed, so it isn't retried for each artist
+    access_token: OnceCell<Option<String>>,
+}
+
+impl SpotifyProvider {
+    pub fn new(settings: &ProviderSettings) -> Self {
+        SpotifyProvider {
+            token_base_url: base_url(settings, "https://open.spotify.com"),
+            api_base_url: base_url(settings, "https://api.spotify.com"),
+            access_token: OnceCell::new(),
+        }
+    }
+
+    async fn access_token(&self, client: &Client) -> Option<&str> {
+        self.access_token
+            .get_or_init(|| async {
+                match get_access_token(client, &self.token_base_url).await {
+                    Ok(token) => Some(token),
+                    Err(e) => {
+                        warn!("Failed to get Spotify token: {}", e);
+                        None
+                    }
+                }
+            })
+            .await
+            .as_deref()
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for SpotifyProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Spotify
+    }
+
+    async fn artist_image(&self, client: &Client, artist: &Artist) -> Option<ArtistImage> {
+        let token = self.access_token(client).await?;
+        let (url, followers) = get_artist_metadata(client, &self.api_base_url, &artist.name, token).await?;
+        Some(ArtistImage { url, followers: Some(followers) })
+    }
+}
+
+pub struct MusicBrainzProvider {
+    base_url: String,
+}
+
+impl MusicBrainzProvider {
+    pub fn new(settings: &ProviderSettings) -> Self {
+        MusicBrainzProvider {
+            base_url: base_url(settings, MUSICBRAINZ_BASE_URL),
+        }
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for MusicBrainzProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::MusicBrainz
+    }
+
+    async fn album_metadata(&self, client: &Client, artist_name: &str, album: &Album) -> Option<AlbumMetadata> {
+        fetch_album_metadata(client, &self.base_url, artist_name, album).await
+    }
+}
+
+pub struct CoverArtArchiveProvider {
+    base_url: String,
+}
+
+impl CoverArtArchiveProvider {
+    pub fn new(settings: &ProviderSettings) -> Self {
+        CoverArtArchiveProvider {
+            base_url: base_url(settings, "http://coverartarchive.org"),
+        }
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for CoverArtArchiveProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::CoverArtArchive
+    }
+
+    async fn album_cover(&self, client: &Client, _artist_name: &str, album: &Album, metadata: &AlbumMetadata) -> Option<String> {
+        // The archive is keyed by MusicBrainz ids, so it needs the album found there first
+        if metadata.musicbrainz_id.is_empty() {
+            return None;
+        }
+
+        match fetch_cover_art_url(client, &self.base_url, &metadata.musicbrainz_id, metadata.is_release_group).await {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("Cover Art Archive lookup failed for Album: {}. Error: {}", album.name, e);
+                None
+            }
+        }
+    }
+}
+
+pub struct WikipediaProvider {
+    wikidata_base_url: String,
+    wikipedia_base_url: String,
+}
+
+impl WikipediaProvider {
+    pub fn new(settings: &ProviderSettings) -> Self {
+        WikipediaProvider {
+            wikidata_base_url: base_url(settings, "https://www.wikidata.org"),
+            wikipedia_base_url: base_url(settings, "https://en.wikipedia.org"),
+        }
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for WikipediaProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Wikipedia
+    }
+
+    async fn artist_description(&self, client: &Client, artist: &Artist, metadata: &ArtistMetadata) -> Option<String> {
+        let wikidata_id = metadata.wikidata_id.as_deref().filter(|id| !id.is_empty());
+        fetch_wikipedia_extract(client, &self.wikidata_base_url, &self.wikipedia_base_url, &artist.name, None, wikidata_id).await
+    }
+
+    async fn album_description(&self, client: &Client, artist_name: &str, album: &Album, metadata: &AlbumMetadata) -> Option<String> {
+        let re = Regex::new(r"(?i)\b.*cd.*\b").unwrap();
+        let album_name = album.name.to_lowercase();
+        let album_name = re.replace_all(&album_name, "").trim().to_string();
+
+        let wikidata_id = metadata.wikidata_id.as_deref().filter(|id| !id.is_empty());
+        fetch_wikipedia_extract(
+            client,
+            &self.wikidata_base_url,
+            &self.wikipedia_base_url,
+            &album_name,
+            Some(artist_name),
+            wikidata_id,
+        )
+        .await
+    }
+}
+
+pub struct TheAudioDbProvider {
+    base_url: String,
+}
+
+impl TheAudioDbProvider {
+    pub fn new(settings: &ProviderSettings) -> Self {
+        TheAudioDbProvider {
+            base_url: base_url(settings, "https://www.theaudiodb.com"),
+        }
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for TheAudioDbProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::TheAudioDb
+    }
+
+    async fn artist_image(&self, client: &Client, artist: &Artist) -> Option<ArtistImage> {
+        match fetch_audio_db_image(client, &self.base_url, &artist.name).await {
+            Ok(url) => url.map(|url| ArtistImage { url, followers: None }),
+            Err(e) => {
+                warn!("AudioDB image lookup failed for {}: {}", artist.name, e);
+                None
+            }
+        }
+    }
+
+    async fn music_videos(&self, client: &Client, artist: &Artist) -> Option<String> {
+        match fetch_audio_db_music_videos(client, &self.base_url, &artist.name).await {
+            Ok(music_videos) => music_videos,
+            Err(e) => {
+                warn!("AudioDB music video lookup failed for {}: {}", artist.name, e);
+                None
+            }
+        }
+    }
+}
+
+/// Reads artist images and descriptions from files in the music folders.
+pub struct LocalFilesProvider;
+
+#[async_trait]
+impl MetadataProvider for LocalFilesProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Local
+    }
+
+    async fn artist_image(&self, _client: &Client, artist: &Artist) -> Option<ArtistImage> {
+        let path = find_artist_image(artist)?;
+        Some(ArtistImage {
+            url: path.to_string_lossy().to_string(),
+            followers: None,
+        })
+    }
+
+    async fn artist_description(&self, _client: &Client, artist: &Artist, _metadata: &ArtistMetadata) -> Option<String> {
+        read_artist_description(artist)
+    }
+
+    async fn album_description(&self, _client: &Client, _artist_name: &str, album: &Album, _metadata: &AlbumMetadata) -> Option<String> {
+        read_album_description(album)
+    }
+}
+
+/// The enabled providers, built from the settings at the start of a scan or
+/// refresh so a settings change mid-run doesn't mix two configurations.
+pub struct MetadataProviders {
+    providers: Vec<Box<dyn MetadataProvider>>,
+    priority: FieldPriority,
+    offline: bool,
+}
+
+impl MetadataProviders {
+    pub fn new(settings: &MetadataProviderSettings) -> Self {
+        let providers = ProviderKind::ALL
+            .into_iter()
+            .filter(|kind| settings.providers.get(*kind).enabled)
+            .filter(|kind| !(settings.offline && kind.is_remote()))
+            .map(|kind| {
+                let provider_settings = settings.providers.get(kind);
+                let provider: Box<dyn MetadataProvider> = match kind {
+                    ProviderKind::Spotify => Box::new(SpotifyProvider::new(provider_settings)),
+                    ProviderKind::MusicBrainz => Box::new(MusicBrainzProvider::new(provider_settings)),
+                    ProviderKind::CoverArtArchive => Box::new(CoverArtArchiveProvider::new(provider_settings)),
+                    ProviderKind::Wikipedia => Box::new(WikipediaProvider::new(provider_settings)),
+                    ProviderKind::TheAudioDb => Box::new(TheAudioDbProvider::new(provider_settings)),
+                    ProviderKind::Local => Box::new(LocalFilesProvider),
+                };
+                provider
+            })
+            .collect();
+
+        MetadataProviders {
+            providers,
+            priority: settings.priority.clone(),
+            offline: settings.offline,
+        }
+    }
+
+    pub fn from_settings() -> Self {
+        Self::new(&metadata_provider_settings())
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// The enabled providers for a field, in priority order.
+    pub fn for_field(&self, field: MetadataField) -> impl Iterator<Item = &dyn MetadataProvider> {
+        self.priority.get(field).iter().filter_map(|kind| {
+            self.providers
+                .iter()
+                .find(|provider| provider.kind() == *kind)
+                .map(|provider| provider.as_ref())
+        })
+    }
+}
@@ -1,11 +1,33 @@
+pub mod backups;
+pub mod catalog;
+pub mod catalog_index;
 pub mod compare;
 pub mod config;
 pub mod database;
 pub mod format;
+pub mod formats;
 pub mod globals;
 pub mod hash;
+pub mod id_migration;
+pub mod jobs;
 pub mod library;
+pub mod library_settings;
+pub mod local_metadata;
 pub mod metadata;
+pub mod metadata_http;
+pub mod metadata_matching;
+pub mod metadata_providers;
+pub mod progress;
+pub mod scan_state;
+pub mod scheduler;
+pub mod watcher;
 pub mod websocket;
 
-pub mod format_test;
\ No newline at end of file
+pub mod compare_test;
+pub mod format_test;
+pub mod formats_test;
+pub mod hash_test;
+pub mod library_settings_test;
+pub mod metadata_http_test;
+pub mod metadata_matching_test;
+pub mod scheduler_test;
\ No newline at end of file
@@ -0,0 +1,262 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use crate::utils::websocket::send_to_ws;
+
+/// How often the progress of a running scan is pushed over the WebSocket.
+const REPORT_INTERVAL: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    static ref CURRENT_SCAN: Mutex<Option<Arc<ScanProgress>>> = Mutex::new(None);
+    static ref FINISHED_SCAN: Mutex<Option<ScanProgressEvent>> = Mutex::new(None);
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanPhase {
+    Discovering,
+    Reading,
+    Metadata,
+    Saving,
+    Finished,
+    Cancelled,
+    Failed,
+}
+
+#[derive(Debug)]
+pub enum ScanError {
+    AlreadyRunning,
+    Cancelled,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::AlreadyRunning => write!(f, "A scan is already running"),
+            ScanError::Cancelled => write!(f, "The scan was cancelled"),
+        }
+    }
+}
+
+impl Error for ScanError {}
+
+/// The event sent over the WebSocket, tagged with `"type": "scan_progress"` so
+/// clients can tell it from plain log lines.
+#[derive(Serialize, Debug)]
+pub struct ScanProgressEvent {
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    pub library_path: String,
+    pub phase: ScanPhase,
+    pub files_discovered: usize,
+    pub files_processed: usize,
+    pub current_path: Option<String>,
+    pub metadata_pending: usize,
+    pub errors: usize,
+    pub elapsed_seconds: u64,
+    pub eta_seconds: Option<u64>,
+}
+
+/// Progress of the running `index` or `refresh`. It is shared with the rayon
+/// workers reading files, so the counters are atomics.
+pub struct ScanProgress {
+    library_path: Mutex<String>,
+    phase: Mutex<(ScanPhase, Instant)>,
+    files_discovered: AtomicUsize,
+    files_processed: AtomicUsize,
+    current_path: Mutex<Option<String>>,
+    metadata_pending: AtomicUsize,
+    metadata_done: AtomicUsize,
+    errors: AtomicUsize,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+    started: Instant,
+}
+
+impl ScanProgress {
+    fn new() -> Self {
+        ScanProgress {
+            library_path: Mutex::new(String::new()),
+            phase: Mutex::new((ScanPhase::Discovering, Instant::now())),
+            files_discovered: AtomicUsize::new(0),
+            files_processed: AtomicUsize::new(0),
+            current_path: Mutex::new(None),
+            metadata_pending: AtomicUsize::new(0),
+            metadata_done: AtomicUsize::new(0),
+            errors: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            started: Instant::now(),
+        }
+    }
+
+    /// Moves on to the next library of a refresh. Errors keep counting up.
+    pub fn start_library(&self, library_path: &str) {
+        *self.library_path.lock().unwrap() = library_path.to_string();
+        self.files_discovered.store(0, Ordering::Relaxed);
+        self.files_processed.store(0, Ordering::Relaxed);
+        *self.current_path.lock().unwrap() = None;
+        self.set_phase(ScanPhase::Discovering);
+    }
+
+    pub fn set_phase(&self, phase: ScanPhase) {
+        *self.phase.lock().unwrap() = (phase, Instant::now());
+    }
+
+    pub fn files_discovered(&self, count: usize) {
+        self.files_discovered.store(count, Ordering::Relaxed);
+    }
+
+    pub fn file_processed(&self, path: &str) {
+        self.files_processed.fetch_add(1, Ordering::Relaxed);
+        *self.current_path.lock().unwrap() = Some(path.to_string());
+    }
+
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn metadata_queued(&self, count: usize) {
+        self.metadata_pending.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn metadata_finished(&self) {
+        self.metadata_pending.fetch_sub(1, Ordering::Relaxed);
+        self.metadata_done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns `ScanError::Cancelled` once the scan has been cancelled, for the
+    /// points where a scan can stop without leaving anything half saved.
+    pub fn check_cancelled(&self) -> Result<(), ScanError> {
+        if self.is_cancelled() {
+            Err(ScanError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn snapshot(&self) -> ScanProgressEvent {
+        let (phase, phase_started) = *self.phase.lock().unwrap();
+        let files_discovered = self.files_discovered.load(Ordering::Relaxed);
+        let files_processed = self.files_processed.load(Ordering::Relaxed);
+        let metadata_pending = self.metadata_pending.load(Ordering::Relaxed);
+
+        // Estimated from the rate of the current phase only, since reading
+        // files and looking up metadata take very different times per item
+        let (done, remaining) = match phase {
+            ScanPhase::Reading => (files_processed, files_discovered.saturating_sub(files_processed)),
+            ScanPhase::Metadata => (self.metadata_done.load(Ordering::Relaxed), metadata_pending),
+            _ => (0, 0),
+        };
+        let eta_seconds = (done > 0).then(|| {
+            (phase_started.elapsed().as_secs_f64() / done as f64 * remaining as f64).round() as u64
+        });
+
+        ScanProgressEvent {
+            event_type: "scan_progress",
+            library_path: self.library_path.lock().unwrap().clone(),
+            phase,
+            files_discovered,
+            files_processed,
+            current_path: self.current_path.lock().unwrap().clone(),
+            metadata_pending,
+            errors: self.errors.load(Ordering::Relaxed),
+            elapsed_seconds: self.started.elapsed().as_secs(),
+            eta_seconds,
+        }
+    }
+}
+
+/// The running scan. Only one scan runs at a time; dropping the handle lets
+/// the next one start.
+pub struct ScanHandle {
+    progress: Arc<ScanProgress>,
+}
+
+impl std::ops::Deref for ScanHandle {
+    type Target = Arc<ScanProgress>;
+
+    fn deref(&self) -> &Arc<ScanProgress> {
+        &self.progress
+    }
+}
+
+impl ScanHandle {
+    /// Sends the final event, marking the scan finished, cancelled or failed.
+    pub async fn finish<T>(self, result: &Result<T, Box<dyn Error>>) {
+        let phase = match result {
+            Ok(_) => ScanPhase::Finished,
+            Err(e) if matches!(e.downcast_ref::<ScanError>(), Some(ScanError::Cancelled)) => ScanPhase::Cancelled,
+            Err(_) => ScanPhase::Failed,
+        };
+
+        self.set_phase(phase);
+        let event = self.snapshot();
+        send_to_ws(&event).await;
+        *FINISHED_SCAN.lock().unwrap() = Some(event);
+    }
+}
+
+impl Drop for ScanHandle {
+    fn drop(&mut self) {
+        self.progress.finished.store(true, Ordering::Relaxed);
+        *CURRENT_SCAN.lock().unwrap() = None;
+    }
+}
+
+/// Starts tracking a scan and reporting its progress over the WebSocket.
+/// Fails with `ScanError::AlreadyRunning` while another scan holds a handle.
+pub fn begin_scan() -> Result<ScanHandle, ScanError> {
+    let mut current = CURRENT_SCAN.lock().unwrap();
+    if current.is_some() {
+        return Err(ScanError::AlreadyRunning);
+    }
+
+    let progress = Arc::new(ScanProgress::new());
+    *current = Some(progress.clone());
+    *FINISHED_SCAN.lock().unwrap() = None;
+
+    let reporter = progress.clone();
+    actix_web::rt::spawn(async move {
+        loop {
+            actix_web::rt::time::sleep(REPORT_INTERVAL).await;
+            if reporter.finished.load(Ordering::Relaxed) {
+                break;
+            }
+            send_to_ws(&reporter.snapshot()).await;
+        }
+    });
+
+    Ok(ScanHandle { progress })
+}
+
+/// A snapshot of the running scan, if there is one.
+pub fn current_scan_progress() -> Option<ScanProgressEvent> {
+    CURRENT_SCAN.lock().unwrap().as_ref().map(|progress| progress.snapshot())
+}
+
+/// Takes the final event of the last scan that finished.
+pub fn take_finished_scan_progress() -> Option<ScanProgressEvent> {
+    FINISHED_SCAN.lock().unwrap().take()
+}
+
+/// Asks the running scan to stop. Returns false when nothing is running.
+pub fn cancel_scan() -> bool {
+    match &*CURRENT_SCAN.lock().unwrap() {
+        Some(progress) => {
+            progress.cancelled.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::Metadata;
+use std::time::UNIX_EPOCH;
+
+use diesel::prelude::*;
+
+use crate::utils::database::database::{establish_connection, with_connection};
+use crate::utils::database::models::{NewScanState, ScanState};
+use crate::utils::library::ScannedSong;
+
+const CHUNK_SIZE: usize = 500;
+
+/// The file's size and modification time in nanoseconds. Whole seconds would
+/// miss a file that is written again within the second it was scanned.
+pub fn file_signature(metadata: &Metadata) -> (i64, i64) {
+    let size = metadata.len() as i64;
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos() as i64)
+        .unwrap_or(0);
+
+    (size, mtime)
+}
+
+pub fn load_scan_states(library: &str) -> Result<HashMap<String, ScanState>, Box<dyn Error>> {
+    use crate::utils::database::schema::scan_state::dsl::*;
+
+    let mut connection = establish_connection().get()?;
+
+    let states = scan_state
+        .filter(library_path.eq(library))
+        .select(ScanState::as_select())
+        .load::<ScanState>(&mut connection)?;
+
+    Ok(states.into_iter().map(|state| (state.path.clone(), state)).collect())
+}
+
+pub fn cached_song(state: &ScanState, size: i64, mtime: i64) -> Option<ScannedSong> {
+    if state.size != size || state.mtime != mtime {
+        return None;
+    }
+
+    serde_json::from_str(&state.song)
+        .ok()
+        .map(|song| ScannedSong {
+            song,
+            album_name: state.album_name.clone(),
+            album_artist: state.album_artist.clone(),
+            compilation: state.compilation,
+            release_date: state.release_date.clone(),
+            genres: serde_json::from_str(&state.genres).unwrap_or_default(),
+            musicbrainz_release_id: state.musicbrainz_release_id.clone(),
+            musicbrainz_release_group_id: state.musicbrainz_release_group_id.clone(),
+            musicbrainz_artist_id: state.musicbrainz_artist_id.clone(),
+        })
+}
+
+pub fn new_scan_state(library: &str, scanned: &ScannedSong, size: i64, mtime: i64) -> Option<NewScanState> {
+    let song_json = serde_json::to_string(&scanned.song).ok()?;
+
+    Some(NewScanState {
+        path: scanned.song.path.clone(),
+        library_path: library.to_string(),
+        size,
+        mtime,
+        song_id: scanned.song.id.clone(),
+        album_name: scanned.album_name.clone(),
+        song: song_json,
+        album_artist: scanned.album_artist.clone(),
+        compilation: scanned.compilation,
+        release_date: scanned.release_date.clone(),
+        genres: serde_json::to_string(&scanned.genres).ok()?,
+        musicbrainz_release_id: scanned.musicbrainz_release_id.clone(),
+        musicbrainz_release_group_id: scanned.musicbrainz_release_group_id.clone(),
+        musicbrainz_artist_id: scanned.musicbrainz_artist_id.clone(),
+    })
+}
+
+pub fn save_scan_states(
+    previous: &HashMap<String, ScanState>,
+    seen_paths: &HashSet<String>,
+    changed: Vec<NewScanState>,
+) -> Result<(), Box<dyn Error>> {
+    use crate::utils::database::schema::scan_state::dsl::*;
+
+    let mut connection = establish_connection().get()?;
+
+    let missing: Vec<&String> = previous
+        .keys()
+        .filter(|state_path| !seen_paths.contains(*state_path))
+        .collect();
+
+    connection.transaction::<_, diesel::result::Error, _>(|connection| {
+        for chunk in missing.chunks(CHUNK_SIZE) {
+            diesel::delete(scan_state.filter(path.eq_any(chunk)))
+                .execute(connection)?;
+        }
+
+        for chunk in changed.chunks(CHUNK_SIZE) {
+            diesel::replace_into(scan_state)
+                .values(chunk)
+                .execute(connection)?;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+/// Forgets every cached file so the next scan reads all tags again, for when
+/// a change affects how tags are interpreted.
+pub async fn clear_scan_states() -> Result<(), Box<dyn Error>> {
+    use crate::utils::database::schema::scan_state::dsl::*;
+
+    with_connection(&establish_connection(), |connection| diesel::delete(scan_state).execute(connection)).await?;
+
+    Ok(())
+}
+
+/// Forgets the cached files of one library, for when the library is removed.
+pub async fn clear_library_scan_states(library: &str) -> Result<(), Box<dyn Error>> {
+    use crate::utils::database::schema::scan_state::dsl::*;
+
+    let library = library.to_string();
+    with_connection(&establish_connection(), move |connection| {
+        diesel::delete(scan_state.filter(library_path.eq(library))).execute(connection)
+    })
+    .await?;
+
+    Ok(())
+}
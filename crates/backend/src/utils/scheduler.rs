@@ -0,0 +1,394 @@
+use std::error::Error;
+use std::fs;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate, Utc};
+use diesel::prelude::*;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::utils::config::get_schedule_config_path;
+use crate::utils::database::database::{establish_connection, with_connection};
+use crate::utils::database::models::{NewTaskRun, TaskRun};
+use crate::utils::jobs::{enqueue_job, wait_for_job, JobKind, JobStatus};
+
+/// How often the scheduler checks whether a task is due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledTask {
+    /// Rescans every enabled library
+    Rescan,
+    /// Looks up metadata again for albums without a MusicBrainz id, and for
+    /// artists and albums indexed offline
+    MetadataRefresh,
+    /// Rebuilds the search index from the catalog
+    SearchReindex,
+}
+
+impl ScheduledTask {
+    pub const ALL: [ScheduledTask; 3] = [ScheduledTask::Rescan, ScheduledTask::MetadataRefresh, ScheduledTask::SearchReindex];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScheduledTask::Rescan => "rescan",
+            ScheduledTask::MetadataRefresh => "metadata_refresh",
+            ScheduledTask::SearchReindex => "search_reindex",
+        }
+    }
+
+    fn job_kind(&self) -> JobKind {
+        match self {
+            ScheduledTask::Rescan => JobKind::Refresh,
+            ScheduledTask::MetadataRefresh => JobKind::MetadataRefresh,
+            ScheduledTask::SearchReindex => JobKind::SearchPopulate,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TaskSchedule {
+    pub enabled: bool,
+    pub frequency: Frequency,
+    /// Hour of the day in the server's local time, 0-23
+    pub hour: u32,
+    /// Day of the week for weekly runs, 0 = Monday
+    pub weekday: u32,
+    /// Day of the month for monthly runs, 1-28 so every month has it
+    pub day_of_month: u32,
+}
+
+impl Default for TaskSchedule {
+    fn default() -> Self {
+        TaskSchedule {
+            enabled: false,
+            frequency: Frequency::Daily,
+            hour: 3,
+            weekday: 0,
+            day_of_month: 1,
+        }
+    }
+}
+
+impl TaskSchedule {
+    fn validate(&self) -> Result<(), String> {
+        if self.hour > 23 {
+            return Err(format!("hour must be between 0 and 23, got {}", self.hour));
+        }
+        if self.weekday > 6 {
+            return Err(format!("weekday must be between 0 (Monday) and 6 (Sunday), got {}", self.weekday));
+        }
+        if !(1..=28).contains(&self.day_of_month) {
+            return Err(format!("day_of_month must be between 1 and 28, got {}", self.day_of_month));
+        }
+        Ok(())
+    }
+
+    fn runs_on(&self, date: NaiveDate) -> bool {
+        match self.frequency {
+            Frequency::Daily => true,
+            Frequency::Weekly => date.weekday().num_days_from_monday() == self.weekday,
+            Frequency::Monthly => date.day() == self.day_of_month,
+        }
+    }
+
+    /// None when the hour is skipped by a daylight saving change that day.
+    fn run_time(&self, date: NaiveDate) -> Option<DateTime<Local>> {
+        date.and_hms_opt(self.hour, 0, 0)?.and_local_timezone(Local).earliest()
+    }
+
+    /// The latest time the task was due at or before `now`.
+    pub fn last_due(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        (0..=31)
+            .filter_map(|days_ago| now.date_naive().checked_sub_days(Days::new(days_ago)))
+            .filter(|date| self.runs_on(*date))
+            .filter_map(|date| self.run_time(date))
+            .find(|time| *time <= now)
+    }
+
+    pub fn next_due(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        (0..=62)
+            .filter_map(|days_ahead| now.date_naive().checked_add_days(Days::new(days_ahead)))
+            .filter(|date| self.runs_on(*date))
+            .filter_map(|date| self.run_time(date))
+            .find(|time| *time > now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ScheduleSettings {
+    pub rescan: TaskSchedule,
+    pub metadata_refresh: TaskSchedule,
+    pub search_reindex: TaskSchedule,
+}
+
+impl Default for ScheduleSettings {
+    fn default() -> Self {
+        ScheduleSettings {
+            rescan: TaskSchedule::default(),
+            metadata_refresh: TaskSchedule {
+                frequency: Frequency::Weekly,
+                hour: 4,
+                weekday: 6,
+                ..TaskSchedule::default()
+            },
+            search_reindex: TaskSchedule {
+                frequency: Frequency::Monthly,
+                hour: 5,
+                ..TaskSchedule::default()
+            },
+        }
+    }
+}
+
+impl ScheduleSettings {
+    pub fn get(&self, task: ScheduledTask) -> &TaskSchedule {
+        match task {
+            ScheduledTask::Rescan => &self.rescan,
+            ScheduledTask::MetadataRefresh => &self.metadata_refresh,
+            ScheduledTask::SearchReindex => &self.search_reindex,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        ScheduledTask::ALL
+            .iter()
+            .try_for_each(|task| self.get(*task).validate().map_err(|e| format!("{}: {}", task.as_str(), e)))
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Running,
+    Succeeded,
+    Failed,
+    /// An identical job was already waiting in the queue
+    Skipped,
+    Cancelled,
+    /// The server stopped while the task was running
+    Interrupted,
+}
+
+impl RunStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Running => "running",
+            RunStatus::Succeeded => "succeeded",
+            RunStatus::Failed => "failed",
+            RunStatus::Skipped => "skipped",
+            RunStatus::Cancelled => "cancelled",
+            RunStatus::Interrupted => "interrupted",
+        }
+    }
+}
+
+lazy_static! {
+    static ref SCHEDULE_SETTINGS: RwLock<ScheduleSettings> = RwLock::new(ScheduleSettings::default());
+    /// When the settings were last saved. Runs that fell due before then are
+    /// not caught up, so enabling a task doesn't start it straight away.
+    static ref SCHEDULE_CHANGED: Mutex<Option<DateTime<Local>>> = Mutex::new(None);
+}
+
+pub fn schedule_settings() -> ScheduleSettings {
+    SCHEDULE_SETTINGS.read().unwrap().clone()
+}
+
+/// Loads the saved schedule into memory, keeping the defaults if none was saved.
+pub fn load_schedule_settings() -> Result<(), Box<dyn Error>> {
+    let path = get_schedule_config_path();
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let settings: ScheduleSettings = serde_json::from_str(&fs::read_to_string(path)?)?;
+    *SCHEDULE_SETTINGS.write().unwrap() = settings;
+    Ok(())
+}
+
+pub fn save_schedule_settings(settings: ScheduleSettings) -> Result<(), Box<dyn Error>> {
+    fs::write(get_schedule_config_path(), serde_json::to_string_pretty(&settings)?)?;
+    *SCHEDULE_SETTINGS.write().unwrap() = settings;
+    *SCHEDULE_CHANGED.lock().unwrap() = Some(Local::now());
+    Ok(())
+}
+
+async fn start_run(task: ScheduledTask, job_id: i32) -> Result<i32, Box<dyn Error>> {
+    use crate::utils::database::schema::task_run::dsl;
+
+    let run_id = with_connection(&establish_connection(), move |connection| {
+        diesel::insert_into(dsl::task_run)
+            .values(NewTaskRun {
+                task: task.as_str().to_string(),
+                status: RunStatus::Running.as_str().to_string(),
+                job_id: Some(job_id),
+            })
+            .returning(dsl::id)
+            .get_result::<i32>(connection)
+    })
+    .await?;
+
+    Ok(run_id)
+}
+
+async fn finish_run(run_id: i32, status: RunStatus, message: Option<String>) -> Result<(), Box<dyn Error>> {
+    use crate::utils::database::schema::task_run::dsl;
+
+    with_connection(&establish_connection(), move |connection| {
+        diesel::update(dsl::task_run.filter(dsl::id.eq(run_id)))
+            .set((
+                dsl::status.eq(status.as_str()),
+                dsl::message.eq(message),
+                dsl::finished_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(connection)
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Marks runs left `running` by a previous server process as interrupted.
+pub fn interrupt_stale_task_runs() -> Result<(), Box<dyn Error>> {
+    use crate::utils::database::schema::task_run::dsl;
+
+    let mut connection = establish_connection().get()?;
+
+    diesel::update(dsl::task_run.filter(dsl::status.eq(RunStatus::Running.as_str())))
+        .set((
+            dsl::status.eq(RunStatus::Interrupted.as_str()),
+            dsl::finished_at.eq(Utc::now().naive_utc()),
+        ))
+        .execute(&mut connection)?;
+
+    Ok(())
+}
+
+/// The most recent runs, newest first.
+pub async fn task_runs(task: Option<ScheduledTask>, limit: i64) -> Result<Vec<TaskRun>, Box<dyn Error>> {
+    use crate::utils::database::schema::task_run::dsl;
+
+    Ok(with_connection(&establish_connection(), move |connection| {
+        let mut query = dsl::task_run.into_boxed();
+        if let Some(task) = task {
+            query = query.filter(dsl::task.eq(task.as_str()));
+        }
+
+        query
+            .order(dsl::id.desc())
+            .limit(limit)
+            .select(TaskRun::as_select())
+            .load::<TaskRun>(connection)
+    })
+    .await?)
+}
+
+async fn last_run_started(task: ScheduledTask) -> Result<Option<DateTime<Local>>, Box<dyn Error>> {
+    use crate::utils::database::schema::task_run::dsl;
+
+    let started_at = with_connection(&establish_connection(), move |connection| {
+        dsl::task_run
+            .filter(dsl::task.eq(task.as_str()))
+            .order(dsl::id.desc())
+            .select(dsl::started_at)
+            .first::<chrono::NaiveDateTime>(connection)
+            .optional()
+    })
+    .await?;
+
+    // SQLite's CURRENT_TIMESTAMP is in UTC
+    Ok(started_at.map(|started_at| started_at.and_utc().with_timezone(&Local)))
+}
+
+/// Queues the task's job, waits for it and records the outcome in the run history.
+async fn record_run(task: ScheduledTask) {
+    let (job_id, queued) = match enqueue_job(task.job_kind()).await {
+        Ok(job) => job,
+        Err(e) => {
+            error!("Failed to queue scheduled {}: {}", task.as_str(), e);
+            return;
+        }
+    };
+    let run_id = match start_run(task, job_id).await {
+        Ok(run_id) => run_id,
+        Err(e) => {
+            error!("Failed to record the start of {}: {}", task.as_str(), e);
+            return;
+        }
+    };
+    info!("Running scheduled {} as job {}", task.as_str(), job_id);
+
+    let (status, message) = if !queued {
+        (RunStatus::Skipped, Some(format!("Job {} was already queued", job_id)))
+    } else {
+        match wait_for_job(job_id).await {
+            Ok(job) => match job.status {
+                JobStatus::Succeeded => (RunStatus::Succeeded, job.result.map(|result| match result {
+                    serde_json::Value::String(message) => message,
+                    result => result.to_string(),
+                })),
+                JobStatus::Cancelled => (RunStatus::Cancelled, None),
+                _ => (RunStatus::Failed, job.error),
+            },
+            Err(e) => (RunStatus::Failed, Some(e.to_string())),
+        }
+    };
+    info!("Scheduled {} {}", task.as_str(), status.as_str());
+
+    if let Err(e) = finish_run(run_id, status, message).await {
+        error!("Failed to record the end of {}: {}", task.as_str(), e);
+    }
+}
+
+/// Queues the scheduled tasks as they fall due, one at a time. A run missed
+/// while the server was down is caught up once on the next check.
+pub async fn run_scheduler() {
+    let started = Local::now();
+
+    loop {
+        actix_web::rt::time::sleep(CHECK_INTERVAL).await;
+
+        let settings = schedule_settings();
+        let changed = *SCHEDULE_CHANGED.lock().unwrap();
+
+        for task in ScheduledTask::ALL {
+            let schedule = settings.get(task);
+            if !schedule.enabled {
+                continue;
+            }
+            let Some(due) = schedule.last_due(Local::now()) else {
+                continue;
+            };
+
+            let last_run = match last_run_started(task).await {
+                Ok(last_run) => last_run,
+                Err(e) => {
+                    warn!("Failed to read the run history of {}: {}", task.as_str(), e);
+                    continue;
+                }
+            };
+            // Without a previous run, only times after the server started count
+            let mut baseline = last_run.unwrap_or(started);
+            if let Some(changed) = changed {
+                baseline = baseline.max(changed);
+            }
+
+            if due > baseline {
+                record_run(task).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Local, TimeZone};
+
+    use crate::utils::scheduler::{Frequency, ScheduleSettings, TaskSchedule};
+
+    // Dates are in January and February, away from daylight saving changes
+    fn at(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn schedule(frequency: Frequency, hour: u32, weekday: u32, day_of_month: u32) -> TaskSchedule {
+        TaskSchedule { enabled: true, frequency, hour, weekday, day_of_month }
+    }
+
+    #[test]
+    fn test_due_times() {
+        let daily = schedule(Frequency::Daily, 3, 0, 1);
+        // Sundays at 4
+        let weekly = schedule(Frequency::Weekly, 4, 6, 1);
+        let monthly = schedule(Frequency::Monthly, 5, 0, 28);
+        let first_of_month = schedule(Frequency::Monthly, 5, 0, 1);
+
+        let cases = [
+            // schedule, now, last due, next due
+            (&daily, at(1, 14, 10, 0), at(1, 14, 3, 0), at(1, 15, 3, 0)),
+            (&daily, at(1, 14, 2, 59), at(1, 13, 3, 0), at(1, 14, 3, 0)),
+            // A run falling due right now is the last one, not the next
+            (&daily, at(1, 14, 3, 0), at(1, 14, 3, 0), at(1, 15, 3, 0)),
+            // Wednesday the 14th
+            (&weekly, at(1, 14, 10, 0), at(1, 11, 4, 0), at(1, 18, 4, 0)),
+            (&weekly, at(1, 18, 4, 30), at(1, 18, 4, 0), at(1, 25, 4, 0)),
+            (&monthly, at(2, 10, 0, 0), at(1, 28, 5, 0), at(2, 28, 5, 0)),
+            (&first_of_month, at(1, 31, 23, 0), at(1, 1, 5, 0), at(2, 1, 5, 0)),
+        ];
+
+        for (schedule, now, last_due, next_due) in cases {
+            assert_eq!(schedule.last_due(now), Some(last_due), "{:?} at {}", schedule.frequency, now);
+            assert_eq!(schedule.next_due(now), Some(next_due), "{:?} at {}", schedule.frequency, now);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ScheduleSettings::default().validate().is_ok());
+
+        let cases = [
+            (schedule(Frequency::Daily, 24, 0, 1), "rescan: hour"),
+            (schedule(Frequency::Weekly, 3, 7, 1), "rescan: weekday"),
+            (schedule(Frequency::Monthly, 3, 0, 0), "rescan: day_of_month"),
+            (schedule(Frequency::Monthly, 3, 0, 29), "rescan: day_of_month"),
+        ];
+
+        for (rescan, error) in cases {
+            let settings = ScheduleSettings { rescan, ..ScheduleSettings::default() };
+            let result = settings.validate();
+            assert!(result.as_ref().is_err_and(|e| e.starts_with(error)), "{:?}", result);
+        }
+    }
+}
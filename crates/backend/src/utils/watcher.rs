@@ -0,0 +1,161 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{error, info, warn};
+
+use crate::utils::config::get_libraries_config_path;
+use crate::utils::library_settings::read_libraries;
+
+/// How long the watcher waits for a library to go quiet before applying changes.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+/// Upper bound on how long changes wait while events keep arriving.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+enum WatchMessage {
+    Event(Event),
+    SyncLibraries,
+}
+
+lazy_static! {
+    static ref WATCH_SENDER: Mutex<Option<Sender<WatchMessage>>> = Mutex::new(None);
+}
+
+/// Starts watching every configured library recursively. `on_changes` runs on
+/// the watcher thread with a library path and the paths that changed in it,
+/// once the library has been quiet for `DEBOUNCE`.
+pub fn start_library_watcher<F>(on_changes: F)
+where
+    F: Fn(&str, Vec<PathBuf>) + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    *WATCH_SENDER.lock().unwrap() = Some(sender.clone());
+
+    std::thread::spawn(move || {
+        let event_sender = sender.clone();
+        let mut watcher = match notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
+            match res {
+                Ok(event) => {
+                    let _ = event_sender.send(WatchMessage::Event(event));
+                }
+                Err(e) => error!("Watch error: {}", e),
+            }
+        }) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                error!("Failed to create watcher: {}", e);
+                return;
+            }
+        };
+
+        // Edits to libraries.json are picked up like the /library/index route
+        let libraries_file = get_libraries_config_path();
+        if let Some(config_dir) = libraries_file.parent() {
+            if let Err(e) = watcher.watch(config_dir, RecursiveMode::NonRecursive) {
+                warn!("Failed to watch {}: {}", config_dir.display(), e);
+            }
+        }
+
+        let mut watched = HashSet::new();
+        sync_libraries(&mut watcher, &mut watched);
+
+        let mut pending: HashMap<String, HashSet<PathBuf>> = HashMap::new();
+        let mut first_pending: Option<Instant> = None;
+
+        loop {
+            let message = match first_pending {
+                None => match receiver.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => break,
+                },
+                Some(first) => {
+                    let timeout = DEBOUNCE.min(MAX_DELAY.saturating_sub(first.elapsed()));
+                    match receiver.recv_timeout(timeout) {
+                        Ok(message) if first.elapsed() < MAX_DELAY => Some(message),
+                        Ok(message) => {
+                            flush(&mut pending, &on_changes);
+                            first_pending = None;
+                            Some(message)
+                        }
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+            };
+
+            match message {
+                Some(WatchMessage::Event(event)) => {
+                    // Reading libraries.json below raises access events of its own
+                    if matches!(event.kind, EventKind::Access(_)) {
+                        continue;
+                    }
+
+                    if event.paths.iter().any(|path| path == &libraries_file) {
+                        sync_libraries(&mut watcher, &mut watched);
+                        continue;
+                    }
+
+                    for path in event.paths {
+                        if let Some(library) = watched.iter().find(|library| path.starts_with(library.as_str())) {
+                            pending.entry(library.clone()).or_default().insert(path);
+                            first_pending.get_or_insert_with(Instant::now);
+                        }
+                    }
+                }
+                Some(WatchMessage::SyncLibraries) => sync_libraries(&mut watcher, &mut watched),
+                None => {
+                    flush(&mut pending, &on_changes);
+                    first_pending = None;
+                }
+            }
+        }
+    });
+}
+
+/// Asks the watcher to start watching newly added libraries and stop watching
+/// removed ones.
+pub fn sync_watched_libraries() {
+    if let Some(sender) = WATCH_SENDER.lock().unwrap().as_ref() {
+        let _ = sender.send(WatchMessage::SyncLibraries);
+    }
+}
+
+fn sync_libraries(watcher: &mut RecommendedWatcher, watched: &mut HashSet<String>) {
+    let libraries: HashSet<String> = read_libraries().enabled_paths().into_iter().collect();
+
+    for library in watched.difference(&libraries) {
+        if let Err(e) = watcher.unwatch(Path::new(library)) {
+            warn!("Failed to stop watching {}: {}", library, e);
+        }
+        info!("Stopped watching {}", library);
+    }
+
+    watched.retain(|library| libraries.contains(library));
+
+    for library in libraries {
+        if watched.contains(&library) {
+            continue;
+        }
+
+        match watcher.watch(Path::new(&library), RecursiveMode::Recursive) {
+            Ok(()) => {
+                info!("Watching {}", library);
+                watched.insert(library);
+            }
+            Err(e) => warn!("Failed to watch {}: {}", library, e),
+        }
+    }
+}
+
+fn flush<F>(pending: &mut HashMap<String, HashSet<PathBuf>>, on_changes: &F)
+where
+    F: Fn(&str, Vec<PathBuf>),
+{
+    for (library, paths) in pending.drain() {
+        on_changes(&library, paths.into_iter().collect());
+    }
+}
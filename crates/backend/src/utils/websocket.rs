@@ -1,7 +1,9 @@
 use actix_web::{web, Error as OtherError, HttpRequest, HttpResponse};
+use serde::Serialize;
 use tracing::info;
 
 use super::globals::GLOBAL_SESSION;
+use super::jobs::log_to_current_job;
 
 pub async fn ws(req: HttpRequest, body: web::Payload) -> Result<HttpResponse, OtherError> {
     let (response, session, mut _msg_stream) = actix_ws::handle(&req, body)?;
@@ -16,14 +18,38 @@ pub async fn ws(req: HttpRequest, body: web::Payload) -> Result<HttpResponse, Ot
     Ok(response)
 }
 
+/// Sends a log line to the client, and to the log of the job sending it.
 pub async fn log_to_ws(message: impl Into<String>) {
     let message = message.into();
-    let mut session_lock = GLOBAL_SESSION.lock().unwrap();
-    if let Some(session) = session_lock.as_mut() {
-        if let Err(e) = session.text(&message).await {
+    log_to_current_job(&message).await;
+
+    // Jobs log from several tasks on one thread, so the lock isn't held across the send
+    let session = GLOBAL_SESSION.lock().unwrap().clone();
+    if let Some(mut session) = session {
+        if let Err(e) = session.text(message).await {
             info!("Failed to send message: {}", e);
         }
     } else {
         info!("No active session found, message not sent");
     }
-}
\ No newline at end of file
+}
+
+/// Sends a structured event as JSON. Events carry a `type` field, which plain
+/// `log_to_ws` lines don't, and are sent often enough that nothing is logged
+/// when no client is connected.
+pub async fn send_to_ws(event: &impl Serialize) {
+    let json = match serde_json::to_string(event) {
+        Ok(json) => json,
+        Err(e) => {
+            info!("Failed to serialize event: {}", e);
+            return;
+        }
+    };
+
+    let session = GLOBAL_SESSION.lock().unwrap().clone();
+    if let Some(mut session) = session {
+        if let Err(e) = session.text(json).await {
+            info!("Failed to send message: {}", e);
+        }
+    }
+}